@@ -1,36 +1,73 @@
 //! Admin functionality for key management and activity tracking
 //! Uses a special admin folder in S3 that users cannot access
 
-use rusoto_core::{Region, HttpClient};
-use rusoto_credential::StaticProvider;
-use rusoto_s3::{
-    S3Client as RusotoS3Client, S3,
-    GetObjectRequest, PutObjectRequest,
-};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use futures::TryStreamExt;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use chrono::{DateTime, Utc};
-use crate::secrets;
-
-// Scaleway S3 Configuration
-const S3_ENDPOINT: &str = "https://s3.nl-ams.scw.cloud";
-const S3_REGION: &str = "nl-ams";
-const S3_BUCKET: &str = "cloud-storage-exad";
+use futures::{StreamExt, TryStreamExt};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::{oneshot, RwLock};
+use crate::crypto::decrypt_key;
+use crate::s3_client::{S3Client, S3ProviderConfig};
 
 // Admin folder path (not accessible by user keys)
 const ADMIN_PREFIX: &str = "_admin/";
 const WHITELIST_FILE: &str = "_admin/whitelist.json";
 const BLACKLIST_FILE: &str = "_admin/blacklist.json";
+// Compacted activity log; newer entries are shards under ACTIVITY_LOG_SHARD_PREFIX,
+// one small object per write so concurrent writers never overwrite each other
 const ACTIVITY_LOG_FILE: &str = "_admin/activity_log.json";
+const ACTIVITY_LOG_SHARD_PREFIX: &str = "_admin/activity_log/";
+const STATS_CACHE_FILE: &str = "_admin/stats_cache.json";
+
+// How long a computed stats snapshot is reused
+const STATS_CACHE_TTL_MINUTES: i64 = 10;
+// Number of users listed in ActivityStats::top_users_by_activity
+const TOP_USERS_LIMIT: usize = 10;
+
+// How long cached whitelist/blacklist data stays fresh
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(60);
+
+// Activity log writes are batched: up to this many entries per S3 PUT...
+const ACTIVITY_BATCH_SIZE: usize = 10;
+// ...or whatever has queued up after this long
+const ACTIVITY_BATCH_INTERVAL: Duration = Duration::from_secs(5);
+// Entries queued before logging falls back to direct writes
+const ACTIVITY_QUEUE_CAPACITY: usize = 256;
+// Keep only the most recent entries to prevent the log from growing too large
+const MAX_ACTIVITY_LOG_ENTRIES: usize = 10000;
+// Shards read at the same time when loading the activity log
+const ACTIVITY_SHARD_READ_CONCURRENCY: usize = 16;
+/// Reading the activity log compacts it once it has this many shards
+const AUTO_COMPACT_SHARD_COUNT: usize = 500;
+/// Times a compaction starts over after losing a race with another writer
+const COMPACTION_ATTEMPTS: usize = 3;
+
+// Tags for how a whitelist/blacklist entry's key_hash was computed. Entries
+// written before the tag existed are SHA-256.
+const HASH_ALGORITHM_BLAKE3: &str = "blake3";
+const HASH_ALGORITHM_SHA256: &str = "sha256";
+// uid_based whitelist entries are stored under "uid:<user_id>" rather than a key hash
+const HASH_ALGORITHM_NONE: &str = "none";
+const UID_ENTRY_PREFIX: &str = "uid:";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WhitelistEntry {
-    pub key_hash: String,  // SHA256 hash of the key (not the full key for security)
+    pub key_hash: String,  // Hash of the key (not the full key for security)
     pub user_name: String,
     pub user_id: String,
     pub created_at: DateTime<Utc>,
     pub notes: Option<String>,
+    #[serde(default = "legacy_hash_algorithm")]
+    pub hash_algorithm: String,
+    /// Allows every key issued to `user_id`, e.g. after the key is rotated
+    #[serde(default)]
+    pub uid_based: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -40,6 +77,8 @@ pub struct BlacklistEntry {
     pub user_id: String,
     pub blacklisted_at: DateTime<Utc>,
     pub reason: String,
+    #[serde(default = "legacy_hash_algorithm")]
+    pub hash_algorithm: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -52,6 +91,19 @@ pub struct ActivityLogEntry {
     pub details: Option<String>,
 }
 
+impl ActivityLogEntry {
+    pub fn new(key: &str, user_name: &str, user_id: &str, action: &str, details: Option<String>) -> Self {
+        Self {
+            key_hash: hash_key(key),
+            user_name: user_name.to_string(),
+            user_id: user_id.to_string(),
+            action: action.to_string(),
+            timestamp: Utc::now(),
+            details,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Whitelist {
     pub entries: HashMap<String, WhitelistEntry>,  // key_hash -> entry
@@ -62,88 +114,98 @@ pub struct Blacklist {
     pub entries: HashMap<String, BlacklistEntry>,  // key_hash -> entry
 }
 
+impl Whitelist {
+    /// The entry for `key`, whichever algorithm it was hashed with
+    pub fn entry_for_key(&self, key: &str) -> Option<&WhitelistEntry> {
+        find_entry(&self.entries, key, |entry| &entry.hash_algorithm)
+    }
+
+    /// The `uid_based` entry covering every key of `user_id`
+    pub fn entry_for_uid(&self, user_id: &str) -> Option<&WhitelistEntry> {
+        self.entries
+            .get(&uid_entry_key(user_id))
+            .filter(|entry| entry.uid_based && entry.user_id == user_id)
+    }
+}
+
+impl Blacklist {
+    /// The entry for `key`, whichever algorithm it was hashed with
+    pub fn entry_for_key(&self, key: &str) -> Option<&BlacklistEntry> {
+        find_entry(&self.entries, key, |entry| &entry.hash_algorithm)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ActivityLog {
     pub entries: Vec<ActivityLogEntry>,
+    /// Shards already merged into the compacted file, which may not be deleted yet
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub compacted_shards: Vec<String>,
 }
 
 /// Hash a key for storage (we don't store raw keys)
 pub fn hash_key(key: &str) -> String {
+    blake3::hash(key.as_bytes()).to_hex().to_string()
+}
+
+/// SHA-256 hash of a key, as stored by entries written before BLAKE3
+fn legacy_hash_key(key: &str) -> String {
     use sha2::{Sha256, Digest};
-    let mut hasher = Sha256::new();
-    hasher.update(key.as_bytes());
-    let result = hasher.finalize();
-    hex::encode(result)
+    hex::encode(Sha256::digest(key.as_bytes()))
 }
 
-pub struct AdminClient {
-    client: RusotoS3Client,
+fn legacy_hash_algorithm() -> String {
+    HASH_ALGORITHM_SHA256.to_string()
 }
 
-impl AdminClient {
-    pub fn new() -> Result<Self, String> {
-        let credentials = StaticProvider::new_minimal(
-            secrets::S3_ACCESS_KEY.to_string(),
-            secrets::S3_SECRET_KEY.to_string(),
-        );
+/// Look up `key` by its BLAKE3 hash, falling back to its SHA-256 hash for
+/// entries tagged as SHA-256
+fn find_entry<'a, E>(
+    entries: &'a HashMap<String, E>,
+    key: &str,
+    hash_algorithm: impl Fn(&E) -> &str,
+) -> Option<&'a E> {
+    entries.get(&hash_key(key)).or_else(|| {
+        entries
+            .get(&legacy_hash_key(key))
+            .filter(|entry| hash_algorithm(entry) == HASH_ALGORITHM_SHA256)
+    })
+}
 
-        let region = Region::Custom {
-            name: S3_REGION.to_string(),
-            endpoint: S3_ENDPOINT.to_string(),
-        };
+/// Where the `uid_based` whitelist entry for `user_id` is stored
+fn uid_entry_key(user_id: &str) -> String {
+    format!("{}{}", UID_ENTRY_PREFIX, user_id)
+}
 
-        let http_client = HttpClient::new()
-            .map_err(|e| e.to_string())?;
+/// Remove the entry for `key` under either hash
+fn remove_entry<E>(entries: &mut HashMap<String, E>, key: &str) {
+    entries.remove(&hash_key(key));
+    entries.remove(&legacy_hash_key(key));
+}
 
-        let client = RusotoS3Client::new_with(http_client, credentials, region);
+pub struct AdminClient {
+    s3: Arc<S3Client>,
+}
 
-        Ok(Self { client })
+impl AdminClient {
+    pub fn new() -> Result<Self, String> {
+        let s3 = S3Client::new_admin().map_err(|e| e.to_string())?;
+        Ok(Self::with_client(Arc::new(s3)))
     }
 
-    /// Read a JSON file from S3
-    async fn read_json<T: for<'de> Deserialize<'de> + Default>(&self, key: &str) -> Result<T, String> {
-        let request = GetObjectRequest {
-            bucket: S3_BUCKET.to_string(),
-            key: key.to_string(),
-            ..Default::default()
-        };
+    /// Use an existing client, which must not have a user folder prefix
+    pub fn with_client(s3: Arc<S3Client>) -> Self {
+        Self { s3 }
+    }
 
-        match self.client.get_object(request).await {
-            Ok(response) => {
-                let body = response.body.ok_or("No body")?;
-                let bytes: Vec<u8> = body
-                    .map_ok(|b| b.to_vec())
-                    .try_concat()
-                    .await
-                    .map_err(|e| e.to_string())?;
-                
-                serde_json::from_slice(&bytes).map_err(|e| e.to_string())
-            }
-            Err(e) => {
-                // If file doesn't exist, return default
-                if e.to_string().contains("NoSuchKey") || e.to_string().contains("404") {
-                    Ok(T::default())
-                } else {
-                    Err(e.to_string())
-                }
-            }
-        }
+    /// Read a JSON file from S3
+    async fn read_json<T: DeserializeOwned + Default>(&self, key: &str) -> Result<T, String> {
+        self.s3.read_json_from_key(key).await.map_err(|e| e.to_string())
     }
 
     /// Write a JSON file to S3
     async fn write_json<T: Serialize>(&self, key: &str, data: &T) -> Result<(), String> {
-        let json = serde_json::to_string_pretty(data).map_err(|e| e.to_string())?;
-        
-        let request = PutObjectRequest {
-            bucket: S3_BUCKET.to_string(),
-            key: key.to_string(),
-            body: Some(json.into_bytes().into()),
-            content_type: Some("application/json".to_string()),
-            ..Default::default()
-        };
-
-        self.client.put_object(request).await.map_err(|e| e.to_string())?;
-        Ok(())
+        self.s3.write_json_to_key(key, data).await.map_err(|e| e.to_string())
     }
 
     /// Get the whitelist
@@ -158,17 +220,15 @@ impl AdminClient {
 
     /// Check if a key is whitelisted
     pub async fn is_whitelisted(&self, key: &str) -> Result<bool, String> {
-        let key_hash = hash_key(key);
         let whitelist = self.get_whitelist().await?;
-        Ok(whitelist.entries.contains_key(&key_hash))
+        Ok(whitelist.entry_for_key(key).is_some())
     }
 
     /// Check if a key is blacklisted
     pub async fn is_blacklisted(&self, key: &str) -> Result<(bool, Option<String>), String> {
-        let key_hash = hash_key(key);
         let blacklist = self.get_blacklist().await?;
         
-        if let Some(entry) = blacklist.entries.get(&key_hash) {
+        if let Some(entry) = blacklist.entry_for_key(key) {
             Ok((true, Some(entry.reason.clone())))
         } else {
             Ok((false, None))
@@ -186,22 +246,42 @@ impl AdminClient {
         let key_hash = hash_key(key);
         let mut whitelist = self.get_whitelist().await?;
         
+        // Replaces any SHA-256 entry for the same key
+        remove_entry(&mut whitelist.entries, key);
         whitelist.entries.insert(key_hash.clone(), WhitelistEntry {
             key_hash,
             user_name: user_name.to_string(),
             user_id: user_id.to_string(),
             created_at: Utc::now(),
             notes,
+            hash_algorithm: HASH_ALGORITHM_BLAKE3.to_string(),
+            uid_based: false,
         });
         
         self.write_json(WHITELIST_FILE, &whitelist).await
     }
 
+    /// Whitelist every key issued to `user_id`, now and after rotation
+    pub async fn add_to_whitelist_by_uid(&self, user_id: &str, user_name: &str) -> Result<(), String> {
+        let key_hash = uid_entry_key(user_id);
+        let mut whitelist = self.get_whitelist().await?;
+        whitelist.entries.insert(key_hash.clone(), WhitelistEntry {
+            key_hash,
+            user_name: user_name.to_string(),
+            user_id: user_id.to_string(),
+            created_at: Utc::now(),
+            notes: None,
+            hash_algorithm: HASH_ALGORITHM_NONE.to_string(),
+            uid_based: true,
+        });
+
+        self.write_json(WHITELIST_FILE, &whitelist).await
+    }
+
     /// Remove a key from the whitelist
     pub async fn remove_from_whitelist(&self, key: &str) -> Result<(), String> {
-        let key_hash = hash_key(key);
         let mut whitelist = self.get_whitelist().await?;
-        whitelist.entries.remove(&key_hash);
+        remove_entry(&mut whitelist.entries, key);
         self.write_json(WHITELIST_FILE, &whitelist).await
     }
 
@@ -216,12 +296,15 @@ impl AdminClient {
         let key_hash = hash_key(key);
         let mut blacklist = self.get_blacklist().await?;
         
+        // Replaces any SHA-256 entry for the same key
+        remove_entry(&mut blacklist.entries, key);
         blacklist.entries.insert(key_hash.clone(), BlacklistEntry {
             key_hash,
             user_name: user_name.to_string(),
             user_id: user_id.to_string(),
             blacklisted_at: Utc::now(),
             reason: reason.to_string(),
+            hash_algorithm: HASH_ALGORITHM_BLAKE3.to_string(),
         });
         
         self.write_json(BLACKLIST_FILE, &blacklist).await
@@ -229,12 +312,51 @@ impl AdminClient {
 
     /// Remove a key from the blacklist
     pub async fn remove_from_blacklist(&self, key: &str) -> Result<(), String> {
-        let key_hash = hash_key(key);
         let mut blacklist = self.get_blacklist().await?;
-        blacklist.entries.remove(&key_hash);
+        remove_entry(&mut blacklist.entries, key);
         self.write_json(BLACKLIST_FILE, &blacklist).await
     }
 
+    /// Lift a key's revocation: take it off the blacklist and, if the whitelist is in
+    /// use and doesn't already allow it, put it back on. An empty whitelist allows
+    /// every key, and adding this one would lock everyone else out. Returns whether
+    /// the key was added to the whitelist.
+    pub async fn unrevoke_key(&self, key: &str, user_name: &str, user_id: &str) -> Result<bool, String> {
+        self.remove_from_blacklist(key).await?;
+
+        let whitelist = self.get_whitelist().await?;
+        if check_key_access(key, &whitelist, &Blacklist::default()).allowed {
+            return Ok(false);
+        }
+        self.add_to_whitelist(key, user_name, user_id, None).await?;
+        Ok(true)
+    }
+
+    /// Store the hash algorithm tag on every whitelist and blacklist entry,
+    /// returning how many are still SHA-256
+    ///
+    /// Raw keys aren't stored, so SHA-256 entries can't be re-hashed; they keep
+    /// working through the SHA-256 lookup and switch to BLAKE3 when the key is
+    /// added again.
+    pub async fn migrate_hash_algorithm(&self) -> Result<usize, String> {
+        let whitelist = self.get_whitelist().await?;
+        let blacklist = self.get_blacklist().await?;
+        let legacy = whitelist
+            .entries
+            .values()
+            .filter(|entry| entry.hash_algorithm == HASH_ALGORITHM_SHA256)
+            .count()
+            + blacklist
+                .entries
+                .values()
+                .filter(|entry| entry.hash_algorithm == HASH_ALGORITHM_SHA256)
+                .count();
+
+        self.write_json(WHITELIST_FILE, &whitelist).await?;
+        self.write_json(BLACKLIST_FILE, &blacklist).await?;
+        Ok(legacy)
+    }
+
     /// Log an activity
     pub async fn log_activity(
         &self,
@@ -244,64 +366,966 @@ impl AdminClient {
         action: &str,
         details: Option<String>,
     ) -> Result<(), String> {
-        let key_hash = hash_key(key);
-        let mut log = self.get_activity_log().await.unwrap_or_default();
-        
-        log.entries.push(ActivityLogEntry {
-            key_hash,
-            user_name: user_name.to_string(),
-            user_id: user_id.to_string(),
-            action: action.to_string(),
-            timestamp: Utc::now(),
-            details,
+        let entry = ActivityLogEntry::new(key, user_name, user_id, action, details);
+        self.append_activity(vec![entry]).await
+    }
+
+    /// Append several entries to the activity log as one new shard
+    pub async fn append_activity(&self, entries: Vec<ActivityLogEntry>) -> Result<(), String> {
+        let shard = ActivityLog {
+            entries,
+            ..Default::default()
+        };
+        self.write_json(&activity_shard_key(Utc::now(), rand::random()), &shard).await
+    }
+
+    /// Log an activity in the background; failures are logged, not returned.
+    /// Must be called from within the tokio runtime.
+    pub fn log_activity_fire_and_forget(entry: ActivityLogEntry) {
+        tokio::spawn(async move {
+            let result = match AdminClient::new() {
+                Ok(admin) => admin.append_activity(vec![entry]).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                log::warn!("Failed to write activity log: {}", e);
+            }
         });
-        
-        // Keep only last 10000 entries to prevent file from growing too large
-        if log.entries.len() > 10000 {
-            log.entries = log.entries.split_off(log.entries.len() - 10000);
-        }
-        
-        self.write_json(ACTIVITY_LOG_FILE, &log).await
     }
 
-    /// Get the activity log
+    /// Get the activity log: the compacted file plus every shard written since.
+    /// Compacts the log first once it has `AUTO_COMPACT_SHARD_COUNT` shards.
     pub async fn get_activity_log(&self) -> Result<ActivityLog, String> {
-        self.read_json(ACTIVITY_LOG_FILE).await
+        let (log, shards, _) = self.read_activity_log().await?;
+        if shards.len() >= AUTO_COMPACT_SHARD_COUNT {
+            if let Err(e) = self.compact_activity_log().await {
+                log::warn!("Failed to compact activity log: {}", e);
+            }
+        }
+        Ok(log)
+    }
+
+    /// Merge the shards into the compacted log file and delete them, returning how many
+    /// were merged. Shards written meanwhile are left for the next compaction.
+    pub async fn compact_activity_log(&self) -> Result<usize, String> {
+        let (merged, _) = self.compact_activity_log_with(|_| ()).await?;
+        Ok(merged)
+    }
+
+    /// Compact the activity log, applying `edit` to the merged log before it's written.
+    ///
+    /// The log file is only written if no other admin rewrote it since it was read, so
+    /// two compactions can't drop each other's shards; the loser starts over.
+    async fn compact_activity_log_with<R>(&self, edit: impl Fn(&mut ActivityLog) -> R) -> Result<(usize, R), String> {
+        for _ in 0..COMPACTION_ATTEMPTS {
+            let (mut log, shards, etag) = self.read_activity_log().await?;
+            let result = edit(&mut log);
+            log.compacted_shards = shards.clone();
+            let written = self
+                .s3
+                .write_json_if_unchanged(ACTIVITY_LOG_FILE, &log, etag.as_deref())
+                .await
+                .map_err(|e| e.to_string())?;
+            if !written {
+                log::info!("Activity log changed during compaction, starting over");
+                continue;
+            }
+
+            for key in &shards {
+                self.s3.delete_object(key).await.map_err(|e| e.to_string())?;
+            }
+            return Ok((shards.len(), result));
+        }
+        Err("Activity log kept changing during compaction, try again later".to_string())
+    }
+
+    /// The merged activity log, along with the keys of the shards it includes and the
+    /// compacted file's ETag
+    async fn read_activity_log(&self) -> Result<(ActivityLog, Vec<String>, Option<String>), String> {
+        let mut shard_keys: Vec<String> = self
+            .s3
+            .list_objects(ACTIVITY_LOG_SHARD_PREFIX)
+            .await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .map(|object| object.key)
+            .collect();
+        shard_keys.sort();
+
+        let (base, etag): (ActivityLog, _) = self
+            .s3
+            .read_json_with_etag(ACTIVITY_LOG_FILE)
+            .await
+            .map_err(|e| e.to_string())?;
+        // Skip shards a compaction merged but hasn't deleted yet. One deleted by a
+        // compaction since the listing reads as empty.
+        let unmerged: Vec<String> = shard_keys
+            .iter()
+            .filter(|key| !base.compacted_shards.contains(key))
+            .cloned()
+            .collect();
+        let shards: Vec<ActivityLog> = futures::stream::iter(unmerged)
+            .map(|key| async move { self.read_json(&key).await })
+            .buffered(ACTIVITY_SHARD_READ_CONCURRENCY)
+            .try_collect()
+            .await?;
+
+        Ok((merge_activity_log(base, shards), shard_keys, etag))
+    }
+
+    /// Get aggregated activity metrics for entries since the given time.
+    /// Results are cached in S3 for 10 minutes so repeated calls don't rescan the log.
+    pub async fn get_activity_stats(&self, since: DateTime<Utc>) -> Result<ActivityStats, String> {
+        let ttl = chrono::Duration::minutes(STATS_CACHE_TTL_MINUTES);
+        let now = Utc::now();
+
+        let cached: Option<StatsCache> = self.read_json(STATS_CACHE_FILE).await.unwrap_or_default();
+        if let Some(cache) = cached {
+            if now - cache.computed_at < ttl && (cache.since - since).abs() < ttl {
+                return Ok(cache.stats);
+            }
+        }
+
+        let log = self.get_activity_log().await?;
+        let stats = compute_activity_stats(&log.entries, since);
+
+        let cache = StatsCache {
+            since,
+            computed_at: now,
+            stats: stats.clone(),
+        };
+        if let Err(e) = self.write_json(STATS_CACHE_FILE, &cache).await {
+            log::warn!("Failed to write stats cache: {}", e);
+        }
+
+        Ok(stats)
+    }
+
+    /// Remove everything stored for a user: cloud files, list entries and activity log entries.
+    /// Matches on user_id since the user's key may no longer be known.
+    pub async fn purge_user_data(&self, user_id: &str, user_name: &str) -> Result<PurgeResult, String> {
+        log::info!("Purging all data for user {} ({})", user_name, user_id);
+
+        // Delete the user's cloud files
+        let s3_client = S3Client::new(S3ProviderConfig::scaleway(), format!("users/{}/", user_id))
+            .await
+            .map_err(|e| e.to_string())?;
+        let files_deleted = s3_client.delete_all_objects().await.map_err(|e| e.to_string())?;
+
+        // Remove from whitelist and blacklist
+        let mut whitelist = self.get_whitelist().await?;
+        whitelist.entries.retain(|_, entry| entry.user_id != user_id);
+        self.write_json(WHITELIST_FILE, &whitelist).await?;
+
+        let mut blacklist = self.get_blacklist().await?;
+        blacklist.entries.retain(|_, entry| entry.user_id != user_id);
+        self.write_json(BLACKLIST_FILE, &blacklist).await?;
+
+        // Drop the user's activity log entries, which all sit in the log file once compacted
+        let (_, log_entries_removed) = self
+            .compact_activity_log_with(|log| remove_user_log_entries(log, user_id))
+            .await?;
+
+        Ok(PurgeResult {
+            files_deleted,
+            log_entries_removed,
+        })
     }
 
     /// Validate a key (check whitelist and blacklist)
     pub async fn validate_key_access(&self, key: &str) -> Result<KeyValidationResult, String> {
-        // First check blacklist
-        let (is_blacklisted, reason) = self.is_blacklisted(key).await?;
-        if is_blacklisted {
-            return Ok(KeyValidationResult {
-                allowed: false,
-                reason: Some(format!("Key has been disabled: {}", reason.unwrap_or_default())),
-            });
+        let blacklist = self.get_blacklist().await?;
+        let whitelist = self.get_whitelist().await?;
+        Ok(check_key_access(key, &whitelist, &blacklist))
+    }
+
+    /// Save the whitelist and blacklist to `path`, for `LocalAdminCache::load`
+    pub async fn save_cache_locally(&self, path: &Path) -> Result<(), String> {
+        LocalAdminCache::new(self.get_whitelist().await?, self.get_blacklist().await?).save(path)
+    }
+}
+
+/// Aggregate log entries at or after `since`
+fn compute_activity_stats(entries: &[ActivityLogEntry], since: DateTime<Utc>) -> ActivityStats {
+    use chrono::Timelike;
+
+    let mut stats = ActivityStats::default();
+    let mut per_user: HashMap<&str, (&str, u64)> = HashMap::new();
+
+    for entry in entries.iter().filter(|e| e.timestamp >= since) {
+        match entry.action.as_str() {
+            "login" => stats.total_logins += 1,
+            "upload_started" => stats.total_uploads += 1,
+            "download_started" => stats.total_downloads += 1,
+            "login_blocked" => stats.total_blocked_logins += 1,
+            _ => {}
+        }
+
+        stats.actions_by_hour[entry.timestamp.hour() as usize] += 1;
+        per_user.entry(&entry.user_id).or_insert((&entry.user_name, 0)).1 += 1;
+    }
+
+    stats.unique_users = per_user.len();
+
+    let mut top: Vec<(String, u64)> = per_user
+        .into_values()
+        .map(|(name, count)| (name.to_string(), count))
+        .collect();
+    top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    top.truncate(TOP_USERS_LIMIT);
+    stats.top_users_by_activity = top;
+
+    stats
+}
+
+/// Key for a new activity log shard; keys sort in the order they were written
+fn activity_shard_key(now: DateTime<Utc>, nonce: u64) -> String {
+    format!("{}{:013}_{:016x}.json", ACTIVITY_LOG_SHARD_PREFIX, now.timestamp_millis(), nonce)
+}
+
+/// Combine the compacted log with its shards in time order, keeping the most recent
+/// `MAX_ACTIVITY_LOG_ENTRIES`
+fn merge_activity_log(base: ActivityLog, shards: Vec<ActivityLog>) -> ActivityLog {
+    let mut entries = base.entries;
+    entries.extend(shards.into_iter().flat_map(|shard| shard.entries));
+    entries.sort_by_key(|entry| entry.timestamp);
+
+    if entries.len() > MAX_ACTIVITY_LOG_ENTRIES {
+        entries = entries.split_off(entries.len() - MAX_ACTIVITY_LOG_ENTRIES);
+    }
+    ActivityLog {
+        entries,
+        ..Default::default()
+    }
+}
+
+/// Remove all log entries for a user, returning how many were removed
+fn remove_user_log_entries(log: &mut ActivityLog, user_id: &str) -> usize {
+    let before = log.entries.len();
+    log.entries.retain(|entry| entry.user_id != user_id);
+    before - log.entries.len()
+}
+
+/// Check a key against the whitelist and blacklist
+fn check_key_access(key: &str, whitelist: &Whitelist, blacklist: &Blacklist) -> KeyValidationResult {
+    // First check blacklist
+    if let Some(entry) = blacklist.entry_for_key(key) {
+        return KeyValidationResult {
+            allowed: false,
+            reason: Some(format!("Key has been disabled: {}", entry.reason)),
+        };
+    }
+
+    // Then check whitelist (if whitelist is empty, allow all keys), by key hash
+    // first and then by the user the key was issued to
+    let whitelisted = || {
+        whitelist.entry_for_key(key).is_some()
+            || decrypt_key(key).is_ok_and(|payload| whitelist.entry_for_uid(&payload.uid).is_some())
+    };
+    if !whitelist.entries.is_empty() && !whitelisted() {
+        return KeyValidationResult {
+            allowed: false,
+            reason: Some("Key is not authorized. Please contact your administrator.".to_string()),
+        };
+    }
+
+    KeyValidationResult {
+        allowed: true,
+        reason: None,
+    }
+}
+
+/// Copy of the admin lists kept on disk, for checking keys when S3 can't be reached
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalAdminCache {
+    pub whitelist: Whitelist,
+    pub blacklist: Blacklist,
+    pub saved_at: DateTime<Utc>,
+}
+
+impl LocalAdminCache {
+    pub fn new(whitelist: Whitelist, blacklist: Blacklist) -> Self {
+        Self { whitelist, blacklist, saved_at: Utc::now() }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_slice(&data).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        let data = serde_json::to_vec(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, data).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// Seconds since the lists were saved
+    pub fn age_secs(&self, now: DateTime<Utc>) -> u64 {
+        (now - self.saved_at).num_seconds().max(0) as u64
+    }
+
+    pub fn validate_key_access(&self, key: &str) -> KeyValidationResult {
+        check_key_access(key, &self.whitelist, &self.blacklist)
+    }
+}
+
+/// Where a key check's lists came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAccessSource {
+    S3,
+    LocalCache { cache_age_secs: u64 },
+}
+
+/// The result of checking `key` against the lists in S3, or when that failed,
+/// against the local cache at `cache_path` if it is at most `max_age_secs` old.
+/// An older cache can't let a key in, but a revocation it records still stands.
+/// Errors when neither can decide, and the caller should refuse the key.
+pub fn key_access_with_fallback(
+    key: &str,
+    from_s3: Result<KeyValidationResult, String>,
+    cache_path: Option<&Path>,
+    max_age_secs: u64,
+) -> Result<(KeyValidationResult, KeyAccessSource), String> {
+    let s3_error = match from_s3 {
+        Ok(result) => return Ok((result, KeyAccessSource::S3)),
+        Err(e) => e,
+    };
+    let path = cache_path.ok_or_else(|| format!("{} (no local cache)", s3_error))?;
+    let cache = LocalAdminCache::load(path).map_err(|e| format!("{} ({})", s3_error, e))?;
+    let cache_age_secs = cache.age_secs(Utc::now());
+    if cache_age_secs > max_age_secs {
+        if cache.blacklist.entry_for_key(key).is_some() {
+            let result = check_key_access(key, &Whitelist::default(), &cache.blacklist);
+            return Ok((result, KeyAccessSource::LocalCache { cache_age_secs }));
+        }
+        return Err(format!("{} (local cache is {}s old)", s3_error, cache_age_secs));
+    }
+    Ok((cache.validate_key_access(key), KeyAccessSource::LocalCache { cache_age_secs }))
+}
+
+/// In-memory copies of the admin lists, with the time they were fetched
+#[derive(Debug, Default)]
+pub struct AdminCache {
+    pub whitelist: Option<(Whitelist, Instant)>,
+    pub blacklist: Option<(Blacklist, Instant)>,
+}
+
+/// Return the cached value if it is still fresh, otherwise fetch and store it.
+/// The caller holds the write lock, so concurrent misses only fetch once.
+async fn cached_or_fetch<T, F, Fut>(
+    slot: &mut Option<(T, Instant)>,
+    ttl: Duration,
+    fetch: F,
+) -> Result<T, String>
+where
+    T: Clone,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T, String>>,
+{
+    if let Some((value, fetched_at)) = slot {
+        if fetched_at.elapsed() < ttl {
+            return Ok(value.clone());
+        }
+    }
+
+    let value = fetch().await?;
+    *slot = Some((value.clone(), Instant::now()));
+    Ok(value)
+}
+
+/// AdminClient wrapper that caches the whitelist and blacklist to cut down on S3 reads
+pub struct CachedAdminClient {
+    inner: AdminClient,
+    cache: Arc<RwLock<AdminCache>>,
+    ttl: Duration,
+}
+
+impl CachedAdminClient {
+    /// Create a client backed by a cache shared across requests
+    pub fn new(cache: Arc<RwLock<AdminCache>>) -> Result<Self, String> {
+        Ok(Self {
+            inner: AdminClient::new()?,
+            cache,
+            ttl: DEFAULT_CACHE_TTL,
+        })
+    }
+
+    /// Override how long cached data stays fresh
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Access the uncached client
+    pub fn inner(&self) -> &AdminClient {
+        &self.inner
+    }
+
+    /// Drop all cached data so the next read goes to S3
+    pub async fn invalidate_cache(&self) {
+        *self.cache.write().await = AdminCache::default();
+    }
+
+    /// Get the whitelist, from cache if fresh
+    pub async fn get_whitelist(&self) -> Result<Whitelist, String> {
+        if let Some((whitelist, fetched_at)) = &self.cache.read().await.whitelist {
+            if fetched_at.elapsed() < self.ttl {
+                return Ok(whitelist.clone());
+            }
         }
 
-        // Then check whitelist (if whitelist is empty, allow all keys)
+        let mut cache = self.cache.write().await;
+        cached_or_fetch(&mut cache.whitelist, self.ttl, || self.inner.get_whitelist()).await
+    }
+
+    /// Get the blacklist, from cache if fresh
+    pub async fn get_blacklist(&self) -> Result<Blacklist, String> {
+        if let Some((blacklist, fetched_at)) = &self.cache.read().await.blacklist {
+            if fetched_at.elapsed() < self.ttl {
+                return Ok(blacklist.clone());
+            }
+        }
+
+        let mut cache = self.cache.write().await;
+        cached_or_fetch(&mut cache.blacklist, self.ttl, || self.inner.get_blacklist()).await
+    }
+
+    /// Validate a key using the cached lists
+    pub async fn validate_key_access(&self, key: &str) -> Result<KeyValidationResult, String> {
+        let blacklist = self.get_blacklist().await?;
         let whitelist = self.get_whitelist().await?;
-        if !whitelist.entries.is_empty() {
-            let key_hash = hash_key(key);
-            if !whitelist.entries.contains_key(&key_hash) {
-                return Ok(KeyValidationResult {
-                    allowed: false,
-                    reason: Some("Key is not authorized. Please contact your administrator.".to_string()),
-                });
+        Ok(check_key_access(key, &whitelist, &blacklist))
+    }
+
+    /// Save the lists to `path`, from cache if fresh
+    pub async fn save_cache_locally(&self, path: &Path) -> Result<(), String> {
+        LocalAdminCache::new(self.get_whitelist().await?, self.get_blacklist().await?).save(path)
+    }
+
+    /// Add a key to the whitelist
+    pub async fn add_to_whitelist(
+        &self,
+        key: &str,
+        user_name: &str,
+        user_id: &str,
+        notes: Option<String>,
+    ) -> Result<(), String> {
+        let result = self.inner.add_to_whitelist(key, user_name, user_id, notes).await;
+        self.invalidate_cache().await;
+        result
+    }
+
+    /// Whitelist every key issued to `user_id`
+    pub async fn add_to_whitelist_by_uid(&self, user_id: &str, user_name: &str) -> Result<(), String> {
+        let result = self.inner.add_to_whitelist_by_uid(user_id, user_name).await;
+        self.invalidate_cache().await;
+        result
+    }
+
+    /// Remove a key from the whitelist
+    pub async fn remove_from_whitelist(&self, key: &str) -> Result<(), String> {
+        let result = self.inner.remove_from_whitelist(key).await;
+        self.invalidate_cache().await;
+        result
+    }
+
+    /// Add a key to the blacklist
+    pub async fn add_to_blacklist(
+        &self,
+        key: &str,
+        user_name: &str,
+        user_id: &str,
+        reason: &str,
+    ) -> Result<(), String> {
+        let result = self.inner.add_to_blacklist(key, user_name, user_id, reason).await;
+        self.invalidate_cache().await;
+        result
+    }
+
+    /// Remove a key from the blacklist
+    pub async fn remove_from_blacklist(&self, key: &str) -> Result<(), String> {
+        let result = self.inner.remove_from_blacklist(key).await;
+        self.invalidate_cache().await;
+        result
+    }
+}
+
+/// Queues activity log entries and writes them to S3 in batches
+#[derive(Clone)]
+pub struct ActivityLogger {
+    entries: mpsc::Sender<ActivityLogEntry>,
+    flushes: mpsc::Sender<oneshot::Sender<()>>,
+}
+
+impl ActivityLogger {
+    /// Create the logger along with its background batching task, which the caller must spawn
+    pub fn new() -> (Self, impl Future<Output = ()> + Send + 'static) {
+        let (entries, entry_rx) = mpsc::channel(ACTIVITY_QUEUE_CAPACITY);
+        let (flushes, flush_rx) = mpsc::channel(4);
+        let task = run_activity_batcher(
+            entry_rx,
+            flush_rx,
+            ACTIVITY_BATCH_SIZE,
+            ACTIVITY_BATCH_INTERVAL,
+            |batch| async move { AdminClient::new()?.append_activity(batch).await },
+        );
+        (Self { entries, flushes }, task)
+    }
+
+    /// Queue an entry; if the queue is full or gone, write it directly in the background
+    pub fn log(&self, entry: ActivityLogEntry) {
+        if let Err(e) = self.entries.try_send(entry) {
+            let entry = match e {
+                TrySendError::Full(entry) | TrySendError::Closed(entry) => entry,
+            };
+            log::warn!("Activity log queue unavailable, writing entry directly");
+            AdminClient::log_activity_fire_and_forget(entry);
+        }
+    }
+
+    /// Write out everything queued so far and wait for it to finish
+    pub async fn flush(&self) {
+        let (done_tx, done_rx) = oneshot::channel();
+        if self.flushes.send(done_tx).await.is_ok() {
+            let _ = done_rx.await;
+        }
+    }
+}
+
+/// Drain `entries`, writing batches of up to `batch_size` entries, or whatever
+/// has queued up `interval` after the first entry of a batch arrived
+async fn run_activity_batcher<W, Fut>(
+    mut entries: mpsc::Receiver<ActivityLogEntry>,
+    mut flushes: mpsc::Receiver<oneshot::Sender<()>>,
+    batch_size: usize,
+    interval: Duration,
+    mut write: W,
+) where
+    W: FnMut(Vec<ActivityLogEntry>) -> Fut,
+    Fut: Future<Output = Result<(), String>>,
+{
+    let mut batch = Vec::new();
+    let mut deadline: Option<tokio::time::Instant> = None;
+
+    loop {
+        let timer = async {
+            match deadline {
+                Some(at) => tokio::time::sleep_until(at).await,
+                None => std::future::pending().await,
             }
+        };
+
+        tokio::select! {
+            entry = entries.recv() => match entry {
+                Some(entry) => {
+                    if batch.is_empty() {
+                        deadline = Some(tokio::time::Instant::now() + interval);
+                    }
+                    batch.push(entry);
+                    if batch.len() < batch_size {
+                        continue;
+                    }
+                }
+                None => {
+                    // All senders are gone: write what's left and stop
+                    write_activity_batch(&mut batch, &mut write).await;
+                    return;
+                }
+            },
+            Some(done) = flushes.recv() => {
+                // Pick up anything queued before the flush was requested
+                while let Ok(entry) = entries.try_recv() {
+                    batch.push(entry);
+                }
+                write_activity_batch(&mut batch, &mut write).await;
+                deadline = None;
+                let _ = done.send(());
+                continue;
+            }
+            _ = timer => {}
         }
 
-        Ok(KeyValidationResult {
-            allowed: true,
-            reason: None,
-        })
+        write_activity_batch(&mut batch, &mut write).await;
+        deadline = None;
     }
 }
 
+/// Hand the pending batch to `write`, logging rather than returning failures
+async fn write_activity_batch<W, Fut>(batch: &mut Vec<ActivityLogEntry>, write: &mut W)
+where
+    W: FnMut(Vec<ActivityLogEntry>) -> Fut,
+    Fut: Future<Output = Result<(), String>>,
+{
+    if batch.is_empty() {
+        return;
+    }
+    let count = batch.len();
+    if let Err(e) = write(std::mem::take(batch)).await {
+        log::warn!("Failed to write {} activity log entries: {}", count, e);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ActivityStats {
+    pub total_logins: u64,
+    pub total_uploads: u64,
+    pub total_downloads: u64,
+    pub total_blocked_logins: u64,
+    pub unique_users: usize,
+    pub top_users_by_activity: Vec<(String, u64)>,  // (user_name, action count)
+    pub actions_by_hour: [u64; 24],  // indexed by UTC hour
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StatsCache {
+    since: DateTime<Utc>,
+    computed_at: DateTime<Utc>,
+    stats: ActivityStats,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PurgeResult {
+    pub files_deleted: usize,
+    pub log_entries_removed: usize,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeyValidationResult {
     pub allowed: bool,
     pub reason: Option<String>,
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{encrypt_key, KeyPayload};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn blacklist_entry(key: &str, reason: &str) -> BlacklistEntry {
+        BlacklistEntry {
+            key_hash: hash_key(key),
+            user_name: "Test".to_string(),
+            user_id: "u_test".to_string(),
+            blacklisted_at: Utc::now(),
+            reason: reason.to_string(),
+            hash_algorithm: HASH_ALGORITHM_BLAKE3.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_key_access_falls_back_to_local_cache() {
+        let path = std::env::temp_dir().join(format!("sync2bucket-admin-cache-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut blacklist = Blacklist::default();
+        blacklist.entries.insert(hash_key("EXAD-a"), blacklist_entry("EXAD-a", "left company"));
+        let allowed = KeyValidationResult { allowed: true, reason: None };
+
+        // S3 answers: its result is used and the cache isn't needed
+        let (result, source) = key_access_with_fallback("EXAD-a", Ok(allowed.clone()), Some(&path), 3600).unwrap();
+        assert!(result.allowed);
+        assert_eq!(source, KeyAccessSource::S3);
+
+        // S3 unreachable with no cache saved
+        let offline = || Err("connection refused".to_string());
+        assert!(key_access_with_fallback("EXAD-a", offline(), Some(&path), 3600).is_err());
+        assert!(key_access_with_fallback("EXAD-a", offline(), None, 3600).is_err());
+
+        // S3 unreachable with a fresh cache: its lists decide
+        LocalAdminCache::new(Whitelist::default(), blacklist.clone()).save(&path).unwrap();
+        let (result, source) = key_access_with_fallback("EXAD-a", offline(), Some(&path), 3600).unwrap();
+        assert!(!result.allowed);
+        assert!(matches!(source, KeyAccessSource::LocalCache { cache_age_secs } if cache_age_secs < 5));
+        let (result, _) = key_access_with_fallback("EXAD-b", offline(), Some(&path), 3600).unwrap();
+        assert!(result.allowed);
+
+        // A cache past the maximum age can't let a key in, but still blocks revoked ones
+        let mut stale = LocalAdminCache::new(Whitelist::default(), blacklist);
+        stale.saved_at = Utc::now() - chrono::Duration::seconds(3601);
+        stale.save(&path).unwrap();
+        let error = key_access_with_fallback("EXAD-b", offline(), Some(&path), 3600).unwrap_err();
+        assert!(error.contains("connection refused") && error.contains("old"), "{}", error);
+        let (result, source) = key_access_with_fallback("EXAD-a", offline(), Some(&path), 3600).unwrap();
+        assert!(!result.allowed);
+        assert!(result.reason.unwrap().contains("left company"));
+        assert!(matches!(source, KeyAccessSource::LocalCache { cache_age_secs } if cache_age_secs > 3600));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_check_key_access() {
+        let mut whitelist = Whitelist::default();
+        let mut blacklist = Blacklist::default();
+
+        // Empty lists allow everything
+        assert!(check_key_access("EXAD-a", &whitelist, &blacklist).allowed);
+
+        blacklist.entries.insert(hash_key("EXAD-a"), blacklist_entry("EXAD-a", "left company"));
+        let result = check_key_access("EXAD-a", &whitelist, &blacklist);
+        assert!(!result.allowed);
+        assert!(result.reason.unwrap().contains("left company"));
+
+        whitelist.entries.insert(hash_key("EXAD-b"), WhitelistEntry {
+            key_hash: hash_key("EXAD-b"),
+            user_name: "Test".to_string(),
+            user_id: "u_test".to_string(),
+            created_at: Utc::now(),
+            notes: None,
+            hash_algorithm: HASH_ALGORITHM_BLAKE3.to_string(),
+            uid_based: false,
+        });
+        assert!(check_key_access("EXAD-b", &whitelist, &blacklist).allowed);
+        assert!(!check_key_access("EXAD-c", &whitelist, &blacklist).allowed);
+    }
+
+    #[test]
+    fn test_check_key_access_by_uid() {
+        let payload = KeyPayload::new("Rotated User");
+        let old_key = encrypt_key(&payload).unwrap();
+        let new_key = encrypt_key(&payload).unwrap();
+        let other_key = encrypt_key(&KeyPayload::new("Someone Else")).unwrap();
+        assert_ne!(hash_key(&old_key), hash_key(&new_key));
+
+        let entry = |key_hash: String, uid_based: bool| WhitelistEntry {
+            key_hash,
+            user_name: payload.name.clone(),
+            user_id: payload.uid.clone(),
+            created_at: Utc::now(),
+            notes: None,
+            hash_algorithm: HASH_ALGORITHM_BLAKE3.to_string(),
+            uid_based,
+        };
+        let mut whitelist = Whitelist::default();
+        let blacklist = Blacklist::default();
+
+        // A hash entry only covers the key it was made from
+        whitelist.entries.insert(hash_key(&old_key), entry(hash_key(&old_key), false));
+        assert!(check_key_access(&old_key, &whitelist, &blacklist).allowed);
+        assert!(!check_key_access(&new_key, &whitelist, &blacklist).allowed);
+
+        // Without uid_based, an entry stored under the uid doesn't count
+        whitelist.entries.insert(uid_entry_key(&payload.uid), entry(uid_entry_key(&payload.uid), false));
+        assert!(!check_key_access(&new_key, &whitelist, &blacklist).allowed);
+
+        whitelist.entries.insert(uid_entry_key(&payload.uid), entry(uid_entry_key(&payload.uid), true));
+        assert!(check_key_access(&new_key, &whitelist, &blacklist).allowed);
+        assert!(!check_key_access(&other_key, &whitelist, &blacklist).allowed);
+        assert!(!check_key_access("EXAD-not-a-key", &whitelist, &blacklist).allowed);
+
+        // The blacklist still wins for a single key
+        let mut blacklist = Blacklist::default();
+        blacklist.entries.insert(hash_key(&new_key), blacklist_entry(&new_key, "lost laptop"));
+        assert!(!check_key_access(&new_key, &whitelist, &blacklist).allowed);
+    }
+
+    #[test]
+    fn test_legacy_sha256_entries() {
+        assert_eq!(hash_key("EXAD-a").len(), 64);
+        assert_ne!(hash_key("EXAD-a"), legacy_hash_key("EXAD-a"));
+
+        // Entries stored before the tag existed are read as SHA-256
+        let json = format!(
+            r#"{{"entries":{{"{0}":{{"key_hash":"{0}","user_name":"Old","user_id":"u_old","blacklisted_at":"2024-01-01T00:00:00Z","reason":"lost laptop"}}}}}}"#,
+            legacy_hash_key("EXAD-old")
+        );
+        let mut blacklist: Blacklist = serde_json::from_str(&json).unwrap();
+        assert_eq!(blacklist.entry_for_key("EXAD-old").unwrap().hash_algorithm, HASH_ALGORITHM_SHA256);
+        assert!(!check_key_access("EXAD-old", &Whitelist::default(), &blacklist).allowed);
+
+        // A SHA-256 hash is only trusted on entries tagged with it
+        blacklist.entries.values_mut().for_each(|entry| entry.hash_algorithm = HASH_ALGORITHM_BLAKE3.to_string());
+        assert!(blacklist.entry_for_key("EXAD-old").is_none());
+
+        blacklist.entries.insert(legacy_hash_key("EXAD-a"), BlacklistEntry {
+            key_hash: legacy_hash_key("EXAD-a"),
+            hash_algorithm: HASH_ALGORITHM_SHA256.to_string(),
+            ..blacklist_entry("EXAD-a", "left company")
+        });
+        blacklist.entries.insert(hash_key("EXAD-a"), blacklist_entry("EXAD-a", "left company"));
+        remove_entry(&mut blacklist.entries, "EXAD-a");
+        assert!(blacklist.entry_for_key("EXAD-a").is_none());
+    }
+
+    #[test]
+    fn test_remove_user_log_entries() {
+        let entry = |user_id: &str| ActivityLogEntry {
+            key_hash: String::new(),
+            user_name: "Test".to_string(),
+            user_id: user_id.to_string(),
+            action: "login".to_string(),
+            timestamp: Utc::now(),
+            details: None,
+        };
+        let mut log = ActivityLog {
+            entries: vec![entry("u_a"), entry("u_b"), entry("u_a")],
+            ..Default::default()
+        };
+
+        assert_eq!(remove_user_log_entries(&mut log, "u_a"), 2);
+        assert_eq!(log.entries.len(), 1);
+        assert_eq!(log.entries[0].user_id, "u_b");
+    }
+
+    #[test]
+    fn test_merge_activity_log_shards() {
+        use chrono::TimeZone;
+
+        let base = Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap();
+        let entry = |minute: i64| ActivityLogEntry {
+            timestamp: base + chrono::Duration::minutes(minute),
+            ..ActivityLogEntry::new("KEY", "user", "uid", &format!("action_{}", minute), None)
+        };
+        let log = |minutes: &[i64]| ActivityLog {
+            entries: minutes.iter().map(|&m| entry(m)).collect(),
+            ..Default::default()
+        };
+
+        // Shards interleave with each other and with the compacted file
+        let merged = merge_activity_log(log(&[0, 3]), vec![log(&[4, 1]), log(&[2]), ActivityLog::default()]);
+        let actions: Vec<_> = merged.entries.iter().map(|e| e.action.as_str()).collect();
+        assert_eq!(actions, ["action_0", "action_1", "action_2", "action_3", "action_4"]);
+
+        // Only the most recent entries are kept
+        let shard = log(&(0..MAX_ACTIVITY_LOG_ENTRIES as i64 + 5).collect::<Vec<_>>());
+        let merged = merge_activity_log(ActivityLog::default(), vec![shard]);
+        assert_eq!(merged.entries.len(), MAX_ACTIVITY_LOG_ENTRIES);
+        assert_eq!(merged.entries[0].action, "action_5");
+
+        // Shard keys sort by time, then nonce
+        let earlier = activity_shard_key(base, u64::MAX);
+        let later = activity_shard_key(base + chrono::Duration::milliseconds(1), 0);
+        assert!(earlier.starts_with(ACTIVITY_LOG_SHARD_PREFIX) && earlier.ends_with(".json"));
+        assert!(earlier < later);
+    }
+
+    #[test]
+    fn test_compute_activity_stats() {
+        use chrono::TimeZone;
+
+        let base = Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap();
+        let actions = ["login", "upload_started", "download_started", "login_blocked"];
+        let entries: Vec<ActivityLogEntry> = (0..200)
+            .map(|i| ActivityLogEntry {
+                key_hash: String::new(),
+                user_name: format!("User {}", i % 5),
+                user_id: format!("u_{}", i % 5),
+                action: actions[i % 4].to_string(),
+                timestamp: base + chrono::Duration::hours(i as i64),
+                details: None,
+            })
+            .collect();
+
+        let stats = compute_activity_stats(&entries, base);
+        assert_eq!(stats.total_logins, 50);
+        assert_eq!(stats.total_uploads, 50);
+        assert_eq!(stats.total_downloads, 50);
+        assert_eq!(stats.total_blocked_logins, 50);
+        assert_eq!(stats.unique_users, 5);
+        assert_eq!(stats.top_users_by_activity.len(), 5);
+        assert!(stats.top_users_by_activity.iter().all(|(_, count)| *count == 40));
+        assert_eq!(stats.actions_by_hour.iter().sum::<u64>(), 200);
+        // 200 hourly entries starting at midnight: hours 0-7 get 9 entries, the rest 8
+        assert_eq!(stats.actions_by_hour[0], 9);
+        assert_eq!(stats.actions_by_hour[23], 8);
+
+        // Entries before `since` are ignored
+        let later = compute_activity_stats(&entries, base + chrono::Duration::hours(100));
+        assert_eq!(later.actions_by_hour.iter().sum::<u64>(), 100);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_cache_misses_fetch_once() {
+        let cache = Arc::new(RwLock::new(AdminCache::default()));
+        let fetches = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..10)
+            .map(|_| {
+                let cache = cache.clone();
+                let fetches = fetches.clone();
+                tokio::spawn(async move {
+                    let mut cache = cache.write().await;
+                    cached_or_fetch(&mut cache.whitelist, DEFAULT_CACHE_TTL, || async {
+                        fetches.fetch_add(1, Ordering::SeqCst);
+                        Ok(Whitelist::default())
+                    })
+                    .await
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_expired_cache_refetches() {
+        let mut slot: Option<(Whitelist, Instant)> = None;
+        let fetches = AtomicUsize::new(0);
+        let fetch = || async {
+            fetches.fetch_add(1, Ordering::SeqCst);
+            Ok(Whitelist::default())
+        };
+
+        cached_or_fetch(&mut slot, Duration::ZERO, fetch).await.unwrap();
+        cached_or_fetch(&mut slot, Duration::ZERO, fetch).await.unwrap();
+
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+
+    fn entry(action: &str) -> ActivityLogEntry {
+        ActivityLogEntry::new("KEY", "user", "uid", action, None)
+    }
+
+    type BatcherHandles = (
+        mpsc::Sender<ActivityLogEntry>,
+        mpsc::Sender<oneshot::Sender<()>>,
+        Arc<std::sync::Mutex<Vec<usize>>>,
+    );
+
+    /// Spawn a batcher whose writes record their batch sizes
+    fn spawn_batcher(interval: Duration) -> BatcherHandles {
+        let (entry_tx, entry_rx) = mpsc::channel(100);
+        let (flush_tx, flush_rx) = mpsc::channel(1);
+        let writes = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = writes.clone();
+        tokio::spawn(run_activity_batcher(entry_rx, flush_rx, 10, interval, move |batch| {
+            recorded.lock().unwrap().push(batch.len());
+            async { Ok(()) }
+        }));
+        (entry_tx, flush_tx, writes)
+    }
+
+    #[tokio::test]
+    async fn test_activity_batcher_batches_by_size_then_interval() {
+        let (entries, _flushes, writes) = spawn_batcher(Duration::from_millis(100));
+
+        for i in 0..25 {
+            entries.send(entry(&format!("upload-{}", i))).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(*writes.lock().unwrap(), vec![10, 10]);
+
+        // The remaining partial batch is written once the interval passes
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(*writes.lock().unwrap(), vec![10, 10, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_activity_batcher_flush_writes_pending_entries() {
+        let (entries, flushes, writes) = spawn_batcher(Duration::from_secs(60));
+
+        for _ in 0..3 {
+            entries.send(entry("login")).await.unwrap();
+        }
+        let (done_tx, done_rx) = oneshot::channel();
+        flushes.send(done_tx).await.unwrap();
+        done_rx.await.unwrap();
+
+        assert_eq!(*writes.lock().unwrap(), vec![3]);
+    }
+}
@@ -1,86 +1,822 @@
 //! Key Generator CLI Tool
-//! 
+//!
 //! Usage: keygen --name "User Name"
-//! 
+//!        keygen batch --input users.csv --output keys.csv
+//!        keygen inspect <KEY>
+//!        keygen validate <KEY>
+//!        keygen bulk-validate --input keys.txt
+//!        keygen revoke <KEY> --reason "Reason text"
+//!        keygen unrevoke <KEY>
+//!
 //! This tool generates encrypted EXAD-prefixed keys for users.
 
-use aes_gcm::{
-    aead::{Aead, KeyInit},
-    Aes256Gcm, Nonce,
-};
-use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
-use chrono::Utc;
-use sha2::{Digest, Sha256};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
 use std::env;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use sync2bucket_lib::admin::{AdminCache, AdminClient, CachedAdminClient, KeyValidationResult};
+use sync2bucket_lib::crypto::{
+    decrypt_key, encrypt_key, key_fingerprint, parse_ip_ranges, validate_key_format_detailed,
+    CryptoError, KeyFormatError, KeyPayload, KeyPermissions,
+};
+use tokio::sync::RwLock;
 
-// Include secrets at compile time
-include!("../secrets.rs");
+/// One row of the batch input CSV
+#[derive(Debug, Deserialize)]
+struct BatchRow {
+    name: String,
+    notes: Option<String>,
+    expires_days: Option<i64>,
+    max_storage_gb: Option<u64>,
+    permissions: Option<String>,
+}
 
-#[derive(serde::Serialize)]
-struct KeyPayload {
-    uid: String,
+/// One row of the batch output CSV
+#[derive(Debug, Serialize)]
+struct GeneratedKey {
     name: String,
+    uid: String,
+    key: String,
+    fingerprint: String,
+    expires_at: String,
+    #[serde(skip)]
     created: i64,
+    #[serde(skip)]
+    notes: Option<String>,
+}
+
+impl GeneratedKey {
+    fn record(&self) -> KeyRecord {
+        KeyRecord {
+            key: self.key.clone(),
+            uid: self.uid.clone(),
+            name: self.name.clone(),
+            fingerprint: self.fingerprint.clone(),
+            created_at: DateTime::<Utc>::from_timestamp(self.created, 0)
+                .map(|t| t.to_rfc3339())
+                .unwrap_or_default(),
+            expires_at: Some(self.expires_at.clone()).filter(|e| !e.is_empty()),
+        }
+    }
+}
+
+/// Machine-readable description of a key for --format json/csv
+#[derive(Debug, Serialize)]
+struct KeyRecord {
+    key: String,
+    uid: String,
+    name: String,
+    fingerprint: String,
+    created_at: String,
+    expires_at: Option<String>,
+}
+
+/// How generated keys are written
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            other => Err(format!("Unknown format: {} (expected text, json or csv)", other)),
+        }
+    }
+}
+
+/// A batch row that could not be turned into a key
+#[derive(Debug)]
+struct FailedRow {
+    line: usize,
+    name: String,
+    error: String,
+}
+
+/// Build the payload for a batch row
+fn payload_from_row(row: &BatchRow) -> Result<KeyPayload, String> {
+    if row.name.trim().is_empty() {
+        return Err("name is empty".to_string());
+    }
+
+    let mut payload = KeyPayload::new(row.name.trim());
+    if let Some(days) = row.expires_days {
+        if days <= 0 {
+            return Err(format!("expires_days must be positive, got {}", days));
+        }
+        payload.expires_at = Some((Utc::now() + Duration::days(days)).timestamp());
+    }
+    payload.max_storage_gb = row.max_storage_gb;
+    payload.permissions = row.permissions.as_deref().unwrap_or("").parse()?;
+    Ok(payload)
+}
+
+/// Encrypt a payload into an output row
+fn generate_key(payload: &KeyPayload, notes: Option<String>) -> Result<GeneratedKey, String> {
+    let key = encrypt_key(payload).map_err(|e| e.to_string())?;
+    let expires_at = payload
+        .expires_at
+        .and_then(|t| DateTime::<Utc>::from_timestamp(t, 0))
+        .map(|t| t.to_rfc3339())
+        .unwrap_or_default();
+
+    Ok(GeneratedKey {
+        name: payload.name.clone(),
+        uid: payload.uid.clone(),
+        fingerprint: key_fingerprint(&key),
+        key,
+        expires_at,
+        created: payload.created,
+        notes,
+    })
+}
+
+/// Read the input CSV and generate a key for every valid row
+fn generate_batch(input: &Path) -> Result<(Vec<GeneratedKey>, Vec<FailedRow>), String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(input)
+        .map_err(|e| format!("Failed to open {}: {}", input.display(), e))?;
+
+    let mut generated = Vec::new();
+    let mut failed = Vec::new();
+
+    for (index, record) in reader.deserialize::<BatchRow>().enumerate() {
+        // Line 1 is the header
+        let line = index + 2;
+        let row = match record {
+            Ok(row) => row,
+            Err(e) => {
+                failed.push(FailedRow { line, name: String::new(), error: e.to_string() });
+                continue;
+            }
+        };
+
+        let notes = row.notes.clone().filter(|n| !n.is_empty());
+        match payload_from_row(&row).and_then(|payload| generate_key(&payload, notes)) {
+            Ok(key) => generated.push(key),
+            Err(error) => failed.push(FailedRow { line, name: row.name, error }),
+        }
+    }
+
+    Ok((generated, failed))
+}
+
+/// Write generated keys as CSV rows or a JSON array
+fn write_batch<W: Write>(
+    writer: W,
+    keys: &[GeneratedKey],
+    format: OutputFormat,
+    header: bool,
+) -> Result<(), String> {
+    if format == OutputFormat::Json {
+        let records: Vec<KeyRecord> = keys.iter().map(GeneratedKey::record).collect();
+        return write_json(writer, &records);
+    }
+
+    let mut writer = csv::WriterBuilder::new().has_headers(header).from_writer(writer);
+    for key in keys {
+        writer.serialize(key).map_err(|e| e.to_string())?;
+    }
+    writer.flush().map_err(|e| e.to_string())
+}
+
+/// Pretty-printed JSON followed by a single newline
+fn write_json<W: Write, T: Serialize + ?Sized>(mut writer: W, value: &T) -> Result<(), String> {
+    serde_json::to_writer_pretty(&mut writer, value).map_err(|e| e.to_string())?;
+    writeln!(writer).map_err(|e| e.to_string())
+}
+
+/// Write a single key in a machine-readable format
+fn write_record<W: Write>(
+    writer: W,
+    record: &KeyRecord,
+    format: OutputFormat,
+    header: bool,
+) -> Result<(), String> {
+    match format {
+        OutputFormat::Json => write_json(writer, record),
+        _ => {
+            let mut writer = csv::WriterBuilder::new().has_headers(header).from_writer(writer);
+            writer.serialize(record).map_err(|e| e.to_string())?;
+            writer.flush().map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// Register generated keys in the admin whitelist, returning the failures
+fn add_batch_to_whitelist(keys: &[GeneratedKey]) -> Vec<(String, String)> {
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(rt) => rt,
+        Err(e) => return vec![(String::from("*"), e.to_string())],
+    };
+
+    runtime.block_on(async {
+        let admin = match AdminClient::new() {
+            Ok(admin) => admin,
+            Err(e) => return vec![(String::from("*"), e)],
+        };
+
+        let mut failures = Vec::new();
+        for key in keys {
+            if let Err(e) = admin
+                .add_to_whitelist(&key.key, &key.name, &key.uid, key.notes.clone())
+                .await
+            {
+                failures.push((key.name.clone(), e));
+            }
+        }
+        failures
+    })
+}
+
+fn print_batch_summary(
+    out: &mut dyn Write,
+    generated: &[GeneratedKey],
+    failed: &[FailedRow],
+    whitelist_failures: &[(String, String)],
+) -> io::Result<()> {
+    writeln!(out)?;
+    writeln!(out, "{:<30} {:<20} {:<19} EXPIRES", "NAME", "UID", "FINGERPRINT")?;
+    for key in generated {
+        let expires = if key.expires_at.is_empty() { "never" } else { &key.expires_at };
+        writeln!(out, "{:<30} {:<20} {:<19} {}", key.name, key.uid, key.fingerprint, expires)?;
+    }
+    writeln!(out)?;
+    writeln!(out, "Generated: {}", generated.len())?;
+    writeln!(out, "Failed:    {}", failed.len())?;
+    for row in failed {
+        writeln!(out, "  line {}: {} {}", row.line, row.name, row.error)?;
+    }
+    if !whitelist_failures.is_empty() {
+        writeln!(out, "Whitelist failures: {}", whitelist_failures.len())?;
+        for (name, error) in whitelist_failures {
+            writeln!(out, "  {}: {}", name, error)?;
+        }
+    }
+    Ok(())
+}
+
+fn parse_format(value: &str) -> OutputFormat {
+    value.parse().unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    })
+}
+
+/// Value following the flag at `args[i]`, exiting if it is missing
+fn flag_value(args: &[String], i: usize) -> String {
+    match args.get(i + 1) {
+        Some(value) => value.clone(),
+        None => {
+            eprintln!("Error: {} requires a value", args[i]);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_batch(args: &[String]) {
+    let mut input: Option<String> = None;
+    let mut output: Option<String> = None;
+    let mut add_to_whitelist = false;
+    let mut dry_run = false;
+    let mut format = OutputFormat::Csv;
+    let mut header = true;
+    let mut quiet = false;
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--format" => {
+                format = parse_format(&flag_value(args, i));
+                i += 2;
+            }
+            "--no-header" => {
+                header = false;
+                i += 1;
+            }
+            "--quiet" | "-q" => {
+                quiet = true;
+                i += 1;
+            }
+            "--input" | "-i" => {
+                input = Some(flag_value(args, i));
+                i += 2;
+            }
+            "--output" | "-o" => {
+                output = Some(flag_value(args, i));
+                i += 2;
+            }
+            "--add-to-whitelist" => {
+                add_to_whitelist = true;
+                i += 1;
+            }
+            "--dry-run" => {
+                dry_run = true;
+                i += 1;
+            }
+            _ => {
+                eprintln!("Unknown argument: {}", args[i]);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let input = input.unwrap_or_else(|| {
+        eprintln!("Error: --input is required");
+        print_usage();
+        std::process::exit(1);
+    });
+    if output.is_none() && !dry_run {
+        eprintln!("Error: --output is required (or use --dry-run)");
+        std::process::exit(1);
+    }
+
+    let (generated, failed) = match generate_batch(Path::new(&input)) {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // With "--output -" the keys go to stdout, so everything else goes to stderr
+    let to_stdout = output.as_deref() == Some("-");
+    let mut info: Box<dyn Write> = if quiet {
+        Box::new(io::sink())
+    } else if to_stdout {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    };
+
+    let mut whitelist_failures = Vec::new();
+    if dry_run {
+        let whitelist = if add_to_whitelist { ", whitelist unchanged" } else { "" };
+        let _ = writeln!(info, "Dry run: no keys written{}", whitelist);
+    } else {
+        if let Some(output) = &output {
+            let written = if to_stdout {
+                write_batch(io::stdout().lock(), &generated, format, header)
+            } else {
+                std::fs::File::create(output)
+                    .map_err(|e| format!("Failed to create {}: {}", output, e))
+                    .and_then(|file| write_batch(file, &generated, format, header))
+            };
+            if let Err(e) = written {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            if !to_stdout {
+                let _ = writeln!(info, "Wrote {} keys to {}", generated.len(), output);
+            }
+        }
+        if add_to_whitelist {
+            whitelist_failures = add_batch_to_whitelist(&generated);
+        }
+    }
+
+    let _ = print_batch_summary(&mut info, &generated, &failed, &whitelist_failures);
+
+    if !failed.is_empty() || !whitelist_failures.is_empty() {
+        std::process::exit(2);
+    }
 }
 
-fn generate_uid(name: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(name.as_bytes());
-    hasher.update(Utc::now().timestamp().to_le_bytes());
-    // Add some randomness
-    hasher.update(rand::random::<[u8; 16]>());
-    let result = hasher.finalize();
-    format!("u_{}", hex::encode(&result[..8]))
-}
-
-fn encrypt_key(payload: &KeyPayload) -> Result<String, String> {
-    let json = serde_json::to_string(payload).map_err(|e| e.to_string())?;
-    
-    // Generate a random nonce (12 bytes for AES-GCM)
-    let nonce_bytes: [u8; 12] = rand::random();
-    let nonce = Nonce::from_slice(&nonce_bytes);
-    
-    let cipher = Aes256Gcm::new_from_slice(MASTER_ENCRYPTION_KEY)
-        .map_err(|e| e.to_string())?;
-    
-    let ciphertext = cipher
-        .encrypt(nonce, json.as_bytes())
-        .map_err(|e| e.to_string())?;
-    
-    // Combine nonce + ciphertext and encode
-    let mut combined = nonce_bytes.to_vec();
-    combined.extend(ciphertext);
-    
-    let encoded = URL_SAFE_NO_PAD.encode(&combined);
-    Ok(format!("EXAD-{}", encoded))
+/// Friendly explanation of why a key could not be read
+fn describe_crypto_error(error: &CryptoError) -> &'static str {
+    match error {
+        CryptoError::InvalidFormat => "not a key: expected EXAD- followed by base64 data",
+        CryptoError::DecryptionFailed => "key was not issued by this generator or has been altered",
+        CryptoError::InvalidPayload => "key decrypted but its contents are not a valid payload",
+        CryptoError::EncryptionFailed => "encryption failed",
+    }
+}
+
+/// Decrypt a key, mapping failures to a friendly message
+fn read_key(key: &str) -> Result<KeyPayload, String> {
+    decrypt_key(key.trim()).map_err(|e| describe_crypto_error(&e).to_string())
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    DateTime::<Utc>::from_timestamp(timestamp, 0)
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+/// Combine a decoded key with its whitelist/blacklist check into a status
+fn key_status(payload: &KeyPayload, access: &KeyValidationResult) -> String {
+    if payload.is_expired() {
+        let expired = payload.expires_at.map(format_timestamp).unwrap_or_default();
+        return format!("BLOCKED (Key expired on {})", expired);
+    }
+    if access.allowed {
+        "ALLOWED".to_string()
+    } else {
+        format!("BLOCKED ({})", access.reason.as_deref().unwrap_or("no reason given"))
+    }
+}
+
+/// Full access status for a key, including the admin lists
+async fn check_key(admin: &CachedAdminClient, key: &str) -> String {
+    let payload = match read_key(key) {
+        Ok(payload) => payload,
+        Err(e) => return format!("INVALID ({})", e),
+    };
+    match admin.validate_key_access(key.trim()).await {
+        Ok(access) => key_status(&payload, &access),
+        Err(e) => format!("ERROR ({})", e),
+    }
+}
+
+/// Runtime and admin client for the subcommands that talk to S3
+fn admin_runtime() -> Result<(tokio::runtime::Runtime, CachedAdminClient), String> {
+    let runtime = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
+    let admin = CachedAdminClient::new(Arc::new(RwLock::new(AdminCache::default())))?;
+    Ok((runtime, admin))
+}
+
+/// Keys listed in a file, one per line; blank lines and # comments are skipped
+fn read_keys_file(path: &Path) -> Result<Vec<String>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+fn run_inspect(args: &[String]) {
+    let key = match args {
+        [key] => key,
+        _ => {
+            eprintln!("Error: inspect takes exactly one key");
+            std::process::exit(1);
+        }
+    };
+
+    // Say what's wrong with a mangled key rather than just that it didn't decrypt
+    let format = validate_key_format_detailed(key.trim());
+    if format != KeyFormatError::Valid {
+        eprintln!("Invalid key: {}", format);
+        std::process::exit(1);
+    }
+    let payload = match read_key(key) {
+        Ok(payload) => payload,
+        Err(e) => {
+            eprintln!("Invalid key: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let expires = match payload.expires_at {
+        Some(t) => format_timestamp(t),
+        None => "never".to_string(),
+    };
+    let storage = match payload.max_storage_gb {
+        Some(gb) => format!("{} GB", gb),
+        None => "unlimited".to_string(),
+    };
+
+    println!("UID:         {}", payload.uid);
+    println!("Name:        {}", payload.name);
+    println!("Created:     {}", format_timestamp(payload.created));
+    println!("Expires:     {}", expires);
+    println!("Storage:     {}", storage);
+    println!("Permissions: {}", payload.permissions);
+    if let Some(ranges) = &payload.allowed_ip_ranges {
+        println!("Allowed IPs: {}", ranges.join(", "));
+    }
+    println!("Fingerprint: {}", key_fingerprint(key.trim()));
+    println!("Status:      {}", if payload.is_expired() { "EXPIRED" } else { "valid" });
+}
+
+fn run_validate(args: &[String]) {
+    let key = match args {
+        [key] => key,
+        _ => {
+            eprintln!("Error: validate takes exactly one key");
+            std::process::exit(1);
+        }
+    };
+
+    // Reject malformed keys before making any network calls
+    if let Err(e) = read_key(key) {
+        eprintln!("Invalid key: {}", e);
+        std::process::exit(1);
+    }
+
+    match admin_runtime().map(|(runtime, admin)| runtime.block_on(check_key(&admin, key))) {
+        Ok(status) => {
+            println!("{}", status);
+            if status != "ALLOWED" {
+                std::process::exit(2);
+            }
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_bulk_validate(args: &[String]) {
+    let input = match args {
+        [flag, path] if flag == "--input" || flag == "-i" => path,
+        _ => {
+            eprintln!("Error: bulk-validate requires --input <file>");
+            std::process::exit(1);
+        }
+    };
+
+    let keys = match read_keys_file(Path::new(input)) {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let (runtime, admin) = match admin_runtime() {
+        Ok(pair) => pair,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // The cached client fetches the admin lists once for the whole file
+    let results: Vec<String> = runtime.block_on(async {
+        let mut results = Vec::with_capacity(keys.len());
+        for key in &keys {
+            results.push(check_key(&admin, key).await);
+        }
+        results
+    });
+
+    println!("{:<19} {:<30} STATUS", "FINGERPRINT", "NAME");
+    for (key, status) in keys.iter().zip(&results) {
+        let name = read_key(key).map(|p| p.name).unwrap_or_default();
+        println!("{:<19} {:<30} {}", key_fingerprint(key), name, status);
+    }
+
+    let allowed = results.iter().filter(|s| *s == "ALLOWED").count();
+    println!();
+    println!("Allowed: {}  Blocked/invalid: {}", allowed, results.len() - allowed);
+}
+
+/// Parsed arguments for `keygen revoke`
+#[derive(Debug, PartialEq)]
+struct RevokeArgs {
+    key: String,
+    reason: String,
+    remove_from_whitelist: bool,
+    force: bool,
+}
+
+fn parse_revoke_args(args: &[String]) -> Result<RevokeArgs, String> {
+    let mut key: Option<String> = None;
+    let mut reason: Option<String> = None;
+    let mut remove_from_whitelist = false;
+    let mut force = false;
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--reason" | "-r" => {
+                let value = args.get(i + 1).ok_or("--reason requires a value")?;
+                reason = Some(value.clone());
+                i += 2;
+            }
+            "--remove-from-whitelist" => {
+                remove_from_whitelist = true;
+                i += 1;
+            }
+            "--force" | "-f" => {
+                force = true;
+                i += 1;
+            }
+            arg if arg.starts_with('-') => return Err(format!("Unknown argument: {}", arg)),
+            arg => {
+                if key.is_some() {
+                    return Err("revoke takes exactly one key".to_string());
+                }
+                key = Some(arg.trim().to_string());
+                i += 1;
+            }
+        }
+    }
+
+    let key = key.ok_or("revoke requires a key")?;
+    let reason = reason
+        .filter(|r| !r.trim().is_empty())
+        .ok_or("--reason is required")?;
+    Ok(RevokeArgs { key, reason, remove_from_whitelist, force })
+}
+
+/// Ask a yes/no question on stdin, defaulting to no
+fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+fn run_revoke(args: &[String]) {
+    let args = match parse_revoke_args(args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Reject malformed keys before making any network calls
+    let payload = match read_key(&args.key) {
+        Ok(payload) => payload,
+        Err(e) => {
+            eprintln!("Invalid key: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let fingerprint = key_fingerprint(&args.key);
+
+    let question = format!("Revoke key {} for {} ({})?", fingerprint, payload.name, payload.uid);
+    if !args.force && !confirm(&question) {
+        println!("Aborted");
+        return;
+    }
+
+    let result = tokio::runtime::Runtime::new()
+        .map_err(|e| e.to_string())
+        .and_then(|runtime| {
+            runtime.block_on(async {
+                let admin = AdminClient::new()?;
+                admin
+                    .add_to_blacklist(&args.key, &payload.name, &payload.uid, &args.reason)
+                    .await?;
+                if args.remove_from_whitelist {
+                    admin.remove_from_whitelist(&args.key).await?;
+                }
+                Ok(())
+            })
+        });
+
+    match result {
+        Ok(()) => println!("Revoked key {} for {} ({})", fingerprint, payload.name, payload.uid),
+        Err(e) => {
+            eprintln!("Error revoking key: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_unrevoke(args: &[String]) {
+    let key = match args {
+        [key] => key.trim(),
+        _ => {
+            eprintln!("Error: unrevoke takes exactly one key");
+            std::process::exit(1);
+        }
+    };
+
+    // Reject malformed keys before making any network calls
+    let payload = match read_key(key) {
+        Ok(payload) => payload,
+        Err(e) => {
+            eprintln!("Invalid key: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let result = tokio::runtime::Runtime::new()
+        .map_err(|e| e.to_string())
+        .and_then(|runtime| {
+            runtime.block_on(async {
+                let admin = AdminClient::new()?;
+                admin.unrevoke_key(key, &payload.name, &payload.uid).await
+            })
+        });
+
+    match result {
+        Ok(whitelisted) => {
+            println!(
+                "Restored key {} for {} ({})",
+                key_fingerprint(key),
+                payload.name,
+                payload.uid
+            );
+            if whitelisted {
+                println!("Added it back to the whitelist");
+            }
+        }
+        Err(e) => {
+            eprintln!("Error restoring key: {}", e);
+            std::process::exit(1);
+        }
+    }
 }
 
 fn print_usage() {
     println!("Sync2Bucket Key Generator");
     println!();
     println!("Usage: keygen --name \"User Name\"");
+    println!("       keygen batch --input users.csv --output keys.csv");
+    println!("       keygen inspect <KEY>");
+    println!("       keygen validate <KEY>");
+    println!("       keygen bulk-validate --input keys.txt");
+    println!("       keygen revoke <KEY> --reason \"Reason text\" [--remove-from-whitelist] [--force]");
+    println!("       keygen unrevoke <KEY>");
     println!();
     println!("Options:");
     println!("  --name <name>    User's name (required)");
+    println!("  --admin          Grant access to admin commands");
+    println!("  --allowed-ips <cidrs>  Only accept the key from these ranges, e.g. 10.0.0.0/8,192.168.0.0/16");
+    println!("  --format <fmt>   Output format: text (default), json or csv");
+    println!("  --no-header      Omit the CSV header row");
+    println!("  --quiet          Only print the key");
     println!("  --help           Show this help message");
     println!();
+    println!("Batch options:");
+    println!("  --input <file>       CSV with columns name,notes,expires_days,max_storage_gb,permissions");
+    println!("  --output <file>      File to write name,uid,key,fingerprint,expires_at to (- for stdout)");
+    println!("  --format <fmt>       csv (default) or json (array)");
+    println!("  --add-to-whitelist   Also add every generated key to the admin whitelist");
+    println!("  --dry-run            Show what would be generated without writing anything");
+    println!();
     println!("Example:");
     println!("  keygen --name \"John Doe\"");
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    
+
     if args.len() < 2 || args.contains(&"--help".to_string()) || args.contains(&"-h".to_string()) {
         print_usage();
         return;
     }
-    
+
+    match args[1].as_str() {
+        "batch" => return run_batch(&args[2..]),
+        "inspect" => return run_inspect(&args[2..]),
+        "validate" => return run_validate(&args[2..]),
+        "bulk-validate" => return run_bulk_validate(&args[2..]),
+        "revoke" => return run_revoke(&args[2..]),
+        "unrevoke" => return run_unrevoke(&args[2..]),
+        _ => {}
+    }
+
     // Parse arguments
     let mut name: Option<String> = None;
+    let mut format = OutputFormat::Text;
+    let mut header = true;
+    let mut quiet = false;
+    let mut admin = false;
+    let mut allowed_ips: Option<Vec<String>> = None;
     let mut i = 1;
     while i < args.len() {
         match args[i].as_str() {
+            "--format" => {
+                format = parse_format(&flag_value(&args, i));
+                i += 2;
+            }
+            "--no-header" => {
+                header = false;
+                i += 1;
+            }
+            "--quiet" | "-q" => {
+                quiet = true;
+                i += 1;
+            }
+            "--admin" => {
+                admin = true;
+                i += 1;
+            }
+            "--allowed-ips" => {
+                match parse_ip_ranges(&flag_value(&args, i)) {
+                    Ok(ranges) => allowed_ips = Some(ranges),
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+                i += 2;
+            }
             "--name" | "-n" => {
                 if i + 1 < args.len() {
                     name = Some(args[i + 1].clone());
@@ -96,7 +832,7 @@ fn main() {
             }
         }
     }
-    
+
     let name = match name {
         Some(n) => n,
         None => {
@@ -105,16 +841,36 @@ fn main() {
             std::process::exit(1);
         }
     };
-    
+
     // Generate key
-    let payload = KeyPayload {
-        uid: generate_uid(&name),
-        name: name.clone(),
-        created: Utc::now().timestamp(),
-    };
-    
-    match encrypt_key(&payload) {
-        Ok(key) => {
+    let mut payload = KeyPayload::new(&name);
+    if admin {
+        payload.permissions |= KeyPermissions::ADMIN;
+    }
+    payload.allowed_ip_ranges = allowed_ips;
+
+    let generated = match generate_key(&payload, None) {
+        Ok(generated) => generated,
+        Err(e) => {
+            eprintln!("Error generating key: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let key = &generated.key;
+
+    if quiet {
+        println!("{}", key);
+        return;
+    }
+
+    match format {
+        OutputFormat::Json | OutputFormat::Csv => {
+            if let Err(e) = write_record(io::stdout().lock(), &generated.record(), format, header) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        OutputFormat::Text => {
             println!();
             println!("╔══════════════════════════════════════════════════════════════╗");
             println!("║                    SYNC2BUCKET LICENSE KEY                    ║");
@@ -125,15 +881,15 @@ fn main() {
             println!("╠══════════════════════════════════════════════════════════════╣");
             println!("║ Key:                                                         ║");
             println!("║                                                              ║");
-            
+
             // Word wrap the key for display
-            let key_display = &key;
+            let key_display = key;
             let chunk_size = 58;
             for chunk in key_display.as_bytes().chunks(chunk_size) {
                 let s = std::str::from_utf8(chunk).unwrap_or("");
                 println!("║ {:<60} ║", s);
             }
-            
+
             println!("║                                                              ║");
             println!("╚══════════════════════════════════════════════════════════════╝");
             println!();
@@ -141,9 +897,138 @@ fn main() {
             println!("{}", key);
             println!();
         }
-        Err(e) => {
-            eprintln!("Error generating key: {}", e);
-            std::process::exit(1);
-        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        env::temp_dir().join(format!("keygen_{}_{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_batch_from_csv() {
+        let input = temp_path("users.csv");
+        let output = temp_path("keys.csv");
+        std::fs::write(
+            &input,
+            "name,notes,expires_days,max_storage_gb,permissions\n\
+             Alice,Design team,30,100,upload|download\n\
+             Bob,,,,\n\
+             Carol,Contractor,7,10,download\n\
+             Dave,Bad perms,,,fly\n\
+             Eve,Expired,-1,,\n",
+        )
+        .unwrap();
+
+        let (generated, failed) = generate_batch(&input).unwrap();
+        assert_eq!(generated.len(), 3);
+        assert_eq!(failed.len(), 2);
+        assert_eq!(failed[0].line, 5);
+        assert_eq!(failed[1].line, 6);
+
+        let file = std::fs::File::create(&output).unwrap();
+        write_batch(file, &generated, OutputFormat::Csv, true).unwrap();
+        let written = std::fs::read_to_string(&output).unwrap();
+        let mut lines = written.lines();
+        assert_eq!(lines.next(), Some("name,uid,key,fingerprint,expires_at"));
+        assert_eq!(lines.count(), 3);
+
+        let alice = decrypt_key(&generated[0].key).unwrap();
+        assert_eq!(alice.name, "Alice");
+        assert_eq!(alice.max_storage_gb, Some(100));
+        assert_eq!(alice.permissions, KeyPermissions::UPLOAD | KeyPermissions::DOWNLOAD);
+        assert!(alice.expires_at.is_some());
+        assert_eq!(generated[0].notes.as_deref(), Some("Design team"));
+
+        let bob = decrypt_key(&generated[1].key).unwrap();
+        assert_eq!(bob.expires_at, None);
+        assert_eq!(bob.permissions, KeyPermissions::default());
+        assert!(generated[1].expires_at.is_empty());
+        assert_eq!(generated[1].fingerprint, key_fingerprint(&generated[1].key));
+
+        let _ = std::fs::remove_file(&input);
+        let _ = std::fs::remove_file(&output);
+    }
+
+    #[test]
+    fn test_friendly_key_errors() {
+        assert!(read_key("not-a-key").unwrap_err().contains("EXAD-"));
+        assert!(read_key("EXAD-!!!").unwrap_err().contains("EXAD-"));
+        assert!(read_key("EXAD-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAA")
+            .unwrap_err()
+            .contains("altered"));
+    }
+
+    #[test]
+    fn test_key_status() {
+        let mut payload = KeyPayload::new("Status User");
+        let allowed = KeyValidationResult { allowed: true, reason: None };
+        let blocked = KeyValidationResult { allowed: false, reason: Some("Left company".to_string()) };
+
+        assert_eq!(key_status(&payload, &allowed), "ALLOWED");
+        assert_eq!(key_status(&payload, &blocked), "BLOCKED (Left company)");
+
+        payload.expires_at = Some(Utc::now().timestamp() - 60);
+        assert!(key_status(&payload, &allowed).starts_with("BLOCKED (Key expired on"));
+    }
+
+    #[test]
+    fn test_read_keys_file() {
+        let path = temp_path("keys.txt");
+        std::fs::write(&path, "# audit\nEXAD-one\n\n  EXAD-two  \n").unwrap();
+        assert_eq!(read_keys_file(&path).unwrap(), vec!["EXAD-one", "EXAD-two"]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_parse_revoke_args() {
+        let args: Vec<String> = ["EXAD-abc", "--reason", "Left company", "--force"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            parse_revoke_args(&args).unwrap(),
+            RevokeArgs {
+                key: "EXAD-abc".to_string(),
+                reason: "Left company".to_string(),
+                remove_from_whitelist: false,
+                force: true,
+            }
+        );
+
+        let missing_reason = vec!["EXAD-abc".to_string()];
+        assert_eq!(parse_revoke_args(&missing_reason).unwrap_err(), "--reason is required");
+
+        let two_keys: Vec<String> = ["EXAD-a", "EXAD-b", "--reason", "x"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert!(parse_revoke_args(&two_keys).is_err());
+    }
+
+    #[test]
+    fn test_json_and_csv_records() {
+        let generated = generate_key(&KeyPayload::new("Script User"), None).unwrap();
+
+        let mut json = Vec::new();
+        write_record(&mut json, &generated.record(), OutputFormat::Json, true).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert!(value["key"].as_str().unwrap().starts_with("EXAD-"));
+        assert_eq!(value["name"], "Script User");
+        assert!(value["expires_at"].is_null());
+
+        let mut csv = Vec::new();
+        write_record(&mut csv, &generated.record(), OutputFormat::Csv, false).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 1);
+        assert!(csv.starts_with("EXAD-"));
+
+        let mut array = Vec::new();
+        write_batch(&mut array, &[generated], OutputFormat::Json, true).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&array).unwrap();
+        assert_eq!(value.as_array().unwrap().len(), 1);
     }
 }
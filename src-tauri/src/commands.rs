@@ -1,26 +1,323 @@
-use crate::admin::AdminClient;
-use crate::crypto::{decrypt_key, KeyPayload};
-use crate::s3_client::S3Client;
-use crate::sync_engine::{CloudFolder, SyncEngine, SyncProgress};
+use crate::admin::{
+    key_access_with_fallback, ActivityLogEntry, ActivityLogger, ActivityStats, AdminCache, AdminClient,
+    CachedAdminClient, KeyAccessSource, PurgeResult,
+};
+use crate::config::{self, AppConfig};
+use crate::crypto::{decrypt_key, KeyPayload, KeyPermissions};
+use crate::error::AppError;
+use crate::notifications;
+use crate::s3_client::{self, CloudFolder, ConnectionInfo, ConnectionStatus, ObjectCannedAcl, S3Client, S3ClientBuilder, S3Destination, S3Error, S3Object, SortDir, SortOrder};
+use crate::sync_cache::SyncCache;
+use crate::sync_engine::{
+    CloudFolderPage, FileTransferRecord, PendingFile, S3CostEstimate, ScanComplete, StorageStats, SyncConfig, SyncEngine, SyncError,
+    SessionStats, SyncPolicy, SyncPolicyCache, SyncProgress, SyncSession, SyncSummary, SyncWindows, MAX_RETAIN_DAYS,
+};
+use crate::sync_filter::SyncFilter;
+use crate::sync_queue::{QueuedJobStatus, SyncJob, SyncQueue};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tauri::State;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager, State};
 use tokio::sync::RwLock;
 
+// How long logout waits for a cancelled sync to stop
+const LOGOUT_CANCEL_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How often a queued job checks whether a manually started sync has finished
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Keys expiring within this many days get a warning at login
+const KEY_EXPIRY_WARNING_DAYS: i64 = 30;
+
+// Longest file or folder name, in bytes, on most file systems
+const MAX_NAME_BYTES: usize = 255;
+
+// How long `start_upload` trusts a preview before scanning again
+const PREVIEW_MAX_AGE: Duration = Duration::from_secs(5 * 60);
+
 /// App state shared across commands
 pub struct AppState {
     pub key_payload: RwLock<Option<KeyPayload>>,
     pub sync_engine: RwLock<Option<Arc<SyncEngine>>>,
     pub current_key: RwLock<Option<String>>,  // Store the key for activity logging
+    pub sync_config: RwLock<SyncConfig>,
+    pub config: AppConfig,
+    pub admin_cache: Arc<RwLock<AdminCache>>,
+    pub activity_log: ActivityLogger,
+    /// Whether the logged-in key carries the `ADMIN` permission
+    pub is_admin: AtomicBool,
+    /// Read-only mode set with `set_read_only_mode`, on top of `SyncConfig::read_only`
+    pub read_only_mode: AtomicBool,
+    /// Backup buckets the sync engine mirrors writes to: the configured ones
+    /// plus any added during the session
+    pub secondary_destinations: RwLock<Vec<S3Destination>>,
+    /// Upload that was still running or paused when the app last quit
+    pub interrupted_sync: RwLock<Option<SyncSession>>,
+    /// Result of the last `preview_upload`, reused by `start_upload` for the same folders
+    pub pending_preview: RwLock<Option<PendingPreview>>,
+    /// Administrators' sync policy, fetched at most every 10 minutes
+    pub sync_policy: SyncPolicyCache,
+    /// Uploads and downloads waiting to run one after another
+    pub sync_queue: SyncQueue,
+    /// Windows registered for sync events, kept across logins
+    pub sync_windows: SyncWindows,
+    /// Totals of the syncs finished since the app started
+    pub session_stats: Arc<RwLock<SessionStats>>,
+    /// Latency measured by the last warm-up of the connection
+    pub connection_info: Arc<RwLock<Option<ConnectionInfo>>>,
+}
+
+/// Files an upload of `source_paths` would transfer, as found by `preview_upload`
+pub struct PendingPreview {
+    pub source_paths: Vec<String>,
+    pub files: Vec<PendingFile>,
+    pub created_at: Instant,
+}
+
+impl PendingPreview {
+    /// Whether an upload of `source_paths` can use this preview instead of scanning:
+    /// the folders must match and the preview be younger than `PREVIEW_MAX_AGE`
+    fn usable_for(&self, source_paths: &[String]) -> bool {
+        self.source_paths == source_paths && self.created_at.elapsed() < PREVIEW_MAX_AGE
+    }
 }
 
 impl AppState {
     pub fn new() -> Self {
+        // Activity log writes are batched in the background so commands never wait on S3
+        let (activity_log, activity_task) = ActivityLogger::new();
+        tauri::async_runtime::spawn(activity_task);
+
+        let config = AppConfig::default();
+        let interrupted_sync = SyncCache::new()
+            .with_sessions_dir(config.sessions_dir())
+            .get_incomplete_session();
+        let secondary_destinations = config.secondary_destinations.clone();
+
         Self {
             key_payload: RwLock::new(None),
             sync_engine: RwLock::new(None),
             current_key: RwLock::new(None),
+            sync_config: RwLock::new(SyncConfig::default()),
+            config,
+            admin_cache: Arc::new(RwLock::new(AdminCache::default())),
+            activity_log,
+            is_admin: AtomicBool::new(false),
+            read_only_mode: AtomicBool::new(false),
+            secondary_destinations: RwLock::new(secondary_destinations),
+            interrupted_sync: RwLock::new(interrupted_sync),
+            pending_preview: RwLock::new(None),
+            sync_policy: Arc::new(RwLock::new(None)),
+            sync_queue: SyncQueue::new(),
+            sync_windows: SyncWindows::default(),
+            session_stats: Arc::new(RwLock::new(SessionStats::default())),
+            connection_info: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// Forget the interrupted upload and delete the sessions the logged-in user saved,
+    /// leaving other users' sessions for them to resume
+    async fn discard_interrupted_sync(&self) -> Result<(), AppError> {
+        let uid = self
+            .key_payload
+            .read()
+            .await
+            .as_ref()
+            .map(|payload| payload.uid.clone())
+            .ok_or(AppError::NotAuthenticated)?;
+        let mut interrupted = self.interrupted_sync.write().await;
+        if interrupted.as_ref().is_some_and(|saved| !saved.belongs_to(Some(&uid))) {
+            return Err(AppError::ForeignSession);
+        }
+        interrupted.take();
+        SyncCache::new()
+            .with_sessions_dir(self.config.sessions_dir())
+            .remove_user_sessions(Some(&uid), None);
+        Ok(())
+    }
+
+    /// Write out any queued activity log entries; call before the app exits
+    pub async fn flush_activity_log(&self) {
+        self.activity_log.flush().await;
+    }
+
+    /// Sync options for the logged-in user: the stored config plus an
+    /// `uploaded_by` tag with their name, cleaned up to be a valid tag value
+    async fn engine_config(&self, payload: &KeyPayload) -> SyncConfig {
+        let mut config = self.sync_config.read().await.clone();
+        config
+            .default_tags
+            .insert("uploaded_by".to_string(), s3_client::sanitize_tag_value(&payload.name));
+        config.read_only |= self.read_only_mode.load(Ordering::Acquire);
+        config
+    }
+
+    /// Fail with `ReadOnlyMode` while uploads and deletions are off, whether through
+    /// `set_read_only_mode`, the sync config or the administrators' sync policy
+    async fn ensure_writable(&self) -> Result<(), AppError> {
+        if self.read_only_mode.load(Ordering::Acquire) || self.sync_config.read().await.read_only {
+            return Err(SyncError::ReadOnlyMode.into());
+        }
+        let engine = self.sync_engine.read().await.clone();
+        if let Some(engine) = engine {
+            engine.ensure_writable().await?;
+        }
+        Ok(())
+    }
+
+    /// Whether the session has a sync engine to talk to S3 with
+    pub async fn is_connected(&self) -> bool {
+        self.sync_engine.read().await.is_some()
+    }
+
+    /// Build a fresh S3 client and sync engine for the logged-in user, keeping
+    /// the session itself. Rechecks credential expiry on the way.
+    async fn reconnect<F, G, H>(
+        &self,
+        on_rate_limited: F,
+        on_scan_complete: G,
+        on_limit_reached: H,
+    ) -> Result<(), AppError>
+    where
+        F: Fn(u64) + Send + Sync + 'static,
+        G: Fn(&ScanComplete) + Send + Sync + 'static,
+        H: Fn(u64, u64) + Send + Sync + 'static,
+    {
+        let payload = self.key_payload.read().await.clone().ok_or(AppError::NotAuthenticated)?;
+
+        let mut sync_engine = self.sync_engine.write().await;
+        if let Some(engine) = sync_engine.as_ref() {
+            if engine.is_running().await {
+                return Err(AppError::InvalidRequest(
+                    "Cannot refresh the connection while a sync is running".to_string(),
+                ));
+            }
+        }
+
+        let s3_client = S3ClientBuilder::from_config(&self.config)
+            .user_prefix(payload.folder_prefix())
+            .build()?;
+        let engine = Arc::new(
+            self.build_engine(&payload, s3_client, on_rate_limited, on_scan_complete, on_limit_reached)
+                .await?,
+        );
+        *sync_engine = Some(Arc::clone(&engine));
+        drop(sync_engine);
+
+        // Warm up in the background, without writing to the bucket; a failed
+        // warm-up still leaves a usable connection
+        let connection_info = Arc::clone(&self.connection_info);
+        tauri::async_runtime::spawn(async move {
+            let info = engine
+                .warm_up(false)
+                .await
+                .map_err(|e| log::warn!("Connection warm-up failed: {}", e))
+                .ok();
+            *connection_info.write().await = info;
+        });
+        Ok(())
+    }
+
+    /// Sync engine for the logged-in user around `s3_client`, mirroring writes to
+    /// every secondary destination
+    async fn build_engine<F, G, H>(
+        &self,
+        payload: &KeyPayload,
+        s3_client: S3Client,
+        on_rate_limited: F,
+        on_scan_complete: G,
+        on_limit_reached: H,
+    ) -> Result<SyncEngine, S3Error>
+    where
+        F: Fn(u64) + Send + Sync + 'static,
+        G: Fn(&ScanComplete) + Send + Sync + 'static,
+        H: Fn(u64, u64) + Send + Sync + 'static,
+    {
+        let sync_config = self.engine_config(payload).await;
+        let mut engine = SyncEngine::new_with_config(s3_client, sync_config)
+            .with_rate_limit_handler(on_rate_limited)
+            .with_scan_complete_handler(on_scan_complete)
+            .with_limit_reached_handler(on_limit_reached)
+            .with_sessions_dir(self.config.sessions_dir())
+            .with_sync_policy(
+                S3ClientBuilder::from_config(&self.config).unscoped().build()?,
+                Arc::clone(&self.sync_policy),
+            )
+            .with_windows(self.sync_windows.clone())
+            .with_user(payload.clone());
+        for destination in self.secondary_destinations.read().await.iter() {
+            engine = engine.with_secondary(self.secondary_client(payload, destination.clone())?);
+        }
+        Ok(engine)
+    }
+
+    /// Client for a backup bucket, writing to the same user folder as the primary
+    fn secondary_client(&self, payload: &KeyPayload, destination: S3Destination) -> Result<S3Client, S3Error> {
+        S3ClientBuilder::from_config(&self.config)
+            .user_prefix(payload.folder_prefix())
+            .destination(destination)
+            .build()
+    }
+
+    /// Cancel any running sync, then clear the session (no keychain to delete)
+    async fn end_session(&self, force: bool) {
+        // Background sync tasks hold their own engine handle, so clearing
+        // the state alone would leave them running
+        let engine = self.sync_engine.read().await.clone();
+        if let Some(engine) = engine {
+            engine.cancel();
+            if !force {
+                let deadline = tokio::time::Instant::now() + LOGOUT_CANCEL_TIMEOUT;
+                while engine.is_running().await && tokio::time::Instant::now() < deadline {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+            if engine.is_running().await {
+                log::warn!("Logging out while a cancelled sync is still stopping");
+            }
+        }
+
+        self.is_admin.store(false, Ordering::Relaxed);
+        *self.secondary_destinations.write().await = self.config.secondary_destinations.clone();
+        *self.key_payload.write().await = None;
+        *self.sync_engine.write().await = None;
+        *self.current_key.write().await = None;
+        *self.pending_preview.write().await = None;
+        self.sync_queue.clear();
+    }
+
+    /// Store a freshly validated session (key stored in memory only, not persisted)
+    async fn begin_session(&self, key: String, payload: KeyPayload, engine: Arc<SyncEngine>) {
+        self.is_admin
+            .store(payload.permissions.contains(KeyPermissions::ADMIN), Ordering::Relaxed);
+        *self.sync_engine.write().await = Some(engine);
+        *self.key_payload.write().await = Some(payload);
+        *self.current_key.write().await = Some(key);
+    }
+
+    /// Fail unless the logged-in key hasn't expired and carries `permission`
+    pub async fn require_permission(&self, permission: KeyPermissions) -> Result<(), AppError> {
+        let payload = self.key_payload.read().await;
+        let payload = payload.as_ref().ok_or(AppError::NotAuthenticated)?;
+        if payload.is_expired() {
+            return Err(AppError::CredentialsExpired);
+        }
+        if !payload.permissions.contains(permission) {
+            return Err(AppError::PermissionDenied);
+        }
+        Ok(())
+    }
+
+    /// Fail unless the current session has admin scope
+    pub fn require_admin(&self) -> Result<(), AppError> {
+        if self.is_admin.load(Ordering::Relaxed) {
+            Ok(())
+        } else {
+            Err(AppError::PermissionDenied)
         }
     }
 }
@@ -30,6 +327,330 @@ pub struct ValidationResult {
     pub valid: bool,
     pub user_name: Option<String>,
     pub error: Option<String>,
+    /// Days until the license key expires, None for keys without an expiry
+    pub key_expires_in_days: Option<i64>,
+    /// Non-fatal notice for the user, e.g. that the key expires soon
+    pub warning: Option<String>,
+    pub s3_credentials_days_remaining: i64,
+}
+
+impl ValidationResult {
+    fn rejected(error: Option<String>) -> Self {
+        Self {
+            valid: false,
+            user_name: None,
+            error,
+            key_expires_in_days: None,
+            warning: None,
+            s3_credentials_days_remaining: s3_client::S3Client::days_until_expiry(),
+        }
+    }
+}
+
+/// Whole days from `now` until `expires_at`, both Unix timestamps
+fn days_until(expires_at: i64, now: i64) -> i64 {
+    (expires_at - now).div_euclid(86400)
+}
+
+/// Why a decrypted key can't be used to log in, before anything is asked of S3
+fn key_rejection(payload: &KeyPayload) -> Option<String> {
+    if payload.is_expired() {
+        return Some("Your sync key has expired. Please contact your administrator for a new key.".to_string());
+    }
+    if !payload.permissions.intersects(KeyPermissions::all()) {
+        return Some("Your sync key doesn't allow anything. Please contact your administrator.".to_string());
+    }
+    None
+}
+
+/// Login warning for a key that expires in `days_remaining` days; expired keys
+/// are turned away by `key_rejection` instead
+fn key_expiry_warning(days_remaining: i64) -> Option<String> {
+    if days_remaining < 0 {
+        None
+    } else if days_remaining == 0 {
+        Some("Your sync key expires today. Please contact your administrator for a new key.".to_string())
+    } else if days_remaining <= KEY_EXPIRY_WARNING_DAYS {
+        Some(format!("Your sync key expires in {} days. Please contact your administrator.", days_remaining))
+    } else {
+        None
+    }
+}
+
+/// Addresses of the machine's non-loopback network interfaces
+fn local_ip_addresses() -> Vec<IpAddr> {
+    match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces
+            .iter()
+            .filter(|iface| !iface.is_loopback())
+            .map(|iface| iface.ip())
+            .collect(),
+        Err(e) => {
+            log::warn!("Failed to read network interfaces: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Resolve `path` to an absolute path without symlinks. Paths that don't exist
+/// yet resolve through their nearest existing ancestor.
+fn resolve_local_path(path: &Path) -> Option<PathBuf> {
+    if let Ok(resolved) = std::fs::canonicalize(path) {
+        return Some(resolved);
+    }
+    // Only the missing tail is taken as-is, so it must not climb back out
+    let name = path.file_name()?;
+    if path.components().any(|c| c == Component::ParentDir) {
+        return None;
+    }
+    Some(resolve_local_path(path.parent()?)?.join(name))
+}
+
+/// Reject local paths outside `allowed_roots`, e.g. `/etc` or `../../secrets`
+fn validate_user_path(path: &Path, allowed_roots: &[PathBuf]) -> Result<(), AppError> {
+    let invalid = || AppError::InvalidPath(path.display().to_string());
+    let resolved = resolve_local_path(path).ok_or_else(invalid)?;
+    let allowed = allowed_roots
+        .iter()
+        .filter_map(|root| std::fs::canonicalize(root).ok())
+        .any(|root| resolved.starts_with(root));
+    if allowed {
+        Ok(())
+    } else {
+        Err(invalid())
+    }
+}
+
+/// Check the folders an upload was started with: how many there are, the length
+/// of their names and that each is under an allowed root
+fn validate_source_paths(source_paths: &[String], config: &AppConfig) -> Result<(), AppError> {
+    if source_paths.len() > config.max_source_paths {
+        return Err(AppError::TooManyPaths {
+            count: source_paths.len(),
+            max: config.max_source_paths,
+        });
+    }
+    for path in source_paths {
+        validate_path_length(path)?;
+        validate_user_path(Path::new(path), &config.allowed_source_roots)?;
+    }
+    Ok(())
+}
+
+/// Reject paths with a file or folder name longer than most file systems allow
+fn validate_path_length(path: &str) -> Result<(), AppError> {
+    if Path::new(path).iter().any(|name| name.len() > MAX_NAME_BYTES) {
+        return Err(AppError::PathTooLong { path: path.to_string() });
+    }
+    Ok(())
+}
+
+/// Check a download's target folder before resolving it
+fn validate_target_path(target_path: &str) -> Result<(), AppError> {
+    if target_path.trim().is_empty() {
+        return Err(AppError::InvalidRequest("No download folder selected".to_string()));
+    }
+    validate_path_length(target_path)
+}
+
+/// Total size of the files under `paths`; unreadable entries are left out
+fn local_size(paths: &[PathBuf]) -> u64 {
+    paths
+        .iter()
+        .flat_map(|path| walkdir::WalkDir::new(path).into_iter().filter_map(Result::ok))
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Reject an upload of more than `limit` bytes
+async fn check_sync_size(paths: &[PathBuf], limit: Option<u64>) -> Result<(), AppError> {
+    let Some(limit) = limit else {
+        return Ok(());
+    };
+    let scanned = paths.to_vec();
+    let total_bytes = tokio::task::spawn_blocking(move || local_size(&scanned))
+        .await
+        .map_err(|e| AppError::InternalError(e.to_string()))?;
+    if total_bytes > limit {
+        return Err(AppError::SyncTooLarge { total_bytes, limit });
+    }
+    Ok(())
+}
+
+/// Reject cloud paths that reach into the admin area or out of the user's folder
+fn validate_remote_path(path: &str) -> Result<(), AppError> {
+    let path = path.trim_start_matches('/');
+    if path.starts_with("_admin") || path.split('/').any(|segment| segment == "..") {
+        return Err(AppError::InvalidPath(path.to_string()));
+    }
+    Ok(())
+}
+
+/// Rate limit handler that forwards each back-off to the frontend
+fn rate_limit_emitter(app: AppHandle) -> impl Fn(u64) + Send + Sync + 'static {
+    move |retry_after_secs| {
+        if let Err(e) = app.emit("sync://rate_limited", RateLimitedEvent { retry_after_secs }) {
+            log::warn!("Failed to emit rate limit event: {}", e);
+        }
+    }
+}
+
+/// Scan complete handler that sends a two-phase upload's files to the frontend
+fn scan_complete_emitter(app: AppHandle) -> impl Fn(&ScanComplete) + Send + Sync + 'static {
+    move |scan| {
+        if let Err(e) = app.emit("sync://scan_complete", scan) {
+            log::warn!("Failed to emit scan complete event: {}", e);
+        }
+    }
+}
+
+/// Limit reached handler that tells the frontend an upload stopped at `max_session_bytes`
+fn limit_reached_emitter(app: AppHandle) -> impl Fn(u64, u64) + Send + Sync + 'static {
+    move |transferred_bytes, limit_bytes| {
+        if let Err(e) = app.emit("sync://limit_reached", LimitReachedEvent { transferred_bytes, limit_bytes }) {
+            log::warn!("Failed to emit limit reached event: {}", e);
+        }
+    }
+}
+
+/// Activity logging for a background sync, which outlives the command that started it
+struct SyncActivity {
+    log: ActivityLogger,
+    /// The signed-in key and its payload
+    user: Option<(String, KeyPayload)>,
+    session_stats: Arc<RwLock<SessionStats>>,
+}
+
+impl SyncActivity {
+    async fn for_state(state: &AppState) -> Self {
+        let user = match (state.current_key.read().await.clone(), state.key_payload.read().await.clone()) {
+            (Some(key), Some(payload)) => Some((key, payload)),
+            _ => None,
+        };
+        Self {
+            log: state.activity_log.clone(),
+            user,
+            session_stats: Arc::clone(&state.session_stats),
+        }
+    }
+
+    fn log(&self, action: &str, details: Option<String>) {
+        if let Some((key, payload)) = &self.user {
+            self.log.log(ActivityLogEntry::new(key, &payload.name, &payload.uid, action, details));
+        }
+    }
+
+    /// Add a finished sync to the session stats and log it, with its summary and
+    /// the session's totals as JSON in the details
+    async fn sync_completed(&self, summary: &SyncSummary) {
+        let mut stats = self.session_stats.write().await;
+        stats.record(summary);
+        let details = serde_json::to_value(summary).map(|mut details| {
+            details["session_stats"] = serde_json::json!(*stats);
+            details.to_string()
+        });
+        drop(stats);
+        self.log("sync_completed", details.ok());
+    }
+}
+
+/// Log how a background sync ended and tell the user, if `notify`
+async fn report_sync_result(
+    app: &AppHandle,
+    result: Result<SyncSummary, SyncError>,
+    notify: bool,
+    activity: &SyncActivity,
+    target_path: Option<&str>,
+) {
+    if notify {
+        notifications::notify_sync_result(app, &result, target_path);
+    }
+    match result {
+        Ok(summary) => activity.sync_completed(&summary).await,
+        Err(SyncError::Cancelled) => {}
+        Err(e) => log::error!("Sync failed: {}", e),
+    }
+}
+
+/// Run a job from the sync queue with the logged-in user's engine, once any
+/// manually started sync has finished. Jobs are dropped after a logout.
+pub(crate) async fn run_queued_job(app: AppHandle, job: SyncJob) {
+    let state = app.state::<AppState>();
+    let engine = loop {
+        let Some(engine) = state.sync_engine.read().await.clone() else {
+            log::warn!("Dropping queued sync: not logged in");
+            return;
+        };
+        if !engine.is_running().await {
+            break engine;
+        }
+        tokio::time::sleep(QUEUE_POLL_INTERVAL).await;
+    };
+    let notify = state.sync_config.read().await.notifications_enabled;
+    let activity = SyncActivity::for_state(&state).await;
+
+    match job {
+        SyncJob::Upload { source_paths } => {
+            let folders: Vec<String> = source_paths.iter().map(|p| p.display().to_string()).collect();
+            activity.log("upload_started", Some(format!("Folders: {} (queued)", folders.join(", "))));
+            let result = engine.sync_to_cloud(&source_paths, Vec::new()).await;
+            report_sync_result(&app, result, notify, &activity, None).await;
+        }
+        SyncJob::Download { cloud_folder, target_path } => {
+            let target = target_path.display().to_string();
+            activity.log("download_started", Some(format!("Folder: {} -> {} (queued)", cloud_folder, target)));
+            let result = engine.sync_to_local(&cloud_folder, &target_path).await;
+            report_sync_result(&app, result, notify, &activity, Some(&target)).await;
+        }
+    }
+}
+
+/// Run `sync_to_cloud` in the background, notifying the user when it ends
+fn spawn_upload(
+    app: AppHandle,
+    engine: Arc<SyncEngine>,
+    paths: Vec<PathBuf>,
+    pending: Option<Vec<PendingFile>>,
+    notify: bool,
+    activity: SyncActivity,
+) {
+    tokio::spawn(async move {
+        let result = match pending {
+            Some(pending) => engine.sync_pending_to_cloud(&paths, pending).await,
+            None => engine.sync_to_cloud(&paths, Vec::new()).await,
+        };
+        report_sync_result(&app, result, notify, &activity, None).await;
+    });
+}
+
+/// Run `resume_upload` for `session` in the background, notifying the user when it ends
+fn spawn_resumed_upload(app: AppHandle, engine: Arc<SyncEngine>, session: SyncSession, notify: bool, activity: SyncActivity) {
+    tokio::spawn(async move {
+        let result = engine.resume_upload(&session).await;
+        report_sync_result(&app, result, notify, &activity, None).await;
+    });
+}
+
+/// Payload of the `security://using_cached_acl` event, sent when a key was checked
+/// against the local copy of the admin lists
+#[derive(Debug, Clone, Serialize)]
+pub struct UsingCachedAclEvent {
+    pub cache_age_secs: u64,
+}
+
+/// Payload of the `sync://rate_limited` event
+#[derive(Debug, Clone, Serialize)]
+pub struct RateLimitedEvent {
+    pub retry_after_secs: u64,
+}
+
+/// Payload of the `sync://limit_reached` event
+#[derive(Debug, Clone, Serialize)]
+pub struct LimitReachedEvent {
+    pub transferred_bytes: u64,
+    pub limit_bytes: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -41,271 +662,897 @@ pub struct SyncRequest {
 
 /// Check if user has a stored key (disabled - always returns false)
 #[tauri::command]
-pub async fn check_stored_key(_state: State<'_, AppState>) -> Result<bool, String> {
+pub async fn check_stored_key(_state: State<'_, AppState>) -> Result<bool, AppError> {
     // Key storage disabled - user must enter key each time
     Ok(false)
 }
 
 /// Validate and store a license key
 #[tauri::command]
-pub async fn validate_key(key: String, state: State<'_, AppState>) -> Result<ValidationResult, String> {
+pub async fn validate_key(
+    app: AppHandle,
+    key: String,
+    state: State<'_, AppState>,
+) -> Result<ValidationResult, AppError> {
     // Validate key format and decrypt
     let payload = match decrypt_key(&key) {
         Ok(p) => p,
         Err(e) => {
-            return Ok(ValidationResult {
-                valid: false,
-                user_name: None,
-                error: Some(format!("Invalid key: {}", e)),
-            });
+            return Ok(ValidationResult::rejected(Some(format!("Invalid key: {}", e))));
         }
     };
+    if let Some(reason) = key_rejection(&payload) {
+        state.activity_log.log(ActivityLogEntry::new(
+            &key,
+            &payload.name,
+            &payload.uid,
+            "login_blocked",
+            Some(reason.clone()),
+        ));
+        return Ok(ValidationResult::rejected(Some(reason)));
+    }
 
-    // Check whitelist/blacklist
-    if let Ok(admin) = AdminClient::new() {
-        match admin.validate_key_access(&key).await {
-            Ok(validation) => {
-                if !validation.allowed {
-                    // Log the failed attempt
-                    let _ = admin.log_activity(
-                        &key,
-                        &payload.name,
-                        &payload.uid,
-                        "login_blocked",
-                        validation.reason.clone(),
-                    ).await;
-
-                    return Ok(ValidationResult {
-                        valid: false,
-                        user_name: None,
-                        error: validation.reason,
-                    });
+    // Check whitelist/blacklist, from the local copy when S3 can't be reached
+    let cache_path = state.config.admin_cache_path.as_deref();
+    let from_s3 = match CachedAdminClient::new(Arc::clone(&state.admin_cache)) {
+        Ok(admin) => {
+            let from_s3 = admin.validate_key_access(&key).await;
+            if let (Ok(_), Some(path)) = (&from_s3, cache_path) {
+                if let Err(e) = admin.save_cache_locally(path).await {
+                    log::warn!("Failed to save admin lists locally: {}", e);
                 }
             }
-            Err(e) => {
-                log::warn!("Failed to check key access: {}", e);
-                // Continue anyway if admin check fails (network issue, etc.)
+            from_s3
+        }
+        Err(e) => Err(e),
+    };
+    let validation = match key_access_with_fallback(&key, from_s3, cache_path, state.config.max_cache_age_secs) {
+        Ok((validation, source)) => {
+            if let KeyAccessSource::LocalCache { cache_age_secs } = source {
+                log::warn!("S3 unreachable, checked key against admin lists from {}s ago", cache_age_secs);
+                if let Err(e) = app.emit("security://using_cached_acl", UsingCachedAclEvent { cache_age_secs }) {
+                    log::warn!("Failed to emit cached ACL event: {}", e);
+                }
             }
+            validation
+        }
+        Err(e) => {
+            // Without the lists a revoked key can't be told apart, so it isn't let in
+            log::warn!("Failed to check key access: {}", e);
+            return Ok(ValidationResult::rejected(Some(
+                "Could not verify the key with the server. Check your connection and try again.".to_string(),
+            )));
         }
+    };
+    if !validation.allowed {
+        // Log the failed attempt
+        state.activity_log.log(ActivityLogEntry::new(
+            &key,
+            &payload.name,
+            &payload.uid,
+            "login_blocked",
+            validation.reason.clone(),
+        ));
+
+        return Ok(ValidationResult::rejected(validation.reason));
+    }
+
+    // Soft network restriction: only local interface addresses are checked
+    if !payload.allows_ips(&local_ip_addresses()) {
+        state.activity_log.log(ActivityLogEntry::new(
+            &key,
+            &payload.name,
+            &payload.uid,
+            "login_blocked",
+            Some("IP address not in allowed ranges".to_string()),
+        ));
+        return Ok(ValidationResult::rejected(Some(
+            "Access not allowed from this network".to_string(),
+        )));
     }
 
     // Try to create S3 client to verify connectivity
-    let s3_client = match S3Client::new(payload.folder_prefix()).await {
+    let s3_client = match S3ClientBuilder::from_config(&state.config)
+        .user_prefix(payload.folder_prefix())
+        .build()
+    {
         Ok(c) => c,
         Err(e) => {
-            return Ok(ValidationResult {
-                valid: false,
-                user_name: None,
-                error: Some(format!("Connection failed: {}", e)),
-            });
+            return Ok(ValidationResult::rejected(Some(format!("Connection failed: {}", e))));
         }
     };
 
     let user_name = payload.name.clone();
     let user_id = payload.uid.clone();
+    let key_expires_in_days = payload
+        .expires_at
+        .map(|expires_at| days_until(expires_at, chrono::Utc::now().timestamp()));
     
     // Log successful login
-    if let Ok(admin) = AdminClient::new() {
-        let _ = admin.log_activity(
-            &key,
-            &user_name,
-            &user_id,
-            "login",
-            Some("Key entry".to_string()),
-        ).await;
-    }
+    state.activity_log.log(ActivityLogEntry::new(
+        &key,
+        &user_name,
+        &user_id,
+        "login",
+        Some("Key entry".to_string()),
+    ));
     
-    // Initialize sync engine (key stored in memory only, not persisted)
-    *state.sync_engine.write().await = Some(Arc::new(SyncEngine::new(s3_client)));
-    *state.key_payload.write().await = Some(payload);
-    *state.current_key.write().await = Some(key);
+    // Initialize sync engine and start the session
+    let engine = match state
+        .build_engine(
+            &payload,
+            s3_client,
+            rate_limit_emitter(app.clone()),
+            scan_complete_emitter(app.clone()),
+            limit_reached_emitter(app),
+        )
+        .await
+    {
+        Ok(engine) => Arc::new(engine),
+        Err(e) => {
+            return Ok(ValidationResult::rejected(Some(format!("Connection failed: {}", e))));
+        }
+    };
+    state.begin_session(key, payload, Arc::clone(&engine)).await;
+
+    // Clear out temp objects from atomic uploads interrupted in an earlier session
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = engine.cleanup_stale_uploads().await {
+            log::warn!("Failed to clean up stale uploads: {}", e);
+        }
+    });
 
     Ok(ValidationResult {
         valid: true,
         user_name: Some(user_name),
         error: None,
+        key_expires_in_days,
+        warning: key_expires_in_days.and_then(key_expiry_warning),
+        s3_credentials_days_remaining: s3_client::S3Client::days_until_expiry(),
     })
 }
 
+/// Replace the S3 client without logging out, e.g. after credentials were renewed
+#[tauri::command]
+pub async fn refresh_connection(app: AppHandle, state: State<'_, AppState>) -> Result<(), AppError> {
+    state
+        .reconnect(rate_limit_emitter(app.clone()), scan_complete_emitter(app.clone()), limit_reached_emitter(app))
+        .await
+}
+
+/// Whether the session's bucket can be reached, reconnecting first if the session isn't connected
+#[tauri::command]
+pub async fn check_connection(app: AppHandle, state: State<'_, AppState>) -> Result<ConnectionStatus, AppError> {
+    if !state.is_connected().await {
+        state
+            .reconnect(rate_limit_emitter(app.clone()), scan_complete_emitter(app.clone()), limit_reached_emitter(app))
+            .await?;
+    }
+    let engine = state.sync_engine.read().await.clone().ok_or(AppError::NotAuthenticated)?;
+    Ok(engine.check_connection().await)
+}
+
+/// Measure round-trip latency to the session's bucket, with the concurrency it
+/// suggests. `probe_upload` also times a small upload, unless the session may not write.
+#[tauri::command]
+pub async fn check_connection_health(
+    probe_upload: bool,
+    state: State<'_, AppState>,
+) -> Result<ConnectionInfo, AppError> {
+    let engine = state.sync_engine.read().await.clone().ok_or(AppError::NotAuthenticated)?;
+    let probe_upload = probe_upload && state.ensure_writable().await.is_ok();
+    let info = engine.warm_up(probe_upload).await?;
+    *state.connection_info.write().await = Some(info.clone());
+    Ok(info)
+}
+
+/// Mirror future uploads to another S3-compatible bucket as well
+#[tauri::command]
+pub async fn add_secondary_destination(
+    endpoint: String,
+    bucket: String,
+    access_key: String,
+    secret_key: String,
+    state: State<'_, AppState>,
+) -> Result<(), AppError> {
+    let payload = state.key_payload.read().await.clone().ok_or(AppError::NotAuthenticated)?;
+    if endpoint.trim().is_empty() || bucket.trim().is_empty() {
+        return Err(AppError::InvalidRequest("Endpoint and bucket are required".to_string()));
+    }
+
+    let mut engine = state.sync_engine.write().await;
+    let current = engine.as_ref().ok_or(AppError::NotAuthenticated)?;
+    if current.is_running().await {
+        return Err(AppError::InvalidRequest(
+            "Cannot add a destination while a sync is running".to_string(),
+        ));
+    }
+
+    let destination = S3Destination { endpoint, bucket, access_key, secret_key };
+    let client = state.secondary_client(&payload, destination.clone())?;
+    let updated = current.reconfigured(current.config().clone()).with_secondary(client);
+    *engine = Some(Arc::new(updated));
+    state.secondary_destinations.write().await.push(destination);
+    Ok(())
+}
+
 /// Get current user info
 #[tauri::command]
-pub async fn get_user_info(state: State<'_, AppState>) -> Result<Option<KeyPayload>, String> {
+pub async fn get_user_info(state: State<'_, AppState>) -> Result<Option<KeyPayload>, AppError> {
     Ok(state.key_payload.read().await.clone())
 }
 
-/// Logout - clear session
+/// Logout - clear session. A running sync is cancelled first; unless `force`
+/// is set, logout waits a few seconds for it to stop.
 #[tauri::command]
-pub async fn logout(state: State<'_, AppState>) -> Result<(), String> {
+pub async fn logout(force: bool, state: State<'_, AppState>) -> Result<(), AppError> {
     // Log logout activity
     if let (Some(key), Some(payload)) = (
         state.current_key.read().await.clone(),
         state.key_payload.read().await.clone(),
     ) {
-        if let Ok(admin) = AdminClient::new() {
-            let _ = admin.log_activity(
-                &key,
-                &payload.name,
-                &payload.uid,
-                "logout",
-                None,
-            ).await;
-        }
+        state.activity_log.log(ActivityLogEntry::new(
+            &key,
+            &payload.name,
+            &payload.uid,
+            "logout",
+            None,
+        ));
     }
 
-    // Clear session (no keychain to delete)
-    *state.key_payload.write().await = None;
-    *state.sync_engine.write().await = None;
-    *state.current_key.write().await = None;
+    state.end_session(force).await;
     Ok(())
 }
 
 /// Start sync from local to cloud
 #[tauri::command]
 pub async fn start_upload(
+    app: AppHandle,
     source_paths: Vec<String>,
     state: State<'_, AppState>,
-) -> Result<(), String> {
+) -> Result<(), AppError> {
+    state.require_permission(KeyPermissions::UPLOAD).await?;
+    state.ensure_writable().await?;
     let engine = state.sync_engine.read().await;
-    let engine = engine.as_ref().ok_or("Not authenticated")?;
+    let engine = engine.as_ref().ok_or(AppError::NotAuthenticated)?;
+    validate_source_paths(&source_paths, &state.config)?;
+    let paths: Vec<PathBuf> = source_paths.iter().map(PathBuf::from).collect();
+    check_sync_size(&paths, state.config.max_single_sync_bytes).await?;
     
     // Log upload activity
     if let (Some(key), Some(payload)) = (
         state.current_key.read().await.clone(),
         state.key_payload.read().await.clone(),
     ) {
-        if let Ok(admin) = AdminClient::new() {
-            let _ = admin.log_activity(
-                &key,
-                &payload.name,
-                &payload.uid,
-                "upload_started",
-                Some(format!("Folders: {}", source_paths.join(", "))),
-            ).await;
-        }
+        state.activity_log.log(ActivityLogEntry::new(
+            &key,
+            &payload.name,
+            &payload.uid,
+            "upload_started",
+            Some(format!("Folders: {}", source_paths.join(", "))),
+        ));
     }
     
-    let paths: Vec<PathBuf> = source_paths.iter().map(PathBuf::from).collect();
+    // Reuse the scan from a recent preview of the same folders
+    let pending = state
+        .pending_preview
+        .write()
+        .await
+        .take()
+        .filter(|preview| preview.usable_for(&source_paths))
+        .map(|preview| preview.files);
     
     // Clone the Arc to move into the async block
     let engine = Arc::clone(engine);
+    let notify = state.sync_config.read().await.notifications_enabled;
+    let activity = SyncActivity::for_state(&state).await;
     
     // Spawn the sync task
+    spawn_upload(app, engine, paths, pending, notify, activity);
+    
+    Ok(())
+}
+
+/// Upload local folders under each of `remote_prefixes`, uploading every file once
+/// and copying it to the other prefixes
+#[tauri::command]
+pub async fn start_multi_dest_upload(
+    app: AppHandle,
+    source_paths: Vec<String>,
+    remote_prefixes: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<(), AppError> {
+    state.require_permission(KeyPermissions::UPLOAD).await?;
+    state.ensure_writable().await?;
+    let engine = state.sync_engine.read().await.clone().ok_or(AppError::NotAuthenticated)?;
+    validate_source_paths(&source_paths, &state.config)?;
+    for prefix in &remote_prefixes {
+        validate_remote_path(prefix)?;
+    }
+    let paths: Vec<PathBuf> = source_paths.iter().map(PathBuf::from).collect();
+    check_sync_size(&paths, state.config.max_single_sync_bytes).await?;
+
+    let notify = state.sync_config.read().await.notifications_enabled;
+    let activity = SyncActivity::for_state(&state).await;
     tokio::spawn(async move {
-        if let Err(e) = engine.sync_to_cloud(&paths).await {
-            log::error!("Upload failed: {}", e);
-        }
+        let result = engine.sync_to_cloud_multi_dest(&paths, &remote_prefixes).await;
+        report_sync_result(&app, result, notify, &activity, None).await;
     });
-    
     Ok(())
 }
 
+/// List the files an upload of `source_paths` would transfer without starting it
+#[tauri::command]
+pub async fn preview_upload(
+    source_paths: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<PendingFile>, AppError> {
+    let engine = state.sync_engine.read().await.clone().ok_or(AppError::NotAuthenticated)?;
+    validate_source_paths(&source_paths, &state.config)?;
+    
+    let paths: Vec<PathBuf> = source_paths.iter().map(PathBuf::from).collect();
+    let files = engine.list_pending_uploads(&paths).await?;
+    *state.pending_preview.write().await = Some(PendingPreview {
+        source_paths,
+        files: files.clone(),
+        created_at: Instant::now(),
+    });
+    Ok(files)
+}
+
+/// What uploading `source_paths` would cost at the configured provider prices
+#[tauri::command]
+pub async fn estimate_sync_cost(
+    source_paths: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<S3CostEstimate, AppError> {
+    let engine = state.sync_engine.read().await.clone().ok_or(AppError::NotAuthenticated)?;
+    validate_source_paths(&source_paths, &state.config)?;
+
+    let paths: Vec<PathBuf> = source_paths.iter().map(PathBuf::from).collect();
+    let files = engine.list_pending_uploads(&paths).await?;
+    Ok(engine.estimate_cost(&files))
+}
+
+/// Whether a cloud folder still matches the checksum stored after its last upload
+/// (see `SyncConfig::folder_checksums`); false if a file was changed or deleted since
+#[tauri::command]
+pub async fn verify_folder_checksum(cloud_folder: String, state: State<'_, AppState>) -> Result<bool, AppError> {
+    validate_remote_path(&cloud_folder)?;
+    let engine = state.sync_engine.read().await.clone().ok_or(AppError::NotAuthenticated)?;
+    engine.verify_folder_checksum(&cloud_folder).await?.ok_or_else(|| {
+        AppError::InvalidRequest(format!("No checksum has been stored for {}", cloud_folder))
+    })
+}
+
+/// JSON manifest of the last upload made with `generate_manifest` on, if any
+#[tauri::command]
+pub async fn get_last_manifest(state: State<'_, AppState>) -> Result<Option<String>, AppError> {
+    let engine = state.sync_engine.read().await.clone().ok_or(AppError::NotAuthenticated)?;
+    Ok(engine.last_manifest().await?)
+}
+
+/// Progress of an upload the app quit during, if the logged-in user has one to resume
+#[tauri::command]
+pub async fn get_interrupted_sync(state: State<'_, AppState>) -> Result<Option<SyncProgress>, AppError> {
+    let uid = state.key_payload.read().await.as_ref().map(|payload| payload.uid.clone());
+    Ok(state
+        .interrupted_sync
+        .read()
+        .await
+        .as_ref()
+        .filter(|saved| saved.belongs_to(uid.as_deref()))
+        .map(|saved| saved.progress.clone()))
+}
+
+/// Continue the upload the app quit during, skipping files it already uploaded.
+/// Returns the progress it had reached, or None if there is nothing to resume.
+/// Only the user who started the upload may resume it.
+#[tauri::command]
+pub async fn resume_last_upload_session(
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<Option<SyncProgress>, AppError> {
+    state.require_permission(KeyPermissions::UPLOAD).await?;
+    let engine = state.sync_engine.read().await.clone().ok_or(AppError::NotAuthenticated)?;
+    if engine.is_running().await {
+        return Err(AppError::InvalidRequest("A sync is already running".to_string()));
+    }
+    state.ensure_writable().await?;
+    let uid = state.key_payload.read().await.as_ref().map(|payload| payload.uid.clone());
+    let mut interrupted = state.interrupted_sync.write().await;
+    let Some(saved) = interrupted.take_if(|saved| saved.belongs_to(uid.as_deref())) else {
+        return match *interrupted {
+            Some(_) => Err(AppError::ForeignSession),
+            None => Ok(None),
+        };
+    };
+    drop(interrupted);
+    for path in &saved.source_paths {
+        validate_user_path(path, &state.config.allowed_source_roots)?;
+    }
+
+    let notify = state.sync_config.read().await.notifications_enabled;
+    let activity = SyncActivity::for_state(&state).await;
+    let progress = saved.progress.clone();
+    spawn_resumed_upload(app, engine, saved, notify, activity);
+    Ok(Some(progress))
+}
+
+/// Forget the interrupted upload instead of resuming it.
+/// Only the user who started the upload may discard it.
+#[tauri::command]
+pub async fn discard_interrupted_sync(state: State<'_, AppState>) -> Result<(), AppError> {
+    state.discard_interrupted_sync().await
+}
+
 /// Start sync from cloud to local
 #[tauri::command]
 pub async fn start_download(
+    app: AppHandle,
     cloud_folder: String,
     target_path: String,
     state: State<'_, AppState>,
-) -> Result<(), String> {
+) -> Result<(), AppError> {
+    state.require_permission(KeyPermissions::DOWNLOAD).await?;
     let engine = state.sync_engine.read().await;
-    let engine = engine.as_ref().ok_or("Not authenticated")?;
+    let engine = engine.as_ref().ok_or(AppError::NotAuthenticated)?;
+    validate_remote_path(&cloud_folder)?;
+    validate_target_path(&target_path)?;
+    let home: Vec<PathBuf> = config::home_dir().into_iter().collect();
+    validate_user_path(Path::new(&target_path), &home)?;
     
     // Log download activity
     if let (Some(key), Some(payload)) = (
         state.current_key.read().await.clone(),
         state.key_payload.read().await.clone(),
     ) {
-        if let Ok(admin) = AdminClient::new() {
-            let _ = admin.log_activity(
-                &key,
-                &payload.name,
-                &payload.uid,
-                "download_started",
-                Some(format!("Folder: {} -> {}", cloud_folder, target_path)),
-            ).await;
-        }
+        state.activity_log.log(ActivityLogEntry::new(
+            &key,
+            &payload.name,
+            &payload.uid,
+            "download_started",
+            Some(format!("Folder: {} -> {}", cloud_folder, target_path)),
+        ));
     }
     
-    let target = PathBuf::from(target_path);
+    let target = PathBuf::from(&target_path);
     let engine = Arc::clone(engine);
     let folder = cloud_folder.clone();
+    let notify = state.sync_config.read().await.notifications_enabled;
+    let activity = SyncActivity::for_state(&state).await;
     
     // Spawn the sync task
     tokio::spawn(async move {
-        if let Err(e) = engine.sync_to_local(&folder, &target).await {
-            log::error!("Download failed: {}", e);
-        }
+        let result = engine.sync_to_local(&folder, &target).await;
+        report_sync_result(&app, result, notify, &activity, Some(&target_path)).await;
     });
     
     Ok(())
 }
 
+/// Replace the sync options, rebuilding the engine around the existing S3 client
+#[tauri::command]
+pub async fn set_sync_config(config: SyncConfig, state: State<'_, AppState>) -> Result<(), AppError> {
+    if config.concurrency == 0 {
+        return Err(AppError::InvalidRequest("Concurrency must be at least 1".to_string()));
+    }
+    if config.encrypt_contents {
+        return Err(AppError::InvalidRequest("Client-side encryption is not supported yet".to_string()));
+    }
+    SyncFilter::new(&config.exclude_patterns).map_err(AppError::InvalidRequest)?;
+    if config
+        .object_lock
+        .is_some_and(|lock| !(1..=MAX_RETAIN_DAYS).contains(&lock.retain_days))
+    {
+        return Err(AppError::InvalidRequest(format!(
+            "Object lock retention must be between 1 and {} days",
+            MAX_RETAIN_DAYS
+        )));
+    }
+    if let (Some(min), Some(max)) = (config.exclude_min_size, config.exclude_max_size) {
+        if min > max {
+            return Err(AppError::InvalidRequest(format!(
+                "Minimum file size ({} bytes) is above the maximum ({} bytes)",
+                min, max
+            )));
+        }
+    }
+
+    if let Some(acl) = &config.default_acl {
+        acl.parse::<ObjectCannedAcl>()?;
+    }
+    if let Some(template) = &config.remote_path_template {
+        validate_remote_path(template)?;
+    }
+
+    // Leave room for the uploaded_by and synced_at tags added to every upload
+    let mut tags = config.default_tags.clone();
+    tags.insert("uploaded_by".to_string(), String::new());
+    tags.insert("synced_at".to_string(), String::new());
+    s3_client::validate_tags(&tags)?;
+
+    let mut engine = state.sync_engine.write().await;
+    if let Some(current) = engine.as_ref() {
+        if current.is_running().await {
+            return Err(AppError::InvalidRequest(
+                "Cannot change sync settings while a sync is running".to_string(),
+            ));
+        }
+    }
+
+    *state.sync_config.write().await = config;
+    if let (Some(current), Some(payload)) = (engine.as_ref(), state.key_payload.read().await.as_ref()) {
+        *engine = Some(Arc::new(current.reconfigured(state.engine_config(payload).await)));
+    }
+
+    Ok(())
+}
+
+/// Turn read-only mode on or off for the session, on top of the sync config's
+/// `read_only`. Downloads and previews keep working.
+#[tauri::command]
+pub async fn set_read_only_mode(enabled: bool, state: State<'_, AppState>) -> Result<(), AppError> {
+    let mut engine = state.sync_engine.write().await;
+    if let Some(current) = engine.as_ref() {
+        if current.is_running().await {
+            return Err(AppError::InvalidRequest(
+                "Cannot change read-only mode while a sync is running".to_string(),
+            ));
+        }
+    }
+
+    state.read_only_mode.store(enabled, Ordering::Release);
+    if let (Some(current), Some(payload)) = (engine.as_ref(), state.key_payload.read().await.as_ref()) {
+        *engine = Some(Arc::new(current.reconfigured(state.engine_config(payload).await)));
+    }
+    Ok(())
+}
+
+/// Admin: copy one cloud folder to another, reporting through get_sync_progress
+#[tauri::command]
+pub async fn copy_cloud_folder(
+    source: String,
+    dest: String,
+    state: State<'_, AppState>,
+) -> Result<(), AppError> {
+    state.require_admin()?;
+    state.ensure_writable().await?;
+    validate_remote_path(&source)?;
+    validate_remote_path(&dest)?;
+
+    let engine = state.sync_engine.read().await;
+    let engine = engine.as_ref().ok_or(AppError::NotAuthenticated)?;
+    if engine.is_running().await {
+        return Err(AppError::InvalidRequest("A sync is already running".to_string()));
+    }
+
+    let engine = Arc::clone(engine);
+    tokio::spawn(async move {
+        match engine.sync_cloud_to_cloud(&source, &dest).await {
+            Ok(()) | Err(SyncError::Cancelled) => {}
+            Err(e) => log::error!("Copy from {} to {} failed: {}", source, dest, e),
+        }
+    });
+
+    Ok(())
+}
+
+/// Queue an upload to run once the jobs ahead of it are done; higher
+/// `priority` goes first. Returns the job ID.
+#[tauri::command]
+pub async fn queue_upload(
+    source_paths: Vec<String>,
+    priority: u8,
+    state: State<'_, AppState>,
+) -> Result<String, AppError> {
+    if !state.is_connected().await {
+        return Err(AppError::NotAuthenticated);
+    }
+    state.require_permission(KeyPermissions::UPLOAD).await?;
+    state.ensure_writable().await?;
+    validate_source_paths(&source_paths, &state.config)?;
+    let source_paths: Vec<PathBuf> = source_paths.iter().map(PathBuf::from).collect();
+    check_sync_size(&source_paths, state.config.max_single_sync_bytes).await?;
+    Ok(state.sync_queue.enqueue(SyncJob::Upload { source_paths }, priority))
+}
+
+/// Queue a download of `cloud_folder` into `target_path`, like `queue_upload`
+#[tauri::command]
+pub async fn queue_download(
+    cloud_folder: String,
+    target_path: String,
+    priority: u8,
+    state: State<'_, AppState>,
+) -> Result<String, AppError> {
+    if !state.is_connected().await {
+        return Err(AppError::NotAuthenticated);
+    }
+    state.require_permission(KeyPermissions::DOWNLOAD).await?;
+    validate_remote_path(&cloud_folder)?;
+    validate_target_path(&target_path)?;
+    let home: Vec<PathBuf> = config::home_dir().into_iter().collect();
+    validate_user_path(Path::new(&target_path), &home)?;
+    let job = SyncJob::Download {
+        cloud_folder,
+        target_path: PathBuf::from(target_path),
+    };
+    Ok(state.sync_queue.enqueue(job, priority))
+}
+
+/// Stop starting queued jobs; one already running carries on
+#[tauri::command]
+pub async fn pause_queue(state: State<'_, AppState>) -> Result<(), AppError> {
+    state.sync_queue.pause();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn resume_queue(state: State<'_, AppState>) -> Result<(), AppError> {
+    state.sync_queue.resume();
+    Ok(())
+}
+
+/// The running queued job, then the waiting ones in the order they will run
+#[tauri::command]
+pub async fn get_queue_status(state: State<'_, AppState>) -> Result<Vec<QueuedJobStatus>, AppError> {
+    Ok(state.sync_queue.status())
+}
+
 /// Pause the current sync
 #[tauri::command]
-pub async fn pause_sync(state: State<'_, AppState>) -> Result<(), String> {
+pub async fn pause_sync(window: tauri::Window, state: State<'_, AppState>) -> Result<(), AppError> {
     let engine = state.sync_engine.read().await;
-    let engine = engine.as_ref().ok_or("No active sync")?;
-    engine.pause();
+    let engine = engine.as_ref().ok_or(SyncError::NoActiveSync)?;
+    engine.pause_with_notify(&window);
     Ok(())
 }
 
 /// Resume the current sync
 #[tauri::command]
-pub async fn resume_sync(state: State<'_, AppState>) -> Result<(), String> {
+pub async fn resume_sync(window: tauri::Window, state: State<'_, AppState>) -> Result<(), AppError> {
     let engine = state.sync_engine.read().await;
-    let engine = engine.as_ref().ok_or("No active sync")?;
-    engine.resume();
+    let engine = engine.as_ref().ok_or(SyncError::NoActiveSync)?;
+    engine.resume_with_notify(&window);
     Ok(())
 }
 
 /// Cancel the current sync
 #[tauri::command]
-pub async fn cancel_sync(state: State<'_, AppState>) -> Result<(), String> {
+pub async fn cancel_sync(window: tauri::Window, state: State<'_, AppState>) -> Result<(), AppError> {
     let engine = state.sync_engine.read().await;
-    let engine = engine.as_ref().ok_or("No active sync")?;
-    engine.cancel();
+    let engine = engine.as_ref().ok_or(SyncError::NoActiveSync)?;
+    engine.cancel_with_notify(&window).await;
     Ok(())
 }
 
-/// Get current sync progress
+/// Send sync events to the calling window; call again after the frontend reloads
+#[tauri::command]
+pub async fn register_for_sync_events(window: tauri::Window, state: State<'_, AppState>) -> Result<(), AppError> {
+    // The registry outlives engines, so this works before login too
+    state.sync_windows.register(window);
+    Ok(())
+}
+
+/// Stop sending sync events to the window labelled `label`
+#[tauri::command]
+pub async fn unregister_from_sync_events(label: String, state: State<'_, AppState>) -> Result<(), AppError> {
+    state.sync_windows.unregister(&label);
+    Ok(())
+}
+
+/// Start uploading what a two-phase sync scanned; it waits in `AwaitingConfirmation` until then
+#[tauri::command]
+pub async fn confirm_sync(state: State<'_, AppState>) -> Result<(), AppError> {
+    let engine = state.sync_engine.read().await;
+    let engine = engine.as_ref().ok_or(SyncError::NoActiveSync)?;
+    engine.confirm().await?;
+    Ok(())
+}
+
+/// Get current sync progress; never waits on the running sync
+#[tauri::command]
+pub async fn get_sync_progress(state: State<'_, AppState>) -> Result<SyncProgress, AppError> {
+    let engine = state.sync_engine.read().await;
+    let engine = engine.as_ref().ok_or(AppError::NotAuthenticated)?;
+    Ok(engine.get_progress_sync())
+}
+
+/// Summary of the last upload or download to finish this session
 #[tauri::command]
-pub async fn get_sync_progress(state: State<'_, AppState>) -> Result<SyncProgress, String> {
+pub async fn get_last_sync_summary(state: State<'_, AppState>) -> Result<Option<SyncSummary>, AppError> {
     let engine = state.sync_engine.read().await;
-    let engine = engine.as_ref().ok_or("Not authenticated")?;
-    Ok(engine.get_progress().await)
+    let engine = engine.as_ref().ok_or(AppError::NotAuthenticated)?;
+    Ok(engine.get_last_summary())
+}
+
+/// Upload restrictions set by the administrators, for display
+#[tauri::command]
+pub async fn get_sync_policy(state: State<'_, AppState>) -> Result<SyncPolicy, AppError> {
+    let engine = state.sync_engine.read().await;
+    let engine = engine.as_ref().ok_or(AppError::NotAuthenticated)?;
+    engine.fetch_remote_sync_policy().await.map_err(AppError::from)
 }
 
 /// List cloud folders
 #[tauri::command]
-pub async fn list_cloud_folders(state: State<'_, AppState>) -> Result<Vec<CloudFolder>, String> {
+pub async fn list_cloud_folders(state: State<'_, AppState>) -> Result<Vec<CloudFolder>, AppError> {
+    let engine = state.sync_engine.read().await;
+    let engine = engine.as_ref().ok_or(AppError::NotAuthenticated)?;
+    engine.list_cloud_folders().await.map_err(AppError::from)
+}
+
+/// List page `page` (from 0) of the cloud folders, `page_size` at a time, without sizes
+#[tauri::command]
+pub async fn list_cloud_folders_page(
+    page: usize,
+    page_size: usize,
+    state: State<'_, AppState>,
+) -> Result<CloudFolderPage, AppError> {
+    if page_size == 0 {
+        return Err(AppError::InvalidRequest("Page size must be at least 1".to_string()));
+    }
+    let engine = state.sync_engine.read().await;
+    let engine = engine.as_ref().ok_or(AppError::NotAuthenticated)?;
+    engine.list_cloud_folders_page(page, page_size).await.map_err(AppError::from)
+}
+
+/// Size and file count of one cloud folder, for folders listed by list_cloud_folders_page
+#[tauri::command]
+pub async fn load_folder_size(folder: String, state: State<'_, AppState>) -> Result<CloudFolder, AppError> {
+    let engine = state.sync_engine.read().await;
+    let engine = engine.as_ref().ok_or(AppError::NotAuthenticated)?;
+    engine.load_folder_size(&folder).await.map_err(AppError::from)
+}
+
+/// List every folder below `root` as a tree
+#[tauri::command]
+pub async fn list_cloud_folder_tree(root: String, state: State<'_, AppState>) -> Result<Vec<CloudFolder>, AppError> {
+    let engine = state.sync_engine.read().await;
+    let engine = engine.as_ref().ok_or(AppError::NotAuthenticated)?;
+    engine.list_cloud_folder_tree(&root).await.map_err(AppError::from)
+}
+
+/// List the files in a cloud folder, sorted by `sort_by` ("name", "size" or
+/// "last_modified") in `sort_dir` ("asc" or "desc") order
+#[tauri::command]
+pub async fn list_cloud_files(
+    folder: String,
+    sort_by: String,
+    sort_dir: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<S3Object>, AppError> {
+    let order: SortOrder = sort_by.parse().map_err(AppError::InvalidRequest)?;
+    let dir: SortDir = sort_dir.parse().map_err(AppError::InvalidRequest)?;
+    validate_remote_path(&folder)?;
+
+    let engine = state.sync_engine.read().await;
+    let engine = engine.as_ref().ok_or(AppError::NotAuthenticated)?;
+    engine.list_cloud_files(&folder, order, dir).await.map_err(AppError::from)
+}
+
+/// Get total cloud usage for the current user
+#[tauri::command]
+pub async fn get_storage_stats(state: State<'_, AppState>) -> Result<StorageStats, AppError> {
+    let engine = state.sync_engine.read().await;
+    let engine = engine.as_ref().ok_or(AppError::NotAuthenticated)?;
+    engine.get_storage_stats().await.map_err(AppError::from)
+}
+
+/// Show only what the bucket lists on the next listing, dropping recent uploads
+/// it may not list yet and the cached storage stats
+#[tauri::command]
+pub async fn invalidate_listing_cache(state: State<'_, AppState>) -> Result<(), AppError> {
+    let engine = state.sync_engine.read().await;
+    let engine = engine.as_ref().ok_or(AppError::NotAuthenticated)?;
+    engine.invalidate_listing_cache().await;
+    Ok(())
+}
+
+/// Totals of the uploads and downloads finished this session
+#[tauri::command]
+pub async fn get_session_stats(state: State<'_, AppState>) -> Result<SessionStats, AppError> {
+    Ok(state.session_stats.read().await.clone())
+}
+
+/// Start the session totals over from now
+#[tauri::command]
+pub async fn reset_session_stats(state: State<'_, AppState>) -> Result<(), AppError> {
+    let stats = match state.sync_engine.read().await.as_ref() {
+        Some(engine) => engine.new_session_stats(),
+        None => SessionStats::default(),
+    };
+    *state.session_stats.write().await = stats;
+    Ok(())
+}
+
+/// Per-file timings of the most recent transfers, oldest first
+#[tauri::command]
+pub async fn get_file_transfer_timings(state: State<'_, AppState>) -> Result<Vec<FileTransferRecord>, AppError> {
     let engine = state.sync_engine.read().await;
-    let engine = engine.as_ref().ok_or("Not authenticated")?;
-    engine.list_cloud_folders().await.map_err(|e| e.to_string())
+    let engine = engine.as_ref().ok_or(AppError::NotAuthenticated)?;
+    Ok(engine.get_transfer_records())
 }
 
 /// Delete all files in the user's cloud storage
 #[tauri::command]
-pub async fn delete_all_files(state: State<'_, AppState>) -> Result<usize, String> {
-    let payload = state.key_payload.read().await;
-    let payload = payload.as_ref().ok_or("Not authenticated")?.clone();
+pub async fn delete_all_files(state: State<'_, AppState>) -> Result<usize, AppError> {
+    state.require_permission(KeyPermissions::DELETE).await?;
+    let payload = state.key_payload.read().await.clone().ok_or(AppError::NotAuthenticated)?;
+    state.ensure_writable().await?;
     
     // Log delete activity
     if let Some(key) = state.current_key.read().await.clone() {
-        if let Ok(admin) = AdminClient::new() {
-            let _ = admin.log_activity(
-                &key,
-                &payload.name,
-                &payload.uid,
-                "delete_all_files",
-                Some("User requested deletion of all cloud files".to_string()),
-            ).await;
-        }
+        state.activity_log.log(ActivityLogEntry::new(
+            &key,
+            &payload.name,
+            &payload.uid,
+            "delete_all_files",
+            Some("User requested deletion of all cloud files".to_string()),
+        ));
     }
     
-    let s3_client = crate::s3_client::S3Client::new(payload.folder_prefix())
-        .await
-        .map_err(|e| e.to_string())?;
-    
-    s3_client.delete_all_objects().await.map_err(|e| e.to_string())
+    // The engine deletes from the backup destinations as well
+    let engine = state.sync_engine.read().await.clone().ok_or(AppError::NotAuthenticated)?;
+    Ok(engine.delete_all_objects().await?)
+}
+
+/// Replace the tags on a file in the user's cloud storage
+#[tauri::command]
+pub async fn tag_cloud_file(
+    cloud_key: String,
+    tags: HashMap<String, String>,
+    state: State<'_, AppState>,
+) -> Result<(), AppError> {
+    let payload = state.key_payload.read().await;
+    let payload = payload.as_ref().ok_or(AppError::NotAuthenticated)?.clone();
+    state.ensure_writable().await?;
+    validate_remote_path(&cloud_key)?;
+
+    let s3_client = S3ClientBuilder::from_config(&state.config)
+        .user_prefix(payload.folder_prefix())
+        .build()?;
+
+    s3_client.put_object_tags(&cloud_key, tags).await.map_err(AppError::from)
+}
+
+/// Get the tags on a file in the user's cloud storage
+#[tauri::command]
+pub async fn get_cloud_file_tags(
+    cloud_key: String,
+    state: State<'_, AppState>,
+) -> Result<HashMap<String, String>, AppError> {
+    let payload = state.key_payload.read().await;
+    let payload = payload.as_ref().ok_or(AppError::NotAuthenticated)?.clone();
+    validate_remote_path(&cloud_key)?;
+
+    let s3_client = S3ClientBuilder::from_config(&state.config)
+        .user_prefix(payload.folder_prefix())
+        .build()?;
+
+    s3_client.get_object_tags(&cloud_key).await.map_err(AppError::from)
+}
+
+/// Set a canned ACL, e.g. "private" or "public-read", on a file in the user's cloud storage
+#[tauri::command]
+pub async fn set_cloud_file_acl(
+    cloud_key: String,
+    acl: String,
+    state: State<'_, AppState>,
+) -> Result<(), AppError> {
+    state.ensure_writable().await?;
+    validate_remote_path(&cloud_key)?;
+    let acl: ObjectCannedAcl = acl.parse()?;
+    let engine = state.sync_engine.read().await.clone().ok_or(AppError::NotAuthenticated)?;
+    Ok(engine.set_file_acl(&cloud_key, acl).await?)
+}
+
+/// Download a file from the user's cloud storage and check it against its stored SHA-256
+#[tauri::command]
+pub async fn verify_file_checksum(cloud_key: String, state: State<'_, AppState>) -> Result<bool, AppError> {
+    validate_remote_path(&cloud_key)?;
+    let engine = state.sync_engine.read().await.clone().ok_or(AppError::NotAuthenticated)?;
+    Ok(engine.verify_file_checksum(&cloud_key).await?)
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -314,11 +1561,16 @@ pub struct CredentialsStatus {
     pub days_remaining: i64,
     pub expiry_date: String,
     pub warning: Option<String>,
+    /// Where the app connects to, to help diagnose connection problems
+    pub endpoint: String,
+    pub bucket: String,
+    /// Detected by the last connection check, or the configured region before one
+    pub region: String,
 }
 
 /// Check credentials expiration status
 #[tauri::command]
-pub async fn check_credentials_status() -> Result<CredentialsStatus, String> {
+pub async fn check_credentials_status(state: State<'_, AppState>) -> Result<CredentialsStatus, AppError> {
     let days_remaining = crate::s3_client::S3Client::days_until_expiry();
     let expiry_date = "2026-11-28".to_string();
     
@@ -332,11 +1584,369 @@ pub async fn check_credentials_status() -> Result<CredentialsStatus, String> {
         None
     };
     
+    let provider = &state.config.provider;
+    let region = match &*state.connection_info.read().await {
+        Some(info) => info.region.clone(),
+        None => provider.region.clone(),
+    };
+
     Ok(CredentialsStatus {
         valid: days_remaining > 0,
         days_remaining,
         expiry_date,
         warning,
+        endpoint: provider.endpoint.clone(),
+        bucket: provider.bucket.clone(),
+        region,
     })
 }
 
+
+/// Admin: delete all cloud files, list entries and log entries for a user
+#[tauri::command]
+pub async fn purge_user_data(user_id: String, state: State<'_, AppState>) -> Result<PurgeResult, AppError> {
+    state.require_admin()?;
+
+    let admin = CachedAdminClient::new(Arc::clone(&state.admin_cache)).map_err(AppError::InternalError)?;
+
+    // Use the name from the whitelist for logging if we have it
+    let user_name = admin
+        .get_whitelist()
+        .await
+        .ok()
+        .and_then(|w| w.entries.values().find(|e| e.user_id == user_id).map(|e| e.user_name.clone()))
+        .unwrap_or_default();
+
+    let result = admin
+        .inner()
+        .purge_user_data(&user_id, &user_name)
+        .await
+        .map_err(AppError::InternalError)?;
+    admin.invalidate_cache().await;
+    Ok(result)
+}
+
+/// Admin: get aggregated activity metrics for the last `since_days` days
+#[tauri::command]
+pub async fn admin_get_stats(since_days: u64, state: State<'_, AppState>) -> Result<ActivityStats, AppError> {
+    state.require_admin()?;
+
+    let since = chrono::Utc::now() - chrono::Duration::days(since_days as i64);
+    let admin = AdminClient::new().map_err(AppError::InternalError)?;
+    admin.get_activity_stats(since).await.map_err(AppError::InternalError)
+}
+
+/// Admin: merge the activity log's shards into a single file, returning how many were merged
+#[tauri::command]
+pub async fn compact_activity_log(state: State<'_, AppState>) -> Result<usize, AppError> {
+    state.require_admin()?;
+
+    let admin = AdminClient::new().map_err(AppError::InternalError)?;
+    admin.compact_activity_log().await.map_err(AppError::InternalError)
+}
+
+/// Admin: tag every whitelist/blacklist entry with its hash algorithm, returning
+/// how many are still SHA-256
+#[tauri::command]
+pub async fn migrate_hash_algorithm(state: State<'_, AppState>) -> Result<usize, AppError> {
+    state.require_admin()?;
+
+    let admin = AdminClient::new().map_err(AppError::InternalError)?;
+    admin.migrate_hash_algorithm().await.map_err(AppError::InternalError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync_engine::SyncDirection;
+
+    #[test]
+    fn test_key_expiry_warning() {
+        let now = 1_700_000_000;
+        assert_eq!(days_until(now + 45 * 86400, now), 45);
+        assert_eq!(days_until(now + 86400 - 1, now), 0);
+        assert_eq!(days_until(now - 1, now), -1);
+
+        assert!(key_expiry_warning(45).is_none());
+        assert!(key_expiry_warning(30).unwrap().contains("30 days"));
+        assert!(key_expiry_warning(0).unwrap().contains("today"));
+        assert!(key_expiry_warning(-3).is_none());
+    }
+
+    #[test]
+    fn test_expired_key_is_refused() {
+        let mut payload = KeyPayload::new("Test User");
+        assert!(key_rejection(&payload).is_none());
+
+        payload.expires_at = Some(chrono::Utc::now().timestamp() + 86400);
+        assert!(key_rejection(&payload).is_none());
+
+        payload.expires_at = Some(chrono::Utc::now().timestamp() - 3 * 86400);
+        assert!(key_rejection(&payload).unwrap().contains("expired"));
+    }
+
+    #[test]
+    fn test_stale_preview_is_not_reused() {
+        let folders = vec!["/home/user/docs".to_string()];
+        let mut preview = PendingPreview {
+            source_paths: folders.clone(),
+            files: Vec::new(),
+            created_at: Instant::now(),
+        };
+        assert!(preview.usable_for(&folders));
+        assert!(!preview.usable_for(&["/home/user/photos".to_string()]));
+
+        preview.created_at = Instant::now() - PREVIEW_MAX_AGE;
+        assert!(!preview.usable_for(&folders));
+    }
+
+    /// Fresh directory under the system temp dir, with a `root/docs` subfolder
+    fn temp_tree(name: &str) -> PathBuf {
+        let base = std::env::temp_dir().join(format!("sync2bucket-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        std::fs::create_dir_all(base.join("root/docs")).unwrap();
+        base
+    }
+
+    #[test]
+    fn test_validate_user_path_rejects_traversal() {
+        let base = temp_tree("traversal");
+        let roots = vec![base.join("root")];
+
+        assert!(validate_user_path(&base.join("root/docs"), &roots).is_ok());
+        // Download targets may not exist yet
+        assert!(validate_user_path(&base.join("root/docs/new/folder"), &roots).is_ok());
+
+        for path in [
+            base.join("root/../../../etc"),
+            base.join("root/docs/../.."),
+            base.join("root/missing/../../outside"),
+            PathBuf::from("/etc"),
+            base.clone(),
+        ] {
+            assert!(
+                matches!(validate_user_path(&path, &roots), Err(AppError::InvalidPath(_))),
+                "{}",
+                path.display()
+            );
+        }
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn test_validate_source_paths() {
+        let base = temp_tree("source-paths");
+        let docs = base.join("root/docs").display().to_string();
+        let config = AppConfig {
+            allowed_source_roots: vec![base.join("root")],
+            max_source_paths: 2,
+            ..Default::default()
+        };
+
+        assert!(validate_source_paths(&[docs.clone(), docs.clone()], &config).is_ok());
+        assert_eq!(
+            validate_source_paths(&vec![docs.clone(); 3], &config),
+            Err(AppError::TooManyPaths { count: 3, max: 2 })
+        );
+
+        let long = format!("{}/{}", docs, "a".repeat(MAX_NAME_BYTES + 1));
+        assert_eq!(
+            validate_source_paths(std::slice::from_ref(&long), &config),
+            Err(AppError::PathTooLong { path: long })
+        );
+        // Only each name is limited, not the whole path
+        let deep = format!("{}/{}", docs, vec!["a".repeat(MAX_NAME_BYTES); 2].join("/"));
+        assert!(validate_path_length(&deep).is_ok());
+
+        assert!(matches!(validate_target_path(""), Err(AppError::InvalidRequest(_))));
+        assert!(matches!(validate_target_path("  "), Err(AppError::InvalidRequest(_))));
+        assert!(validate_target_path(&docs).is_ok());
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_check_sync_size() {
+        let base = temp_tree("sync-size");
+        let docs = base.join("root/docs");
+        std::fs::write(docs.join("a.bin"), vec![0u8; 600]).unwrap();
+        std::fs::create_dir(docs.join("nested")).unwrap();
+        std::fs::write(docs.join("nested/b.bin"), vec![0u8; 400]).unwrap();
+
+        let paths = vec![docs];
+        assert_eq!(local_size(&paths), 1000);
+        assert!(check_sync_size(&paths, None).await.is_ok());
+        assert!(check_sync_size(&paths, Some(1000)).await.is_ok());
+        assert_eq!(
+            check_sync_size(&paths, Some(999)).await,
+            Err(AppError::SyncTooLarge { total_bytes: 1000, limit: 999 })
+        );
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn test_validate_remote_path() {
+        assert!(validate_remote_path("photos/2024").is_ok());
+        assert_eq!(
+            validate_remote_path("_admin/whitelist.json"),
+            Err(AppError::InvalidPath("_admin/whitelist.json".to_string()))
+        );
+        assert!(validate_remote_path("/_admin").is_err());
+        assert!(validate_remote_path("photos/../../other-user").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_restores_sync_engine() {
+        let state = AppState::new();
+        *state.key_payload.write().await = Some(KeyPayload::new("Test User"));
+        *state.current_key.write().await = Some("KEY".to_string());
+        assert!(!state.is_connected().await);
+
+        state.reconnect(|_| {}, |_| {}, |_, _| {}).await.unwrap();
+
+        assert!(state.is_connected().await);
+        // The session itself is left alone
+        assert!(state.key_payload.read().await.is_some());
+        assert_eq!(state.current_key.read().await.as_deref(), Some("KEY"));
+    }
+
+    #[tokio::test]
+    async fn test_discard_refuses_other_users_session() {
+        let base = temp_tree("discard");
+        let mut state = AppState::new();
+        state.config.data_dir = Some(base.clone());
+        let cache = SyncCache::new().with_sessions_dir(state.config.sessions_dir());
+        let owner = KeyPayload::new("Owner");
+        let other = KeyPayload::new("Other");
+        let session = |payload: &KeyPayload| {
+            let session = SyncSession {
+                session_id: format!("{}-upload", payload.name),
+                uid: Some(payload.uid.clone()),
+                progress: SyncProgress::default(),
+                source_paths: vec![base.join("root/docs")],
+                rendered_template: None,
+                direction: SyncDirection::LocalToCloud,
+                transferred_keys: Vec::new(),
+            };
+            session.save(&cache.session_file(&session.session_id).unwrap()).unwrap();
+            session
+        };
+        let owners = session(&owner);
+        let others = session(&other);
+        let saved = |session: &SyncSession| cache.session_file(&session.session_id).unwrap().exists();
+
+        // Nobody logged in
+        *state.interrupted_sync.write().await = Some(owners.clone());
+        assert_eq!(state.discard_interrupted_sync().await, Err(AppError::NotAuthenticated));
+
+        // Someone else's upload is left for them to resume
+        *state.key_payload.write().await = Some(other.clone());
+        assert_eq!(state.discard_interrupted_sync().await, Err(AppError::ForeignSession));
+        assert!(state.interrupted_sync.read().await.is_some());
+        assert!(saved(&owners) && saved(&others));
+
+        // The owner's discard deletes only their own session
+        *state.key_payload.write().await = Some(owner.clone());
+        state.discard_interrupted_sync().await.unwrap();
+        assert!(state.interrupted_sync.read().await.is_none());
+        assert!(!saved(&owners));
+        assert!(saved(&others));
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_only_mode_blocks_writes() {
+        let read_only = |result: Result<(), AppError>| {
+            matches!(result, Err(AppError::InvalidRequest(message)) if message.contains("Read-only mode"))
+        };
+        let state = AppState::new();
+        assert!(state.ensure_writable().await.is_ok());
+
+        state.read_only_mode.store(true, Ordering::Release);
+        assert!(read_only(state.ensure_writable().await));
+        state.read_only_mode.store(false, Ordering::Release);
+        state.sync_config.write().await.read_only = true;
+        assert!(read_only(state.ensure_writable().await));
+        state.sync_config.write().await.read_only = false;
+
+        // Forced by the administrators' policy, which the engine checks for every write
+        *state.key_payload.write().await = Some(KeyPayload::new("Test User"));
+        state.reconnect(|_| {}, |_| {}, |_, _| {}).await.unwrap();
+        let policy = SyncPolicy { force_read_only: true, ..Default::default() };
+        *state.sync_policy.write().await = Some((policy, std::time::Instant::now()));
+        assert!(read_only(state.ensure_writable().await));
+
+        let engine = state.sync_engine.read().await.clone().unwrap();
+        assert!(matches!(engine.sync_to_cloud(&[], Vec::new()).await, Err(SyncError::ReadOnlyMode)));
+        assert!(matches!(engine.sync_cloud_to_cloud("a", "b").await, Err(SyncError::ReadOnlyMode)));
+        assert!(matches!(engine.delete_all_objects().await, Err(SyncError::ReadOnlyMode)));
+    }
+
+    #[tokio::test]
+    async fn test_logout_cancels_sync_before_clearing_state() {
+        let state = AppState::new();
+        *state.key_payload.write().await = Some(KeyPayload::new("Test User"));
+        state.reconnect(|_| {}, |_| {}, |_, _| {}).await.unwrap();
+
+        // Stands in for the handle a background sync task keeps
+        let engine = state.sync_engine.read().await.clone().unwrap();
+        assert!(!engine.is_cancelled());
+
+        state.end_session(false).await;
+
+        assert!(engine.is_cancelled());
+        assert!(!state.is_connected().await);
+        assert!(state.key_payload.read().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_admin_commands_require_admin_key() {
+        let state = AppState::new();
+        let engine = || Arc::new(SyncEngine::new(S3ClientBuilder::new().unscoped().build().unwrap()));
+
+        let user = KeyPayload::new("Regular User");
+        state.begin_session("USER-KEY".to_string(), user, engine()).await;
+        assert_eq!(state.require_admin(), Err(AppError::PermissionDenied));
+
+        let mut admin = KeyPayload::new("Admin User");
+        admin.permissions |= KeyPermissions::ADMIN;
+        state.begin_session("ADMIN-KEY".to_string(), admin, engine()).await;
+        assert_eq!(state.require_admin(), Ok(()));
+
+        // Admin scope ends with the session
+        state.end_session(true).await;
+        assert_eq!(state.require_admin(), Err(AppError::PermissionDenied));
+    }
+
+    #[tokio::test]
+    async fn test_commands_require_key_permissions() {
+        let state = AppState::new();
+        assert_eq!(state.require_permission(KeyPermissions::UPLOAD).await, Err(AppError::NotAuthenticated));
+
+        let mut payload = KeyPayload::new("Download Only");
+        payload.permissions = KeyPermissions::DOWNLOAD;
+        *state.key_payload.write().await = Some(payload.clone());
+        assert_eq!(state.require_permission(KeyPermissions::DOWNLOAD).await, Ok(()));
+        assert_eq!(state.require_permission(KeyPermissions::UPLOAD).await, Err(AppError::PermissionDenied));
+        assert_eq!(state.require_permission(KeyPermissions::DELETE).await, Err(AppError::PermissionDenied));
+
+        // A key that expires during the session stops working
+        payload.expires_at = Some(chrono::Utc::now().timestamp() - 60);
+        *state.key_payload.write().await = Some(payload.clone());
+        assert_eq!(state.require_permission(KeyPermissions::DOWNLOAD).await, Err(AppError::CredentialsExpired));
+
+        // Nor can a key without permissions log in
+        payload.expires_at = None;
+        payload.permissions = KeyPermissions::empty();
+        assert!(key_rejection(&payload).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_requires_login() {
+        let state = AppState::new();
+        assert_eq!(state.reconnect(|_| {}, |_| {}, |_, _| {}).await, Err(AppError::NotAuthenticated));
+        assert!(!state.is_connected().await);
+    }
+}
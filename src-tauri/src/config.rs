@@ -0,0 +1,163 @@
+//! Application-level configuration shared across commands
+
+use crate::s3_client::{RetryPolicy, S3ClientConfig, S3Destination, S3ProviderConfig, StorageClass};
+use crate::secrets;
+use std::path::PathBuf;
+
+// S3 access and secret keys must be longer than this
+const MIN_S3_KEY_LEN: usize = 10;
+// AES-256 key length
+const MASTER_KEY_LEN: usize = 32;
+// Values in secrets.example.rs start with this and must be replaced
+const SECRET_PLACEHOLDER_PREFIX: &str = "YOUR_";
+// Folders one upload may be started with
+const DEFAULT_MAX_SOURCE_PATHS: usize = 50;
+// Oldest local copy of the admin lists used when S3 can't be reached
+const DEFAULT_MAX_CACHE_AGE_SECS: u64 = 3600;
+
+/// App-wide settings, fixed for the lifetime of the process
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    // S3 client settings
+    pub provider: S3ProviderConfig,
+    pub retry_policy: RetryPolicy,
+    pub storage_class: StorageClass,
+    pub max_concurrency: usize,
+    /// Buckets uploads and deletions are mirrored to, as backups of the primary
+    pub secondary_destinations: Vec<S3Destination>,
+    /// Local folders uploads may read from; defaults to the user's home directory
+    pub allowed_source_roots: Vec<PathBuf>,
+    /// Where the app keeps files between runs, such as an interrupted upload
+    pub data_dir: Option<PathBuf>,
+    /// Most folders one upload may be started with
+    pub max_source_paths: usize,
+    /// Largest total size, in bytes, of the folders one upload may be started with
+    pub max_single_sync_bytes: Option<u64>,
+    /// Local copy of the whitelist and blacklist, checked at login when S3 can't be reached
+    pub admin_cache_path: Option<PathBuf>,
+    /// Oldest that local copy may be, in seconds, and still be used
+    pub max_cache_age_secs: u64,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        let s3 = S3ClientConfig::default();
+        Self {
+            provider: s3.provider,
+            retry_policy: s3.retry_policy,
+            storage_class: s3.storage_class,
+            max_concurrency: s3.max_concurrency,
+            secondary_destinations: Vec::new(),
+            allowed_source_roots: home_dir().into_iter().collect(),
+            data_dir: data_dir(),
+            max_source_paths: DEFAULT_MAX_SOURCE_PATHS,
+            max_single_sync_bytes: None,
+            admin_cache_path: data_dir().map(|dir| dir.join("admin_cache.json")),
+            max_cache_age_secs: DEFAULT_MAX_CACHE_AGE_SECS,
+        }
+    }
+}
+
+impl AppConfig {
+    /// Folder upload sessions are saved to, so an interrupted one can be resumed after a restart
+    pub fn sessions_dir(&self) -> Option<PathBuf> {
+        self.data_dir.as_ref().map(|dir| dir.join("sessions"))
+    }
+}
+
+/// Check the secrets compiled in from secrets.rs and the S3 endpoint, so a missing
+/// or malformed value is reported at startup instead of failing deep inside a request
+pub fn validate_secrets(endpoint: &str) -> Result<(), String> {
+    check_secrets(
+        secrets::S3_ACCESS_KEY,
+        secrets::S3_SECRET_KEY,
+        secrets::MASTER_ENCRYPTION_KEY,
+        endpoint,
+    )
+}
+
+fn check_secrets(access_key: &str, secret_key: &str, master_key: &[u8], endpoint: &str) -> Result<(), String> {
+    for (name, value) in [("S3_ACCESS_KEY", access_key.as_bytes()), ("S3_SECRET_KEY", secret_key.as_bytes())] {
+        if value.len() <= MIN_S3_KEY_LEN {
+            return Err(format!("{} must be longer than {} characters", name, MIN_S3_KEY_LEN));
+        }
+    }
+    if master_key.len() != MASTER_KEY_LEN {
+        return Err(format!(
+            "MASTER_ENCRYPTION_KEY must be exactly {} bytes, not {}",
+            MASTER_KEY_LEN,
+            master_key.len()
+        ));
+    }
+    for (name, value) in [
+        ("S3_ACCESS_KEY", access_key.as_bytes()),
+        ("S3_SECRET_KEY", secret_key.as_bytes()),
+        ("MASTER_ENCRYPTION_KEY", master_key),
+    ] {
+        if value.starts_with(SECRET_PLACEHOLDER_PREFIX.as_bytes()) {
+            return Err(format!("{} still has its placeholder value from secrets.example.rs", name));
+        }
+    }
+
+    let url = url::Url::parse(endpoint).map_err(|e| format!("S3 endpoint {:?} is not a valid URL: {}", endpoint, e))?;
+    if !matches!(url.scheme(), "http" | "https") || url.host().is_none() {
+        return Err(format!("S3 endpoint {:?} must be an http(s) URL with a host", endpoint));
+    }
+    Ok(())
+}
+
+/// Per-user application data directory for this app
+pub fn data_dir() -> Option<PathBuf> {
+    let base = if cfg!(target_os = "windows") {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home_dir().map(|home| home.join("Library/Application Support"))
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| home_dir().map(|home| home.join(".local/share")))
+    };
+    base.map(|dir| dir.join("sync2bucket"))
+}
+
+/// The current user's home directory
+pub fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod example {
+        include!("secrets.example.rs");
+    }
+
+    const ENDPOINT: &str = "https://s3.nl-ams.scw.cloud";
+
+    #[test]
+    fn test_check_secrets() {
+        let master_key = [7u8; 32];
+        assert!(check_secrets("SCWABCDEFGHIJKL", "0123456789abcdef", &master_key, ENDPOINT).is_ok());
+
+        // The template's placeholders are rejected
+        let result = check_secrets(example::S3_ACCESS_KEY, example::S3_SECRET_KEY, example::MASTER_ENCRYPTION_KEY, ENDPOINT);
+        assert!(result.unwrap_err().contains("S3_ACCESS_KEY"));
+        let result = check_secrets("SCWABCDEFGHIJKL", "0123456789abcdef", example::MASTER_ENCRYPTION_KEY, ENDPOINT);
+        assert!(result.unwrap_err().contains("MASTER_ENCRYPTION_KEY"));
+
+        assert!(check_secrets("short", "0123456789abcdef", &master_key, ENDPOINT).unwrap_err().contains("S3_ACCESS_KEY"));
+        assert!(check_secrets("SCWABCDEFGHIJKL", "", &master_key, ENDPOINT).unwrap_err().contains("S3_SECRET_KEY"));
+        let result = check_secrets("SCWABCDEFGHIJKL", "0123456789abcdef", &master_key[..31], ENDPOINT);
+        assert!(result.unwrap_err().contains("exactly 32 bytes, not 31"));
+
+        for endpoint in ["", "s3.nl-ams.scw.cloud", "ftp://s3.example.com", "https://"] {
+            assert!(check_secrets("SCWABCDEFGHIJKL", "0123456789abcdef", &master_key, endpoint).is_err(), "{}", endpoint);
+        }
+        assert!(check_secrets("SCWABCDEFGHIJKL", "0123456789abcdef", &master_key, "http://localhost:9000").is_ok());
+    }
+}
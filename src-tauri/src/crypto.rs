@@ -3,12 +3,31 @@ use aes_gcm::{
     Aes256Gcm, Nonce,
 };
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bitflags::bitflags;
+use hkdf::Hkdf;
+use ipnetwork::IpNetwork;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
 use thiserror::Error;
 
 use crate::secrets;
 
+// Every key starts with this
+const KEY_PREFIX: &str = "EXAD-";
+// AES-GCM nonce, stored in front of the ciphertext
+const NONCE_LEN: usize = 12;
+// AES-GCM authentication tag at the end of the ciphertext
+const TAG_LEN: usize = 16;
+// HKDF purpose of the SSE-C keys derived from the master key, followed by the UID
+const SSE_C_KEY_INFO: &str = "sync2bucket sse-c ";
+/// HKDF purpose of the subkey license keys are encrypted with
+pub const ENCRYPTION_PURPOSE: &str = "user_key_encryption_v1";
+/// Fewest bytes a key's decoded payload can have: a nonce and an authentication tag
+pub const MIN_PAYLOAD_LEN: usize = NONCE_LEN + TAG_LEN;
+
 #[derive(Debug, Error)]
 pub enum CryptoError {
     #[error("Invalid key format")]
@@ -21,11 +40,79 @@ pub enum CryptoError {
     InvalidPayload,
 }
 
+/// What a key is allowed to do, stored as a bitmask in the payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct KeyPermissions(u32);
+
+bitflags! {
+    impl KeyPermissions: u32 {
+        const UPLOAD = 0x01;
+        const DOWNLOAD = 0x02;
+        const DELETE = 0x04;
+        /// Admin commands: usage stats, purging users, server-side copies
+        const ADMIN = 0x10;
+    }
+}
+
+impl Default for KeyPermissions {
+    /// Regular user keys can upload, download and delete their own files
+    fn default() -> Self {
+        Self::UPLOAD | Self::DOWNLOAD | Self::DELETE
+    }
+}
+
+impl fmt::Display for KeyPermissions {
+    /// Flag names joined with " | ", or "none"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("none");
+        }
+        bitflags::parser::to_writer(self, f)
+    }
+}
+
+impl FromStr for KeyPermissions {
+    type Err = String;
+
+    /// Parse a list of flag names like "upload|download" (case-insensitive)
+    /// An empty string gives the default permissions
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().is_empty() {
+            return Ok(Self::default());
+        }
+
+        let mut permissions = Self::empty();
+        for name in s.split(['|', ';', '+', ' ']).filter(|n| !n.is_empty()) {
+            let flag = Self::from_name(&name.to_uppercase())
+                .ok_or_else(|| format!("Unknown permission: {}", name))?;
+            permissions |= flag;
+        }
+        Ok(permissions)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeyPayload {
     pub uid: String,
     pub name: String,
     pub created: i64,
+    /// Unix timestamp after which the key is refused at login, and by the
+    /// upload, download and delete commands of a session already running
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    /// Storage quota for the user's folder; an upload that would go past it
+    /// stops with `QuotaExceeded`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_storage_gb: Option<u64>,
+    /// What the key may do; the upload, download and delete commands each need
+    /// their flag, and a key with none can't log in
+    #[serde(default)]
+    pub permissions: KeyPermissions,
+    /// CIDR ranges the key may be used from (e.g. "192.168.1.0/24"); checked
+    /// against local interface addresses, so a VPN can get around it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_ip_ranges: Option<Vec<String>>,
 }
 
 impl KeyPayload {
@@ -35,6 +122,10 @@ impl KeyPayload {
             uid,
             name: name.to_string(),
             created: chrono::Utc::now().timestamp(),
+            expires_at: None,
+            max_storage_gb: None,
+            permissions: KeyPermissions::default(),
+            allowed_ip_ranges: None,
         }
     }
 
@@ -42,6 +133,49 @@ impl KeyPayload {
     pub fn folder_prefix(&self) -> String {
         format!("users/{}/", self.uid)
     }
+
+    /// `max_storage_gb` in bytes
+    pub fn max_storage_bytes(&self) -> Option<u64> {
+        self.max_storage_gb.map(|gb| gb.saturating_mul(1024 * 1024 * 1024))
+    }
+
+    /// Whether the key's expiry date has passed
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .map(|t| t <= chrono::Utc::now().timestamp())
+            .unwrap_or(false)
+    }
+
+    /// Whether any of `ips` falls in the allowed ranges; keys without ranges
+    /// are allowed from anywhere
+    pub fn allows_ips(&self, ips: &[IpAddr]) -> bool {
+        let ranges = match &self.allowed_ip_ranges {
+            Some(ranges) if !ranges.is_empty() => ranges,
+            _ => return true,
+        };
+        ranges
+            .iter()
+            .filter_map(|range| range.trim().parse::<IpNetwork>().ok())
+            .any(|network| ips.iter().any(|ip| network.contains(*ip)))
+    }
+}
+
+/// Parse a comma-separated list of CIDR ranges like "10.0.0.0/8,192.168.0.0/16"
+pub fn parse_ip_ranges(s: &str) -> Result<Vec<String>, String> {
+    let ranges: Vec<String> = s
+        .split(',')
+        .map(str::trim)
+        .filter(|r| !r.is_empty())
+        .map(|r| {
+            r.parse::<IpNetwork>()
+                .map(|network| network.to_string())
+                .map_err(|e| format!("Invalid IP range {}: {}", r, e))
+        })
+        .collect::<Result<_, _>>()?;
+    if ranges.is_empty() {
+        return Err("No IP ranges given".to_string());
+    }
+    Ok(ranges)
 }
 
 /// Generate a unique ID from a name
@@ -49,19 +183,51 @@ fn generate_uid(name: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(name.as_bytes());
     hasher.update(chrono::Utc::now().timestamp().to_le_bytes());
+    // Add some randomness so keys generated in the same second differ
+    hasher.update(rand::random::<[u8; 16]>());
     let result = hasher.finalize();
     format!("u_{}", hex::encode(&result[..8]))
 }
 
+/// Short, human-comparable fingerprint of a key (e.g. "3f2a:91bc:04de:77e1")
+///
+/// Keys are ASCII, but the payload inside may hold Unicode (e.g. a `KeyPayload::name`
+/// in Japanese); that is fine since the payload is stored as JSON, which keeps it as UTF-8.
+pub fn key_fingerprint(key: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(key.as_bytes());
+    let digest = hex::encode(&hasher.finalize()[..8]);
+    digest
+        .as_bytes()
+        .chunks(4)
+        .map(|c| std::str::from_utf8(c).unwrap_or(""))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Derive a 32-byte subkey of `master` for one `purpose` with HKDF-SHA256, so a
+/// leaked subkey exposes neither the master key nor the other subkeys
+pub fn derive_key(master: &[u8; 32], purpose: &str) -> [u8; 32] {
+    let hkdf = Hkdf::<Sha256>::new(None, master);
+    let mut okm = [0u8; 32];
+    hkdf.expand(purpose.as_bytes(), &mut okm)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    okm
+}
+
 /// Encrypt a KeyPayload into an EXAD-prefixed license key
 pub fn encrypt_key(payload: &KeyPayload) -> Result<String, CryptoError> {
+    encrypt_key_with(&derive_key(secrets::MASTER_ENCRYPTION_KEY, ENCRYPTION_PURPOSE), payload)
+}
+
+fn encrypt_key_with(encryption_key: &[u8; 32], payload: &KeyPayload) -> Result<String, CryptoError> {
     let json = serde_json::to_string(payload).map_err(|_| CryptoError::InvalidPayload)?;
     
     // Generate a random nonce (12 bytes for AES-GCM)
     let nonce_bytes: [u8; 12] = rand::random();
     let nonce = Nonce::from_slice(&nonce_bytes);
     
-    let cipher = Aes256Gcm::new_from_slice(secrets::MASTER_ENCRYPTION_KEY)
+    let cipher = Aes256Gcm::new_from_slice(encryption_key)
         .map_err(|_| CryptoError::EncryptionFailed)?;
     
     let ciphertext = cipher
@@ -73,58 +239,110 @@ pub fn encrypt_key(payload: &KeyPayload) -> Result<String, CryptoError> {
     combined.extend(ciphertext);
     
     let encoded = URL_SAFE_NO_PAD.encode(&combined);
-    Ok(format!("EXAD-{}", encoded))
+    Ok(format!("{}{}", KEY_PREFIX, encoded))
 }
 
 /// Decrypt an EXAD-prefixed license key into a KeyPayload
 pub fn decrypt_key(key: &str) -> Result<KeyPayload, CryptoError> {
     // Remove EXAD- prefix
     let encoded = key
-        .strip_prefix("EXAD-")
+        .strip_prefix(KEY_PREFIX)
         .ok_or(CryptoError::InvalidFormat)?;
     
     let combined = URL_SAFE_NO_PAD
         .decode(encoded)
         .map_err(|_| CryptoError::InvalidFormat)?;
     
-    if combined.len() < 13 {
+    if combined.len() <= NONCE_LEN {
         return Err(CryptoError::InvalidFormat);
     }
     
-    let (nonce_bytes, ciphertext) = combined.split_at(12);
-    let nonce = Nonce::from_slice(nonce_bytes);
-    
-    let cipher = Aes256Gcm::new_from_slice(secrets::MASTER_ENCRYPTION_KEY)
-        .map_err(|_| CryptoError::DecryptionFailed)?;
-    
-    let plaintext = cipher
-        .decrypt(nonce, ciphertext)
-        .map_err(|_| CryptoError::DecryptionFailed)?;
+    let (nonce_bytes, ciphertext) = combined.split_at(NONCE_LEN);
+    let encryption_key = derive_key(secrets::MASTER_ENCRYPTION_KEY, ENCRYPTION_PURPOSE);
+    // Keys issued before the subkey was introduced are encrypted with the master key itself
+    let plaintext = decrypt_with(&encryption_key, nonce_bytes, ciphertext)
+        .or_else(|_| decrypt_with(secrets::MASTER_ENCRYPTION_KEY, nonce_bytes, ciphertext))?;
     
     let json = String::from_utf8(plaintext).map_err(|_| CryptoError::DecryptionFailed)?;
     
     serde_json::from_str(&json).map_err(|_| CryptoError::InvalidPayload)
 }
 
+fn decrypt_with(encryption_key: &[u8; 32], nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let cipher = Aes256Gcm::new_from_slice(encryption_key)
+        .map_err(|_| CryptoError::DecryptionFailed)?;
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| CryptoError::DecryptionFailed)
+}
+
+/// Key a user's objects are encrypted with server-side (SSE-C), derived from the
+/// master key and their UID so it is the same on every machine and never stored
+pub fn derive_sse_c_key(uid: &str) -> [u8; 32] {
+    derive_key(secrets::MASTER_ENCRYPTION_KEY, &format!("{}{}", SSE_C_KEY_INFO, uid))
+}
+
+/// What's wrong with the format of a key, from `validate_key_format_detailed`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum KeyFormatError {
+    #[error("valid key format")]
+    Valid,
+    #[error("not a key: it should start with {}", KEY_PREFIX)]
+    MissingPrefix,
+    #[error("nothing after {}", KEY_PREFIX)]
+    TooShort,
+    #[error("contains characters that can't be in a key; was it copied in full?")]
+    InvalidBase64Characters,
+    #[error("too short to hold a key; was it copied in full?")]
+    PayloadTooShort,
+}
+
 /// Validate a key without fully decrypting (just check format)
 pub fn validate_key_format(key: &str) -> bool {
-    if !key.starts_with("EXAD-") {
-        return false;
+    validate_key_format_detailed(key) == KeyFormatError::Valid
+}
+
+/// Like `validate_key_format`, saying what's wrong. Safe to call on any input.
+pub fn validate_key_format_detailed(key: &str) -> KeyFormatError {
+    match try_decode_payload_without_decrypt(key) {
+        Ok(_) => KeyFormatError::Valid,
+        Err(e) => e,
     }
-    
-    let encoded = match key.strip_prefix("EXAD-") {
-        Some(e) => e,
-        None => return false,
-    };
-    
-    // Check if it's valid base64
-    URL_SAFE_NO_PAD.decode(encoded).is_ok()
+}
+
+/// Decode a key's base64 without decrypting it, returning the payload's length in bytes
+pub fn try_decode_payload_without_decrypt(key: &str) -> Result<usize, KeyFormatError> {
+    let encoded = key.strip_prefix(KEY_PREFIX).ok_or(KeyFormatError::MissingPrefix)?;
+    if encoded.is_empty() {
+        return Err(KeyFormatError::TooShort);
+    }
+    let payload = URL_SAFE_NO_PAD
+        .decode(encoded)
+        .map_err(|_| KeyFormatError::InvalidBase64Characters)?;
+    if payload.len() < MIN_PAYLOAD_LEN {
+        return Err(KeyFormatError::PayloadTooShort);
+    }
+    Ok(payload.len())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_sse_c_key_is_per_user_and_stable() {
+        let key = derive_sse_c_key("u_alice");
+        assert_eq!(key, derive_sse_c_key("u_alice"));
+        assert_ne!(key, derive_sse_c_key("u_bob"));
+        assert_ne!(&key, secrets::MASTER_ENCRYPTION_KEY);
+
+        // The same key as before it was built on derive_key, so existing uploads still decrypt
+        let hkdf = Hkdf::<Sha256>::new(None, secrets::MASTER_ENCRYPTION_KEY);
+        let mut expected = [0u8; 32];
+        hkdf.expand_multi_info(&[SSE_C_KEY_INFO.as_bytes(), b"u_alice"], &mut expected).unwrap();
+        assert_eq!(key, expected);
+    }
+
     #[test]
     fn test_encrypt_decrypt_roundtrip() {
         let payload = KeyPayload::new("Test User");
@@ -137,10 +355,154 @@ mod tests {
         assert!(decrypted.uid.starts_with("u_"));
     }
 
+    #[test]
+    fn test_derived_keys_differ_by_purpose() {
+        let master = secrets::MASTER_ENCRYPTION_KEY;
+        let encryption = derive_key(master, ENCRYPTION_PURPOSE);
+        let other = derive_key(master, "user_key_other_v1");
+        assert_eq!(encryption.len(), 32);
+        assert_eq!(other.len(), 32);
+        assert_ne!(encryption, other);
+        assert_ne!(&encryption, master);
+        assert_eq!(encryption, derive_key(master, ENCRYPTION_PURPOSE));
+    }
+
+    #[test]
+    fn test_keys_encrypted_with_subkey() {
+        let payload = KeyPayload::new("Test User");
+        let (nonce, ciphertext) = {
+            let key = encrypt_key(&payload).unwrap();
+            let combined = URL_SAFE_NO_PAD.decode(key.strip_prefix(KEY_PREFIX).unwrap()).unwrap();
+            let (nonce, ciphertext) = combined.split_at(NONCE_LEN);
+            (nonce.to_vec(), ciphertext.to_vec())
+        };
+        let subkey = derive_key(secrets::MASTER_ENCRYPTION_KEY, ENCRYPTION_PURPOSE);
+        assert!(decrypt_with(&subkey, &nonce, &ciphertext).is_ok());
+        assert!(decrypt_with(secrets::MASTER_ENCRYPTION_KEY, &nonce, &ciphertext).is_err());
+
+        // Keys issued with the master key itself still work
+        let legacy = encrypt_key_with(secrets::MASTER_ENCRYPTION_KEY, &payload).unwrap();
+        assert_eq!(decrypt_key(&legacy).unwrap().uid, payload.uid);
+    }
+
     #[test]
     fn test_invalid_key() {
         assert!(decrypt_key("invalid").is_err());
         assert!(decrypt_key("EXAD-invalid").is_err());
     }
+
+    #[test]
+    fn test_validate_key_format_detailed() {
+        let key = encrypt_key(&KeyPayload::new("Test User")).unwrap();
+        assert_eq!(validate_key_format_detailed(&key), KeyFormatError::Valid);
+        assert!(validate_key_format(&key));
+        let payload_len = try_decode_payload_without_decrypt(&key).unwrap();
+        assert_eq!(payload_len, URL_SAFE_NO_PAD.decode(&key[KEY_PREFIX.len()..]).unwrap().len());
+
+        let short = format!("{}{}", KEY_PREFIX, URL_SAFE_NO_PAD.encode([0u8; MIN_PAYLOAD_LEN - 1]));
+        let cases = [
+            ("", KeyFormatError::MissingPrefix),
+            ("exad-abc", KeyFormatError::MissingPrefix),
+            ("EXAD-", KeyFormatError::TooShort),
+            ("EXAD-not base64!", KeyFormatError::InvalidBase64Characters),
+            ("EXAD-héllo", KeyFormatError::InvalidBase64Characters),
+            (short.as_str(), KeyFormatError::PayloadTooShort),
+        ];
+        for (key, expected) in cases {
+            assert_eq!(validate_key_format_detailed(key), expected, "{:?}", key);
+            assert_eq!(try_decode_payload_without_decrypt(key), Err(expected));
+            assert!(!validate_key_format(key));
+        }
+        let minimal = format!("{}{}", KEY_PREFIX, URL_SAFE_NO_PAD.encode([0u8; MIN_PAYLOAD_LEN]));
+        assert_eq!(try_decode_payload_without_decrypt(&minimal), Ok(MIN_PAYLOAD_LEN));
+    }
+
+    #[test]
+    fn test_payload_fields_roundtrip() {
+        let mut payload = KeyPayload::new("Quota User");
+        payload.expires_at = Some(chrono::Utc::now().timestamp() + 3600);
+        payload.max_storage_gb = Some(50);
+        payload.permissions = KeyPermissions::DOWNLOAD;
+
+        let decrypted = decrypt_key(&encrypt_key(&payload).unwrap()).unwrap();
+        assert_eq!(decrypted.expires_at, payload.expires_at);
+        assert_eq!(decrypted.max_storage_gb, Some(50));
+        assert_eq!(decrypted.permissions, KeyPermissions::DOWNLOAD);
+        assert!(!decrypted.is_expired());
+    }
+
+    #[test]
+    fn test_legacy_payload_defaults() {
+        let payload: KeyPayload =
+            serde_json::from_str(r#"{"uid":"u_1","name":"Old","created":0}"#).unwrap();
+        assert_eq!(payload.expires_at, None);
+        assert_eq!(payload.permissions, KeyPermissions::default());
+        assert_eq!(payload.allowed_ip_ranges, None);
+    }
+
+    #[test]
+    fn test_allows_ips() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let mut payload = KeyPayload::new("Office User");
+        assert!(payload.allows_ips(&[ip("203.0.113.7")]));
+
+        payload.allowed_ip_ranges = Some(vec!["192.168.1.0/24".into(), "10.1.2.3/32".into()]);
+        assert!(payload.allows_ips(&[ip("192.168.1.0")]));
+        assert!(payload.allows_ips(&[ip("192.168.1.255")]));
+        assert!(payload.allows_ips(&[ip("127.0.0.1"), ip("10.1.2.3")]));
+        assert!(!payload.allows_ips(&[ip("192.168.2.1")]));
+        assert!(!payload.allows_ips(&[ip("10.1.2.4")]));
+        assert!(!payload.allows_ips(&[ip("::ffff:192.168.1.5")]));
+        assert!(!payload.allows_ips(&[]));
+
+        payload.allowed_ip_ranges = Some(vec!["2001:db8::/32".into(), "not-a-range".into()]);
+        assert!(payload.allows_ips(&[ip("2001:db8:ffff::1")]));
+        assert!(!payload.allows_ips(&[ip("2001:db9::1"), ip("192.168.1.5")]));
+
+        payload.allowed_ip_ranges = Some(vec!["0.0.0.0/0".into()]);
+        assert!(payload.allows_ips(&[ip("8.8.8.8")]));
+        assert!(!payload.allows_ips(&[ip("fe80::1")]));
+
+        payload.allowed_ip_ranges = Some(vec![]);
+        assert!(payload.allows_ips(&[]));
+
+        let decrypted = decrypt_key(&encrypt_key(&payload).unwrap()).unwrap();
+        assert_eq!(decrypted.allowed_ip_ranges, Some(vec![]));
+    }
+
+    #[test]
+    fn test_parse_ip_ranges() {
+        assert_eq!(
+            parse_ip_ranges("10.0.0.0/8, 192.168.0.0/16,").unwrap(),
+            vec!["10.0.0.0/8", "192.168.0.0/16"]
+        );
+        assert_eq!(parse_ip_ranges("2001:db8::/48").unwrap(), vec!["2001:db8::/48"]);
+        assert_eq!(parse_ip_ranges("10.0.0.1").unwrap(), vec!["10.0.0.1/32"]);
+        assert!(parse_ip_ranges("10.0.0.0/33").is_err());
+        assert!(parse_ip_ranges("office").is_err());
+        assert!(parse_ip_ranges(" , ").is_err());
+    }
+
+    #[test]
+    fn test_parse_permissions() {
+        assert_eq!("".parse::<KeyPermissions>().unwrap(), KeyPermissions::default());
+        assert_eq!(
+            "upload|Download".parse::<KeyPermissions>().unwrap(),
+            KeyPermissions::UPLOAD | KeyPermissions::DOWNLOAD
+        );
+        assert!("upload|fly".parse::<KeyPermissions>().is_err());
+        assert_eq!(KeyPermissions::default().to_string(), "UPLOAD | DOWNLOAD | DELETE");
+        assert_eq!(KeyPermissions::empty().to_string(), "none");
+        assert!("upload|admin".parse::<KeyPermissions>().unwrap().contains(KeyPermissions::ADMIN));
+    }
+
+    #[test]
+    fn test_key_fingerprint() {
+        let fp = key_fingerprint("EXAD-abc");
+        assert_eq!(fp.len(), 19);
+        assert_eq!(fp.matches(':').count(), 3);
+        assert_eq!(fp, key_fingerprint("EXAD-abc"));
+        assert_ne!(fp, key_fingerprint("EXAD-abd"));
+    }
 }
 
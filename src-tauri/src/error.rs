@@ -0,0 +1,261 @@
+//! Error type returned by Tauri commands
+
+use crate::crypto::CryptoError;
+use crate::s3_client::S3Error;
+use crate::sync_engine::SyncError;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use thiserror::Error;
+
+/// Command failure the frontend can act on.
+/// Serialized as `{ "type": "InvalidPath", "message": "...", "detail": "..." }`;
+/// `QuotaExceeded` carries `used` and `limit` instead of `detail`, `DiskFull`
+/// `path`, `required_bytes` and `available_bytes`.
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum AppError {
+    #[error("Not authenticated")]
+    NotAuthenticated,
+    #[error("Network error: {0}")]
+    NetworkError(String),
+    #[error("Path not allowed: {0}")]
+    InvalidPath(String),
+    #[error("Storage quota exceeded: {used} of {limit} bytes used")]
+    QuotaExceeded { used: u64, limit: u64 },
+    #[error("API credentials have expired. Please contact your administrator to renew access.")]
+    CredentialsExpired,
+    #[error("Sync cancelled")]
+    Cancelled,
+    /// The logged-in key doesn't carry the permission a command needs
+    #[error("Your key doesn't allow this")]
+    PermissionDenied,
+    /// The interrupted upload belongs to a different user
+    #[error("The interrupted upload was started by another user")]
+    ForeignSession,
+    /// A local file or folder can't be read or written
+    #[error("Permission denied: {0}")]
+    FileAccessDenied(String),
+    /// The byte counts are 0 when not known
+    #[error("Not enough disk space to write {path}")]
+    DiskFull { path: String, required_bytes: u64, available_bytes: u64 },
+    /// The request can't be carried out as asked, e.g. while a sync is running
+    #[error("{0}")]
+    InvalidRequest(String),
+    #[error("Too many folders selected: {count}, at most {max} allowed")]
+    TooManyPaths { count: usize, max: usize },
+    /// A file or folder name is longer than file systems allow
+    #[error("Path too long: {path}")]
+    PathTooLong { path: String },
+    #[error("Selected folders hold {total_bytes} bytes, more than the {limit} allowed in one sync")]
+    SyncTooLarge { total_bytes: u64, limit: u64 },
+    #[error("{0}")]
+    InternalError(String),
+}
+
+impl AppError {
+    /// Name of the variant, used as the `type` tag
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AppError::NotAuthenticated => "NotAuthenticated",
+            AppError::NetworkError(_) => "NetworkError",
+            AppError::InvalidPath(_) => "InvalidPath",
+            AppError::QuotaExceeded { .. } => "QuotaExceeded",
+            AppError::CredentialsExpired => "CredentialsExpired",
+            AppError::Cancelled => "Cancelled",
+            AppError::PermissionDenied => "PermissionDenied",
+            AppError::ForeignSession => "ForeignSession",
+            AppError::FileAccessDenied(_) => "FileAccessDenied",
+            AppError::DiskFull { .. } => "DiskFull",
+            AppError::InvalidRequest(_) => "InvalidRequest",
+            AppError::TooManyPaths { .. } => "TooManyPaths",
+            AppError::PathTooLong { .. } => "PathTooLong",
+            AppError::SyncTooLarge { .. } => "SyncTooLarge",
+            AppError::InternalError(_) => "InternalError",
+        }
+    }
+}
+
+impl Serialize for AppError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("type", self.kind())?;
+        map.serialize_entry("message", &self.to_string())?;
+        match self {
+            AppError::NetworkError(detail)
+            | AppError::InvalidPath(detail)
+            | AppError::FileAccessDenied(detail)
+            | AppError::InvalidRequest(detail)
+            | AppError::InternalError(detail) => map.serialize_entry("detail", detail)?,
+            AppError::QuotaExceeded { used, limit } => {
+                map.serialize_entry("used", used)?;
+                map.serialize_entry("limit", limit)?;
+            }
+            AppError::DiskFull { path, required_bytes, available_bytes } => {
+                map.serialize_entry("path", path)?;
+                map.serialize_entry("required_bytes", required_bytes)?;
+                map.serialize_entry("available_bytes", available_bytes)?;
+            }
+            AppError::TooManyPaths { count, max } => {
+                map.serialize_entry("count", count)?;
+                map.serialize_entry("max", max)?;
+            }
+            AppError::PathTooLong { path } => map.serialize_entry("path", path)?,
+            AppError::SyncTooLarge { total_bytes, limit } => {
+                map.serialize_entry("total_bytes", total_bytes)?;
+                map.serialize_entry("limit", limit)?;
+            }
+            AppError::NotAuthenticated
+            | AppError::CredentialsExpired
+            | AppError::Cancelled
+            | AppError::PermissionDenied
+            | AppError::ForeignSession => {}
+        }
+        map.end()
+    }
+}
+
+impl From<S3Error> for AppError {
+    fn from(e: S3Error) -> Self {
+        match e {
+            S3Error::CredentialsExpired(_) => AppError::CredentialsExpired,
+            S3Error::OperationFailed(_)
+            | S3Error::RequestRejected(_)
+            | S3Error::RateLimited { .. }
+            | S3Error::BucketNotFound(_) => {
+                AppError::NetworkError(e.to_string())
+            }
+            S3Error::FileNotFound(path) | S3Error::LocalFileGone { path } => AppError::InvalidPath(path),
+            S3Error::InvalidTag(_)
+            | S3Error::ConflictError { .. }
+            | S3Error::ObjectLocked(_)
+            | S3Error::PartTooSmall { .. }
+            | S3Error::InvalidAcl(_)
+            | S3Error::FileChangedDuringUpload { .. }
+            | S3Error::SseCMismatch(_) => {
+                AppError::InvalidRequest(e.to_string())
+            }
+            S3Error::IoError(_) | S3Error::InvalidConfiguration(_) => AppError::InternalError(e.to_string()),
+            S3Error::PermissionDenied(path) => AppError::FileAccessDenied(path),
+            S3Error::DiskFull { path, required_bytes } => AppError::DiskFull {
+                path,
+                required_bytes,
+                available_bytes: 0,
+            },
+        }
+    }
+}
+
+impl From<SyncError> for AppError {
+    fn from(e: SyncError) -> Self {
+        match e {
+            SyncError::S3Error(message) => AppError::NetworkError(message),
+            SyncError::IoError(_) => AppError::InternalError(e.to_string()),
+            SyncError::PermissionDenied { path } => AppError::FileAccessDenied(path),
+            SyncError::DiskFull { path, required_bytes, available_bytes } => AppError::DiskFull {
+                path,
+                required_bytes,
+                available_bytes,
+            },
+            SyncError::FileChanged { path } => AppError::InvalidRequest(format!("{} was modified during upload", path)),
+            SyncError::LocalFileGone { path } => AppError::InvalidPath(path),
+            SyncError::PolicyViolation { .. } | SyncError::InvalidTemplate(_) => AppError::InvalidRequest(e.to_string()),
+            SyncError::Cancelled => AppError::Cancelled,
+            SyncError::NoActiveSync | SyncError::ReadOnlyMode => AppError::InvalidRequest(e.to_string()),
+            SyncError::ForeignSession => AppError::ForeignSession,
+            SyncError::QuotaExceeded { used, limit } => AppError::QuotaExceeded { used, limit },
+        }
+    }
+}
+
+impl From<CryptoError> for AppError {
+    fn from(e: CryptoError) -> Self {
+        match e {
+            // A malformed or foreign key is bad input, not a bug
+            CryptoError::InvalidFormat | CryptoError::DecryptionFailed | CryptoError::InvalidPayload => {
+                AppError::InvalidRequest(e.to_string())
+            }
+            CryptoError::EncryptionFailed => AppError::InternalError(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(
+            AppError::from(S3Error::CredentialsExpired("2026-11-28".to_string())),
+            AppError::CredentialsExpired
+        );
+        assert!(matches!(
+            AppError::from(S3Error::OperationFailed("timeout".to_string())),
+            AppError::NetworkError(_)
+        ));
+        assert!(matches!(
+            AppError::from(S3Error::RateLimited { retry_after_secs: 5 }),
+            AppError::NetworkError(_)
+        ));
+        assert_eq!(
+            AppError::from(S3Error::FileNotFound("a.txt".to_string())),
+            AppError::InvalidPath("a.txt".to_string())
+        );
+        assert!(matches!(
+            AppError::from(S3Error::InvalidTag("too long".to_string())),
+            AppError::InvalidRequest(_)
+        ));
+
+        assert_eq!(AppError::from(SyncError::Cancelled), AppError::Cancelled);
+        assert_eq!(
+            AppError::from(SyncError::S3Error("boom".to_string())),
+            AppError::NetworkError("boom".to_string())
+        );
+        assert!(matches!(AppError::from(SyncError::IoError("disk".to_string())), AppError::InternalError(_)));
+        assert_eq!(
+            AppError::from(SyncError::PermissionDenied { path: "/a".to_string() }),
+            AppError::FileAccessDenied("/a".to_string())
+        );
+        assert_eq!(AppError::from(SyncError::ForeignSession), AppError::ForeignSession);
+        assert_eq!(
+            AppError::from(SyncError::QuotaExceeded { used: 12, limit: 10 }),
+            AppError::QuotaExceeded { used: 12, limit: 10 }
+        );
+        assert_eq!(
+            AppError::from(S3Error::DiskFull { path: "/b".to_string(), required_bytes: 7 }),
+            AppError::DiskFull { path: "/b".to_string(), required_bytes: 7, available_bytes: 0 }
+        );
+
+        assert!(matches!(AppError::from(CryptoError::InvalidFormat), AppError::InvalidRequest(_)));
+        assert!(matches!(AppError::from(CryptoError::EncryptionFailed), AppError::InternalError(_)));
+    }
+
+    #[test]
+    fn test_serializes_as_tagged_object() {
+        let json = serde_json::to_value(AppError::NotAuthenticated).unwrap();
+        assert_eq!(json, serde_json::json!({ "type": "NotAuthenticated", "message": "Not authenticated" }));
+
+        let json = serde_json::to_value(AppError::TooManyPaths { count: 80, max: 50 }).unwrap();
+        assert_eq!(json["type"], "TooManyPaths");
+        assert_eq!((json["count"].as_u64(), json["max"].as_u64()), (Some(80), Some(50)));
+
+        let json = serde_json::to_value(AppError::InvalidPath("/etc".to_string())).unwrap();
+        assert_eq!(json["type"], "InvalidPath");
+        assert_eq!(json["detail"], "/etc");
+        assert_eq!(json["message"], "Path not allowed: /etc");
+
+        let json = serde_json::to_value(AppError::QuotaExceeded { used: 12, limit: 10 }).unwrap();
+        assert_eq!(json["type"], "QuotaExceeded");
+        assert_eq!(json["used"], 12);
+        assert_eq!(json["limit"], 10);
+
+        let json = serde_json::to_value(AppError::DiskFull {
+            path: "/b".to_string(),
+            required_bytes: 7,
+            available_bytes: 3,
+        })
+        .unwrap();
+        assert_eq!(json["type"], "DiskFull");
+        assert_eq!(json["path"], "/b");
+        assert_eq!(json["required_bytes"], 7);
+        assert_eq!(json["available_bytes"], 3);
+    }
+}
@@ -1,35 +1,113 @@
-mod admin;
+pub mod admin;
 mod commands;
-mod crypto;
+mod config;
+pub mod crypto;
+mod error;
 mod keychain;
-mod s3_client;
+mod notifications;
+pub mod s3_client;
 mod secrets;
+mod sync_cache;
 mod sync_engine;
+mod sync_filter;
+mod sync_queue;
+mod time_source;
 
 use commands::AppState;
+use tauri::Manager;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    // Fail with a clear message now rather than a panic inside the first request
+    if let Err(e) = config::validate_secrets(&config::AppConfig::default().provider.endpoint) {
+        panic!("Invalid app configuration: {}", e);
+    }
+
     tauri::Builder::default()
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_shell::init())
+        .plugin(tauri_plugin_notification::init())
         .plugin(tauri_plugin_log::Builder::new().build())
         .manage(AppState::new())
+        .setup(|app| {
+            // Queued syncs run one at a time for as long as the app does
+            let handle = app.handle().clone();
+            let runner = app
+                .state::<AppState>()
+                .sync_queue
+                .run(move |job| commands::run_queued_job(handle.clone(), job));
+            tauri::async_runtime::spawn(runner);
+            Ok(())
+        })
+        .on_window_event(|window, event| {
+            if let tauri::WindowEvent::Destroyed = event {
+                window.state::<AppState>().sync_windows.unregister(window.label());
+            }
+        })
         .invoke_handler(tauri::generate_handler![
             commands::check_stored_key,
             commands::validate_key,
+            commands::refresh_connection,
+            commands::check_connection,
+            commands::check_connection_health,
             commands::get_user_info,
             commands::logout,
             commands::start_upload,
+            commands::start_multi_dest_upload,
+            commands::preview_upload,
+            commands::estimate_sync_cost,
+            commands::get_last_manifest,
+            commands::verify_folder_checksum,
             commands::start_download,
+            commands::queue_upload,
+            commands::queue_download,
+            commands::pause_queue,
+            commands::resume_queue,
+            commands::get_queue_status,
+            commands::copy_cloud_folder,
             commands::pause_sync,
             commands::resume_sync,
             commands::cancel_sync,
+            commands::register_for_sync_events,
+            commands::unregister_from_sync_events,
+            commands::confirm_sync,
+            commands::set_sync_config,
+            commands::set_read_only_mode,
             commands::get_sync_progress,
+            commands::get_last_sync_summary,
+            commands::get_sync_policy,
             commands::list_cloud_folders,
+            commands::list_cloud_folders_page,
+            commands::load_folder_size,
+            commands::list_cloud_folder_tree,
+            commands::list_cloud_files,
+            commands::add_secondary_destination,
+            commands::get_interrupted_sync,
+            commands::resume_last_upload_session,
+            commands::discard_interrupted_sync,
+            commands::get_storage_stats,
+            commands::invalidate_listing_cache,
+            commands::get_session_stats,
+            commands::reset_session_stats,
+            commands::get_file_transfer_timings,
             commands::delete_all_files,
+            commands::tag_cloud_file,
+            commands::get_cloud_file_tags,
+            commands::set_cloud_file_acl,
+            commands::verify_file_checksum,
             commands::check_credentials_status,
+            commands::purge_user_data,
+            commands::admin_get_stats,
+            commands::compact_activity_log,
+            commands::migrate_hash_algorithm,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                // Don't lose activity log entries still waiting for their batch
+                let state = app.state::<AppState>();
+                tauri::async_runtime::block_on(state.flush_activity_log());
+            }
+        });
 }
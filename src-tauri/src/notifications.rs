@@ -0,0 +1,148 @@
+//! Native OS notifications for sync completion and errors
+//! Backed by tauri-plugin-notification, which routes through the system
+//! notification center. On desktop the plugin can't set an importance or
+//! interruption level, so these are shown at the system default and Focus
+//! modes treat them like any other app's notifications.
+
+use tauri::{AppHandle, Runtime};
+use tauri_plugin_notification::NotificationExt;
+
+use crate::sync_engine::{SyncDirection, SyncError, SyncSummary};
+
+/// Where notifications are shown: the system notification center for an app
+pub trait Notifier {
+    fn show(&self, title: &str, body: &str);
+}
+
+impl<R: Runtime> Notifier for AppHandle<R> {
+    fn show(&self, title: &str, body: &str) {
+        if let Err(e) = self.notification().builder().title(title).body(body).show() {
+            log::warn!("Failed to show notification: {}", e);
+        }
+    }
+}
+
+/// Format a byte count for display (e.g. "1.5 GB")
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Notify the user that a sync finished successfully
+///
+/// `target_path` is the local download folder, shown in the body so the
+/// user knows where to find the files.
+pub fn notify_sync_complete(app: &impl Notifier, summary: &SyncSummary, target_path: Option<&str>) {
+    let title = match summary.direction {
+        SyncDirection::LocalToCloud => "Upload complete",
+        SyncDirection::CloudToLocal => "Download complete",
+        SyncDirection::CloudToCloud { .. } => "Copy complete",
+    };
+
+    let mut body = format!(
+        "{} files ({}) synced in {:.0}s",
+        summary.total_files,
+        format_bytes(summary.transferred_bytes),
+        summary.duration_secs,
+    );
+    if let Some(path) = target_path {
+        body.push_str(&format!("\nSaved to {}", path));
+    }
+
+    app.show(title, &body);
+}
+
+/// Notify the user that a sync failed
+pub fn notify_sync_error(app: &impl Notifier, error: &str) {
+    app.show("Sync failed", error);
+}
+
+/// Notify the user of how a sync ended; a cancelled sync was stopped by them,
+/// so it gets no notification
+pub fn notify_sync_result(app: &impl Notifier, result: &Result<SyncSummary, SyncError>, target_path: Option<&str>) {
+    match result {
+        Ok(summary) => notify_sync_complete(app, summary, target_path),
+        Err(SyncError::Cancelled) => {}
+        Err(e) => notify_sync_error(app, &e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::s3_client::S3ClientBuilder;
+    use crate::sync_engine::SyncEngine;
+    use std::sync::Mutex;
+
+    /// Keeps the notifications it is asked to show
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<(String, String)>>);
+
+    impl Notifier for Recorder {
+        fn show(&self, title: &str, body: &str) {
+            self.0.lock().unwrap().push((title.to_string(), body.to_string()));
+        }
+    }
+
+    impl Recorder {
+        fn shown(&self) -> Vec<(String, String)> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GB");
+    }
+
+    #[tokio::test]
+    async fn test_completed_sync_notifies() {
+        let dir = std::env::temp_dir().join(format!("sync2bucket-notify-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // An upload of an empty folder finishes without a request
+        let engine = SyncEngine::new(S3ClientBuilder::new().unscoped().build().unwrap());
+        let result = engine.sync_to_cloud(std::slice::from_ref(&dir), Vec::new()).await;
+        let recorder = Recorder::default();
+        notify_sync_result(&recorder, &result, None);
+        let shown = recorder.shown();
+        assert_eq!(shown.len(), 1);
+        assert_eq!(shown[0].0, "Upload complete");
+        assert!(shown[0].1.starts_with("0 files (0 B) synced in"), "{}", shown[0].1);
+
+        // Downloads say where the files went
+        let mut summary = result.unwrap();
+        summary.direction = SyncDirection::CloudToLocal;
+        let recorder = Recorder::default();
+        notify_sync_result(&recorder, &Ok(summary), Some("/home/user/Downloads"));
+        let shown = recorder.shown();
+        assert_eq!(shown[0].0, "Download complete");
+        assert!(shown[0].1.ends_with("\nSaved to /home/user/Downloads"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_sync_notifies_and_cancelled_does_not() {
+        let recorder = Recorder::default();
+        notify_sync_result(&recorder, &Err(SyncError::Cancelled), None);
+        assert!(recorder.shown().is_empty());
+
+        let error = SyncError::NoActiveSync;
+        notify_sync_result(&recorder, &Err(SyncError::NoActiveSync), None);
+        assert_eq!(recorder.shown(), [("Sync failed".to_string(), error.to_string())]);
+    }
+}
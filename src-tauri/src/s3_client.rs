@@ -1,23 +1,79 @@
-use rusoto_core::{Region, HttpClient};
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::{Client as RusotoClient, Region, HttpClient, RusotoError};
 use rusoto_credential::StaticProvider;
 use rusoto_s3::{
     S3Client as RusotoS3Client, S3,
-    GetObjectRequest, PutObjectRequest, ListObjectsV2Request,
-    HeadObjectRequest, DeleteObjectRequest,
+    GetObjectRequest, GetObjectError, PutObjectRequest, PutObjectError, ListObjectsV2Request,
+    HeadObjectRequest, HeadObjectError, HeadObjectOutput, DeleteObjectRequest, CopyObjectRequest,
+    PutObjectTaggingRequest, GetObjectTaggingRequest, Tagging, Tag,
+    GetObjectAclRequest, PutObjectAclRequest, Grant,
+    CreateMultipartUploadRequest, UploadPartRequest, CompleteMultipartUploadRequest,
+    AbortMultipartUploadRequest, CompletedMultipartUpload, CompletedPart, HeadBucketRequest,
+    GetBucketLocationRequest,
 };
+use chrono::{DateTime, Utc};
+use futures::{StreamExt, TryStreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::SeekFrom;
 use std::path::Path;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use futures::TryStreamExt;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt,
+    BufReader,
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use md5::{Digest, Md5};
+use sha2::Sha256;
 
+use crate::config::AppConfig;
 use crate::secrets;
 
-// Scaleway S3 Configuration
+// Scaleway S3 configuration, the default provider
 const S3_ENDPOINT: &str = "https://s3.nl-ams.scw.cloud";
 const S3_REGION: &str = "nl-ams";
 const S3_BUCKET: &str = "cloud-storage-exad";
 
+// Files larger than one part are uploaded with multipart upload
+pub const DEFAULT_MULTIPART_PART_SIZE: usize = 8 * 1024 * 1024;
+
+// S3 rejects parts other than the last below this size, and uploads of more parts
+pub const MIN_MULTIPART_PART_SIZE: usize = 5 * 1024 * 1024;
+pub const MAX_MULTIPART_PARTS: u64 = 10_000;
+// Largest part, and largest single PUT, which files up to one part are sent as
+pub const MAX_MULTIPART_PART_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+const _: () = assert!(DEFAULT_MULTIPART_PART_SIZE >= MIN_MULTIPART_PART_SIZE, "Part size must be >= 5 MiB");
+const _: () = assert!(DEFAULT_MULTIPART_PART_SIZE as u64 <= MAX_MULTIPART_PART_SIZE, "Part size must be <= 5 GiB");
+
+// S3 object tagging limits
+pub const MAX_TAGS_PER_OBJECT: usize = 10;
+const MAX_TAG_KEY_LEN: usize = 128;
+const MAX_TAG_VALUE_LEN: usize = 256;
+
+// Wait used when a 429 response has no usable Retry-After header
+const DEFAULT_RETRY_AFTER_SECS: u64 = 5;
+// Longest wait taken from a Retry-After header, so a bad value can't stall a sync
+const MAX_RETRY_AFTER_SECS: u64 = 5 * 60;
+
+// Base64 SHA-256 of single-PUT uploads, stored as `x-amz-meta-sha256` since rusoto
+// 0.48 predates the `x-amz-checksum-sha256` header
+const SHA256_METADATA_KEY: &str = "sha256";
+
+// Listings ask S3 to URL-encode keys, so any character survives the XML response
+const LIST_ENCODING_TYPE: &str = "url";
+
+// Grantee of the public canned ACLs
+const ALL_USERS_URI: &str = "http://acs.amazonaws.com/groups/global/AllUsers";
+
+// Downloads are streamed to disk in chunks of this size
+pub(crate) const DOWNLOAD_CHUNK_SIZE: usize = 256 * 1024;
+
 // Credentials expiration date (November 28, 2025 + 1 year = November 28, 2026)
 // Update this when renewing credentials
 const CREDENTIALS_EXPIRY_YEAR: i32 = 2026;
@@ -28,17 +84,684 @@ const CREDENTIALS_EXPIRY_DAY: u32 = 28;
 pub enum S3Error {
     #[error("S3 operation failed: {0}")]
     OperationFailed(String),
+    /// The store refused the request, e.g. as not allowed or malformed; sending it
+    /// again won't help
+    #[error("S3 request rejected: {0}")]
+    RequestRejected(String),
     #[error("File not found: {0}")]
     FileNotFound(String),
     #[error("IO error: {0}")]
     IoError(String),
     #[error("API credentials have expired. Please contact your administrator to renew access. Expiry date: {0}")]
     CredentialsExpired(String),
+    #[error("Rate limited by the server, retry in {retry_after_secs}s")]
+    RateLimited { retry_after_secs: u64 },
+    #[error("Invalid tag: {0}")]
+    InvalidTag(String),
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+    #[error("Not enough disk space to write {path} ({required_bytes} bytes)")]
+    DiskFull { path: String, required_bytes: u64 },
+    #[error("Bucket {0} does not exist")]
+    BucketNotFound(String),
+    #[error("Another upload already stored a different object here (ETag {existing_etag})")]
+    ConflictError { existing_etag: String },
+    #[error("{0} is locked and can't be changed or deleted until its retention period ends")]
+    ObjectLocked(String),
+    #[error("File modified during upload: {path}")]
+    FileChangedDuringUpload { path: String },
+    /// The local file was deleted before it could be uploaded
+    #[error("File no longer exists: {path}")]
+    LocalFileGone { path: String },
+    /// The store refused a multipart upload with `EntityTooSmall`
+    #[error("Upload parts of {size} bytes are too small, the minimum is {min} bytes")]
+    PartTooSmall { size: usize, min: usize },
+    #[error("Unknown ACL: {0}")]
+    InvalidAcl(String),
+    #[error("Invalid S3 client configuration: {0}")]
+    InvalidConfiguration(String),
+    /// The store refused a request's SSE-C key, usually because the object is stored without SSE-C
+    #[error("The SSE-C key doesn't apply to this object: {0}")]
+    SseCMismatch(String),
+}
+
+/// Map an error on local file `path`, keeping permission and disk space problems apart
+/// from other IO errors
+fn local_io_error(path: &Path, required_bytes: u64, e: std::io::Error) -> S3Error {
+    match e.kind() {
+        std::io::ErrorKind::PermissionDenied => S3Error::PermissionDenied(path.display().to_string()),
+        std::io::ErrorKind::StorageFull | std::io::ErrorKind::WriteZero => S3Error::DiskFull {
+            path: path.display().to_string(),
+            required_bytes,
+        },
+        _ => S3Error::IoError(e.to_string()),
+    }
+}
+
+/// Map an error opening a local file for upload, telling a deleted file apart
+fn upload_source_error(path: &Path, e: std::io::Error) -> S3Error {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => S3Error::LocalFileGone {
+            path: path.display().to_string(),
+        },
+        _ => local_io_error(path, 0, e),
+    }
+}
+
+/// How far the upload of one file has got
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProgressEvent {
+    pub bytes_sent: u64,
+    pub total_bytes: u64,
+    /// Since this file's upload started
+    pub elapsed_ms: u64,
+    pub bytes_per_second: f64,
+    /// None until there is a speed to estimate from
+    pub eta_secs: Option<u64>,
+}
+
+impl ProgressEvent {
+    pub fn new(bytes_sent: u64, total_bytes: u64, elapsed: Duration) -> Self {
+        let elapsed_ms = elapsed.as_millis() as u64;
+        let bytes_per_second = if elapsed_ms > 0 {
+            bytes_sent as f64 / (elapsed_ms as f64 / 1000.0)
+        } else {
+            0.0
+        };
+        let eta_secs = (bytes_per_second > 0.0)
+            .then(|| (total_bytes.saturating_sub(bytes_sent) as f64 / bytes_per_second).ceil() as u64);
+        Self {
+            bytes_sent,
+            total_bytes,
+            elapsed_ms,
+            bytes_per_second,
+            eta_secs,
+        }
+    }
+}
+
+/// Outcome of `download_file_conditional`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadResult {
+    Downloaded,
+    /// The object still has the ETag given, so the local file was left alone
+    NotModified,
+}
+
+/// An ETag as sent in `If-None-Match`, which needs it quoted
+fn quoted_etag(etag: &str) -> String {
+    format!("\"{}\"", etag.trim_matches('"'))
+}
+
+/// Turn the `(bytes_sent, total_bytes)` reports of an upload into `ProgressEvent`s,
+/// timed from now
+fn timed_progress(on_progress: impl Fn(ProgressEvent) + Send + 'static) -> impl Fn(u64, u64) + Send + 'static {
+    let started = Instant::now();
+    move |sent, total| on_progress(ProgressEvent::new(sent, total, started.elapsed()))
+}
+
+/// A local file's size and modification time, taken before reading it for upload
+struct FileStamp {
+    path: std::path::PathBuf,
+    size: u64,
+    modified: Option<std::time::SystemTime>,
+}
+
+impl FileStamp {
+    async fn of(path: &Path) -> Result<Self, S3Error> {
+        let metadata = tokio::fs::metadata(path)
+            .await
+            .map_err(|e| upload_source_error(path, e))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+
+    /// `FileChangedDuringUpload` if another process wrote to the file since the stamp
+    async fn check_unchanged(&self) -> Result<(), S3Error> {
+        let now = Self::of(&self.path).await?;
+        if now.size != self.size || now.modified != self.modified {
+            return Err(S3Error::FileChangedDuringUpload {
+                path: self.path.display().to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Map a rusoto error, recognising HTTP 429 responses as rate limiting and other
+/// 4xx responses as rejected requests. Server errors, including S3's 503 SlowDown,
+/// and requests that got no response stay `OperationFailed`, which is retried.
+fn map_rusoto_error<E: std::error::Error + 'static>(e: RusotoError<E>) -> S3Error {
+    match e {
+        RusotoError::Unknown(ref response) if response.status.as_u16() == 429 => {
+            let retry_after = response.headers.get("retry-after").map(String::as_str);
+            S3Error::RateLimited {
+                retry_after_secs: parse_retry_after(retry_after),
+            }
+        }
+        RusotoError::Unknown(ref response) if response.status.is_client_error() => {
+            S3Error::RequestRejected(e.to_string())
+        }
+        // Typed errors are all refusals like NoSuchKey, and the others fail before sending
+        RusotoError::Service(_) | RusotoError::Validation(_) | RusotoError::Credentials(_) => {
+            S3Error::RequestRejected(e.to_string())
+        }
+        e => S3Error::OperationFailed(e.to_string()),
+    }
+}
+
+/// Whether a multipart upload was refused for a part below the minimum size. Rusoto
+/// has no typed error for it, so it arrives as an unparsed response.
+fn is_entity_too_small<E: std::error::Error>(e: &RusotoError<E>) -> bool {
+    match e {
+        RusotoError::Unknown(response) => error_code(response.body_as_str()) == Some("EntityTooSmall"),
+        _ => false,
+    }
+}
+
+/// The `<Code>` of an S3 XML error body
+fn error_code(body: &str) -> Option<&str> {
+    let (_, rest) = body.split_once("<Code>")?;
+    let (code, _) = rest.split_once("</Code>")?;
+    Some(code.trim())
+}
+
+/// Whether a 400 response refused a request's SSE-C headers. A GET says the encryption
+/// parameters don't apply to the object; a HEAD's 400 has no body to say anything.
+fn is_sse_c_mismatch(status: u16, body: &str) -> bool {
+    status == 400 && (body.is_empty() || body.contains("encryption parameters are not applicable"))
+}
+
+/// Whether an error says the bucket doesn't exist
+fn is_no_such_bucket<E: std::error::Error>(e: &RusotoError<E>) -> bool {
+    match e {
+        // HEAD responses have no body, so a missing bucket is a bare 404
+        RusotoError::Unknown(response) => {
+            response.status.as_u16() == 404 || response.body_as_str().contains("<Code>NoSuchBucket</Code>")
+        }
+        // Typed errors only display their message, so check the variant name
+        RusotoError::Service(e) => format!("{:?}", e).starts_with("NoSuchBucket"),
+        _ => false,
+    }
+}
+
+/// Seconds to wait from a Retry-After header (delay in seconds or an HTTP date),
+/// at most `MAX_RETRY_AFTER_SECS`
+fn parse_retry_after(value: Option<&str>) -> u64 {
+    let Some(value) = value.map(str::trim) else {
+        return DEFAULT_RETRY_AFTER_SECS;
+    };
+    let secs = value.parse::<u64>().unwrap_or_else(|_| match parse_timestamp(value) {
+        Some(at) => at.saturating_sub(chrono::Utc::now().timestamp()).max(0) as u64,
+        None => DEFAULT_RETRY_AFTER_SECS,
+    });
+    secs.min(MAX_RETRY_AFTER_SECS)
+}
+
+/// Retry behaviour for individual S3 requests
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay_ms: 500,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff delay for the given (zero-based) attempt
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        Duration::from_millis(self.base_delay_ms.saturating_mul(1 << attempt.min(10)))
+    }
+}
+
+/// S3 storage class for uploaded objects
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub enum StorageClass {
+    #[default]
+    Standard,
+    OneZoneIa,
+    Glacier,
+}
+
+impl StorageClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StorageClass::Standard => "STANDARD",
+            StorageClass::OneZoneIa => "ONEZONE_IA",
+            StorageClass::Glacier => "GLACIER",
+        }
+    }
+}
+
+/// Object lock retention mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum LockMode {
+    /// Users with the `s3:BypassGovernanceRetention` permission can still delete the object
+    Governance,
+    /// Nobody, not even the bucket owner, can delete the object before it expires
+    #[default]
+    Compliance,
+}
+
+impl LockMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LockMode::Governance => "GOVERNANCE",
+            LockMode::Compliance => "COMPLIANCE",
+        }
+    }
+}
+
+/// Canned ACLs that can be set on uploaded objects
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ObjectCannedAcl {
+    Private,
+    PublicRead,
+    /// For uploads to another account's bucket, so its owner can read them
+    BucketOwnerFullControl,
+}
+
+impl ObjectCannedAcl {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectCannedAcl::Private => "private",
+            ObjectCannedAcl::PublicRead => "public-read",
+            ObjectCannedAcl::BucketOwnerFullControl => "bucket-owner-full-control",
+        }
+    }
+}
+
+impl std::str::FromStr for ObjectCannedAcl {
+    type Err = S3Error;
+
+    fn from_str(acl: &str) -> Result<Self, S3Error> {
+        match acl {
+            "private" => Ok(ObjectCannedAcl::Private),
+            "public-read" => Ok(ObjectCannedAcl::PublicRead),
+            "bucket-owner-full-control" => Ok(ObjectCannedAcl::BucketOwnerFullControl),
+            _ => Err(S3Error::InvalidAcl(acl.to_string())),
+        }
+    }
+}
+
+/// The canned ACL that gives an object owned by `owner_id` these `grants`, or
+/// a list of the grants when none does
+fn describe_acl(owner_id: Option<&str>, grants: &[Grant]) -> String {
+    let grantee = |grant: &Grant| {
+        let grantee = grant.grantee.as_ref()?;
+        grantee.uri.clone().or_else(|| grantee.id.clone()).or_else(|| grantee.email_address.clone())
+    };
+    let mut others: Vec<(String, &str)> = grants
+        .iter()
+        .filter_map(|grant| Some((grantee(grant)?, grant.permission.as_deref()?)))
+        .filter(|(grantee, permission)| !(Some(grantee.as_str()) == owner_id && *permission == "FULL_CONTROL"))
+        .collect();
+    others.sort();
+    match others.as_slice() {
+        [] => ObjectCannedAcl::Private.as_str().to_string(),
+        [(grantee, "READ")] if grantee == ALL_USERS_URI => ObjectCannedAcl::PublicRead.as_str().to_string(),
+        [(_, "FULL_CONTROL")] => ObjectCannedAcl::BucketOwnerFullControl.as_str().to_string(),
+        _ => others
+            .iter()
+            .map(|(grantee, permission)| format!("{} {}", permission, grantee))
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
+
+/// The retention set on a locked object
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ObjectLockInfo {
+    pub mode: String,
+    pub retain_until: DateTime<Utc>,
+}
+
+/// S3-compatible storage services the app can talk to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum S3Provider {
+    #[default]
+    Scaleway,
+    BackblazeB2,
+    Aws,
+    Minio,
+    Custom,
+}
+
+/// Where the app's bucket lives
+#[derive(Debug, Clone, PartialEq)]
+pub struct S3ProviderConfig {
+    pub provider: S3Provider,
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+}
+
+impl S3ProviderConfig {
+    /// The app's own Scaleway bucket
+    pub fn scaleway() -> Self {
+        Self {
+            provider: S3Provider::Scaleway,
+            endpoint: S3_ENDPOINT.to_string(),
+            region: S3_REGION.to_string(),
+            bucket: S3_BUCKET.to_string(),
+        }
+    }
+
+    /// A Backblaze B2 bucket through its S3-compatible API, e.g. region "us-west-004"
+    pub fn backblaze_b2(bucket: impl Into<String>, region: impl Into<String>) -> Self {
+        let region = region.into();
+        Self {
+            provider: S3Provider::BackblazeB2,
+            endpoint: format!("https://s3.{}.backblazeb2.com", region),
+            region,
+            bucket: bucket.into(),
+        }
+    }
+
+    /// An AWS S3 bucket
+    pub fn aws(bucket: impl Into<String>, region: impl Into<String>) -> Self {
+        let region = region.into();
+        Self {
+            provider: S3Provider::Aws,
+            endpoint: format!("https://s3.{}.amazonaws.com", region),
+            region,
+            bucket: bucket.into(),
+        }
+    }
+
+    /// Any other S3-compatible endpoint, such as a self-hosted MinIO
+    pub fn custom(
+        provider: S3Provider,
+        endpoint: impl Into<String>,
+        region: impl Into<String>,
+        bucket: impl Into<String>,
+    ) -> Self {
+        Self {
+            provider,
+            endpoint: endpoint.into(),
+            region: region.into(),
+            bucket: bucket.into(),
+        }
+    }
+}
+
+impl Default for S3ProviderConfig {
+    fn default() -> Self {
+        Self::scaleway()
+    }
+}
+
+/// Settings an S3Client was built with
+#[derive(Debug, Clone, PartialEq)]
+pub struct S3ClientConfig {
+    pub provider: S3ProviderConfig,
+    pub user_prefix: String,
+    pub retry_policy: RetryPolicy,
+    pub storage_class: StorageClass,
+    pub max_concurrency: usize,
+    /// Size of each part of a multipart upload; files up to this size are sent in one PUT
+    pub multipart_part_size: usize,
+    /// Key objects are encrypted with server-side (SSE-C); needed again to read them
+    pub sse_customer_key: Option<SseCustomerKey>,
+    /// Canned ACL uploaded files and copies are stored with; the bucket's default when None
+    pub acl: Option<ObjectCannedAcl>,
+}
+
+impl Default for S3ClientConfig {
+    fn default() -> Self {
+        Self {
+            provider: S3ProviderConfig::default(),
+            user_prefix: String::new(),
+            retry_policy: RetryPolicy::default(),
+            storage_class: StorageClass::default(),
+            max_concurrency: 4,
+            multipart_part_size: DEFAULT_MULTIPART_PART_SIZE,
+            sse_customer_key: None,
+            acl: None,
+        }
+    }
+}
+
+// Algorithm S3 uses with a customer-provided key
+const SSE_C_ALGORITHM: &str = "AES256";
+
+/// A customer-provided key for server-side encryption (SSE-C), sent base64-encoded
+/// along with its MD5 on every request that writes or reads an object
+#[derive(Clone, PartialEq)]
+pub struct SseCustomerKey {
+    key: String,
+    key_md5: String,
+}
+
+impl SseCustomerKey {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self {
+            key: BASE64.encode(key),
+            key_md5: BASE64.encode(Md5::digest(key)),
+        }
+    }
+
+    /// Base64 MD5 of the key, which S3 uses to check it arrived intact
+    pub fn key_md5(&self) -> &str {
+        &self.key_md5
+    }
+}
+
+impl std::fmt::Debug for SseCustomerKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Keep the key itself out of logs
+        f.debug_struct("SseCustomerKey")
+            .field("key_md5", &self.key_md5)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A bucket other than the app's own, with its own credentials
+#[derive(Clone, PartialEq)]
+pub struct S3Destination {
+    pub endpoint: String,
+    pub bucket: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+impl std::fmt::Debug for S3Destination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Keep the secret out of logs
+        f.debug_struct("S3Destination")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .field("access_key", &self.access_key)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Signing region for an S3-compatible endpoint such as
+/// `https://s3.us-west-004.backblazeb2.com`; "us-east-1" when the host doesn't name one
+fn region_from_endpoint(endpoint: &str) -> String {
+    let host = endpoint
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .split(['/', ':'])
+        .next()
+        .unwrap_or_default();
+    let labels: Vec<&str> = host.split('.').collect();
+    match labels.as_slice() {
+        ["s3", region, _, ..] if region.contains('-') => region.to_string(),
+        _ => "us-east-1".to_string(),
+    }
+}
+
+/// Builder for S3Client
+#[derive(Debug, Clone, Default)]
+pub struct S3ClientBuilder {
+    config: S3ClientConfig,
+    destination: Option<S3Destination>,
+    unscoped: bool,
+}
+
+impl S3ClientBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start from the S3 settings in the app configuration
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            config: S3ClientConfig {
+                provider: config.provider.clone(),
+                user_prefix: String::new(),
+                retry_policy: config.retry_policy.clone(),
+                storage_class: config.storage_class,
+                max_concurrency: config.max_concurrency,
+                multipart_part_size: DEFAULT_MULTIPART_PART_SIZE,
+                sse_customer_key: None,
+                acl: None,
+            },
+            destination: None,
+            unscoped: false,
+        }
+    }
+
+    pub fn provider(mut self, provider: S3ProviderConfig) -> Self {
+        self.config.provider = provider;
+        self
+    }
+
+    pub fn user_prefix(mut self, user_prefix: impl Into<String>) -> Self {
+        self.config.user_prefix = user_prefix.into();
+        self
+    }
+
+    /// Allow building without a user prefix, reaching the whole bucket. Only for
+    /// clients that don't act for one user, like the admin client.
+    pub fn unscoped(mut self) -> Self {
+        self.unscoped = true;
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.config.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn storage_class(mut self, storage_class: StorageClass) -> Self {
+        self.config.storage_class = storage_class;
+        self
+    }
+
+    pub fn max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.config.max_concurrency = max_concurrency.max(1);
+        self
+    }
+
+    /// Size of each multipart upload part
+    ///
+    /// # Panics
+    ///
+    /// If `size` is below S3's minimum of 5 MiB or above its maximum of 5 GiB
+    pub fn part_size(mut self, size: usize) -> Self {
+        assert!(
+            size >= MIN_MULTIPART_PART_SIZE,
+            "Multipart part size must be at least 5 MiB ({} bytes), got {}",
+            MIN_MULTIPART_PART_SIZE,
+            size
+        );
+        assert!(
+            size as u64 <= MAX_MULTIPART_PART_SIZE,
+            "Multipart part size must be at most 5 GiB ({} bytes), got {}",
+            MAX_MULTIPART_PART_SIZE,
+            size
+        );
+        self.config.multipart_part_size = size;
+        self
+    }
+
+    /// Encrypt objects server-side with a key of our own (SSE-C)
+    pub fn sse_customer_key(mut self, key: &[u8; 32]) -> Self {
+        self.config.sse_customer_key = Some(SseCustomerKey::new(key));
+        self
+    }
+
+    /// Talk to another bucket instead of the app's own
+    pub fn destination(mut self, destination: S3Destination) -> Self {
+        self.destination = Some(destination);
+        self
+    }
+
+    /// Create the S3 client
+    pub fn build(self) -> Result<S3Client, S3Error> {
+        let prefix = &self.config.user_prefix;
+        // An empty prefix would reach every user's files
+        if prefix.is_empty() && !self.unscoped {
+            return Err(S3Error::InvalidConfiguration("user_prefix cannot be empty".to_string()));
+        }
+        if !prefix.is_empty() && !prefix.ends_with('/') {
+            return Err(S3Error::InvalidConfiguration(format!(
+                "user_prefix must end with '/', got \"{}\"",
+                prefix
+            )));
+        }
+        let (credentials, region, bucket) = match self.destination {
+            Some(dest) => (
+                StaticProvider::new_minimal(dest.access_key, dest.secret_key),
+                Region::Custom {
+                    name: region_from_endpoint(&dest.endpoint),
+                    endpoint: dest.endpoint,
+                },
+                dest.bucket,
+            ),
+            None => {
+                // Check if the app's own credentials have expired
+                S3Client::check_credentials_expiry()?;
+                (
+                    StaticProvider::new_minimal(
+                        secrets::S3_ACCESS_KEY.to_string(),
+                        secrets::S3_SECRET_KEY.to_string(),
+                    ),
+                    Region::Custom {
+                        name: self.config.provider.region.clone(),
+                        endpoint: self.config.provider.endpoint.clone(),
+                    },
+                    self.config.provider.bucket.clone(),
+                )
+            }
+        };
+
+        let http_client = HttpClient::new()
+            .map_err(|e| S3Error::OperationFailed(e.to_string()))?;
+
+        // Kept for requests the typed client can't make, like conditional PUTs
+        let raw_client = RusotoClient::new_with(credentials, http_client);
+        let client = RusotoS3Client::new_with_client(raw_client.clone(), region.clone());
+
+        Ok(S3Client {
+            client,
+            raw_client,
+            region,
+            bucket,
+            config: self.config,
+        })
+    }
 }
 
+#[derive(Clone)]
 pub struct S3Client {
     client: RusotoS3Client,
-    user_prefix: String,
+    raw_client: RusotoClient,
+    region: Region,
+    bucket: String,
+    config: S3ClientConfig,
 }
 
 impl S3Client {
@@ -82,274 +805,3198 @@ impl S3Client {
         (expiry_date - now).num_days()
     }
 
-    /// Create a new S3 client with the user's folder prefix
-    pub async fn new(user_prefix: String) -> Result<Self, S3Error> {
-        // Check if credentials have expired
-        Self::check_credentials_expiry()?;
+    /// Create a new S3 client for the provider's bucket with the user's folder prefix and
+    /// default settings. Fails with `BucketNotFound` if the bucket is known not to exist.
+    pub async fn new(provider: S3ProviderConfig, user_prefix: String) -> Result<Self, S3Error> {
+        let client = S3ClientBuilder::new()
+            .provider(provider)
+            .user_prefix(user_prefix)
+            .build()?;
+        match client.bucket_exists().await {
+            Ok(true) => {}
+            Ok(false) => return Err(S3Error::BucketNotFound(client.bucket.clone())),
+            // Most likely offline; requests report the problem once they're made
+            Err(e) => log::warn!("Failed to check bucket {}: {}", client.bucket, e),
+        }
+        Ok(client)
+    }
 
-        let credentials = StaticProvider::new_minimal(
-            secrets::S3_ACCESS_KEY.to_string(),
-            secrets::S3_SECRET_KEY.to_string(),
-        );
+    /// Client for the app's own bucket without a user folder prefix, for admin data
+    pub fn new_admin() -> Result<Self, S3Error> {
+        S3ClientBuilder::new()
+            .provider(S3ProviderConfig::scaleway())
+            .unscoped()
+            .build()
+    }
 
-        let region = Region::Custom {
-            name: S3_REGION.to_string(),
-            endpoint: S3_ENDPOINT.to_string(),
+    /// Bucket this client reads and writes
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    /// Endpoint requests are sent to, such as `https://s3.nl-ams.scw.cloud`
+    pub fn endpoint(&self) -> &str {
+        match &self.region {
+            Region::Custom { endpoint, .. } => endpoint,
+            _ => "",
+        }
+    }
+
+    /// Region the bucket was created in, using GetBucketLocation. This can differ
+    /// from the configured region, which is only used to sign requests.
+    pub async fn detected_region(&self) -> Result<String, S3Error> {
+        let request = GetBucketLocationRequest {
+            bucket: self.bucket.clone(),
+            ..Default::default()
         };
+        let output = self
+            .client
+            .get_bucket_location(request)
+            .await
+            .map_err(|e| self.map_error(e))?;
+        // AWS leaves the location empty for buckets in us-east-1
+        Ok(output
+            .location_constraint
+            .filter(|location| !location.is_empty())
+            .unwrap_or_else(|| "us-east-1".to_string()))
+    }
 
-        let http_client = HttpClient::new()
-            .map_err(|e| S3Error::OperationFailed(e.to_string()))?;
+    /// Settings this client was built with
+    pub fn config(&self) -> &S3ClientConfig {
+        &self.config
+    }
 
-        let client = RusotoS3Client::new_with(http_client, credentials, region);
+    /// Smallest part size that uploads `file_size` bytes in at most 10,000 parts,
+    /// kept within S3's 5 MiB to 5 GiB; files too large for that many 5 GiB parts
+    /// get the maximum and are refused by the store
+    pub fn optimal_part_size(file_size: u64) -> usize {
+        let size = file_size
+            .div_ceil(MAX_MULTIPART_PARTS)
+            .clamp(MIN_MULTIPART_PART_SIZE as u64, MAX_MULTIPART_PART_SIZE);
+        usize::try_from(size).unwrap_or(usize::MAX)
+    }
 
-        Ok(Self {
-            client,
-            user_prefix,
-        })
+    /// Part size for uploading `file_size` bytes: the configured one, unless the
+    /// file needs larger parts to fit the part limit
+    fn part_size_for(&self, file_size: u64) -> usize {
+        self.config.multipart_part_size.max(Self::optimal_part_size(file_size))
     }
 
-    /// Get the full S3 key for a relative path
-    fn full_key(&self, relative_path: &str) -> String {
-        format!("{}{}", self.user_prefix, relative_path)
+    /// PUT-class requests an upload of `file_size` bytes makes: one, or for a
+    /// multipart upload one per part plus starting and completing it
+    pub fn upload_requests(&self, file_size: u64) -> u64 {
+        if file_size <= self.config.multipart_part_size as u64 {
+            return 1;
+        }
+        file_size.div_ceil(self.part_size_for(file_size) as u64) + 2
     }
 
-    /// Upload a file to S3
-    pub async fn upload_file(
-        &self,
-        local_path: &Path,
-        remote_path: &str,
-    ) -> Result<(), S3Error> {
-        let mut file = File::open(local_path)
-            .await
-            .map_err(|e| S3Error::IoError(e.to_string()))?;
+    /// Map a rusoto error, recognising a missing bucket and a refused SSE-C key
+    fn map_error<E: std::error::Error + 'static>(&self, e: RusotoError<E>) -> S3Error {
+        match e {
+            // A bare 404 is a missing key as often as a missing bucket
+            RusotoError::Unknown(ref response) if response.status.as_u16() == 404 => map_rusoto_error(e),
+            RusotoError::Unknown(ref response)
+                if self.config.sse_customer_key.is_some() && is_sse_c_mismatch(response.status.as_u16(), response.body_as_str()) =>
+            {
+                S3Error::SseCMismatch(response.body_as_str().to_string())
+            }
+            e if is_no_such_bucket(&e) => S3Error::BucketNotFound(self.bucket.clone()),
+            e => map_rusoto_error(e),
+        }
+    }
 
-        let mut contents = Vec::new();
-        file.read_to_end(&mut contents)
+    /// Whether the bucket exists, using HeadBucket
+    pub async fn bucket_exists(&self) -> Result<bool, S3Error> {
+        let request = HeadBucketRequest {
+            bucket: self.bucket.clone(),
+            ..Default::default()
+        };
+        match self.client.head_bucket(request).await {
+            Ok(()) => Ok(true),
+            Err(e) if is_no_such_bucket(&e) => Ok(false),
+            Err(e) => Err(map_rusoto_error(e)),
+        }
+    }
+
+    /// Probe the bucket with HeadBucket to see how far a request gets
+    pub async fn check_connection(&self) -> ConnectionStatus {
+        let request = HeadBucketRequest {
+            bucket: self.bucket.clone(),
+            ..Default::default()
+        };
+        connection_status(self.client.head_bucket(request).await)
+    }
+
+    /// Pre-flight check before a large sync: list one key to time a round trip.
+    /// With `probe_upload`, also write and delete a 1-byte probe object to time an
+    /// upload; leave it off for keys that may not write to the bucket.
+    pub async fn warm_up(&self, probe_upload: bool) -> Result<ConnectionInfo, S3Error> {
+        let started = Instant::now();
+        let request = ListObjectsV2Request {
+            bucket: self.bucket.clone(),
+            prefix: Some(self.full_key("")),
+            max_keys: Some(1),
+            ..Default::default()
+        };
+        self.client
+            .list_objects_v2(request)
             .await
-            .map_err(|e| S3Error::IoError(e.to_string()))?;
+            .map_err(|e| self.map_error(e))?;
+        let rtt_ms = started.elapsed().as_millis() as u64;
 
-        let key = self.full_key(remote_path);
+        let upload_latency_ms = if probe_upload {
+            Some(self.time_probe_upload().await?)
+        } else {
+            None
+        };
+
+        let region = match self.detected_region().await {
+            Ok(region) => region,
+            Err(e) => {
+                log::debug!("Couldn't detect the bucket's region, showing the configured one: {}", e);
+                self.region.name().to_string()
+            }
+        };
+        Ok(ConnectionInfo::new(rtt_ms, upload_latency_ms, self, region))
+    }
 
+    /// Milliseconds to PUT a 1-byte probe object, which is deleted again
+    async fn time_probe_upload(&self) -> Result<u64, S3Error> {
+        let probe = format!("{}{:016x}", WARMUP_PROBE_PREFIX, rand::random::<u64>());
+        let started = Instant::now();
         let request = PutObjectRequest {
-            bucket: S3_BUCKET.to_string(),
-            key,
-            body: Some(contents.into()),
+            bucket: self.bucket.clone(),
+            key: self.full_key(&probe),
+            body: Some(vec![0u8].into()),
+            content_length: Some(1),
             ..Default::default()
         };
-
         self.client
             .put_object(request)
             .await
-            .map_err(|e| S3Error::OperationFailed(e.to_string()))?;
+            .map_err(|e| self.map_error(e))?;
+        let upload_latency_ms = started.elapsed().as_millis() as u64;
+        self.delete_object(&probe).await?;
+        Ok(upload_latency_ms)
+    }
 
-        Ok(())
+    /// This client with `key` used for SSE-C, or without SSE-C when None
+    pub fn with_sse_customer_key(&self, key: Option<&[u8; 32]>) -> Self {
+        let mut client = self.clone();
+        client.config.sse_customer_key = key.map(SseCustomerKey::new);
+        client
     }
 
-    /// Download a file from S3
-    pub async fn download_file(
-        &self,
+    /// Run `read` with this client, then once more without SSE-C if the object turns out
+    /// to be stored without it, e.g. because it was uploaded before SSE-C was turned on
+    async fn with_sse_c_fallback<T, F, Fut>(&self, read: F) -> Result<T, S3Error>
+    where
+        F: Fn(S3Client) -> Fut,
+        Fut: Future<Output = Result<T, S3Error>>,
+    {
+        match read(self.clone()).await {
+            Err(S3Error::SseCMismatch(e)) => {
+                log::debug!("Object isn't stored with SSE-C, reading it without: {}", e);
+                read(self.with_sse_customer_key(None)).await
+            }
+            result => result,
+        }
+    }
+
+    /// This client with uploads and copies stored with `acl`, or the bucket's default when None
+    pub fn with_acl(&self, acl: Option<ObjectCannedAcl>) -> Self {
+        let mut client = self.clone();
+        client.config.acl = acl;
+        client
+    }
+
+    /// The `x-amz-acl` value for an upload, if an ACL is set
+    fn acl_header(&self) -> Option<String> {
+        self.config.acl.map(|acl| acl.as_str().to_string())
+    }
+
+    /// SSE-C algorithm, key and key MD5 for a request; all None without a customer key
+    fn sse_c_headers(&self) -> (Option<String>, Option<String>, Option<String>) {
+        match &self.config.sse_customer_key {
+            Some(sse) => (Some(SSE_C_ALGORITHM.to_string()), Some(sse.key.clone()), Some(sse.key_md5.clone())),
+            None => (None, None, None),
+        }
+    }
+
+    /// Get the full S3 key for a relative path
+    fn full_key(&self, relative_path: &str) -> String {
+        format!("{}{}", self.config.user_prefix, relative_path)
+    }
+
+    /// Relative path of a key from a listing, decoding it if the listing was URL-encoded
+    fn relative_key(&self, key: &str, encoding_type: Option<&str>) -> String {
+        let key = match encoding_type {
+            Some(LIST_ENCODING_TYPE) => decode_listed_key(key),
+            _ => key.to_string(),
+        };
+        match key.strip_prefix(&self.config.user_prefix) {
+            Some(relative) => relative.to_string(),
+            None => key,
+        }
+    }
+
+    /// Run an S3 request, retrying server errors and requests that got no response
+    /// according to the retry policy. Rejected requests fail straight away, and rate
+    /// limiting is left to the caller, which waits as long as the server asked.
+    async fn with_retry<T, F, Fut>(&self, mut request: F) -> Result<T, S3Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, S3Error>>,
+    {
+        let policy = &self.config.retry_policy;
+        let mut attempt = 0;
+        loop {
+            match request().await {
+                Err(S3Error::OperationFailed(e)) if attempt < policy.max_retries => {
+                    log::warn!("S3 request failed (attempt {}): {}", attempt + 1, e);
+                    tokio::time::sleep(policy.delay_for(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Upload a file to S3
+    pub async fn upload_file(
+        &self,
+        local_path: &Path,
         remote_path: &str,
+    ) -> Result<(), S3Error> {
+        self.upload_file_with_progress(local_path, remote_path, &HashMap::new(), |_| {}).await
+    }
+
+    /// Upload a file to S3, reporting its progress and speed as it goes
+    ///
+    /// Small files are sent in a single PUT and report once on completion.
+    /// Larger files use multipart upload and report after each part.
+    /// `tags` are attached to the object as part of the upload.
+    pub async fn upload_file_with_progress(
+        &self,
         local_path: &Path,
+        remote_path: &str,
+        tags: &HashMap<String, String>,
+        on_progress: impl Fn(ProgressEvent) + Send + 'static,
     ) -> Result<(), S3Error> {
-        let key = self.full_key(remote_path);
+        self.upload(local_path, remote_path, tags, None, on_progress).await
+    }
 
-        let request = GetObjectRequest {
-            bucket: S3_BUCKET.to_string(),
-            key,
-            ..Default::default()
-        };
+    /// Upload a file in compliance mode, so that nobody can overwrite or delete it
+    /// before `retain_until`
+    ///
+    /// Needs a bucket created with object locking enabled, which can't be turned on
+    /// afterwards. Such buckets are versioned, so uploading to the same path again
+    /// adds a new version and deleting only hides the locked one.
+    pub async fn upload_with_lock(
+        &self,
+        local_path: &Path,
+        remote_path: &str,
+        retain_until: DateTime<Utc>,
+    ) -> Result<(), S3Error> {
+        self.upload_file_locked_with_progress(
+            local_path,
+            remote_path,
+            &HashMap::new(),
+            LockMode::Compliance,
+            retain_until,
+            |_| {},
+        )
+        .await
+    }
 
-        let response = self
-            .client
-            .get_object(request)
+    /// Like `upload_file_with_progress`, locking the object in `mode` until `retain_until`
+    pub async fn upload_file_locked_with_progress(
+        &self,
+        local_path: &Path,
+        remote_path: &str,
+        tags: &HashMap<String, String>,
+        mode: LockMode,
+        retain_until: DateTime<Utc>,
+        on_progress: impl Fn(ProgressEvent) + Send + 'static,
+    ) -> Result<(), S3Error> {
+        self.upload(local_path, remote_path, tags, Some((mode, retain_until)), on_progress)
             .await
-            .map_err(|e| S3Error::OperationFailed(e.to_string()))?;
+    }
 
-        let body = response.body.ok_or_else(|| S3Error::FileNotFound("No body".into()))?;
-        
-        let bytes: Vec<u8> = body
-            .map_ok(|b| b.to_vec())
-            .try_concat()
+    /// Upload for `upload_file_with_progress`, with the object lock to set, if any
+    async fn upload(
+        &self,
+        local_path: &Path,
+        remote_path: &str,
+        tags: &HashMap<String, String>,
+        lock: Option<(LockMode, DateTime<Utc>)>,
+        on_progress: impl Fn(ProgressEvent) + Send + 'static,
+    ) -> Result<(), S3Error> {
+        validate_tags(tags)?;
+        let tagging = Some(encode_tagging(tags)).filter(|t| !t.is_empty());
+        let (lock_mode, retain_until) = match lock {
+            Some((mode, until)) => (
+                Some(mode.as_str().to_string()),
+                Some(until.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)),
+            ),
+            None => (None, None),
+        };
+        let on_progress = timed_progress(on_progress);
+
+        let stamp = FileStamp::of(local_path).await?;
+        let total_bytes = stamp.size;
+        let mut file = File::open(local_path)
             .await
-            .map_err(|e| S3Error::IoError(e.to_string()))?;
+            .map_err(|e| upload_source_error(local_path, e))?;
 
-        // Ensure parent directory exists
-        if let Some(parent) = local_path.parent() {
-            tokio::fs::create_dir_all(parent)
-                .await
-                .map_err(|e| S3Error::IoError(e.to_string()))?;
+        let key = self.full_key(remote_path);
+
+        if total_bytes > self.config.multipart_part_size as u64 {
+            let (sse_customer_algorithm, sse_customer_key, sse_customer_key_md5) = self.sse_c_headers();
+            let request = CreateMultipartUploadRequest {
+                bucket: self.bucket.clone(),
+                key: key.clone(),
+                storage_class: Some(self.config.storage_class.as_str().to_string()),
+                tagging,
+                object_lock_mode: lock_mode,
+                object_lock_retain_until_date: retain_until,
+                acl: self.acl_header(),
+                sse_customer_algorithm, sse_customer_key, sse_customer_key_md5,
+                ..Default::default()
+            };
+            return self
+                .upload_multipart(&mut file, &stamp, request, lock.is_some(), &on_progress)
+                .await;
         }
 
-        let mut file = File::create(local_path)
-            .await
-            .map_err(|e| S3Error::IoError(e.to_string()))?;
-        
-        file.write_all(&bytes)
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)
             .await
             .map_err(|e| S3Error::IoError(e.to_string()))?;
+        stamp.check_unchanged().await?;
+        let checksum = checksum_metadata(&contents);
+        // S3 only accepts object lock settings on requests with a Content-MD5
+        let content_md5 = lock.map(|_| BASE64.encode(Md5::digest(&contents)));
+        let (sse_customer_algorithm, sse_customer_key, sse_customer_key_md5) = self.sse_c_headers();
+
+        self.with_retry(|| async {
+            let request = PutObjectRequest {
+                bucket: self.bucket.clone(),
+                key: key.clone(),
+                body: Some(contents.clone().into()),
+                // Given explicitly so an empty file isn't sent without a length
+                content_length: Some(contents.len() as i64),
+                content_md5: content_md5.clone(),
+                metadata: Some(checksum.clone()),
+                storage_class: Some(self.config.storage_class.as_str().to_string()),
+                tagging: tagging.clone(),
+                object_lock_mode: lock_mode.clone(),
+                object_lock_retain_until_date: retain_until.clone(),
+                acl: self.acl_header(),
+                sse_customer_algorithm: sse_customer_algorithm.clone(),
+                sse_customer_key: sse_customer_key.clone(),
+                sse_customer_key_md5: sse_customer_key_md5.clone(),
+                ..Default::default()
+            };
 
+            self.client
+                .put_object(request)
+                .await
+                .map_err(|e| self.map_error(e))
+        })
+        .await?;
+
+        on_progress(contents.len() as u64, contents.len() as u64);
         Ok(())
     }
 
-    /// List all objects in the user's folder
-    pub async fn list_objects(&self, prefix: &str) -> Result<Vec<S3Object>, S3Error> {
-        let full_prefix = self.full_key(prefix);
-        let mut objects = Vec::new();
-        let mut continuation_token: Option<String> = None;
+    /// Upload a file unless something is already stored at `remote_path`
+    ///
+    /// Returns false without uploading when the same contents are already there, and
+    /// `ConflictError` when a different object is.
+    pub async fn upload_file_if_absent(&self, local_path: &Path, remote_path: &str) -> Result<bool, S3Error> {
+        self.upload_file_if_absent_with_progress(local_path, remote_path, &HashMap::new(), |_| {})
+            .await
+    }
 
-        loop {
-            let request = ListObjectsV2Request {
-                bucket: S3_BUCKET.to_string(),
-                prefix: Some(full_prefix.clone()),
-                continuation_token: continuation_token.clone(),
-                ..Default::default()
-            };
+    /// Like `upload_file_with_progress`, but never overwrites an object another
+    /// client stored at `remote_path`
+    ///
+    /// Small files are sent as a PUT with `If-None-Match: *`, so the store itself
+    /// refuses to overwrite. Stores without conditional writes, and files large
+    /// enough for multipart upload, are checked with a HEAD first instead, which
+    /// leaves a window for another client to write between the check and the upload.
+    /// Stores that ignore the header overwrite like a plain PUT.
+    pub async fn upload_file_if_absent_with_progress(
+        &self,
+        local_path: &Path,
+        remote_path: &str,
+        tags: &HashMap<String, String>,
+        on_progress: impl Fn(ProgressEvent) + Send + 'static,
+    ) -> Result<bool, S3Error> {
+        self.upload_if_absent(local_path, remote_path, tags, false, on_progress).await
+    }
 
-            let response = self
-                .client
-                .list_objects_v2(request)
-                .await
-                .map_err(|e| S3Error::OperationFailed(e.to_string()))?;
+    /// Like `upload_file_if_absent_with_progress`, but files that can't be sent as one
+    /// conditional PUT go through a temp key like `upload_file_atomic_with_progress`,
+    /// and `remote_path` is checked again just before the copy into place
+    pub async fn upload_file_atomic_if_absent_with_progress(
+        &self,
+        local_path: &Path,
+        remote_path: &str,
+        tags: &HashMap<String, String>,
+        on_progress: impl Fn(ProgressEvent) + Send + 'static,
+    ) -> Result<bool, S3Error> {
+        self.upload_if_absent(local_path, remote_path, tags, true, on_progress).await
+    }
 
-            if let Some(contents) = response.contents {
-                for obj in contents {
-                    if let Some(key) = obj.key {
-                        // Remove user prefix to get relative path
-                        let relative_key = key
-                            .strip_prefix(&self.user_prefix)
-                            .unwrap_or(&key)
-                            .to_string();
-
-                        objects.push(S3Object {
-                            key: relative_key,
-                            size: obj.size.unwrap_or(0) as u64,
-                            last_modified: obj
-                                .last_modified
-                                .and_then(|s| chrono::DateTime::parse_from_rfc3339(&s).ok())
-                                .map(|dt| dt.timestamp())
-                                .unwrap_or(0),
-                        });
-                    }
-                }
-            }
+    async fn upload_if_absent(
+        &self,
+        local_path: &Path,
+        remote_path: &str,
+        tags: &HashMap<String, String>,
+        atomic: bool,
+        on_progress: impl Fn(ProgressEvent) + Send + 'static,
+    ) -> Result<bool, S3Error> {
+        validate_tags(tags)?;
+        let started = Instant::now();
+        let total_bytes = tokio::fs::metadata(local_path)
+            .await
+            .map_err(|e| upload_source_error(local_path, e))?
+            .len();
 
-            if response.is_truncated.unwrap_or(false) {
-                continuation_token = response.next_continuation_token;
-            } else {
-                break;
+        if total_bytes <= self.config.multipart_part_size as u64 {
+            let stamp = FileStamp::of(local_path).await?;
+            let contents = tokio::fs::read(local_path)
+                .await
+                .map_err(|e| upload_source_error(local_path, e))?;
+            stamp.check_unchanged().await?;
+            let key = self.full_key(remote_path);
+            let tagging = Some(encode_tagging(tags)).filter(|t| !t.is_empty());
+            let outcome = self
+                .with_retry(|| self.put_object_conditional(&key, &contents, tagging.as_deref(), &Precondition::Absent))
+                .await?;
+            match outcome {
+                ConditionalPut::Written => {
+                    on_progress(ProgressEvent::new(total_bytes, total_bytes, started.elapsed()));
+                    return Ok(true);
+                }
+                ConditionalPut::Exists => return self.existing_object_conflict(local_path, remote_path).await,
+                ConditionalPut::Unsupported => {
+                    log::debug!("Conditional PUT not supported, checking {} with a HEAD", remote_path);
+                }
             }
         }
 
-        Ok(objects)
+        match self.get_object_info(remote_path).await {
+            Ok(_) => return self.existing_object_conflict(local_path, remote_path).await,
+            Err(S3Error::FileNotFound(_)) => {}
+            Err(e) => return Err(e),
+        }
+        if atomic {
+            return self.upload_via_temp_key(local_path, remote_path, tags, true, on_progress).await;
+        }
+        self.upload_file_with_progress(local_path, remote_path, tags, on_progress).await?;
+        Ok(true)
     }
 
-    /// List folders (common prefixes) at a given path
-    pub async fn list_folders(&self, prefix: &str) -> Result<Vec<String>, S3Error> {
-        let full_prefix = self.full_key(prefix);
-        let mut folders = Vec::new();
-
-        let request = ListObjectsV2Request {
-            bucket: S3_BUCKET.to_string(),
-            prefix: Some(full_prefix.clone()),
-            delimiter: Some("/".to_string()),
-            ..Default::default()
-        };
+    /// PUT `contents` to `key` only if `precondition` holds, which rusoto's
+    /// PutObjectRequest has no fields for
+    async fn put_object_conditional(
+        &self,
+        key: &str,
+        contents: &[u8],
+        tagging: Option<&str>,
+        precondition: &Precondition,
+    ) -> Result<ConditionalPut, S3Error> {
+        let mut request = SignedRequest::new("PUT", "s3", &self.region, &format!("/{}/{}", self.bucket, key));
+        for (name, value) in self.conditional_put_headers(precondition, contents, tagging) {
+            request.add_header(name, &value);
+        }
+        request.set_payload(Some(contents.to_vec()));
 
-        let response = self
-            .client
-            .list_objects_v2(request)
+        let mut response = self
+            .raw_client
+            .sign_and_dispatch(request)
             .await
-            .map_err(|e| S3Error::OperationFailed(e.to_string()))?;
+            .map_err(|e| map_rusoto_error(RusotoError::<PutObjectError>::from(e)))?;
+        let response = response
+            .buffer()
+            .await
+            .map_err(|e| map_rusoto_error(RusotoError::<PutObjectError>::from(e)))?;
+        match conditional_put_outcome(response.status.as_u16(), response.body_as_str()) {
+            Some(outcome) => Ok(outcome),
+            None => Err(self.map_error(RusotoError::<PutObjectError>::Unknown(response))),
+        }
+    }
 
-        if let Some(common_prefixes) = response.common_prefixes {
-            for prefix in common_prefixes {
-                if let Some(p) = prefix.prefix {
-                    // Remove user prefix to get relative path
-                    let relative = p
-                        .strip_prefix(&self.user_prefix)
-                        .unwrap_or(&p)
-                        .to_string();
-                    folders.push(relative);
-                }
-            }
+    /// Headers of a `put_object_conditional` request, including SSE-C's when it's on
+    fn conditional_put_headers(
+        &self,
+        precondition: &Precondition,
+        contents: &[u8],
+        tagging: Option<&str>,
+    ) -> Vec<(String, String)> {
+        let mut headers = vec![
+            match precondition {
+                Precondition::Absent => ("If-None-Match".to_string(), "*".to_string()),
+                Precondition::ETag(etag) => ("If-Match".to_string(), quoted_etag(etag)),
+            },
+            ("x-amz-storage-class".to_string(), self.config.storage_class.as_str().to_string()),
+        ];
+        if let Some(tagging) = tagging {
+            headers.push(("x-amz-tagging".to_string(), tagging.to_string()));
         }
+        if let Some(acl) = self.acl_header() {
+            headers.push(("x-amz-acl".to_string(), acl));
+        }
+        for (name, value) in checksum_metadata(contents) {
+            headers.push((format!("x-amz-meta-{}", name), value));
+        }
+        if let (Some(algorithm), Some(key), Some(key_md5)) = self.sse_c_headers() {
+            headers.push(("x-amz-server-side-encryption-customer-algorithm".to_string(), algorithm));
+            headers.push(("x-amz-server-side-encryption-customer-key".to_string(), key));
+            headers.push(("x-amz-server-side-encryption-customer-key-MD5".to_string(), key_md5));
+        }
+        headers
+    }
 
-        Ok(folders)
+    /// Outcome for an upload that found an object already at `remote_path`: false if it
+    /// holds the local file's contents, otherwise a `ConflictError` with its ETag
+    async fn existing_object_conflict(&self, local_path: &Path, remote_path: &str) -> Result<bool, S3Error> {
+        let existing_etag = self.get_object_info(remote_path).await?.etag.unwrap_or_default();
+        // Multipart ETags aren't a content MD5, so those always conflict
+        if !existing_etag.contains('-') && file_md5(local_path).await?.eq_ignore_ascii_case(&existing_etag) {
+            return Ok(false);
+        }
+        Err(S3Error::ConflictError { existing_etag })
     }
 
-    /// Delete an object from S3
-    pub async fn delete_object(&self, remote_path: &str) -> Result<(), S3Error> {
-        let key = self.full_key(remote_path);
+    /// Upload a file so that `remote_path` is either absent or complete
+    pub async fn upload_file_atomic(&self, local_path: &Path, remote_path: &str) -> Result<(), S3Error> {
+        self.upload_file_atomic_with_progress(local_path, remote_path, &HashMap::new(), |_| {})
+            .await
+    }
 
-        let request = DeleteObjectRequest {
-            bucket: S3_BUCKET.to_string(),
-            key,
-            ..Default::default()
+    /// Like `upload_file_with_progress`, but uploads to a temp key first and
+    /// copies it into place server-side once the upload has finished. The
+    /// temp key is deleted either way. Limited to the 5 GB a single
+    /// CopyObject can handle.
+    pub async fn upload_file_atomic_with_progress(
+        &self,
+        local_path: &Path,
+        remote_path: &str,
+        tags: &HashMap<String, String>,
+        on_progress: impl Fn(ProgressEvent) + Send + 'static,
+    ) -> Result<(), S3Error> {
+        self.upload_via_temp_key(local_path, remote_path, tags, false, on_progress)
+            .await
+            .map(|_| ())
+    }
+
+    /// Upload to a temp key and copy it into place; with `if_absent`, an object found at
+    /// `remote_path` once the upload has finished is left alone as in `existing_object_conflict`
+    async fn upload_via_temp_key(
+        &self,
+        local_path: &Path,
+        remote_path: &str,
+        tags: &HashMap<String, String>,
+        if_absent: bool,
+        on_progress: impl Fn(ProgressEvent) + Send + 'static,
+    ) -> Result<bool, S3Error> {
+        let temp_path = temp_upload_path(remote_path);
+        let temp_key = TempUploadGuard {
+            client: self.client.clone(),
+            bucket: self.bucket.clone(),
+            key: Some(self.full_key(&temp_path)),
         };
 
-        self.client
-            .delete_object(request)
+        let uploaded = self
+            .upload_file_with_progress(local_path, &temp_path, tags, on_progress)
+            .await;
+        let result = match uploaded {
+            Ok(()) if if_absent => match self.get_object_info(remote_path).await {
+                Ok(_) => self.existing_object_conflict(local_path, remote_path).await,
+                Err(S3Error::FileNotFound(_)) => self.copy_object(&temp_path, remote_path).await.map(|_| true),
+                Err(e) => Err(e),
+            },
+            Ok(()) => self.copy_object(&temp_path, remote_path).await.map(|_| true),
+            Err(e) => Err(e),
+        };
+
+        temp_key.disarm();
+        if let Err(e) = self.delete_object(&temp_path).await {
+            log::warn!("Failed to delete temp upload {}: {}", temp_path, e);
+        }
+        result
+    }
+
+    /// Upload a file, then HEAD it to check the size and, when one was sent, that the
+    /// SHA-256 checksum came back unchanged
+    pub async fn upload_file_with_verify(&self, local_path: &Path, remote_path: &str) -> Result<(), S3Error> {
+        self.upload_file(local_path, remote_path).await?;
+
+        let uploaded = self.get_object_info(remote_path).await?;
+        let size = tokio::fs::metadata(local_path)
             .await
-            .map_err(|e| S3Error::OperationFailed(e.to_string()))?;
+            .map_err(|e| S3Error::IoError(e.to_string()))?
+            .len();
+        if uploaded.size != size {
+            return Err(S3Error::OperationFailed(format!(
+                "Uploaded {} is {} bytes, expected {}",
+                remote_path, uploaded.size, size
+            )));
+        }
 
+        if let Some(stored) = uploaded.checksum_sha256 {
+            let local = file_sha256(local_path).await?;
+            if stored != local {
+                return Err(S3Error::OperationFailed(format!(
+                    "Checksum of uploaded {} is {}, expected {}",
+                    remote_path, stored, local
+                )));
+            }
+        }
         Ok(())
     }
 
-    /// Delete all objects in the user's folder
-    pub async fn delete_all_objects(&self) -> Result<usize, S3Error> {
-        // First list all objects
-        let objects = self.list_objects("").await?;
-        let count = objects.len();
+    /// Upload in-memory data to S3 in a single PUT
+    pub async fn upload_bytes(&self, data: &[u8], remote_path: &str, content_type: &str) -> Result<(), S3Error> {
+        let key = self.full_key(remote_path);
+        let checksum = checksum_metadata(data);
+        let (sse_customer_algorithm, sse_customer_key, sse_customer_key_md5) = self.sse_c_headers();
 
-        // Delete each object
-        for obj in objects {
-            let key = self.full_key(&obj.key);
-            let request = DeleteObjectRequest {
-                bucket: S3_BUCKET.to_string(),
-                key,
+        self.with_retry(|| async {
+            let request = PutObjectRequest {
+                bucket: self.bucket.clone(),
+                key: key.clone(),
+                body: Some(data.to_vec().into()),
+                content_length: Some(data.len() as i64),
+                content_type: Some(content_type.to_string()),
+                metadata: Some(checksum.clone()),
+                storage_class: Some(self.config.storage_class.as_str().to_string()),
+                sse_customer_algorithm: sse_customer_algorithm.clone(),
+                sse_customer_key: sse_customer_key.clone(),
+                sse_customer_key_md5: sse_customer_key_md5.clone(),
                 ..Default::default()
             };
 
             self.client
-                .delete_object(request)
+                .put_object(request)
                 .await
-                .map_err(|e| S3Error::OperationFailed(e.to_string()))?;
-        }
+                .map_err(|e| self.map_error(e))
+        })
+        .await?;
+        Ok(())
+    }
 
-        Ok(count)
+    /// Upload a string as UTF-8 text
+    pub async fn upload_string(&self, data: &str, remote_path: &str) -> Result<(), S3Error> {
+        self.upload_bytes(data.as_bytes(), remote_path, "text/plain; charset=utf-8").await
     }
 
-    /// Get object metadata (size, last modified)
-    pub async fn get_object_info(&self, remote_path: &str) -> Result<S3Object, S3Error> {
-        let key = self.full_key(remote_path);
+    /// Read and parse a JSON object, or `T::default()` if it doesn't exist yet
+    pub async fn read_json_from_key<T: DeserializeOwned + Default>(&self, key: &str) -> Result<T, S3Error> {
+        let (data, _) = self.read_json_with_etag(key).await?;
+        Ok(data)
+    }
 
-        let request = HeadObjectRequest {
-            bucket: S3_BUCKET.to_string(),
-            key: key.clone(),
-            ..Default::default()
+    /// Like `read_json_from_key`, also returning the object's ETag, or None if it's absent
+    pub async fn read_json_with_etag<T: DeserializeOwned + Default>(
+        &self,
+        key: &str,
+    ) -> Result<(T, Option<String>), S3Error> {
+        let full_key = &self.full_key(key);
+
+        let result = self
+            .with_sse_c_fallback(|client| async move {
+                let (sse_customer_algorithm, sse_customer_key, sse_customer_key_md5) = client.sse_c_headers();
+                client
+                    .with_retry(|| async {
+                        let request = GetObjectRequest {
+                            bucket: client.bucket.clone(),
+                            key: full_key.clone(),
+                            sse_customer_algorithm: sse_customer_algorithm.clone(),
+                            sse_customer_key: sse_customer_key.clone(),
+                            sse_customer_key_md5: sse_customer_key_md5.clone(),
+                            ..Default::default()
+                        };
+
+                        client.client.get_object(request).await.map_err(|e| match e {
+                            RusotoError::Service(GetObjectError::NoSuchKey(_)) => S3Error::FileNotFound(key.to_string()),
+                            RusotoError::Unknown(ref response) if response.status.as_u16() == 404 => {
+                                S3Error::FileNotFound(key.to_string())
+                            }
+                            e => client.map_error(e),
+                        })
+                    })
+                    .await
+            })
+            .await;
+
+        let response = match result {
+            Ok(response) => response,
+            Err(S3Error::FileNotFound(_)) => return Ok((T::default(), None)),
+            Err(e) => return Err(e),
         };
 
-        let response = self
-            .client
-            .head_object(request)
+        let etag = response.e_tag.as_deref().map(strip_etag_quotes);
+        let body = response.body.ok_or_else(|| S3Error::FileNotFound("No body".into()))?;
+        let mut bytes = Vec::new();
+        body.into_async_read()
+            .read_to_end(&mut bytes)
             .await
-            .map_err(|e| S3Error::OperationFailed(e.to_string()))?;
+            .map_err(|e| S3Error::IoError(e.to_string()))?;
 
-        Ok(S3Object {
-            key: remote_path.to_string(),
-            size: response.content_length.unwrap_or(0) as u64,
-            last_modified: response
-                .last_modified
-                .and_then(|s| chrono::DateTime::parse_from_rfc3339(&s).ok())
-                .map(|dt| dt.timestamp())
-                .unwrap_or(0),
+        let data = serde_json::from_slice(&bytes)
+            .map_err(|e| S3Error::OperationFailed(format!("Invalid JSON in {}: {}", key, e)))?;
+        Ok((data, etag))
+    }
+
+    /// Write `data` as pretty-printed JSON
+    pub async fn write_json_to_key<T: Serialize>(&self, key: &str, data: &T) -> Result<(), S3Error> {
+        let json = serde_json::to_string_pretty(data)
+            .map_err(|e| S3Error::OperationFailed(format!("Failed to serialize {}: {}", key, e)))?;
+        self.upload_bytes(json.as_bytes(), key, "application/json").await
+    }
+
+    /// Write `data` as JSON only if the object still has the ETag `read_json_with_etag`
+    /// returned (None meaning it was absent), returning false if it changed since.
+    /// Stores without conditional writes overwrite like `write_json_to_key`.
+    pub async fn write_json_if_unchanged<T: Serialize>(
+        &self,
+        key: &str,
+        data: &T,
+        etag: Option<&str>,
+    ) -> Result<bool, S3Error> {
+        let json = serde_json::to_string_pretty(data)
+            .map_err(|e| S3Error::OperationFailed(format!("Failed to serialize {}: {}", key, e)))?;
+        let precondition = match etag {
+            Some(etag) => Precondition::ETag(etag.to_string()),
+            None => Precondition::Absent,
+        };
+        let full_key = self.full_key(key);
+        let outcome = self
+            .with_retry(|| self.put_object_conditional(&full_key, json.as_bytes(), None, &precondition))
+            .await?;
+        match outcome {
+            ConditionalPut::Written => Ok(true),
+            ConditionalPut::Exists => Ok(false),
+            ConditionalPut::Unsupported => {
+                log::debug!("Conditional PUT not supported, overwriting {}", key);
+                self.upload_bytes(json.as_bytes(), key, "application/json").await?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Delete temp objects from atomic uploads that never finished, returning how many
+    pub async fn cleanup_stale_uploads(&self) -> Result<usize, S3Error> {
+        let now = chrono::Utc::now().timestamp();
+        let mut stale = Vec::new();
+        self.for_each_object("", |obj| {
+            if is_stale_temp_upload(&obj, now) {
+                stale.push(obj.key);
+            }
         })
+        .await?;
+
+        for key in &stale {
+            log::info!("Removing stale temp upload {}", key);
+            self.delete_object(key).await?;
+        }
+        Ok(stale.len())
     }
-}
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct S3Object {
-    pub key: String,
-    pub size: u64,
-    pub last_modified: i64,
+    /// Upload an open file in parts, aborting the upload if any part fails
+    ///
+    /// `send_md5` adds a Content-MD5 to each part, which locked uploads need. The
+    /// upload is aborted too if the file changed from `stamp` while being read.
+    async fn upload_multipart(
+        &self,
+        file: &mut File,
+        stamp: &FileStamp,
+        request: CreateMultipartUploadRequest,
+        send_md5: bool,
+        on_progress: &(impl Fn(u64, u64) + Send),
+    ) -> Result<(), S3Error> {
+        let key = request.key.clone();
+        let key = key.as_str();
+        let upload_id = self
+            .client
+            .create_multipart_upload(request)
+            .await
+            .map_err(|e| self.map_error(e))?
+            .upload_id
+            .ok_or_else(|| S3Error::OperationFailed("No upload ID returned".into()))?;
+
+        let part_size = self.part_size_for(stamp.size);
+        let result = match self
+            .upload_parts(file, key, &upload_id, send_md5, stamp.size, on_progress)
+            .await
+        {
+            Ok(parts) => stamp.check_unchanged().await.map(|()| parts),
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(parts) => {
+                let request = CompleteMultipartUploadRequest {
+                    bucket: self.bucket.clone(),
+                    key: key.to_string(),
+                    upload_id,
+                    multipart_upload: Some(CompletedMultipartUpload { parts: Some(parts) }),
+                    ..Default::default()
+                };
+
+                self.client
+                    .complete_multipart_upload(request)
+                    .await
+                    .map_err(|e| match e {
+                        e if is_entity_too_small(&e) => S3Error::PartTooSmall {
+                            size: part_size,
+                            min: MIN_MULTIPART_PART_SIZE,
+                        },
+                        e => self.map_error(e),
+                    })?;
+
+                Ok(())
+            }
+            Err(e) => {
+                let request = AbortMultipartUploadRequest {
+                    bucket: self.bucket.clone(),
+                    key: key.to_string(),
+                    upload_id,
+                    ..Default::default()
+                };
+
+                if let Err(abort_err) = self.client.abort_multipart_upload(request).await {
+                    log::warn!("Failed to abort multipart upload for {}: {}", key, abort_err);
+                }
+
+                Err(e)
+            }
+        }
+    }
+
+    /// Upload each part of a multipart upload in order
+    async fn upload_parts(
+        &self,
+        file: &mut File,
+        key: &str,
+        upload_id: &str,
+        send_md5: bool,
+        total_bytes: u64,
+        on_progress: &(impl Fn(u64, u64) + Send),
+    ) -> Result<Vec<CompletedPart>, S3Error> {
+        let part_size = self.part_size_for(total_bytes);
+        let (sse_customer_algorithm, sse_customer_key, sse_customer_key_md5) = self.sse_c_headers();
+        let mut parts = Vec::new();
+        let mut bytes_sent = 0u64;
+        let mut part_number = 1i64;
+
+        loop {
+            let chunk = read_chunk(file, part_size).await?;
+            if chunk.is_empty() {
+                break;
+            }
+            let chunk_len = chunk.len() as u64;
+            let content_md5 = send_md5.then(|| BASE64.encode(Md5::digest(&chunk)));
+
+            let response = self
+                .with_retry(|| async {
+                    let request = UploadPartRequest {
+                        bucket: self.bucket.clone(),
+                        key: key.to_string(),
+                        upload_id: upload_id.to_string(),
+                        part_number,
+                        content_length: Some(chunk_len as i64),
+                        content_md5: content_md5.clone(),
+                        body: Some(chunk.clone().into()),
+                        sse_customer_algorithm: sse_customer_algorithm.clone(),
+                        sse_customer_key: sse_customer_key.clone(),
+                        sse_customer_key_md5: sse_customer_key_md5.clone(),
+                        ..Default::default()
+                    };
+
+                    self.client
+                        .upload_part(request)
+                        .await
+                        .map_err(|e| self.map_error(e))
+                })
+                .await?;
+
+            parts.push(CompletedPart {
+                e_tag: response.e_tag,
+                part_number: Some(part_number),
+            });
+
+            bytes_sent += chunk_len;
+            part_number += 1;
+            on_progress(bytes_sent, total_bytes);
+        }
+
+        Ok(parts)
+    }
+
+    /// Download a file from S3
+    pub async fn download_file(
+        &self,
+        remote_path: &str,
+        local_path: &Path,
+    ) -> Result<(), S3Error> {
+        self.download_file_with_progress(remote_path, local_path, |_, _| {}).await
+    }
+
+    /// Download a file uploaded with `upload_with_sse_c`, decrypted server-side with `key`
+    pub async fn download_file_with_sse_c(
+        &self,
+        remote_path: &str,
+        local_path: &Path,
+        key: &[u8; 32],
+    ) -> Result<(), S3Error> {
+        self.with_sse_customer_key(Some(key)).download_file(remote_path, local_path).await
+    }
+
+    /// Download a file from S3, reporting `(bytes_received, total_bytes)`
+    /// each time a chunk (up to DOWNLOAD_CHUNK_SIZE) is written to disk
+    pub async fn download_file_with_progress(
+        &self,
+        remote_path: &str,
+        local_path: &Path,
+        on_progress: impl Fn(u64, u64) + Send + 'static,
+    ) -> Result<(), S3Error> {
+        self.download_file_conditional_with_progress(remote_path, local_path, None, on_progress)
+            .await
+            .map(|_| ())
+    }
+
+    /// Download a file unless the object's ETag is still `local_etag`, the ETag
+    /// it had when the local copy was downloaded
+    pub async fn download_file_conditional(
+        &self,
+        remote_path: &str,
+        local_path: &Path,
+        local_etag: Option<&str>,
+    ) -> Result<DownloadResult, S3Error> {
+        self.download_file_conditional_with_progress(remote_path, local_path, local_etag, |_, _| {})
+            .await
+    }
+
+    /// Like `download_file_conditional`, reporting progress as `download_file_with_progress` does.
+    /// Nothing is written when the object is not modified.
+    pub async fn download_file_conditional_with_progress(
+        &self,
+        remote_path: &str,
+        local_path: &Path,
+        local_etag: Option<&str>,
+        on_progress: impl Fn(u64, u64) + Send + 'static,
+    ) -> Result<DownloadResult, S3Error> {
+        let key = &self.full_key(remote_path);
+        let if_none_match = &local_etag.map(quoted_etag);
+
+        let response = self
+            .with_sse_c_fallback(|client| async move {
+                let (sse_customer_algorithm, sse_customer_key, sse_customer_key_md5) = client.sse_c_headers();
+                client
+                    .with_retry(|| async {
+                        let request = GetObjectRequest {
+                            bucket: client.bucket.clone(),
+                            key: key.clone(),
+                            if_none_match: if_none_match.clone(),
+                            sse_customer_algorithm: sse_customer_algorithm.clone(),
+                            sse_customer_key: sse_customer_key.clone(),
+                            sse_customer_key_md5: sse_customer_key_md5.clone(),
+                            ..Default::default()
+                        };
+
+                        match client.client.get_object(request).await {
+                            Ok(response) => Ok(Some(response)),
+                            // 304 has no body, so it comes back as an unparsed error
+                            Err(RusotoError::Unknown(ref response)) if response.status.as_u16() == 304 => Ok(None),
+                            Err(e) => Err(client.map_error(e)),
+                        }
+                    })
+                    .await
+            })
+            .await?;
+        let Some(response) = response else {
+            return Ok(DownloadResult::NotModified);
+        };
+
+        let total_bytes = response.content_length.unwrap_or(0).max(0) as u64;
+
+        // Ensure parent directory exists
+        if let Some(parent) = local_path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| local_io_error(parent, total_bytes, e))?;
+        }
+
+        let mut file = File::create(local_path)
+            .await
+            .map_err(|e| local_io_error(local_path, total_bytes, e))?;
+
+        // Some endpoints send no body at all for an empty object
+        if let Some(body) = response.body {
+            let reader = BufReader::with_capacity(DOWNLOAD_CHUNK_SIZE, body.into_async_read());
+            copy_with_progress(reader, &mut file, total_bytes, &on_progress)
+                .await
+                .map_err(|e| local_io_error(local_path, total_bytes, e))?;
+        }
+
+        file.flush()
+            .await
+            .map_err(|e| local_io_error(local_path, total_bytes, e))?;
+
+        Ok(DownloadResult::Downloaded)
+    }
+
+    /// Download a file as parallel ranged GETs of `chunk_size` bytes, `parallelism` at a time
+    pub async fn download_file_multipart(
+        &self,
+        remote_path: &str,
+        local_path: &Path,
+        chunk_size: u64,
+        parallelism: usize,
+    ) -> Result<(), S3Error> {
+        self.download_file_multipart_with_progress(remote_path, local_path, chunk_size, parallelism, |_, _| {})
+            .await
+    }
+
+    /// Like `download_file_multipart`, reporting `(bytes_received, total_bytes)` as each
+    /// chunk is written
+    ///
+    /// Chunks are written at their offset as they arrive. When the object has a stored
+    /// SHA-256, the assembled file is checked against it and removed on a mismatch.
+    pub async fn download_file_multipart_with_progress(
+        &self,
+        remote_path: &str,
+        local_path: &Path,
+        chunk_size: u64,
+        parallelism: usize,
+        on_progress: impl Fn(u64, u64) + Send + 'static,
+    ) -> Result<(), S3Error> {
+        let info = self.get_object_info(remote_path).await?;
+        let key = self.full_key(remote_path);
+
+        if let Some(parent) = local_path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| local_io_error(parent, info.size, e))?;
+        }
+
+        let mut file = File::create(local_path)
+            .await
+            .map_err(|e| local_io_error(local_path, info.size, e))?;
+        file.set_len(info.size)
+            .await
+            .map_err(|e| local_io_error(local_path, info.size, e))?;
+
+        let fetch = |start, end| self.get_object_range(&key, start, end);
+        write_chunks(&mut file, info.size, chunk_size, parallelism, fetch, &on_progress).await?;
+        file.flush()
+            .await
+            .map_err(|e| local_io_error(local_path, info.size, e))?;
+
+        if let Some(expected) = info.checksum_sha256 {
+            let actual = file_sha256(local_path).await?;
+            if actual != expected {
+                let _ = tokio::fs::remove_file(local_path).await;
+                return Err(S3Error::OperationFailed(format!(
+                    "Checksum of downloaded {} is {}, expected {}",
+                    remote_path, actual, expected
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Bytes `start..=end` of an object
+    async fn get_object_range(&self, key: &str, start: u64, end: u64) -> Result<Vec<u8>, S3Error> {
+        let response = self
+            .with_sse_c_fallback(|client| async move {
+                let (sse_customer_algorithm, sse_customer_key, sse_customer_key_md5) = client.sse_c_headers();
+                client
+                    .with_retry(|| async {
+                        let request = GetObjectRequest {
+                            bucket: client.bucket.clone(),
+                            key: key.to_string(),
+                            range: Some(format!("bytes={}-{}", start, end)),
+                            sse_customer_algorithm: sse_customer_algorithm.clone(),
+                            sse_customer_key: sse_customer_key.clone(),
+                            sse_customer_key_md5: sse_customer_key_md5.clone(),
+                            ..Default::default()
+                        };
+
+                        client
+                            .client
+                            .get_object(request)
+                            .await
+                            .map_err(|e| client.map_error(e))
+                    })
+                    .await
+            })
+            .await?;
+
+        let body = response.body.ok_or_else(|| S3Error::FileNotFound("No body".into()))?;
+        let mut bytes = Vec::with_capacity((end - start + 1) as usize);
+        body.into_async_read()
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(|e| S3Error::IoError(e.to_string()))?;
+        Ok(bytes)
+    }
+
+    /// Download an object and check its SHA-256 against the checksum stored with it
+    ///
+    /// Multipart uploads are stored without a checksum and can't be verified.
+    pub async fn verify_object_checksum(&self, remote_path: &str) -> Result<bool, S3Error> {
+        let stored = self.get_object_info(remote_path).await?.checksum_sha256.ok_or_else(|| {
+            S3Error::OperationFailed(format!("{} has no stored SHA-256 checksum", remote_path))
+        })?;
+        let key = &self.full_key(remote_path);
+
+        let response = self
+            .with_sse_c_fallback(|client| async move {
+                let (sse_customer_algorithm, sse_customer_key, sse_customer_key_md5) = client.sse_c_headers();
+                client
+                    .with_retry(|| async {
+                        let request = GetObjectRequest {
+                            bucket: client.bucket.clone(),
+                            key: key.clone(),
+                            sse_customer_algorithm: sse_customer_algorithm.clone(),
+                            sse_customer_key: sse_customer_key.clone(),
+                            sse_customer_key_md5: sse_customer_key_md5.clone(),
+                            ..Default::default()
+                        };
+
+                        client
+                            .client
+                            .get_object(request)
+                            .await
+                            .map_err(|e| client.map_error(e))
+                    })
+                    .await
+            })
+            .await?;
+
+        let body = response.body.ok_or_else(|| S3Error::FileNotFound("No body".into()))?;
+        let digest = digest_reader::<Sha256>(body.into_async_read())
+            .await
+            .map_err(|e| S3Error::IoError(e.to_string()))?;
+        Ok(BASE64.encode(digest) == stored)
+    }
+
+    /// List all objects in the user's folder
+    pub async fn list_objects(&self, prefix: &str) -> Result<Vec<S3Object>, S3Error> {
+        let mut objects = Vec::new();
+        self.for_each_object(prefix, |obj| objects.push(obj)).await?;
+        Ok(objects)
+    }
+
+    /// List objects under a prefix, sorted in memory
+    pub async fn list_objects_sorted(
+        &self,
+        prefix: &str,
+        order: SortOrder,
+        dir: SortDir,
+    ) -> Result<Vec<S3Object>, S3Error> {
+        let mut objects = self.list_objects(prefix).await?;
+        sort_objects(&mut objects, order, dir);
+        Ok(objects)
+    }
+
+    /// Total size and object count under a prefix
+    pub async fn get_bucket_usage(&self, prefix: &str) -> Result<BucketUsage, S3Error> {
+        let mut usage = BucketUsage::default();
+        self.for_each_object(prefix, |obj| usage.add(obj)).await?;
+        Ok(usage)
+    }
+
+    /// Page through every object under a prefix, one listing page at a time
+    async fn for_each_object(
+        &self,
+        prefix: &str,
+        mut visit: impl FnMut(S3Object),
+    ) -> Result<(), S3Error> {
+        let full_prefix = self.full_key(prefix);
+        let mut continuation_token: Option<String> = None;
+
+        loop {
+            let request = ListObjectsV2Request {
+                bucket: self.bucket.clone(),
+                prefix: Some(full_prefix.clone()),
+                continuation_token: continuation_token.clone(),
+                encoding_type: Some(LIST_ENCODING_TYPE.to_string()),
+                ..Default::default()
+            };
+
+            let response = self
+                .client
+                .list_objects_v2(request)
+                .await
+                .map_err(|e| self.map_error(e))?;
+
+            let encoding_type = response.encoding_type.as_deref();
+            if let Some(contents) = response.contents {
+                for obj in contents {
+                    if let Some(key) = obj.key {
+                        visit(S3Object {
+                            key: self.relative_key(&key, encoding_type),
+                            size: obj.size.unwrap_or(0) as u64,
+                            last_modified: obj
+                                .last_modified
+                                .as_deref()
+                                .and_then(parse_timestamp)
+                                .unwrap_or(0),
+                            etag: obj.e_tag.as_deref().map(strip_etag_quotes),
+                            content_type: None,
+                            checksum_sha256: None,
+                        });
+                    }
+                }
+            }
+
+            if response.is_truncated.unwrap_or(false) {
+                continuation_token = response.next_continuation_token;
+            } else {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// List folders (common prefixes) at a given path
+    pub async fn list_folders(&self, prefix: &str) -> Result<Vec<String>, S3Error> {
+        collect_folder_pages(|token| self.list_folders_page(prefix, token)).await
+    }
+
+    /// One listing page (up to 1000 on S3) of the folders at `prefix`, starting at
+    /// `continuation_token`, and the token for the next page if there is one
+    async fn list_folders_page(
+        &self,
+        prefix: &str,
+        continuation_token: Option<String>,
+    ) -> Result<(Vec<String>, Option<String>), S3Error> {
+        let full_prefix = self.full_key(prefix);
+        let mut folders = Vec::new();
+
+        let request = ListObjectsV2Request {
+            bucket: self.bucket.clone(),
+            prefix: Some(full_prefix.clone()),
+            delimiter: Some("/".to_string()),
+            encoding_type: Some(LIST_ENCODING_TYPE.to_string()),
+            continuation_token,
+            ..Default::default()
+        };
+
+        let response = self
+            .client
+            .list_objects_v2(request)
+            .await
+            .map_err(|e| self.map_error(e))?;
+
+        let encoding_type = response.encoding_type.as_deref();
+        if let Some(common_prefixes) = response.common_prefixes {
+            for prefix in common_prefixes {
+                if let Some(p) = prefix.prefix {
+                    folders.push(self.relative_key(&p, encoding_type));
+                }
+            }
+        }
+
+        let next = if response.is_truncated.unwrap_or(false) {
+            response.next_continuation_token
+        } else {
+            None
+        };
+        Ok((folders, next))
+    }
+
+    /// Every folder below `root_prefix`, nested, with the size and file count of
+    /// everything inside each one. Lists one level at a time with `list_folders`.
+    pub async fn list_folder_tree(&self, root_prefix: &str) -> Result<Vec<CloudFolder>, S3Error> {
+        let children = discover_folders(root_prefix, |prefix| async move { self.list_folders(&prefix).await }).await?;
+        let objects = self.list_objects(root_prefix).await?;
+        Ok(folder_tree(root_prefix, &children, &objects))
+    }
+
+    /// Like `list_folder_tree`, but every folder in one list, parents before their
+    /// children, with `children` left empty
+    pub async fn list_folder_tree_flat(&self, root_prefix: &str) -> Result<Vec<CloudFolder>, S3Error> {
+        Ok(flatten_folders(self.list_folder_tree(root_prefix).await?))
+    }
+
+    /// Delete an object from S3
+    pub async fn delete_object(&self, remote_path: &str) -> Result<(), S3Error> {
+        let key = self.full_key(remote_path);
+
+        let request = DeleteObjectRequest {
+            bucket: self.bucket.clone(),
+            key,
+            ..Default::default()
+        };
+
+        self.client
+            .delete_object(request)
+            .await
+            .map_err(|e| match e {
+                RusotoError::Unknown(ref response)
+                    if is_object_locked(response.status.as_u16(), response.body_as_str()) =>
+                {
+                    S3Error::ObjectLocked(remote_path.to_string())
+                }
+                e => self.map_error(e),
+            })?;
+
+        Ok(())
+    }
+
+    /// Copy an object to a new key within the bucket, server-side
+    pub async fn copy_object(&self, source_path: &str, dest_path: &str) -> Result<(), S3Error> {
+        // The source is read with the customer key too, unless it was stored without SSE-C
+        match self.copy_object_from(source_path, dest_path, true).await {
+            Err(S3Error::SseCMismatch(e)) => {
+                log::debug!("Copy source isn't stored with SSE-C, reading it without: {}", e);
+                self.copy_object_from(source_path, dest_path, false).await
+            }
+            result => result,
+        }
+    }
+
+    /// Copy an object, sending the SSE-C key for the source only if `source_sse_c`.
+    /// The copy is always stored with this client's SSE-C key.
+    async fn copy_object_from(&self, source_path: &str, dest_path: &str, source_sse_c: bool) -> Result<(), S3Error> {
+        let copy_source = encode_copy_source(&self.bucket, &self.full_key(source_path));
+        let key = self.full_key(dest_path);
+
+        let (sse_customer_algorithm, sse_customer_key, sse_customer_key_md5) = self.sse_c_headers();
+        let (source_algorithm, source_key, source_key_md5) = if source_sse_c {
+            (sse_customer_algorithm.clone(), sse_customer_key.clone(), sse_customer_key_md5.clone())
+        } else {
+            (None, None, None)
+        };
+
+        self.with_retry(|| async {
+            let request = CopyObjectRequest {
+                bucket: self.bucket.clone(),
+                copy_source: copy_source.clone(),
+                key: key.clone(),
+                storage_class: Some(self.config.storage_class.as_str().to_string()),
+                copy_source_sse_customer_algorithm: source_algorithm.clone(),
+                copy_source_sse_customer_key: source_key.clone(),
+                copy_source_sse_customer_key_md5: source_key_md5.clone(),
+                acl: self.acl_header(),
+                sse_customer_algorithm: sse_customer_algorithm.clone(),
+                sse_customer_key: sse_customer_key.clone(),
+                sse_customer_key_md5: sse_customer_key_md5.clone(),
+                ..Default::default()
+            };
+
+            self.client
+                .copy_object(request)
+                .await
+                .map_err(|e| self.map_error(e))?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Replace the tags on an existing object
+    pub async fn put_object_tags(
+        &self,
+        remote_path: &str,
+        tags: HashMap<String, String>,
+    ) -> Result<(), S3Error> {
+        validate_tags(&tags)?;
+        let key = self.full_key(remote_path);
+        let tag_set: Vec<Tag> = tags
+            .into_iter()
+            .map(|(key, value)| Tag { key, value })
+            .collect();
+
+        self.with_retry(|| async {
+            let request = PutObjectTaggingRequest {
+                bucket: self.bucket.clone(),
+                key: key.clone(),
+                tagging: Tagging { tag_set: tag_set.clone() },
+                ..Default::default()
+            };
+
+            self.client
+                .put_object_tagging(request)
+                .await
+                .map_err(|e| self.map_error(e))?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Upload a file stored with `acl`, set in the same request as the upload
+    pub async fn upload_with_acl(&self, local_path: &Path, remote_path: &str, acl: ObjectCannedAcl) -> Result<(), S3Error> {
+        self.with_acl(Some(acl)).upload_file(local_path, remote_path).await
+    }
+
+    /// Upload a file encrypted server-side with `key` (SSE-C). Reading it back
+    /// takes the same key, e.g. with `download_file_with_sse_c`.
+    pub async fn upload_with_sse_c(&self, local_path: &Path, remote_path: &str, key: &[u8; 32]) -> Result<(), S3Error> {
+        self.with_sse_customer_key(Some(key)).upload_file(local_path, remote_path).await
+    }
+
+    /// Replace the ACL on an existing object with a canned one
+    pub async fn put_object_acl(&self, remote_path: &str, acl: ObjectCannedAcl) -> Result<(), S3Error> {
+        let key = self.full_key(remote_path);
+
+        self.with_retry(|| async {
+            let request = PutObjectAclRequest {
+                bucket: self.bucket.clone(),
+                key: key.clone(),
+                acl: Some(acl.as_str().to_string()),
+                ..Default::default()
+            };
+
+            self.client
+                .put_object_acl(request)
+                .await
+                .map_err(|e| self.map_error(e))?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// The canned ACL on an object, e.g. "private", or its grants when they match none
+    pub async fn get_object_acl(&self, remote_path: &str) -> Result<String, S3Error> {
+        let key = self.full_key(remote_path);
+
+        let response = self
+            .with_retry(|| async {
+                let request = GetObjectAclRequest {
+                    bucket: self.bucket.clone(),
+                    key: key.clone(),
+                    ..Default::default()
+                };
+
+                self.client
+                    .get_object_acl(request)
+                    .await
+                    .map_err(|e| self.map_error(e))
+            })
+            .await?;
+
+        let owner_id = response.owner.and_then(|owner| owner.id);
+        Ok(describe_acl(owner_id.as_deref(), &response.grants.unwrap_or_default()))
+    }
+
+    /// Get the tags on an object
+    pub async fn get_object_tags(&self, remote_path: &str) -> Result<HashMap<String, String>, S3Error> {
+        let key = self.full_key(remote_path);
+
+        let response = self
+            .with_retry(|| async {
+                let request = GetObjectTaggingRequest {
+                    bucket: self.bucket.clone(),
+                    key: key.clone(),
+                    ..Default::default()
+                };
+
+                self.client
+                    .get_object_tagging(request)
+                    .await
+                    .map_err(|e| self.map_error(e))
+            })
+            .await?;
+
+        Ok(response
+            .tag_set
+            .into_iter()
+            .map(|tag| (tag.key, tag.value))
+            .collect())
+    }
+
+    /// Delete all objects in the user's folder
+    pub async fn delete_all_objects(&self) -> Result<usize, S3Error> {
+        // First list all objects
+        let objects = self.list_objects("").await?;
+        let count = objects.len();
+
+        // Delete each object
+        for obj in objects {
+            let key = self.full_key(&obj.key);
+            let request = DeleteObjectRequest {
+                bucket: self.bucket.clone(),
+                key,
+                ..Default::default()
+            };
+
+            self.client
+                .delete_object(request)
+                .await
+                .map_err(|e| self.map_error(e))?;
+        }
+
+        Ok(count)
+    }
+
+    /// Get object metadata (size, last modified)
+    pub async fn get_object_info(&self, remote_path: &str) -> Result<S3Object, S3Error> {
+        let response = self.head_object(remote_path).await?;
+
+        Ok(S3Object {
+            key: remote_path.to_string(),
+            size: response.content_length.unwrap_or(0) as u64,
+            last_modified: response
+                .last_modified
+                .as_deref()
+                .and_then(parse_timestamp)
+                .unwrap_or(0),
+            etag: response.e_tag.as_deref().map(strip_etag_quotes),
+            content_type: response.content_type,
+            checksum_sha256: response
+                .metadata
+                .and_then(|mut metadata| metadata.remove(SHA256_METADATA_KEY)),
+        })
+    }
+
+    /// The object lock on `remote_path`, or None if it was uploaded without one
+    pub async fn get_object_lock_info(&self, remote_path: &str) -> Result<Option<ObjectLockInfo>, S3Error> {
+        let response = self.head_object(remote_path).await?;
+        let (Some(mode), Some(retain_until)) = (response.object_lock_mode, response.object_lock_retain_until_date)
+        else {
+            return Ok(None);
+        };
+        let retain_until = DateTime::parse_from_rfc3339(&retain_until)
+            .map_err(|e| S3Error::OperationFailed(format!("Invalid retain-until date {:?}: {}", retain_until, e)))?
+            .with_timezone(&Utc);
+        Ok(Some(ObjectLockInfo { mode, retain_until }))
+    }
+
+    /// HEAD `remote_path`, with a missing key reported as `FileNotFound`
+    async fn head_object(&self, remote_path: &str) -> Result<HeadObjectOutput, S3Error> {
+        self.with_sse_c_fallback(|client| async move {
+            let (sse_customer_algorithm, sse_customer_key, sse_customer_key_md5) = client.sse_c_headers();
+            let request = HeadObjectRequest {
+                bucket: client.bucket.clone(),
+                key: client.full_key(remote_path),
+                sse_customer_algorithm, sse_customer_key, sse_customer_key_md5,
+                ..Default::default()
+            };
+
+            client.client.head_object(request).await.map_err(|e| match e {
+                // HEAD responses have no body, so a missing key usually arrives as a bare 404
+                RusotoError::Service(HeadObjectError::NoSuchKey(_)) => S3Error::FileNotFound(remote_path.to_string()),
+                RusotoError::Unknown(ref response) if response.status.as_u16() == 404 => {
+                    S3Error::FileNotFound(remote_path.to_string())
+                }
+                e => client.map_error(e),
+            })
+        })
+        .await
+    }
+
+    /// Check with a single HEAD request whether a local file differs from its cloud copy
+    ///
+    /// S3's last_modified is the upload time, so the cloud copy counts as current
+    /// when the sizes match and it was uploaded at or after the local modification time.
+    pub async fn object_needs_upload(&self, local_path: &Path, remote_path: &str) -> Result<bool, S3Error> {
+        let metadata = tokio::fs::metadata(local_path)
+            .await
+            .map_err(|e| upload_source_error(local_path, e))?;
+
+        let remote = match self.get_object_info(remote_path).await {
+            Ok(remote) => remote,
+            Err(S3Error::FileNotFound(_)) => return Ok(true),
+            Err(e) => return Err(e),
+        };
+
+        Ok(differs_by_size_or_mtime(&metadata, &remote))
+    }
+
+    /// Whether a local file differs from a listed cloud object, comparing its MD5
+    /// with the ETag
+    ///
+    /// Multipart ETags (`<hash>-<parts>`) aren't a content MD5, and neither is the ETag
+    /// of an object stored with SSE-C, so with SSE-C on, and for multipart objects and
+    /// objects without an ETag, this falls back to comparing size and modification time.
+    pub async fn object_needs_upload_by_etag(&self, local_path: &Path, remote: &S3Object) -> Result<bool, S3Error> {
+        let metadata = tokio::fs::metadata(local_path)
+            .await
+            .map_err(|e| S3Error::IoError(e.to_string()))?;
+
+        match remote.etag.as_deref() {
+            Some(etag) if !etag.contains('-') && self.config.sse_customer_key.is_none() => {
+                if remote.size != metadata.len() {
+                    return Ok(true);
+                }
+                Ok(!file_md5(local_path).await?.eq_ignore_ascii_case(etag))
+            }
+            _ => Ok(differs_by_size_or_mtime(&metadata, remote)),
+        }
+    }
+}
+
+/// Whether the cloud copy has a different size or was uploaded before the local file last changed
+pub(crate) fn differs_by_size_or_mtime(metadata: &std::fs::Metadata, remote: &S3Object) -> bool {
+    if remote.size != metadata.len() {
+        return true;
+    }
+
+    let local_modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(i64::MAX);
+
+    remote.last_modified < local_modified
+}
+
+/// What a conditional PUT requires of the object stored under its key
+#[derive(Debug, Clone, PartialEq)]
+enum Precondition {
+    /// Nothing is stored under the key
+    Absent,
+    /// The stored object has this ETag
+    ETag(String),
+}
+
+/// Result of a conditional PUT
+#[derive(Debug, PartialEq)]
+enum ConditionalPut {
+    Written,
+    /// The precondition failed: something is already stored under the key, or it changed
+    Exists,
+    /// The store doesn't support conditional writes
+    Unsupported,
+}
+
+/// Read a conditional PUT's response; None for any other error
+fn conditional_put_outcome(status: u16, body: &str) -> Option<ConditionalPut> {
+    match status {
+        200..=299 => Some(ConditionalPut::Written),
+        // 409 is AWS losing a race with a concurrent conditional write
+        412 | 409 => Some(ConditionalPut::Exists),
+        501 => Some(ConditionalPut::Unsupported),
+        _ if body.contains("<Code>NotImplemented</Code>") => Some(ConditionalPut::Unsupported),
+        _ => None,
+    }
+}
+
+/// Whether a failed request was refused because the object is under an object lock.
+/// AWS answers 403 AccessDenied mentioning the lock; MinIO has its own error code.
+fn is_object_locked(status: u16, body: &str) -> bool {
+    body.contains("<Code>ObjectLocked</Code>")
+        || (status == 403 && body.to_ascii_lowercase().contains("object lock"))
+}
+
+/// Hex MD5 of a file, read in chunks
+async fn file_md5(path: &Path) -> Result<String, S3Error> {
+    let file = File::open(path)
+        .await
+        .map_err(|e| S3Error::IoError(e.to_string()))?;
+    let digest = digest_reader::<Md5>(file)
+        .await
+        .map_err(|e| S3Error::IoError(e.to_string()))?;
+    Ok(hex::encode(digest))
+}
+
+/// Base64 SHA-256 of a file, read in chunks
+async fn file_sha256(path: &Path) -> Result<String, S3Error> {
+    let file = File::open(path)
+        .await
+        .map_err(|e| S3Error::IoError(e.to_string()))?;
+    let digest = digest_reader::<Sha256>(file)
+        .await
+        .map_err(|e| S3Error::IoError(e.to_string()))?;
+    Ok(BASE64.encode(digest))
+}
+
+/// Inclusive byte ranges covering `size` bytes in `chunk_size` pieces; the last may be shorter
+fn chunk_ranges(size: u64, chunk_size: u64) -> Vec<(u64, u64)> {
+    let chunk_size = chunk_size.max(1);
+    (0..size)
+        .step_by(chunk_size as usize)
+        .map(|start| (start, (start + chunk_size).min(size) - 1))
+        .collect()
+}
+
+/// Fetch each chunk of a `size`-byte object, `parallelism` at a time, writing it at its offset
+async fn write_chunks<W, F, Fut>(
+    writer: &mut W,
+    size: u64,
+    chunk_size: u64,
+    parallelism: usize,
+    fetch: F,
+    on_progress: &(impl Fn(u64, u64) + Send),
+) -> Result<(), S3Error>
+where
+    W: AsyncWrite + AsyncSeek + Unpin,
+    F: Fn(u64, u64) -> Fut,
+    Fut: Future<Output = Result<Vec<u8>, S3Error>>,
+{
+    let mut chunks = futures::stream::iter(chunk_ranges(size, chunk_size))
+        .map(|(start, end)| {
+            let chunk = fetch(start, end);
+            async move { chunk.await.map(|bytes| (start, end, bytes)) }
+        })
+        .buffer_unordered(parallelism.max(1));
+
+    let mut received = 0u64;
+    while let Some((start, end, bytes)) = chunks.try_next().await? {
+        if bytes.len() as u64 != end - start + 1 {
+            return Err(S3Error::OperationFailed(format!(
+                "Range {}-{} returned {} bytes",
+                start,
+                end,
+                bytes.len()
+            )));
+        }
+        writer
+            .seek(SeekFrom::Start(start))
+            .await
+            .map_err(|e| S3Error::IoError(e.to_string()))?;
+        writer
+            .write_all(&bytes)
+            .await
+            .map_err(|e| S3Error::IoError(e.to_string()))?;
+        received += bytes.len() as u64;
+        on_progress(received, size);
+    }
+    Ok(())
+}
+
+/// Digest of everything `reader` yields
+async fn digest_reader<D: Digest>(mut reader: impl AsyncRead + Unpin) -> std::io::Result<Vec<u8>> {
+    let mut hasher = D::new();
+    let mut buffer = vec![0u8; DOWNLOAD_CHUNK_SIZE];
+    loop {
+        let n = reader.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// User metadata carrying the base64 SHA-256 of `data`
+fn checksum_metadata(data: &[u8]) -> HashMap<String, String> {
+    HashMap::from([(SHA256_METADATA_KEY.to_string(), BASE64.encode(Sha256::digest(data)))])
+}
+
+/// Parse an S3 timestamp (ISO 8601 in listings, RFC 2822 in HEAD/GET headers)
+fn parse_timestamp(value: &str) -> Option<i64> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .or_else(|_| chrono::DateTime::parse_from_rfc2822(value))
+        .ok()
+        .map(|dt| dt.timestamp())
+}
+
+/// Read up to `size` bytes, stopping early only at end of file
+async fn read_chunk(file: &mut File, size: usize) -> Result<Vec<u8>, S3Error> {
+    let mut buffer = vec![0u8; size];
+    let mut filled = 0;
+    while filled < size {
+        let n = file
+            .read(&mut buffer[filled..])
+            .await
+            .map_err(|e| S3Error::IoError(e.to_string()))?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    buffer.truncate(filled);
+    Ok(buffer)
+}
+
+/// Copy a buffered reader into a writer one buffer at a time,
+/// reporting the running byte count after each chunk is written
+pub(crate) async fn copy_with_progress<R, W>(
+    mut reader: R,
+    writer: &mut W,
+    total_bytes: u64,
+    on_progress: &(impl Fn(u64, u64) + Send),
+) -> std::io::Result<u64>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut received = 0u64;
+    loop {
+        let chunk = reader.fill_buf().await?;
+        if chunk.is_empty() {
+            break;
+        }
+        let n = chunk.len();
+        writer.write_all(chunk).await?;
+        reader.consume(n);
+
+        received += n as u64;
+        on_progress(received, total_bytes.max(received));
+    }
+    Ok(received)
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct S3Object {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: i64,
+    /// ETag without the surrounding quotes: the content MD5 for single-part
+    /// uploads, `<hash>-<parts>` for multipart ones
+    #[serde(default)]
+    pub etag: Option<String>,
+    /// Content-Type, only known from a HEAD request
+    #[serde(default)]
+    pub content_type: Option<String>,
+    /// Base64 SHA-256 stored with single-PUT uploads, only known from a HEAD request
+    #[serde(default)]
+    pub checksum_sha256: Option<String>,
+}
+
+/// How far a request to the bucket gets
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ConnectionStatus {
+    pub reachable: bool,
+    pub bucket_exists: bool,
+    pub credentials_valid: bool,
+}
+
+/// Read a connection status from the result of a HeadBucket request
+fn connection_status<E: std::error::Error>(head: Result<(), RusotoError<E>>) -> ConnectionStatus {
+    let status = |reachable, bucket_exists, credentials_valid| ConnectionStatus {
+        reachable,
+        bucket_exists,
+        credentials_valid,
+    };
+    match head {
+        Ok(()) => status(true, true, true),
+        Err(RusotoError::HttpDispatch(_)) => status(false, false, false),
+        Err(ref e) if is_no_such_bucket(e) => status(true, false, true),
+        // 403 means the bucket exists but these credentials can't use it
+        Err(RusotoError::Unknown(ref response)) if response.status.as_u16() == 403 => status(true, true, false),
+        Err(_) => status(true, false, false),
+    }
+}
+
+// Key of the object `warm_up` writes and deletes again with `probe_upload`, followed by random hex
+const WARMUP_PROBE_PREFIX: &str = ".warmup_probe_";
+
+/// Latency to the bucket as measured by `S3Client::warm_up`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ConnectionInfo {
+    pub rtt_ms: u64,
+    /// None unless the warm-up was asked to time an upload
+    pub upload_latency_ms: Option<u64>,
+    pub endpoint: String,
+    /// Where the bucket actually is, or the configured region if that can't be detected
+    pub region: String,
+    pub bucket: String,
+    /// `SyncConfig::concurrency` worth using at this round-trip time
+    pub suggested_concurrency: usize,
+}
+
+impl ConnectionInfo {
+    fn new(rtt_ms: u64, upload_latency_ms: Option<u64>, client: &S3Client, region: String) -> Self {
+        Self {
+            rtt_ms,
+            upload_latency_ms,
+            endpoint: client.endpoint().to_string(),
+            region,
+            bucket: client.bucket().to_string(),
+            suggested_concurrency: suggested_concurrency(rtt_ms),
+        }
+    }
+}
+
+/// Parallel transfers to suggest for a round-trip time: a fast link keeps many
+/// transfers busy, while on a slow one extra transfers mostly sit waiting
+pub fn suggested_concurrency(rtt_ms: u64) -> usize {
+    match rtt_ms {
+        0..=20 => 16,
+        21..=50 => 8,
+        51..=150 => 4,
+        _ => 2,
+    }
+}
+
+/// A folder in the bucket, with totals for everything below it
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CloudFolder {
+    pub name: String,
+    pub path: String,
+    pub total_size: u64,
+    pub file_count: usize,
+    /// Subfolders, only filled in by `list_folder_tree`
+    #[serde(default)]
+    pub children: Vec<CloudFolder>,
+    /// Whether `total_size` and `file_count` were computed; folders from
+    /// `list_cloud_folders_page` leave them at 0 until `load_folder_size`
+    #[serde(default)]
+    pub size_loaded: bool,
+}
+
+/// Every folder from a paged listing, following continuation tokens until
+/// `list_page` returns none
+async fn collect_folder_pages<L, Fut>(mut list_page: L) -> Result<Vec<String>, S3Error>
+where
+    L: FnMut(Option<String>) -> Fut,
+    Fut: Future<Output = Result<(Vec<String>, Option<String>), S3Error>>,
+{
+    let mut folders = Vec::new();
+    let mut token = None;
+    loop {
+        let (page, next) = list_page(token).await?;
+        folders.extend(page);
+        match next {
+            Some(next) => token = Some(next),
+            None => return Ok(folders),
+        }
+    }
+}
+
+/// Subfolders of `root_prefix` and of everything below it, keyed by parent prefix.
+/// Each prefix is listed at most once, however often it turns up.
+async fn discover_folders<L, Fut>(root_prefix: &str, mut list_folders: L) -> Result<HashMap<String, Vec<String>>, S3Error>
+where
+    L: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<Vec<String>, S3Error>>,
+{
+    let mut children: HashMap<String, Vec<String>> = HashMap::new();
+    let mut pending = vec![root_prefix.to_string()];
+    while let Some(prefix) = pending.pop() {
+        if children.contains_key(&prefix) {
+            continue;
+        }
+        let folders = list_folders(prefix.clone()).await?;
+        pending.extend(folders.iter().cloned());
+        children.insert(prefix, folders);
+    }
+    Ok(children)
+}
+
+/// Nest the folders found by `discover_folders`, totalling `objects` into each one
+fn folder_tree(prefix: &str, children: &HashMap<String, Vec<String>>, objects: &[S3Object]) -> Vec<CloudFolder> {
+    let Some(folders) = children.get(prefix) else {
+        return Vec::new();
+    };
+    folders
+        .iter()
+        .map(|path| {
+            let contents: Vec<&S3Object> = objects.iter().filter(|o| o.key.starts_with(path.as_str())).collect();
+            CloudFolder {
+                name: path.trim_end_matches('/').rsplit('/').next().unwrap_or_default().to_string(),
+                path: path.clone(),
+                total_size: contents.iter().map(|o| o.size).sum(),
+                file_count: contents.len(),
+                children: folder_tree(path, children, objects),
+                size_loaded: true,
+            }
+        })
+        .collect()
+}
+
+/// Depth-first list of a folder tree, each folder followed by its subfolders
+fn flatten_folders(folders: Vec<CloudFolder>) -> Vec<CloudFolder> {
+    let mut flat = Vec::new();
+    for mut folder in folders {
+        let children = std::mem::take(&mut folder.children);
+        flat.push(folder);
+        flat.extend(flatten_folders(children));
+    }
+    flat
+}
+
+/// An ETag as S3 returns it, minus the double quotes around it
+fn strip_etag_quotes(etag: &str) -> String {
+    etag.trim_matches('"').to_string()
+}
+
+/// Percent-encode everything except RFC 3986 unreserved characters and `keep`
+fn percent_encode(value: &str, keep: &[u8]) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ if keep.contains(&byte) => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Decode a key from a listing made with `encoding-type=url`: `%XX` escapes of the
+/// UTF-8 bytes, with spaces as `+`
+fn decode_listed_key(key: &str) -> String {
+    let hex = |byte: u8| (byte as char).to_digit(16);
+    let bytes = key.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1).copied().and_then(hex), bytes.get(i + 2).copied().and_then(hex)) {
+            (b'%', Some(high), Some(low)) => {
+                decoded.push((high * 16 + low) as u8);
+                i += 3;
+            }
+            (b'+', ..) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, ..) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Build the `x-amz-copy-source` value ("bucket/key"), percent-encoding the key
+fn encode_copy_source(bucket: &str, key: &str) -> String {
+    format!("{}/{}", bucket, percent_encode(key, b"/"))
+}
+
+/// Encode tags as the `x-amz-tagging` query string ("k1=v1&k2=v2"), sorted by key
+fn encode_tagging(tags: &HashMap<String, String>) -> String {
+    let mut pairs: Vec<_> = tags.iter().collect();
+    pairs.sort();
+    pairs
+        .into_iter()
+        .map(|(k, v)| format!("{}={}", percent_encode(k, b""), percent_encode(v, b"")))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Check tags against S3's limits: at most 10 tags, keys up to 128
+/// characters and values up to 256
+pub fn validate_tags(tags: &HashMap<String, String>) -> Result<(), S3Error> {
+    if tags.len() > MAX_TAGS_PER_OBJECT {
+        return Err(S3Error::InvalidTag(format!(
+            "{} tags given, at most {} are allowed",
+            tags.len(),
+            MAX_TAGS_PER_OBJECT
+        )));
+    }
+    for (key, value) in tags {
+        if key.is_empty() {
+            return Err(S3Error::InvalidTag("tag keys cannot be empty".to_string()));
+        }
+        if key.chars().count() > MAX_TAG_KEY_LEN {
+            return Err(S3Error::InvalidTag(format!(
+                "key \"{}\" is longer than {} characters",
+                key, MAX_TAG_KEY_LEN
+            )));
+        }
+        if value.chars().count() > MAX_TAG_VALUE_LEN {
+            return Err(S3Error::InvalidTag(format!(
+                "value for \"{}\" is longer than {} characters",
+                key, MAX_TAG_VALUE_LEN
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// `value` made fit for a tag value: characters S3 doesn't allow in tags replaced
+/// with `_`, and cut to 256 characters
+pub fn sanitize_tag_value(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == ' ' || "+-=._:/@".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(MAX_TAG_VALUE_LEN)
+        .collect()
+}
+
+// Marks the temp key an atomic upload writes to before it is copied into place
+const TEMP_UPLOAD_MARKER: &str = ".tmp_";
+// Hex digits of the random suffix after the marker
+const TEMP_UPLOAD_SUFFIX_LEN: usize = 16;
+// Temp uploads older than this are left over from crashed sessions
+const STALE_TEMP_UPLOAD_SECS: i64 = 24 * 60 * 60;
+
+/// Temp key for an atomic upload to `remote_path`: `{remote_path}.tmp_{16 hex digits}`
+fn temp_upload_path(remote_path: &str) -> String {
+    format!(
+        "{}{}{:0width$x}",
+        remote_path,
+        TEMP_UPLOAD_MARKER,
+        rand::random::<u64>(),
+        width = TEMP_UPLOAD_SUFFIX_LEN
+    )
+}
+
+/// Deletes an atomic upload's temp key if the upload is dropped before finishing,
+/// e.g. when the sync is cancelled
+struct TempUploadGuard {
+    client: RusotoS3Client,
+    bucket: String,
+    key: Option<String>,
+}
+
+impl TempUploadGuard {
+    /// The upload ran to the end and cleans up after itself
+    fn disarm(mut self) {
+        self.key = None;
+    }
+}
+
+impl Drop for TempUploadGuard {
+    fn drop(&mut self) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        // Drop can't wait for the delete, so it runs in the background
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let (client, bucket) = (self.client.clone(), self.bucket.clone());
+        runtime.spawn(async move {
+            let request = DeleteObjectRequest {
+                bucket,
+                key: key.clone(),
+                ..Default::default()
+            };
+            if let Err(e) = client.delete_object(request).await {
+                log::warn!("Failed to delete temp upload {}: {}", key, e);
+            }
+        });
+    }
+}
+
+/// Whether `obj` is an atomic upload temp object older than a day. Only the exact
+/// suffix `temp_upload_path` writes counts, so user files are never mistaken for one.
+fn is_stale_temp_upload(obj: &S3Object, now: i64) -> bool {
+    let is_temp = match obj.key.rsplit_once(TEMP_UPLOAD_MARKER) {
+        Some((_, suffix)) => {
+            suffix.len() == TEMP_UPLOAD_SUFFIX_LEN
+                && suffix.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+        }
+        None => false,
+    };
+    is_temp && now - obj.last_modified > STALE_TEMP_UPLOAD_SECS
+}
+
+/// Key to sort listed objects by
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+pub enum SortOrder {
+    ByName,
+    BySize,
+    ByLastModified,
+}
+
+impl std::str::FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(SortOrder::ByName),
+            "size" => Ok(SortOrder::BySize),
+            "last_modified" => Ok(SortOrder::ByLastModified),
+            _ => Err(format!("Unknown sort key: {} (expected name, size or last_modified)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortDir {
+    Asc,
+    Desc,
+}
+
+impl std::str::FromStr for SortDir {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "asc" => Ok(SortDir::Asc),
+            "desc" => Ok(SortDir::Desc),
+            _ => Err(format!("Unknown sort direction: {} (expected asc or desc)", s)),
+        }
+    }
+}
+
+/// Sort objects in place; ties are broken by key so the order is stable
+fn sort_objects(objects: &mut [S3Object], order: SortOrder, dir: SortDir) {
+    objects.sort_by(|a, b| {
+        let ordering = match order {
+            SortOrder::ByName => a.key.cmp(&b.key),
+            SortOrder::BySize => a.size.cmp(&b.size).then_with(|| a.key.cmp(&b.key)),
+            SortOrder::ByLastModified => a
+                .last_modified
+                .cmp(&b.last_modified)
+                .then_with(|| a.key.cmp(&b.key)),
+        };
+        match dir {
+            SortDir::Asc => ordering,
+            SortDir::Desc => ordering.reverse(),
+        }
+    });
+}
+
+/// Running totals for the objects under a prefix
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct BucketUsage {
+    pub total_bytes: u64,
+    pub object_count: u64,
+    pub largest_object: Option<S3Object>,
+}
+
+impl BucketUsage {
+    /// Account for one more object
+    fn add(&mut self, obj: S3Object) {
+        self.total_bytes += obj.size;
+        self.object_count += 1;
+        let is_largest = match &self.largest_object {
+            Some(largest) => obj.size > largest.size,
+            None => true,
+        };
+        if is_largest {
+            self.largest_object = Some(obj);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn sample_objects() -> Vec<S3Object> {
+        // Sizes and dates deliberately don't follow the key order
+        (0..20)
+            .map(|i| S3Object {
+                key: format!("photos/img_{:02}.jpg", i),
+                size: (i * 7 % 20) as u64 * 1000,
+                last_modified: 1_700_000_000 + (i * 13 % 20) as i64 * 60,
+                etag: None,
+                content_type: None,
+                checksum_sha256: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sort_objects_by_each_key() {
+        let mut objects = sample_objects();
+
+        sort_objects(&mut objects, SortOrder::ByName, SortDir::Desc);
+        assert!(objects.windows(2).all(|w| w[0].key > w[1].key));
+        sort_objects(&mut objects, SortOrder::ByName, SortDir::Asc);
+        assert!(objects.windows(2).all(|w| w[0].key < w[1].key));
+
+        sort_objects(&mut objects, SortOrder::BySize, SortDir::Desc);
+        assert!(objects.windows(2).all(|w| w[0].size >= w[1].size));
+        assert_eq!(objects[0].size, 19_000);
+        sort_objects(&mut objects, SortOrder::BySize, SortDir::Asc);
+        assert!(objects.windows(2).all(|w| w[0].size <= w[1].size));
+
+        sort_objects(&mut objects, SortOrder::ByLastModified, SortDir::Desc);
+        assert!(objects.windows(2).all(|w| w[0].last_modified >= w[1].last_modified));
+        sort_objects(&mut objects, SortOrder::ByLastModified, SortDir::Asc);
+        assert!(objects.windows(2).all(|w| w[0].last_modified <= w[1].last_modified));
+        assert_eq!(objects.len(), 20);
+    }
+
+    #[test]
+    fn test_stale_temp_uploads() {
+        let now = 1_700_000_000;
+        let temp_path = temp_upload_path("docs/report.pdf");
+        assert!(temp_path.starts_with("docs/report.pdf.tmp_"));
+
+        let object = |key: &str, age_secs: i64| S3Object {
+            key: key.to_string(),
+            size: 10,
+            last_modified: now - age_secs,
+            etag: None,
+            content_type: None,
+            checksum_sha256: None,
+        };
+        assert!(is_stale_temp_upload(&object(&temp_path, 25 * 3600), now));
+        // Recent temp uploads may still be in progress
+        assert!(!is_stale_temp_upload(&object(&temp_path, 3600), now));
+        // Regular files that happen to contain the marker are left alone
+        assert!(!is_stale_temp_upload(&object("notes.tmp_draft.txt", 25 * 3600), now));
+        assert!(!is_stale_temp_upload(&object("data.tmp_cafe", 25 * 3600), now));
+        assert!(!is_stale_temp_upload(&object("x.tmp_1", 25 * 3600), now));
+        assert!(!is_stale_temp_upload(&object("x.tmp_0123456789ABCDEF", 25 * 3600), now));
+        assert!(!is_stale_temp_upload(&object("x.tmp_0123456789abcdef0", 25 * 3600), now));
+        assert!(!is_stale_temp_upload(&object("docs/report.pdf", 25 * 3600), now));
+    }
+
+    #[test]
+    fn test_parse_sort_options() {
+        assert_eq!("size".parse::<SortOrder>(), Ok(SortOrder::BySize));
+        assert_eq!("last_modified".parse::<SortOrder>(), Ok(SortOrder::ByLastModified));
+        assert!("date".parse::<SortOrder>().is_err());
+        assert_eq!("desc".parse::<SortDir>(), Ok(SortDir::Desc));
+        assert!("DESC".parse::<SortDir>().is_err());
+    }
+
+    #[test]
+    fn test_builder_defaults() {
+        let builder = S3ClientBuilder::default();
+        assert_eq!(builder.config, S3ClientConfig::default());
+        assert_eq!(builder.config.user_prefix, "");
+        assert_eq!(builder.config.retry_policy.max_retries, 3);
+        assert_eq!(builder.config.storage_class, StorageClass::Standard);
+        assert_eq!(builder.config.max_concurrency, 4);
+    }
+
+    #[test]
+    fn test_builder_overrides() {
+        let builder = S3ClientBuilder::new()
+            .user_prefix("users/u_123/")
+            .retry_policy(RetryPolicy { max_retries: 0, base_delay_ms: 10 })
+            .storage_class(StorageClass::Glacier)
+            .max_concurrency(0);
+
+        assert_eq!(builder.config.user_prefix, "users/u_123/");
+        assert_eq!(builder.config.retry_policy.max_retries, 0);
+        assert_eq!(builder.config.storage_class.as_str(), "GLACIER");
+        // Concurrency is clamped to at least one request
+        assert_eq!(builder.config.max_concurrency, 1);
+    }
+
+    #[test]
+    fn test_optimal_part_size() {
+        const MIB: u64 = 1024 * 1024;
+        // Small files use the minimum
+        assert_eq!(S3Client::optimal_part_size(0), MIN_MULTIPART_PART_SIZE);
+        assert_eq!(S3Client::optimal_part_size(100 * MIB), MIN_MULTIPART_PART_SIZE);
+        // Up to 10,000 minimum parts
+        assert_eq!(S3Client::optimal_part_size(50_000 * MIB), MIN_MULTIPART_PART_SIZE);
+        // Past that, parts grow just enough to fit
+        let size = 50_000 * MIB + 1;
+        let part = S3Client::optimal_part_size(size);
+        assert_eq!(part, MIN_MULTIPART_PART_SIZE + 1);
+        assert!(size.div_ceil(part as u64) <= MAX_MULTIPART_PARTS);
+        let size = 1024 * 1024 * MIB;
+        let part = S3Client::optimal_part_size(size) as u64;
+        assert!(size.div_ceil(part) <= MAX_MULTIPART_PARTS);
+        assert!(size.div_ceil(part - 1) > MAX_MULTIPART_PARTS);
+        // Never above the 5 GiB limit, however large the file
+        assert_eq!(S3Client::optimal_part_size(u64::MAX) as u64, MAX_MULTIPART_PART_SIZE);
+
+        // The configured size is used unless the file needs larger parts
+        let client = S3ClientBuilder::new().unscoped().part_size(16 * MIB as usize).build().unwrap();
+        assert_eq!(client.part_size_for(100 * MIB), 16 * MIB as usize);
+        assert_eq!(client.part_size_for(1024 * 1024 * MIB), S3Client::optimal_part_size(1024 * 1024 * MIB));
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 5 MiB")]
+    fn test_builder_rejects_small_parts() {
+        let _ = S3ClientBuilder::new().part_size(MIN_MULTIPART_PART_SIZE - 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "at most 5 GiB")]
+    fn test_builder_rejects_large_parts() {
+        let _ = S3ClientBuilder::new().part_size(MAX_MULTIPART_PART_SIZE as usize + 1);
+    }
+
+    #[test]
+    fn test_builder_from_config() {
+        let app_config = AppConfig {
+            storage_class: StorageClass::OneZoneIa,
+            max_concurrency: 8,
+            provider: S3ProviderConfig::backblaze_b2("photos", "us-west-004"),
+            ..Default::default()
+        };
+        let builder = S3ClientBuilder::from_config(&app_config).user_prefix("x/");
+
+        assert_eq!(builder.config.provider.provider, S3Provider::BackblazeB2);
+        assert_eq!(builder.config.storage_class, StorageClass::OneZoneIa);
+        assert_eq!(builder.config.max_concurrency, 8);
+        assert_eq!(builder.config.user_prefix, "x/");
+    }
+
+    #[tokio::test]
+    async fn test_read_chunk_splits_on_part_boundaries() {
+        let path = std::env::temp_dir().join(format!("s3_chunk_{}", std::process::id()));
+        tokio::fs::write(&path, vec![7u8; DEFAULT_MULTIPART_PART_SIZE * 2 + 10]).await.unwrap();
+
+        let mut file = File::open(&path).await.unwrap();
+        let mut sizes = Vec::new();
+        loop {
+            let chunk = read_chunk(&mut file, DEFAULT_MULTIPART_PART_SIZE).await.unwrap();
+            if chunk.is_empty() {
+                break;
+            }
+            sizes.push(chunk.len());
+        }
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        assert_eq!(sizes, vec![DEFAULT_MULTIPART_PART_SIZE, DEFAULT_MULTIPART_PART_SIZE, 10]);
+    }
+
+    #[tokio::test]
+    async fn test_copy_with_progress_reports_each_chunk() {
+        let data = vec![1u8; DOWNLOAD_CHUNK_SIZE * 3 + 100];
+        let reader = BufReader::with_capacity(DOWNLOAD_CHUNK_SIZE, &data[..]);
+        let mut output = Vec::new();
+
+        let calls = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        let copied = copy_with_progress(reader, &mut output, data.len() as u64, &move |received, total| {
+            recorded.lock().unwrap().push((received, total));
+        })
+        .await
+        .unwrap();
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(copied, data.len() as u64);
+        assert_eq!(output, data);
+        assert_eq!(calls.len(), 4);
+        assert_eq!(calls.last(), Some(&(data.len() as u64, data.len() as u64)));
+    }
+
+    #[test]
+    fn test_parse_timestamp_formats() {
+        assert_eq!(parse_timestamp("2025-11-28T12:00:00.000Z"), Some(1764331200));
+        assert_eq!(parse_timestamp("Fri, 28 Nov 2025 12:00:00 GMT"), Some(1764331200));
+        assert_eq!(parse_timestamp("not a date"), None);
+    }
+
+    /// Client for a local server answering every request with `status`, and how many
+    /// requests it has answered
+    async fn status_server(status: u16) -> (S3Client, Arc<AtomicUsize>) {
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let counter = Arc::clone(&counter);
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    let mut line = String::new();
+                    while stream.read_line(&mut line).await.unwrap_or(0) > 0 {
+                        let mut content_length = 0;
+                        loop {
+                            let mut header = String::new();
+                            if stream.read_line(&mut header).await.unwrap_or(0) == 0 {
+                                return;
+                            }
+                            let header = header.trim_end();
+                            if header.is_empty() {
+                                break;
+                            }
+                            if let Some((name, value)) = header.split_once(':') {
+                                if name.eq_ignore_ascii_case("content-length") {
+                                    content_length = value.trim().parse().unwrap_or(0);
+                                }
+                            }
+                        }
+                        let mut body = vec![0; content_length];
+                        if stream.read_exact(&mut body).await.is_err() {
+                            return;
+                        }
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        let response = format!("HTTP/1.1 {} Status\r\nContent-Length: 0\r\n\r\n", status);
+                        if stream.get_mut().write_all(response.as_bytes()).await.is_err() {
+                            return;
+                        }
+                        line.clear();
+                    }
+                });
+            }
+        });
+
+        let client = S3ClientBuilder::new()
+            .unscoped()
+            .retry_policy(RetryPolicy { max_retries: 2, base_delay_ms: 1 })
+            .destination(S3Destination {
+                endpoint,
+                bucket: "mock".to_string(),
+                access_key: "key".to_string(),
+                secret_key: "secret".to_string(),
+            })
+            .build()
+            .unwrap();
+        (client, requests)
+    }
+
+    #[tokio::test]
+    async fn test_only_server_errors_are_retried() {
+        let upload = |client: S3Client| async move { client.upload_bytes(b"data", "k", "text/plain").await };
+
+        // A refused request is sent once
+        for status in [400, 403] {
+            let (client, requests) = status_server(status).await;
+            let result = upload(client).await;
+            assert!(matches!(result, Err(S3Error::RequestRejected(_))), "{}: {:?}", status, result);
+            assert_eq!(requests.load(Ordering::SeqCst), 1);
+        }
+
+        // Server errors and throttling with 503 are tried again
+        for status in [500, 503] {
+            let (client, requests) = status_server(status).await;
+            let result = upload(client).await;
+            assert!(matches!(result, Err(S3Error::OperationFailed(_))), "{}: {:?}", status, result);
+            assert_eq!(requests.load(Ordering::SeqCst), 3);
+        }
+
+        // A request that got no response is retried like a server error
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let client = S3ClientBuilder::new()
+            .unscoped()
+            .retry_policy(RetryPolicy { max_retries: 2, base_delay_ms: 1 })
+            .destination(S3Destination {
+                endpoint,
+                bucket: "mock".to_string(),
+                access_key: "key".to_string(),
+                secret_key: "secret".to_string(),
+            })
+            .build()
+            .unwrap();
+        assert!(matches!(upload(client).await, Err(S3Error::OperationFailed(_))));
+    }
+
+    #[test]
+    fn test_retry_delay_backoff() {
+        let policy = RetryPolicy { max_retries: 3, base_delay_ms: 100 };
+        assert_eq!(policy.delay_for(0), Duration::from_millis(100));
+        assert_eq!(policy.delay_for(2), Duration::from_millis(400));
+    }
+
+    #[test]
+    fn test_bucket_usage_accumulates() {
+        let mut usage = BucketUsage::default();
+        for (i, size) in [100u64, 2048, 0, 4096, 512].into_iter().enumerate() {
+            usage.add(S3Object { key: format!("folder/file{}.bin", i), size, last_modified: 0, etag: None, content_type: None, checksum_sha256: None });
+        }
+
+        assert_eq!(usage.object_count, 5);
+        assert_eq!(usage.total_bytes, 6756);
+        assert_eq!(usage.largest_object.unwrap().key, "folder/file3.bin");
+        assert!(BucketUsage::default().largest_object.is_none());
+    }
+
+    #[test]
+    fn test_provider_endpoints() {
+        let scaleway = S3ProviderConfig::scaleway();
+        assert_eq!(scaleway.provider, S3Provider::Scaleway);
+        assert_eq!(scaleway.endpoint, "https://s3.nl-ams.scw.cloud");
+        assert_eq!(scaleway.region, "nl-ams");
+        assert_eq!(S3ProviderConfig::default(), scaleway);
+
+        let b2 = S3ProviderConfig::backblaze_b2("photos", "us-west-004");
+        assert_eq!(b2.provider, S3Provider::BackblazeB2);
+        assert_eq!(b2.endpoint, "https://s3.us-west-004.backblazeb2.com");
+        assert_eq!(b2.region, "us-west-004");
+        assert_eq!(b2.bucket, "photos");
+        assert_eq!(region_from_endpoint(&b2.endpoint), b2.region);
+
+        let aws = S3ProviderConfig::aws("photos", "eu-central-1");
+        assert_eq!(aws.provider, S3Provider::Aws);
+        assert_eq!(aws.endpoint, "https://s3.eu-central-1.amazonaws.com");
+        assert_eq!(aws.region, "eu-central-1");
+
+        let minio = S3ProviderConfig::custom(S3Provider::Minio, "http://nas.local:9000", "us-east-1", "backup");
+        assert_eq!(minio.endpoint, "http://nas.local:9000");
+        assert_eq!(minio.bucket, "backup");
+    }
+
+    #[test]
+    fn test_quoted_etag() {
+        assert_eq!(quoted_etag("5d41402abc4b2a76b9719d911017c592"), "\"5d41402abc4b2a76b9719d911017c592\"");
+        assert_eq!(quoted_etag("\"9b2cf535f27731c974343645a3985328-3\""), "\"9b2cf535f27731c974343645a3985328-3\"");
+    }
+
+    #[test]
+    fn test_strip_etag_quotes() {
+        assert_eq!(strip_etag_quotes("\"5d41402abc4b2a76b9719d911017c592\""), "5d41402abc4b2a76b9719d911017c592");
+        assert_eq!(strip_etag_quotes("\"9b2cf535f27731c974343645a3985328-3\""), "9b2cf535f27731c974343645a3985328-3");
+        assert_eq!(strip_etag_quotes("unquoted"), "unquoted");
+    }
+
+    #[tokio::test]
+    async fn test_object_needs_upload_by_etag() {
+        let path = std::env::temp_dir().join(format!("s3_etag_{}", std::process::id()));
+        std::fs::write(&path, "hello").unwrap();
+        assert_eq!(file_md5(&path).await.unwrap(), "5d41402abc4b2a76b9719d911017c592");
+
+        let client = S3ClientBuilder::new().unscoped().build().unwrap();
+        let remote = |etag: Option<&str>, size: u64, last_modified: i64| S3Object {
+            key: "hello.txt".to_string(),
+            size,
+            last_modified,
+            etag: etag.map(str::to_string),
+            content_type: None,
+            checksum_sha256: None,
+        };
+        let needs_upload = |object: S3Object| {
+            let (client, path) = (&client, &path);
+            async move { client.object_needs_upload_by_etag(path, &object).await.unwrap() }
+        };
+
+        // Single-part ETags are compared with the MD5, regardless of timestamps
+        assert!(!needs_upload(remote(Some("5D41402ABC4B2A76B9719D911017C592"), 5, 0)).await);
+        assert!(needs_upload(remote(Some("d41d8cd98f00b204e9800998ecf8427e"), 5, i64::MAX)).await);
+        assert!(needs_upload(remote(Some("5d41402abc4b2a76b9719d911017c592"), 6, 0)).await);
+
+        // Multipart or missing ETags fall back to size and modification time
+        for etag in [Some("9b2cf535f27731c974343645a3985328-3"), None] {
+            assert!(!needs_upload(remote(etag, 5, i64::MAX)).await);
+            assert!(needs_upload(remote(etag, 5, 0)).await);
+        }
+
+        // With SSE-C the ETag isn't an MD5, so a matching-looking one isn't trusted either
+        let client = client.with_sse_customer_key(Some(&[7u8; 32]));
+        let needs_upload = |object: S3Object| {
+            let (client, path) = (&client, &path);
+            async move { client.object_needs_upload_by_etag(path, &object).await.unwrap() }
+        };
+        assert!(!needs_upload(remote(Some("d41d8cd98f00b204e9800998ecf8427e"), 5, i64::MAX)).await);
+        assert!(needs_upload(remote(Some("5d41402abc4b2a76b9719d911017c592"), 5, 0)).await);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sha256_checksums() {
+        // Known SHA-256 of "abc", base64 as in x-amz-checksum-sha256
+        const ABC_SHA256: &str = "ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=";
+        assert_eq!(checksum_metadata(b"abc")[SHA256_METADATA_KEY], ABC_SHA256);
+
+        let path = std::env::temp_dir().join(format!("s3_sha256_{}", std::process::id()));
+        std::fs::write(&path, "abc").unwrap();
+        assert_eq!(file_sha256(&path).await.unwrap(), ABC_SHA256);
+
+        // A single flipped byte changes the checksum
+        std::fs::write(&path, "abd").unwrap();
+        assert_ne!(file_sha256(&path).await.unwrap(), ABC_SHA256);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_chunk_ranges() {
+        assert_eq!(chunk_ranges(10, 4), vec![(0, 3), (4, 7), (8, 9)]);
+        assert_eq!(chunk_ranges(8, 4), vec![(0, 3), (4, 7)]);
+        assert_eq!(chunk_ranges(3, 4), vec![(0, 2)]);
+        assert!(chunk_ranges(0, 4).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_write_chunks_assembles_32mib_object() {
+        const SIZE: usize = 32 * 1024 * 1024;
+        // Not a multiple of the size, so the last chunk is shorter
+        const CHUNK_SIZE: u64 = 3 * 1024 * 1024;
+        let object: Vec<u8> = (0..SIZE).map(|i| (i % 251) as u8).collect();
+        let expected = Sha256::digest(&object);
+
+        // Later chunks answer first, so they arrive out of order
+        let chunk_count = chunk_ranges(SIZE as u64, CHUNK_SIZE).len() as u64;
+        let fetch = |start: u64, end: u64| {
+            let chunk = object[start as usize..=end as usize].to_vec();
+            async move {
+                tokio::time::sleep(Duration::from_millis(chunk_count - start / CHUNK_SIZE)).await;
+                Ok(chunk)
+            }
+        };
+        let progress = std::sync::Mutex::new(Vec::new());
+        let on_progress = |received, total| progress.lock().unwrap().push((received, total));
+
+        let mut assembled = std::io::Cursor::new(vec![0u8; SIZE]);
+        write_chunks(&mut assembled, SIZE as u64, CHUNK_SIZE, 4, fetch, &on_progress)
+            .await
+            .unwrap();
+
+        assert_eq!(Sha256::digest(assembled.get_ref()), expected);
+        let progress = progress.into_inner().unwrap();
+        assert_eq!(progress.len() as u64, chunk_count);
+        assert_eq!(progress.last(), Some(&(SIZE as u64, SIZE as u64)));
+
+        // A short range response is an error rather than a corrupt file
+        let truncated = |start: u64, end: u64| {
+            let chunk = object[start as usize..end as usize].to_vec();
+            async move { Ok(chunk) }
+        };
+        let mut assembled = std::io::Cursor::new(Vec::new());
+        assert!(write_chunks(&mut assembled, SIZE as u64, CHUNK_SIZE, 4, truncated, &|_, _| {})
+            .await
+            .is_err());
+    }
+
+    // Talks to the real bucket: run with `cargo test -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn test_upload_bytes_round_trip() {
+        let client = S3ClientBuilder::new().user_prefix("_tests/").build().unwrap();
+        let remote_path = format!("upload_bytes_{}.bin", std::process::id());
+        let data: Vec<u8> = (0..1024).map(|i| (i % 251) as u8).collect();
+
+        client.upload_bytes(&data, &remote_path, "application/octet-stream").await.unwrap();
+
+        let info = client.get_object_info(&remote_path).await.unwrap();
+        assert_eq!(info.size, 1024);
+        assert_eq!(info.content_type.as_deref(), Some("application/octet-stream"));
+
+        let local = std::env::temp_dir().join(&remote_path);
+        client.download_file(&remote_path, &local).await.unwrap();
+        assert_eq!(std::fs::read(&local).unwrap(), data);
+
+        client.upload_string("hello", &remote_path).await.unwrap();
+        let info = client.get_object_info(&remote_path).await.unwrap();
+        assert_eq!(info.size, 5);
+        assert_eq!(info.content_type.as_deref(), Some("text/plain; charset=utf-8"));
+
+        client.delete_object(&remote_path).await.unwrap();
+        std::fs::remove_file(local).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_collect_folder_pages_follows_tokens() {
+        // A bucket with 500 folders, listed 100 at a time
+        let all: Vec<String> = (0..500).map(|i| format!("folder{:03}/", i)).collect();
+        let requests = std::sync::atomic::AtomicUsize::new(0);
+        let folders = collect_folder_pages(|token: Option<String>| {
+            requests.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let start: usize = token.map_or(0, |t| t.parse().unwrap());
+            let page = all[start..start + 100].to_vec();
+            let next = Some(start + 100).filter(|&n| n < all.len()).map(|n| n.to_string());
+            async move { Ok((page, next)) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(folders, all);
+        assert_eq!(requests.into_inner(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_folder_tree_three_levels() {
+        // What list_folders would return for each prefix
+        let listing: HashMap<&str, Vec<&str>> = HashMap::from([
+            ("", vec!["Photos/", "Docs/"]),
+            ("Photos/", vec!["Photos/2024/"]),
+            ("Photos/2024/", vec!["Photos/2024/Summer/", "Photos/2024/Winter/"]),
+            ("Photos/2024/Summer/", vec![]),
+            ("Photos/2024/Winter/", vec![]),
+            ("Docs/", vec![]),
+        ]);
+        let calls = std::sync::Mutex::new(Vec::new());
+        let children = discover_folders("", |prefix| {
+            calls.lock().unwrap().push(prefix.clone());
+            let folders = listing[prefix.as_str()].iter().map(|f| f.to_string()).collect();
+            async move { Ok(folders) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(calls.lock().unwrap().len(), listing.len());
+
+        let object = |key: &str, size: u64| S3Object {
+            key: key.to_string(),
+            size,
+            last_modified: 0,
+            etag: None,
+            content_type: None,
+            checksum_sha256: None,
+        };
+        let objects = vec![
+            object("Photos/cover.jpg", 1),
+            object("Photos/2024/Summer/a.jpg", 10),
+            object("Photos/2024/Summer/b.jpg", 20),
+            object("Photos/2024/Winter/c.jpg", 100),
+            object("Docs/d.pdf", 1000),
+        ];
+
+        let tree = folder_tree("", &children, &objects);
+        assert_eq!(tree.len(), 2);
+        let photos = &tree[0];
+        assert_eq!((photos.name.as_str(), photos.total_size, photos.file_count), ("Photos", 131, 4));
+        let year = &photos.children[0];
+        assert_eq!((year.name.as_str(), year.total_size, year.file_count), ("2024", 130, 3));
+        let summer = &year.children[0];
+        assert_eq!((summer.path.as_str(), summer.total_size, summer.file_count), ("Photos/2024/Summer/", 30, 2));
+        assert!(summer.children.is_empty());
+        assert_eq!(tree[1].total_size, 1000);
+
+        let flat: Vec<_> = flatten_folders(tree).into_iter().map(|f| (f.path, f.children.len())).collect();
+        assert_eq!(
+            flat,
+            [
+                ("Photos/".to_string(), 0),
+                ("Photos/2024/".to_string(), 0),
+                ("Photos/2024/Summer/".to_string(), 0),
+                ("Photos/2024/Winter/".to_string(), 0),
+                ("Docs/".to_string(), 0),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_discover_folders_lists_each_prefix_once() {
+        // A server that reports the same subfolder under two parents
+        let calls = std::sync::Mutex::new(Vec::new());
+        let children = discover_folders("", |prefix| {
+            calls.lock().unwrap().push(prefix.clone());
+            let folders = match prefix.as_str() {
+                "" => vec!["a/".to_string(), "b/".to_string()],
+                "a/" | "b/" => vec!["shared/".to_string()],
+                _ => vec![],
+            };
+            async move { Ok(folders) }
+        })
+        .await
+        .unwrap();
+
+        let mut calls = calls.into_inner().unwrap();
+        calls.sort();
+        assert_eq!(calls, ["", "a/", "b/", "shared/"]);
+        assert_eq!(children.len(), 4);
+    }
+
+    #[test]
+    fn test_region_from_endpoint() {
+        assert_eq!(region_from_endpoint("https://s3.us-west-004.backblazeb2.com"), "us-west-004");
+        assert_eq!(region_from_endpoint("https://s3.nl-ams.scw.cloud"), "nl-ams");
+        assert_eq!(region_from_endpoint("https://s3.eu-west-1.amazonaws.com/"), "eu-west-1");
+        assert_eq!(region_from_endpoint("http://localhost:9000"), "us-east-1");
+        assert_eq!(region_from_endpoint("https://s3.amazonaws.com"), "us-east-1");
+    }
+
+    #[test]
+    fn test_encode_copy_source() {
+        assert_eq!(
+            encode_copy_source("backup", "users/u_1/Photos/a b+é.jpg"),
+            "backup/users/u_1/Photos/a%20b%2B%C3%A9.jpg"
+        );
+    }
+
+    #[test]
+    fn test_unicode_keys_from_listings() {
+        // As listed with encoding-type=url
+        assert_eq!(
+            decode_listed_key("%E6%97%A5%E6%9C%AC%E8%AA%9E/%E3%83%86%E3%82%B9%E3%83%88.txt"),
+            "日本語/テスト.txt"
+        );
+        assert_eq!(decode_listed_key("a+b%2Bc%F0%9F%8E%89.txt"), "a b+c🎉.txt");
+        assert_eq!(decode_listed_key("100%.txt"), "100%.txt");
+        assert_eq!(decode_listed_key("%zz"), "%zz");
+
+        let client = S3ClientBuilder::new().user_prefix("users/u_1/").build().unwrap();
+        assert_eq!(
+            client.relative_key("users%2Fu_1%2F%D8%B5%D9%88%D8%B1/x.jpg", Some(LIST_ENCODING_TYPE)),
+            "صور/x.jpg"
+        );
+        // Servers that ignore the encoding type send keys as they are
+        assert_eq!(client.relative_key("users/u_1/日本語/a+b.txt", None), "日本語/a+b.txt");
+        assert_eq!(client.full_key("日本語/テスト.txt"), "users/u_1/日本語/テスト.txt");
+    }
+
+    #[test]
+    fn test_detects_missing_bucket() {
+        use rusoto_s3::{HeadBucketError, ListObjectsV2Error};
+
+        let client = S3ClientBuilder::new().unscoped().build().unwrap();
+        let missing = || RusotoError::Service(ListObjectsV2Error::NoSuchBucket("The bucket does not exist".to_string()));
+        assert!(matches!(client.map_error(missing()), S3Error::BucketNotFound(bucket) if bucket == client.bucket()));
+
+        let status = connection_status(Err(RusotoError::Service(HeadBucketError::NoSuchBucket(String::new()))));
+        assert_eq!(
+            status,
+            ConnectionStatus { reachable: true, bucket_exists: false, credentials_valid: true }
+        );
+        let status = connection_status::<HeadBucketError>(Ok(()));
+        assert!(status.reachable && status.bucket_exists && status.credentials_valid);
+    }
+
+    #[tokio::test]
+    async fn test_user_prefix_validation() {
+        let err = S3Client::new(S3ProviderConfig::default(), String::new()).await.err().unwrap();
+        assert!(matches!(&err, S3Error::InvalidConfiguration(_)));
+        assert_eq!(err.to_string(), "Invalid S3 client configuration: user_prefix cannot be empty");
+
+        let err = S3Client::new(S3ProviderConfig::default(), "no-trailing-slash".to_string()).await.err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Invalid S3 client configuration: user_prefix must end with '/', got \"no-trailing-slash\""
+        );
+
+        let client = S3ClientBuilder::new().user_prefix("valid/prefix/").build().unwrap();
+        assert_eq!(client.full_key("foo"), "valid/prefix/foo");
+        // Clients not scoped to a user, like the admin client, have to say so
+        assert!(matches!(S3ClientBuilder::new().unscoped().build(), Err(S3Error::InvalidConfiguration(_))));
+        let client = S3ClientBuilder::new().unscoped().build().unwrap();
+        assert_eq!(client.full_key("foo"), "foo");
+    }
+
+    #[test]
+    fn test_sse_c_headers() {
+        let key: [u8; 32] = std::array::from_fn(|i| i as u8);
+        let sse = SseCustomerKey::new(&key);
+        assert_eq!(sse.key_md5(), "tP/LI3N87DFaSk0aoqYgzg==");
+        assert!(!format!("{:?}", sse).contains(&sse.key));
+
+        let client = S3ClientBuilder::new().unscoped().build().unwrap();
+        assert_eq!(client.sse_c_headers(), (None, None, None));
+        let client = client.with_sse_customer_key(Some(&key));
+        assert_eq!(
+            client.sse_c_headers(),
+            (
+                Some("AES256".to_string()),
+                Some("AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=".to_string()),
+                Some("tP/LI3N87DFaSk0aoqYgzg==".to_string()),
+            )
+        );
+        assert_eq!(client.with_sse_customer_key(None).sse_c_headers(), (None, None, None));
+
+        // Uploads that mustn't overwrite go through a raw request, which needs them too
+        let header = |client: &S3Client, name: &str| {
+            client
+                .conditional_put_headers(&Precondition::Absent, b"hello", None)
+                .into_iter()
+                .find(|(header, _)| header == name)
+                .map(|(_, value)| value)
+        };
+        assert_eq!(header(&client, "x-amz-server-side-encryption-customer-algorithm").as_deref(), Some("AES256"));
+        assert_eq!(header(&client, "x-amz-server-side-encryption-customer-key"), Some(sse.key.clone()));
+        assert_eq!(header(&client, "x-amz-server-side-encryption-customer-key-MD5"), Some(sse.key_md5.clone()));
+        let plain = client.with_sse_customer_key(None);
+        assert_eq!(header(&plain, "If-None-Match").as_deref(), Some("*"));
+        assert_eq!(header(&plain, "x-amz-server-side-encryption-customer-key"), None);
+
+        let if_match = plain.conditional_put_headers(&Precondition::ETag("abc".to_string()), b"hello", None);
+        assert!(if_match.contains(&("If-Match".to_string(), "\"abc\"".to_string())));
+        assert!(!if_match.iter().any(|(name, _)| name == "If-None-Match"));
+
+        // The ACL goes with the upload rather than in a second request
+        assert_eq!(header(&plain, "x-amz-acl"), None);
+        let public = plain.with_acl(Some(ObjectCannedAcl::PublicRead));
+        assert_eq!(header(&public, "x-amz-acl").as_deref(), Some("public-read"));
+    }
+
+    #[test]
+    fn test_suggested_concurrency_falls_with_rtt() {
+        assert_eq!(suggested_concurrency(5), 16);
+        assert_eq!(suggested_concurrency(50), 8);
+        assert_eq!(suggested_concurrency(100), 4);
+        assert_eq!(suggested_concurrency(800), 2);
+        let rtts = [0, 20, 21, 50, 51, 150, 151, 10_000];
+        assert!(rtts.windows(2).all(|w| suggested_concurrency(w[0]) >= suggested_concurrency(w[1])));
+
+        let client = S3ClientBuilder::new().unscoped().build().unwrap();
+        let info = ConnectionInfo::new(30, Some(120), &client, "nl-ams".to_string());
+        assert_eq!(info.suggested_concurrency, 8);
+        assert_eq!((info.region.as_str(), info.bucket.as_str()), ("nl-ams", client.bucket()));
+        assert_eq!(info.endpoint, client.endpoint());
+    }
+
+    #[test]
+    fn test_endpoint_and_bucket_accessors() {
+        let provider = S3ProviderConfig::custom(S3Provider::Minio, "http://nas.local:9000", "us-east-1", "backup");
+        let client = S3ClientBuilder::new().unscoped().provider(provider).build().unwrap();
+        assert_eq!(client.endpoint(), "http://nas.local:9000");
+        assert_eq!(client.bucket(), "backup");
+
+        let client = S3ClientBuilder::new()
+            .unscoped()
+            .destination(S3Destination {
+                endpoint: "https://s3.us-west-004.backblazeb2.com".to_string(),
+                bucket: "photos".to_string(),
+                access_key: "key".to_string(),
+                secret_key: "secret".to_string(),
+            })
+            .build()
+            .unwrap();
+        assert_eq!(client.endpoint(), "https://s3.us-west-004.backblazeb2.com");
+        assert_eq!(client.bucket(), "photos");
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after(Some("12")), 12);
+        assert_eq!(parse_retry_after(Some(" 3 ")), 3);
+        assert_eq!(parse_retry_after(Some("Fri, 28 Nov 2025 12:00:00 GMT")), 0);
+        assert_eq!(parse_retry_after(Some("soon")), DEFAULT_RETRY_AFTER_SECS);
+        assert_eq!(parse_retry_after(None), DEFAULT_RETRY_AFTER_SECS);
+        // Absurd waits are capped, in seconds or as a date
+        assert_eq!(parse_retry_after(Some("86400")), MAX_RETRY_AFTER_SECS);
+        assert_eq!(parse_retry_after(Some("18446744073709551615")), MAX_RETRY_AFTER_SECS);
+        assert_eq!(parse_retry_after(Some("Fri, 01 Jan 2100 00:00:00 GMT")), MAX_RETRY_AFTER_SECS);
+    }
+
+    #[test]
+    fn test_conditional_put_outcome() {
+        assert_eq!(conditional_put_outcome(200, ""), Some(ConditionalPut::Written));
+        assert_eq!(conditional_put_outcome(412, ""), Some(ConditionalPut::Exists));
+        assert_eq!(conditional_put_outcome(409, ""), Some(ConditionalPut::Exists));
+        assert_eq!(conditional_put_outcome(501, ""), Some(ConditionalPut::Unsupported));
+        assert_eq!(
+            conditional_put_outcome(400, "<Error><Code>NotImplemented</Code></Error>"),
+            Some(ConditionalPut::Unsupported)
+        );
+        assert_eq!(conditional_put_outcome(403, "<Error><Code>AccessDenied</Code></Error>"), None);
+    }
+
+    #[tokio::test]
+    async fn test_file_stamp_detects_concurrent_writes() {
+        let path = std::env::temp_dir().join(format!("s3_client_stamp_{}.txt", std::process::id()));
+        std::fs::write(&path, "original").unwrap();
+
+        let stamp = FileStamp::of(&path).await.unwrap();
+        assert!(stamp.check_unchanged().await.is_ok());
+
+        // Another process appends while the upload is reading
+        let writer = std::thread::spawn({
+            let path = path.clone();
+            move || {
+                use std::io::Write;
+                let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+                file.write_all(b" and more").unwrap();
+            }
+        });
+        writer.join().unwrap();
+        match stamp.check_unchanged().await {
+            Err(S3Error::FileChangedDuringUpload { path: changed }) => assert_eq!(changed, path.display().to_string()),
+            other => panic!("expected FileChangedDuringUpload, got {:?}", other),
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_progress_event_eta_decreases() {
+        const PARTS: u64 = 10;
+        let part = DEFAULT_MULTIPART_PART_SIZE as u64;
+        let total = PARTS * part;
+        assert_eq!(ProgressEvent::new(0, total, Duration::ZERO).eta_secs, None);
+
+        // Each part takes a second
+        let events: Vec<ProgressEvent> = (1..=PARTS)
+            .map(|sent| ProgressEvent::new(sent * part, total, Duration::from_secs(sent)))
+            .collect();
+
+        let etas: Vec<u64> = events.iter().map(|event| event.eta_secs.unwrap()).collect();
+        assert!(etas.windows(2).all(|w| w[0] > w[1]), "ETA went up: {:?}", etas);
+        assert_eq!(etas, [9, 8, 7, 6, 5, 4, 3, 2, 1, 0]);
+        assert_eq!(events[0].elapsed_ms, 1000);
+        assert_eq!(events[0].bytes_per_second, part as f64);
+    }
+
+    #[tokio::test]
+    async fn test_upload_of_deleted_file() {
+        let client = S3ClientBuilder::new().unscoped().build().unwrap();
+        let path = std::env::temp_dir().join(format!("s3_client_gone_{}.txt", std::process::id()));
+        let result = client
+            .upload_file_with_progress(&path, "gone.txt", &HashMap::new(), |_| {})
+            .await;
+        match result {
+            Err(S3Error::LocalFileGone { path: gone }) => assert_eq!(gone, path.display().to_string()),
+            other => panic!("expected LocalFileGone, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_is_object_locked() {
+        let aws = "<Error><Code>AccessDenied</Code><Message>Access Denied because object protected by object lock.</Message></Error>";
+        assert!(is_object_locked(403, aws));
+        assert!(is_object_locked(400, "<Error><Code>ObjectLocked</Code></Error>"));
+        assert!(!is_object_locked(403, "<Error><Code>AccessDenied</Code><Message>Access Denied</Message></Error>"));
+        assert!(!is_object_locked(500, "object lock"));
+    }
+
+    #[test]
+    fn test_error_code() {
+        let body = "<?xml version=\"1.0\"?><Error><Code>EntityTooSmall</Code><Message>Your proposed upload is smaller than the minimum allowed size</Message></Error>";
+        assert_eq!(error_code(body), Some("EntityTooSmall"));
+        // Only the code counts, not a message that mentions another one
+        let body = "<Error><Code>InvalidPart</Code><Message>Not EntityTooSmall</Message></Error>";
+        assert_eq!(error_code(body), Some("InvalidPart"));
+        assert_eq!(error_code(""), None);
+    }
+
+    #[test]
+    fn test_is_sse_c_mismatch() {
+        let aws = "<Error><Code>InvalidRequest</Code><Message>The encryption parameters are not applicable to this object.</Message></Error>";
+        assert!(is_sse_c_mismatch(400, aws));
+        assert!(is_sse_c_mismatch(400, ""));
+        assert!(!is_sse_c_mismatch(400, "<Error><Code>InvalidArgument</Code></Error>"));
+        assert!(!is_sse_c_mismatch(403, ""));
+    }
+
+    #[test]
+    fn test_canned_acls() {
+        assert_eq!("private".parse::<ObjectCannedAcl>().unwrap(), ObjectCannedAcl::Private);
+        assert_eq!("public-read".parse::<ObjectCannedAcl>().unwrap().as_str(), "public-read");
+        assert!(matches!("PUBLIC-READ".parse::<ObjectCannedAcl>(), Err(S3Error::InvalidAcl(acl)) if acl == "PUBLIC-READ"));
+        assert!("public-read-write".parse::<ObjectCannedAcl>().is_err());
+
+        let grant = |id: Option<&str>, uri: Option<&str>, permission: &str| Grant {
+            grantee: Some(rusoto_s3::Grantee {
+                id: id.map(str::to_string),
+                uri: uri.map(str::to_string),
+                ..Default::default()
+            }),
+            permission: Some(permission.to_string()),
+        };
+        let owner = grant(Some("owner"), None, "FULL_CONTROL");
+        assert_eq!(describe_acl(Some("owner"), std::slice::from_ref(&owner)), "private");
+        let public = [owner.clone(), grant(None, Some(ALL_USERS_URI), "READ")];
+        assert_eq!(describe_acl(Some("owner"), &public), "public-read");
+        let cross_account = [owner.clone(), grant(Some("bucket-owner"), None, "FULL_CONTROL")];
+        assert_eq!(describe_acl(Some("owner"), &cross_account), "bucket-owner-full-control");
+        let custom = [owner, grant(Some("other"), None, "READ_ACP")];
+        assert_eq!(describe_acl(Some("owner"), &custom), "READ_ACP other");
+    }
+
+    #[test]
+    fn test_validate_tags() {
+        let mut tags: HashMap<String, String> = (0..10)
+            .map(|i| (format!("key{}", i), "value".to_string()))
+            .collect();
+        assert!(validate_tags(&tags).is_ok());
+
+        tags.insert("key10".to_string(), "value".to_string());
+        assert!(matches!(validate_tags(&tags), Err(S3Error::InvalidTag(_))));
+
+        let long_key = HashMap::from([("k".repeat(129), "v".to_string())]);
+        assert!(matches!(validate_tags(&long_key), Err(S3Error::InvalidTag(_))));
+
+        let long_value = HashMap::from([("k".to_string(), "v".repeat(257))]);
+        assert!(matches!(validate_tags(&long_value), Err(S3Error::InvalidTag(_))));
+
+        let empty_key = HashMap::from([(String::new(), "v".to_string())]);
+        assert!(matches!(validate_tags(&empty_key), Err(S3Error::InvalidTag(_))));
+    }
+
+    #[test]
+    fn test_sanitize_tag_value() {
+        assert_eq!(sanitize_tag_value("Jane Doe"), "Jane Doe");
+        assert_eq!(sanitize_tag_value("José García-López"), "José García-López");
+        assert_eq!(sanitize_tag_value("O'Brien & Sons, <admin>"), "O_Brien _ Sons_ _admin_");
+        assert_eq!(sanitize_tag_value("a\nb\tc"), "a_b_c");
+
+        let long = sanitize_tag_value(&"é".repeat(300));
+        assert_eq!(long.chars().count(), MAX_TAG_VALUE_LEN);
+        let tags = HashMap::from([("uploaded_by".to_string(), long)]);
+        assert!(validate_tags(&tags).is_ok());
+    }
+
+    #[test]
+    fn test_encode_tagging() {
+        let tags = HashMap::from([
+            ("uploaded_by".to_string(), "Jane Doe".to_string()),
+            ("app".to_string(), "sync2bucket".to_string()),
+        ]);
+        assert_eq!(encode_tagging(&tags), "app=sync2bucket&uploaded_by=Jane%20Doe");
+        assert_eq!(encode_tagging(&HashMap::new()), "");
+    }
 }
@@ -0,0 +1,197 @@
+//! Per-file results remembered between syncs, so unchanged files skip repeated work,
+//! and the files each upload session has uploaded, so an interrupted one can resume
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::sync_engine::SyncSession;
+
+/// Content hash of a file as it was when hashed
+#[derive(Debug, Clone)]
+struct CachedHash {
+    modified: SystemTime,
+    size: u64,
+    sha256: String,
+}
+
+/// ETag of the object a local file was downloaded from, with the file's
+/// mtime and size right after the download
+#[derive(Debug, Clone)]
+struct CachedEtag {
+    modified: SystemTime,
+    size: u64,
+    etag: String,
+}
+
+/// Cache of local file hashes and download ETags, keyed by path, modification
+/// time and size, and of the remote paths uploaded in each upload session
+#[derive(Debug, Default)]
+pub struct SyncCache {
+    hashes: HashMap<PathBuf, CachedHash>,
+    etags: HashMap<PathBuf, CachedEtag>,
+    /// Remote paths uploaded so far, by session ID
+    sessions: HashMap<String, HashSet<String>>,
+    /// Where sessions are saved as `{session_id}.json`; not saved when None
+    sessions_dir: Option<PathBuf>,
+}
+
+impl SyncCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Save upload sessions to `dir`, so an interrupted one can be resumed after a restart
+    pub fn with_sessions_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.sessions_dir = dir;
+        self
+    }
+
+    /// SHA-256 of a file, if it was hashed with the same mtime and size
+    pub fn content_hash(&self, path: &Path, modified: SystemTime, size: u64) -> Option<&str> {
+        self.hashes
+            .get(path)
+            .filter(|cached| cached.modified == modified && cached.size == size)
+            .map(|cached| cached.sha256.as_str())
+    }
+
+    /// Remember a file's SHA-256, replacing any hash of an older version
+    pub fn set_content_hash(&mut self, path: PathBuf, modified: SystemTime, size: u64, sha256: String) {
+        self.hashes.insert(path, CachedHash { modified, size, sha256 });
+    }
+
+    /// ETag of the object a file was downloaded from, if the file hasn't changed since
+    pub fn downloaded_etag(&self, path: &Path, modified: SystemTime, size: u64) -> Option<&str> {
+        self.etags
+            .get(path)
+            .filter(|cached| cached.modified == modified && cached.size == size)
+            .map(|cached| cached.etag.as_str())
+    }
+
+    /// Remember the ETag of the object a file was just downloaded from
+    pub fn set_downloaded_etag(&mut self, path: PathBuf, modified: SystemTime, size: u64, etag: String) {
+        self.etags.insert(path, CachedEtag { modified, size, etag });
+    }
+
+    /// Start tracking a session, with `uploaded` already done by an earlier one
+    pub fn start_session(&mut self, session_id: &str, uploaded: impl IntoIterator<Item = String>) {
+        self.sessions.insert(session_id.to_string(), uploaded.into_iter().collect());
+    }
+
+    /// Note that `remote_path` was uploaded in the session
+    pub fn record_upload(&mut self, session_id: &str, remote_path: &str) {
+        if let Some(uploaded) = self.sessions.get_mut(session_id) {
+            uploaded.insert(remote_path.to_string());
+        }
+    }
+
+    /// Whether the session, or the one it resumed, already uploaded `remote_path`
+    pub fn was_uploaded_in_session(&self, session_id: &str, remote_path: &str) -> bool {
+        self.sessions
+            .get(session_id)
+            .is_some_and(|uploaded| uploaded.contains(remote_path))
+    }
+
+    /// Stop tracking a finished session
+    pub fn end_session(&mut self, session_id: &str) {
+        self.sessions.remove(session_id);
+    }
+
+    /// File the session is saved to, if sessions are saved
+    pub fn session_file(&self, session_id: &str) -> Option<PathBuf> {
+        self.sessions_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.json", session_id)))
+    }
+
+    /// The most recently saved session; only unfinished sessions keep their file
+    pub fn get_incomplete_session(&self) -> Option<SyncSession> {
+        self.saved_sessions()
+            .into_iter()
+            .max_by_key(|(_, modified)| *modified)
+            .and_then(|(path, _)| SyncSession::load(&path))
+    }
+
+    /// Delete the saved sessions started by the user with `uid`, other than `keep`.
+    /// Sessions that can't be read are deleted too, as nobody could resume them.
+    pub fn remove_user_sessions(&self, uid: Option<&str>, keep: Option<&str>) {
+        let keep = keep.and_then(|id| self.session_file(id));
+        for (path, _) in self.saved_sessions() {
+            if Some(&path) != keep.as_ref() && SyncSession::load(&path).is_none_or(|session| session.belongs_to(uid)) {
+                SyncSession::remove(&path);
+            }
+        }
+    }
+
+    /// Saved session files and when each was written
+    fn saved_sessions(&self) -> Vec<(PathBuf, SystemTime)> {
+        let Some(entries) = self.sessions_dir.as_ref().and_then(|dir| std::fs::read_dir(dir).ok()) else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|path| {
+                let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+                Some((path, modified))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_content_hash_requires_same_mtime_and_size() {
+        let path = PathBuf::from("/photos/a.jpg");
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let mut cache = SyncCache::new();
+        assert_eq!(cache.content_hash(&path, modified, 10), None);
+
+        cache.set_content_hash(path.clone(), modified, 10, "abc".to_string());
+        assert_eq!(cache.content_hash(&path, modified, 10), Some("abc"));
+        assert_eq!(cache.content_hash(&path, modified, 11), None);
+        assert_eq!(cache.content_hash(&path, modified + Duration::from_secs(1), 10), None);
+
+        // A newer version replaces the old entry
+        cache.set_content_hash(path.clone(), modified + Duration::from_secs(1), 10, "def".to_string());
+        assert_eq!(cache.content_hash(&path, modified, 10), None);
+        assert_eq!(cache.content_hash(&path, modified + Duration::from_secs(1), 10), Some("def"));
+    }
+
+    #[test]
+    fn test_downloaded_etag_requires_unchanged_file() {
+        let path = PathBuf::from("/downloads/a.jpg");
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let mut cache = SyncCache::new();
+        cache.set_downloaded_etag(path.clone(), modified, 10, "5d41402a".to_string());
+        assert_eq!(cache.downloaded_etag(&path, modified, 10), Some("5d41402a"));
+        // Edited locally since the download
+        assert_eq!(cache.downloaded_etag(&path, modified, 12), None);
+        assert_eq!(cache.downloaded_etag(&path, modified + Duration::from_secs(5), 10), None);
+        // Hashes and ETags are kept apart
+        assert_eq!(cache.content_hash(&path, modified, 10), None);
+    }
+
+    #[test]
+    fn test_was_uploaded_in_session() {
+        let mut cache = SyncCache::new();
+        cache.start_session("resumed", ["a.txt".to_string()]);
+        cache.record_upload("resumed", "b.txt");
+        cache.record_upload("unknown", "c.txt");
+
+        assert!(cache.was_uploaded_in_session("resumed", "a.txt"));
+        assert!(cache.was_uploaded_in_session("resumed", "b.txt"));
+        assert!(!cache.was_uploaded_in_session("resumed", "c.txt"));
+        assert!(!cache.was_uploaded_in_session("other", "a.txt"));
+
+        cache.end_session("resumed");
+        assert!(!cache.was_uploaded_in_session("resumed", "a.txt"));
+    }
+}
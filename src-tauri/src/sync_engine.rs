@@ -1,42 +1,490 @@
-use crate::s3_client::S3Client;
+use crate::s3_client::{differs_by_size_or_mtime, CloudFolder, ConnectionInfo, ConnectionStatus, DownloadResult, LockMode, ObjectCannedAcl, ProgressEvent, S3Client, S3Error, S3Object, SortDir, SortOrder, DEFAULT_MULTIPART_PART_SIZE};
+use crate::crypto::{derive_sse_c_key, KeyPayload};
+use crate::sync_cache::SyncCache;
+use crate::sync_filter::SyncFilter;
+use crate::time_source::{RealTimeSource, TimeSource};
+use chrono::{DateTime, Local, Utc};
+use futures::{StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tauri::{Emitter, EventTarget, Runtime, Window, Wry};
 use thiserror::Error;
-use tokio::sync::RwLock;
+use tokio::sync::{mpsc, RwLock};
 use walkdir::WalkDir;
 
+// How long get_storage_stats reuses a previous result
+const STORAGE_STATS_TTL: Duration = Duration::from_secs(5 * 60);
+// How often the progress snapshot is refreshed during a sync
+const PROGRESS_SNAPSHOT_INTERVAL: Duration = Duration::from_millis(100);
+// Progress lists at most this many of the files in flight, keeping polls small
+const MAX_ACTIVE_FILES_REPORTED: usize = 10;
+// How often a running upload is saved for resuming; pausing always saves
+const STATE_PERSIST_INTERVAL: Duration = Duration::from_secs(2);
+// Per-file transfer timings kept for performance analysis
+const MAX_TRANSFER_RECORDS: usize = 1000;
+
+// bytes_per_second is measured over this much recent progress, once it spans at least
+// MIN_SPEED_SPAN; before that it is the average since the sync started
+const SPEED_WINDOW: Duration = Duration::from_secs(5);
+const MIN_SPEED_SPAN: Duration = Duration::from_secs(1);
+// Files found between updates of the Scanning status
+const SCAN_PROGRESS_INTERVAL: usize = 100;
+// Scanned files buffered between the folder walkers and the scan
+const SCAN_CHANNEL_CAPACITY: usize = 4096;
+// Reason given in the summary for scanned files deleted before they were uploaded
+const DELETED_DURING_SYNC: &str = "deleted during sync";
+// Reason given for files left out once `max_session_bytes` was reached
+const SESSION_LIMIT_REACHED: &str = "session byte limit reached";
+// Downloaded files between checks that the target disk still has room for the rest
+const FREE_SPACE_CHECK_INTERVAL: usize = 10;
+// Upload rules set by administrators, relative to the bucket root
+const SYNC_POLICY_FILE: &str = "_admin/sync_policy.json";
+// How long a fetched sync policy is reused
+const SYNC_POLICY_TTL: Duration = Duration::from_secs(10 * 60);
+// How long an uploaded file is added to listings that may not show it yet
+const RECENT_UPLOAD_TTL: Duration = Duration::from_secs(30);
+// File in a source folder overriding the sync options for the files under it
+const FOLDER_CONFIG_FILE: &str = ".sync.toml";
+// Upload manifests sit at the top of the user's folder as `{MANIFEST_PREFIX}{datetime}.json`
+const MANIFEST_PREFIX: &str = ".sync_manifest_";
+// Manifests kept after each upload; older ones are deleted
+const MANIFESTS_KEPT: usize = 5;
+// Stored at the top of a cloud folder by `store_folder_checksum`
+const FOLDER_CHECKSUM_FILE: &str = ".folder_checksum.json";
+
 #[derive(Debug, Error)]
 pub enum SyncError {
     #[error("S3 error: {0}")]
     S3Error(String),
     #[error("IO error: {0}")]
     IoError(String),
+    #[error("Permission denied: {path}")]
+    PermissionDenied { path: String },
+    /// Another process kept writing to a file while it was uploaded
+    #[error("File modified during upload")]
+    FileChanged { path: String },
+    /// A scanned file was deleted before it was uploaded
+    #[error("File deleted during sync: {path}")]
+    LocalFileGone { path: String },
+    /// The byte counts are 0 when not known
+    #[error("Not enough disk space to write {path}")]
+    DiskFull { path: String, required_bytes: u64, available_bytes: u64 },
+    /// A file breaks the administrators' sync policy
+    #[error("{path} is not allowed: {reason}")]
+    PolicyViolation { path: String, reason: String },
     #[error("Sync cancelled")]
     Cancelled,
     #[error("No active sync")]
     NoActiveSync,
+    /// Uploads and deletions are turned off; a preview shows what an upload would do
+    #[error("Read-only mode is on, so nothing can be uploaded or deleted; preview the upload instead")]
+    ReadOnlyMode,
+    /// `remote_path_template` can't be used for this upload
+    #[error("Invalid remote path template: {0}")]
+    InvalidTemplate(String),
+    /// A saved upload can only be resumed by the user who started it
+    #[error("The interrupted upload was started by another user")]
+    ForeignSession,
+    /// An upload would take the user's folder past their key's `max_storage_gb`;
+    /// `used` counts the file that didn't fit
+    #[error("Storage quota exceeded: {used} of {limit} bytes used")]
+    QuotaExceeded { used: u64, limit: u64 },
+}
+
+impl From<S3Error> for SyncError {
+    fn from(e: S3Error) -> Self {
+        match e {
+            S3Error::PermissionDenied(path) => SyncError::PermissionDenied { path },
+            S3Error::FileChangedDuringUpload { path } => SyncError::FileChanged { path },
+            S3Error::LocalFileGone { path } => SyncError::LocalFileGone { path },
+            S3Error::DiskFull { path, required_bytes } => SyncError::DiskFull {
+                path,
+                required_bytes,
+                available_bytes: 0,
+            },
+            e => SyncError::S3Error(e.to_string()),
+        }
+    }
+}
+
+/// Map an error on local file `path`, keeping permission and disk space problems apart
+/// from other IO errors
+fn io_error(path: &Path, e: std::io::Error) -> SyncError {
+    match e.kind() {
+        std::io::ErrorKind::PermissionDenied => SyncError::PermissionDenied {
+            path: path.display().to_string(),
+        },
+        std::io::ErrorKind::StorageFull | std::io::ErrorKind::WriteZero => SyncError::DiskFull {
+            path: path.display().to_string(),
+            required_bytes: 0,
+            available_bytes: 0,
+        },
+        _ => SyncError::IoError(e.to_string()),
+    }
+}
+
+/// A remote path with `/` separators only and no leading `/`. Backslashes are only
+/// separators on Windows; elsewhere they are part of a file name and kept.
+pub fn normalize_remote_path(path: &str) -> String {
+    let path = if cfg!(windows) { path.replace('\\', "/") } else { path.to_string() };
+    path.trim_start_matches('/').to_string()
+}
+
+/// A local path with the platform's separator throughout. Paths that aren't
+/// valid UTF-8 are returned unchanged.
+pub fn normalize_local_path(path: &Path) -> PathBuf {
+    match path.to_str() {
+        Some(path) => PathBuf::from(path.replace('/', std::path::MAIN_SEPARATOR_STR)),
+        None => path.to_path_buf(),
+    }
+}
+
+/// The part of `remote_path` below the source folder `folder_name`, which may span
+/// several segments, if it's in that folder. The folder name is compared ignoring
+/// case unless `case_sensitive`.
+fn strip_source_folder<'a>(remote_path: &'a str, folder_name: &str, case_sensitive: bool) -> Option<&'a str> {
+    let first = remote_path.get(..folder_name.len())?;
+    let rest = remote_path[folder_name.len()..].strip_prefix('/')?;
+    let matches = if case_sensitive {
+        first == folder_name
+    } else {
+        first.to_lowercase() == folder_name.to_lowercase()
+    };
+    matches.then_some(rest)
+}
+
+/// Name of a local source folder, the first part of its files' remote paths
+fn source_folder_name(base_path: &Path) -> String {
+    base_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "folder".to_string())
+}
+
+/// Where a source folder's files go: its name, or `template` rendered by `render_path_template`
+fn remote_root(base_path: &Path, template: Option<&str>) -> String {
+    let folder_name = source_folder_name(base_path);
+    match template {
+        Some(template) => normalize_remote_path(&template.replace("{folder_name}", &folder_name))
+            .trim_end_matches('/')
+            .to_string(),
+        None => folder_name,
+    }
+}
+
+/// `template` with `{uid}` and the `{date}` (YYYY-MM-DD) and `{datetime}` (YYYYMMDD_HHMMSS)
+/// of `now` filled in; `{folder_name}` is left for each source folder
+pub fn render_path_template(template: &str, uid: &str, now: DateTime<Local>) -> String {
+    template
+        .replace("{date}", &now.format("%Y-%m-%d").to_string())
+        .replace("{datetime}", &now.format("%Y%m%d_%H%M%S").to_string())
+        .replace("{uid}", uid)
+}
+
+/// A file found by a scan
+struct ScannedFile {
+    /// Index of the source folder it was found under
+    root: usize,
+    entry: FileEntry,
+    /// Local path and mtime, kept for hashing
+    local: Option<(PathBuf, SystemTime)>,
+    /// Why a link was left out of the scan; such entries are only counted as skipped
+    skipped: Option<&'static str>,
+}
+
+/// A file or link found by `walk_folder`
+enum Walked {
+    File(FileEntry, Option<(PathBuf, SystemTime)>),
+    /// A link at this remote path that isn't followed, and why
+    SkippedLink(String, &'static str),
+    /// A folder that was entered
+    Dir,
+}
+
+/// Walk every folder in `roots` on `pool`, sending the files found to `tx` under
+/// the matching `remote_roots`, and counting the folders entered in `dirs_scanned`
+fn walk_folders(
+    config: &SyncConfig,
+    pool: &rayon::ThreadPool,
+    roots: &[PathBuf],
+    remote_roots: &[String],
+    tx: mpsc::Sender<ScannedFile>,
+    dirs_scanned: &AtomicU64,
+) -> Result<(), SyncError> {
+    use rayon::prelude::*;
+
+    let filter = SyncFilter::new(&config.exclude_patterns).map_err(SyncError::IoError)?;
+    pool.install(|| {
+        roots.par_iter().enumerate().try_for_each(|(root, base_path)| {
+            let folder_name = source_folder_name(base_path);
+            // Walked under the folder's own name, which exclude patterns are matched below
+            let remote_path = |path: String| match path.split_once('/') {
+                Some((_, rest)) if remote_roots[root] != folder_name => format!("{}/{}", remote_roots[root], rest),
+                _ => path,
+            };
+            
+            let real_root = base_path.canonicalize().map_err(|e| io_error(base_path, e))?;
+            let mut visited = HashSet::new();
+            walk_folder(config, &filter, &real_root, base_path, &folder_name, &mut visited, &mut |walked| {
+                let scanned = match walked {
+                    Walked::File(mut entry, local) => {
+                        entry.path = remote_path(entry.path);
+                        ScannedFile { root, entry, local, skipped: None }
+                    }
+                    Walked::SkippedLink(path, reason) => ScannedFile {
+                        root,
+                        entry: FileEntry { path: remote_path(path), size: 0, is_dir: false, content_hash: None },
+                        local: None,
+                        skipped: Some(reason),
+                    },
+                    Walked::Dir => {
+                        dirs_scanned.fetch_add(1, Ordering::Relaxed);
+                        return Ok(());
+                    }
+                };
+                // The receiver only goes away when the scan itself was dropped
+                tx.blocking_send(scanned).map_err(|_| SyncError::Cancelled)
+            })
+        })
+    })
+}
+
+/// Pass each file under `dir` that `filter` doesn't exclude to `found` as
+/// `remote_prefix/<relative path>`, following symlinks and junctions as configured.
+/// Links are only followed to targets inside `real_root`, the canonical source
+/// folder; broken ones, ones leading elsewhere and ones back into a folder
+/// in `visited` are passed on as skipped.
+fn walk_folder(
+    config: &SyncConfig,
+    filter: &SyncFilter,
+    real_root: &Path,
+    dir: &Path,
+    remote_prefix: &str,
+    visited: &mut HashSet<PathBuf>,
+    found: &mut dyn FnMut(Walked) -> Result<(), SyncError>,
+) -> Result<(), SyncError> {
+    let real_dir = dir.canonicalize().map_err(|e| io_error(dir, e))?;
+    visited.insert(real_dir.clone());
+    
+    // Path of an entry under its source folder, which is the first part of remote_prefix
+    let source_relative = |path: &Path| {
+        let relative = path.strip_prefix(dir).unwrap_or(path);
+        let remote_path = normalize_remote_path(&format!("{}/{}", remote_prefix, relative.display()));
+        remote_path.split_once('/').map(|(_, rest)| rest.to_string()).unwrap_or_default()
+    };
+    // Excluded folders aren't entered at all
+    let walk = WalkDir::new(dir)
+        .follow_links(false)
+        .into_iter()
+        .filter_entry(|entry| entry.depth() == 0 || !filter.should_exclude(&source_relative(entry.path())));
+    
+    for entry in walk {
+        let entry = entry.map_err(walk_error)?;
+        let path = entry.path();
+        let relative = path
+            .strip_prefix(dir)
+            .map_err(|e| SyncError::IoError(e.to_string()))?;
+        let remote_path = normalize_remote_path(&format!("{}/{}", remote_prefix, relative.display()));
+        
+        // The walk never enters links itself; followed ones are walked separately
+        let mut target = path.to_path_buf();
+        if entry.path_is_symlink() && entry.depth() > 0 {
+            let follow = if is_junction(path) {
+                config.follow_junctions
+            } else {
+                config.follow_symlinks
+            };
+            if !follow {
+                continue;
+            }
+            target = match path.canonicalize() {
+                Ok(target) => target,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    log::warn!("Skipping broken link {}", path.display());
+                    found(Walked::SkippedLink(remote_path, "broken link"))?;
+                    continue;
+                }
+                Err(e) => return Err(io_error(path, e)),
+            };
+            if !target.starts_with(real_root) {
+                log::warn!("Skipping link {} to {} outside the source folder", path.display(), target.display());
+                found(Walked::SkippedLink(remote_path, "link leads outside the source folder"))?;
+                continue;
+            }
+            if target.is_dir() {
+                if visited.contains(&target) {
+                    log::warn!("Skipping link {} back into {}", path.display(), target.display());
+                    found(Walked::SkippedLink(remote_path, "link loops back to a parent folder"))?;
+                } else {
+                    walk_folder(config, filter, real_root, path, &remote_path, visited, found)?;
+                }
+                continue;
+            }
+        } else if entry.file_type().is_dir() {
+            found(Walked::Dir)?;
+            continue;
+        }
+        // Folder configs are settings for the app, not files to back up
+        if entry.file_name() == FOLDER_CONFIG_FILE {
+            continue;
+        }
+        
+        let metadata = std::fs::metadata(&target).map_err(|e| io_error(path, e))?;
+        if !metadata.is_file() {
+            log::warn!("Skipping special file {}", path.display());
+            continue;
+        }
+        
+        let local = if config.compute_hashes {
+            let modified = metadata.modified().map_err(|e| io_error(path, e))?;
+            Some((target, modified))
+        } else {
+            None
+        };
+        found(Walked::File(
+            FileEntry {
+                path: remote_path,
+                size: metadata.len(),
+                is_dir: false,
+                content_hash: None,
+            },
+            local,
+        ))?;
+    }
+    
+    visited.remove(&real_dir);
+    Ok(())
 }
 
+/// Whether `path` is an NTFS junction rather than a symbolic link; both show
+/// up as symlinks
+#[cfg(windows)]
+fn is_junction(path: &Path) -> bool {
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Storage::FileSystem::{
+        FileAttributeTagInfo, GetFileInformationByHandleEx, FILE_ATTRIBUTE_TAG_INFO,
+        FILE_FLAG_BACKUP_SEMANTICS, FILE_FLAG_OPEN_REPARSE_POINT, FILE_READ_ATTRIBUTES,
+    };
+    use windows_sys::Win32::System::SystemServices::IO_REPARSE_TAG_MOUNT_POINT;
+
+    // Open the link itself rather than its target
+    let Ok(file) = std::fs::OpenOptions::new()
+        .access_mode(FILE_READ_ATTRIBUTES)
+        .custom_flags(FILE_FLAG_OPEN_REPARSE_POINT | FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path)
+    else {
+        return false;
+    };
+    let mut info = FILE_ATTRIBUTE_TAG_INFO { FileAttributes: 0, ReparseTag: 0 };
+    // SAFETY: the handle is open for the duration of the call and `info` is a
+    // FILE_ATTRIBUTE_TAG_INFO of the size passed
+    let ok = unsafe {
+        GetFileInformationByHandleEx(
+            file.as_raw_handle() as _,
+            FileAttributeTagInfo,
+            &mut info as *mut FILE_ATTRIBUTE_TAG_INFO as *mut _,
+            std::mem::size_of::<FILE_ATTRIBUTE_TAG_INFO>() as u32,
+        )
+    };
+    ok != 0 && info.ReparseTag == IO_REPARSE_TAG_MOUNT_POINT
+}
+
+#[cfg(not(windows))]
+fn is_junction(_path: &Path) -> bool {
+    false
+}
+
+/// Map a directory walk error, which carries the path it failed on
+fn walk_error(e: walkdir::Error) -> SyncError {
+    let message = e.to_string();
+    let path = e.path().map(Path::to_path_buf).unwrap_or_default();
+    match e.into_io_error() {
+        Some(io) => io_error(&path, io),
+        None => SyncError::IoError(message),
+    }
+}
+
+/// Serialized externally tagged: `"LocalToCloud"`, `"CloudToLocal"` or
+/// `{ "CloudToCloud": { "source_folder": "...", "dest_folder": "..." } }`
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum SyncDirection {
     LocalToCloud,
     CloudToLocal,
+    /// Server-side copy between two folders in the bucket
+    CloudToCloud { source_folder: String, dest_folder: String },
 }
 
+/// State of the current sync.
+/// Serialized externally tagged, e.g. `"Syncing"`, `{ "Error": "..." }` or
+/// `{ "CompletedWithErrors": { "failed_count": 3 } }`
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum SyncStatus {
     Idle,
-    Scanning,
+    /// Listing the files to transfer; counts the local files and folders scanned so far
+    Scanning { files_found: u64, dirs_scanned: u64 },
+    /// Computing content hashes of the scanned files
+    Hashing { files_hashed: u64, total_files: u64 },
+    /// Scanned with `two_phase`; nothing is uploaded until the sync is confirmed
+    AwaitingConfirmation { scan_summary: ScanSummary },
     Syncing,
     Paused,
+    /// Cancel requested; the sync task stops before its next file
+    Cancelling,
     Completed,
+    /// Finished, but some files were skipped after failing (see `SyncProgress::failed_files`)
+    CompletedWithErrors { failed_count: usize },
+    /// Ended early at `SyncConfig::max_session_bytes`; the files left were skipped
+    StoppedByLimit { transferred: u64, limit: u64 },
     Error(String),
 }
 
+/// What a two-phase upload is about to transfer
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ScanSummary {
+    pub total_files: u64,
+    pub total_bytes: u64,
+    /// Files not in the cloud yet; only checked in differential mode
+    pub new_files: u64,
+    /// Files changed since they were last uploaded; only checked in differential mode
+    pub modified_files: u64,
+}
+
+impl ScanSummary {
+    fn of(pending: &[PendingFile]) -> Self {
+        let count = |reason: UploadReason| pending.iter().filter(|file| file.reason == reason).count() as u64;
+        Self {
+            total_files: pending.len() as u64,
+            total_bytes: pending.iter().map(|file| file.size).sum(),
+            new_files: count(UploadReason::NewFile),
+            modified_files: count(UploadReason::Modified),
+        }
+    }
+}
+
+/// Payload of the `sync://scan_complete` event a two-phase upload sends once it has scanned
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanComplete {
+    pub files: Vec<FileEntry>,
+    pub total_bytes: u64,
+}
+
+/// A file that could not be transferred and was skipped
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FailedFile {
+    pub path: String,
+    pub error: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncProgress {
+    /// `Completed` or `CompletedWithErrors` once the sync has finished
     pub status: SyncStatus,
     pub direction: Option<SyncDirection>,
     pub total_files: u64,
@@ -44,8 +492,43 @@ pub struct SyncProgress {
     pub total_bytes: u64,
     pub transferred_bytes: u64,
     pub current_file: Option<String>,
+    pub current_file_bytes_transferred: u64,
+    pub current_file_bytes_total: u64,
+    pub current_file_bytes_received: u64,
+    /// Speed of `current_file`'s transfer since it started
+    pub current_file_bps: f64,
+    pub current_file_eta_secs: Option<u64>,
+    pub skipped_files: u64,
+    /// Skipped files that couldn't be uploaded, with why
+    pub skipped_reasons: Vec<(String, String)>,
     pub bytes_per_second: f64,
     pub eta_seconds: Option<u64>,
+    /// Per source folder totals, sorted by folder name
+    pub folder_progress: Vec<FolderProgress>,
+    /// Source folder the current file belongs to
+    pub active_folder: Option<String>,
+    /// Files skipped after failing, when the error policy is `Skip`
+    pub failed_files: Vec<FailedFile>,
+    /// One-line description of the failures, if any
+    pub error_summary: Option<String>,
+    /// Buckets an upload writes to: the primary plus any backup destinations
+    pub destination_count: usize,
+    /// Writes that failed on a backup destination; these don't fail the sync
+    pub secondary_errors: Vec<String>,
+    /// Files the scan left out by size, when `notify_excluded` is set
+    pub excluded_files: Vec<String>,
+    /// Files being transferred right now
+    pub active_transfers: u64,
+    /// Names of files being transferred right now, at most `MAX_ACTIVE_FILES_REPORTED`
+    pub active_files: Vec<String>,
+    /// Speed and time left of each file being transferred that has reported
+    /// progress, sorted by path, at most `MAX_ACTIVE_FILES_REPORTED`
+    pub active_file_progress: Vec<ActiveFileProgress>,
+    /// Local files the current sync's scan has found so far
+    pub files_found_during_scan: u64,
+    /// When the current sync started; speed and ETA are measured from here
+    #[serde(skip)]
+    pub started_at: Option<Instant>,
 }
 
 impl Default for SyncProgress {
@@ -58,335 +541,5988 @@ impl Default for SyncProgress {
             total_bytes: 0,
             transferred_bytes: 0,
             current_file: None,
+            current_file_bytes_transferred: 0,
+            current_file_bytes_total: 0,
+            current_file_bytes_received: 0,
+            current_file_bps: 0.0,
+            current_file_eta_secs: None,
+            skipped_files: 0,
+            skipped_reasons: Vec::new(),
             bytes_per_second: 0.0,
             eta_seconds: None,
+            folder_progress: Vec::new(),
+            active_folder: None,
+            failed_files: Vec::new(),
+            error_summary: None,
+            destination_count: 0,
+            secondary_errors: Vec::new(),
+            excluded_files: Vec::new(),
+            files_found_during_scan: 0,
+            active_transfers: 0,
+            active_files: Vec::new(),
+            active_file_progress: Vec::new(),
+            started_at: None,
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FileEntry {
+/// How far one of the files being transferred has got
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ActiveFileProgress {
     pub path: String,
-    pub size: u64,
-    pub is_dir: bool,
+    pub bytes_sent: u64,
+    pub total_bytes: u64,
+    pub bytes_per_second: f64,
+    pub eta_secs: Option<u64>,
 }
 
-pub struct SyncEngine {
-    s3_client: Arc<S3Client>,
-    progress: Arc<RwLock<SyncProgress>>,
-    is_paused: Arc<AtomicBool>,
-    is_cancelled: Arc<AtomicBool>,
-    transferred_bytes: Arc<AtomicU64>,
-    start_time: Arc<RwLock<Option<std::time::Instant>>>,
+/// Progress of a single source folder within a sync
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FolderProgress {
+    pub folder_name: String,
+    pub total_files: u64,
+    pub completed_files: u64,
+    pub total_bytes: u64,
+    pub transferred_bytes: u64,
 }
 
-impl SyncEngine {
-    pub fn new(s3_client: S3Client) -> Self {
-        Self {
-            s3_client: Arc::new(s3_client),
-            progress: Arc::new(RwLock::new(SyncProgress::default())),
-            is_paused: Arc::new(AtomicBool::new(false)),
-            is_cancelled: Arc::new(AtomicBool::new(false)),
-            transferred_bytes: Arc::new(AtomicU64::new(0)),
-            start_time: Arc::new(RwLock::new(None)),
-        }
+/// Final status and summary line for a finished sync
+fn completion_status(failed_files: &[FailedFile], direction: &SyncDirection) -> (SyncStatus, Option<String>) {
+    if failed_files.is_empty() {
+        return (SyncStatus::Completed, None);
     }
 
-    /// Get current sync progress
-    pub async fn get_progress(&self) -> SyncProgress {
-        let mut progress = self.progress.read().await.clone();
-        
-        // Calculate transfer speed and ETA
-        if let Some(start) = *self.start_time.read().await {
-            let elapsed = start.elapsed().as_secs_f64();
-            if elapsed > 0.0 {
-                let transferred = self.transferred_bytes.load(Ordering::Relaxed);
-                progress.transferred_bytes = transferred;
-                progress.bytes_per_second = transferred as f64 / elapsed;
-                
-                if progress.bytes_per_second > 0.0 && progress.total_bytes > transferred {
-                    let remaining = progress.total_bytes - transferred;
-                    progress.eta_seconds = Some((remaining as f64 / progress.bytes_per_second) as u64);
-                }
+    let failed_count = failed_files.len();
+    let verb = match direction {
+        SyncDirection::LocalToCloud => "upload",
+        SyncDirection::CloudToLocal => "download",
+        SyncDirection::CloudToCloud { .. } => "copy",
+    };
+    let files = if failed_count == 1 { "file" } else { "files" };
+    let summary = format!(
+        "{} {} failed to {} (see failed_files for details)",
+        failed_count, files, verb
+    );
+    (SyncStatus::CompletedWithErrors { failed_count }, Some(summary))
+}
+
+/// Called with the server's Retry-After delay whenever a transfer is rate limited
+pub type RateLimitHandler = Arc<dyn Fn(u64) + Send + Sync>;
+
+/// Called when a two-phase upload has scanned and starts waiting for confirmation
+pub type ScanCompleteHandler = Arc<dyn Fn(&ScanComplete) + Send + Sync>;
+
+/// Called with the bytes transferred and the limit when an upload stops at
+/// `SyncConfig::max_session_bytes`
+pub type LimitReachedHandler = Arc<dyn Fn(u64, u64) + Send + Sync>;
+
+/// Wait for a rate limit to clear, plus up to half a second of jitter
+fn rate_limit_delay(retry_after_secs: u64) -> Duration {
+    Duration::from_secs(retry_after_secs) + Duration::from_millis(rand::random::<u64>() % 500)
+}
+
+/// Run a transfer, sleeping out rate limit responses up to `max_retries` times
+async fn retry_rate_limited<T, F, Fut>(
+    max_retries: u32,
+    on_rate_limited: impl Fn(u64),
+    mut transfer: F,
+) -> Result<T, S3Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, S3Error>>,
+{
+    let mut attempt = 0;
+    loop {
+        match transfer().await {
+            Err(S3Error::RateLimited { retry_after_secs }) if attempt < max_retries => {
+                log::warn!("Rate limited, retrying in {}s", retry_after_secs);
+                on_rate_limited(retry_after_secs);
+                tokio::time::sleep(rate_limit_delay(retry_after_secs)).await;
+                attempt += 1;
             }
+            result => return result,
         }
-        
-        progress
     }
+}
 
-    /// Pause the sync
-    pub fn pause(&self) {
-        self.is_paused.store(true, Ordering::Relaxed);
-    }
+/// Key an object under `source_folder` is copied to under `dest_folder`
+fn copy_destination(key: &str, source_folder: &str, dest_folder: &str) -> String {
+    let source = source_folder.trim_end_matches('/');
+    let relative = key
+        .strip_prefix(source)
+        .unwrap_or(key)
+        .trim_start_matches('/');
+    format!("{}/{}", dest_folder.trim_end_matches('/'), relative)
+}
 
-    /// Resume the sync
-    pub fn resume(&self) {
-        self.is_paused.store(false, Ordering::Relaxed);
+/// Where `path` goes under each destination prefix
+fn destination_keys(prefixes: &[String], path: &str) -> Vec<String> {
+    prefixes.iter().map(|prefix| format!("{}/{}", prefix, path)).collect()
+}
+
+/// Top-level folder of a remote path ("Photos/2024/a.jpg" -> "Photos")
+fn top_folder(remote_path: &str) -> &str {
+    remote_path.split('/').next().unwrap_or(remote_path)
+}
+
+/// Why a local file needs uploading given its cloud copy, if it has one;
+/// `None` when the cloud copy is current
+fn upload_reason(metadata: &std::fs::Metadata, remote: Option<&S3Object>) -> Option<UploadReason> {
+    match remote {
+        None => Some(UploadReason::NewFile),
+        Some(remote) if differs_by_size_or_mtime(metadata, remote) => Some(UploadReason::Modified),
+        Some(_) => None,
     }
+}
 
-    /// Cancel the sync
-    pub fn cancel(&self) {
-        self.is_cancelled.store(true, Ordering::Relaxed);
-        self.is_paused.store(false, Ordering::Relaxed);
+/// Per-folder totals for a list of scanned files
+fn folder_totals(files: &[FileEntry]) -> HashMap<String, FolderProgress> {
+    let mut folders: HashMap<String, FolderProgress> = HashMap::new();
+    for file in files {
+        let name = top_folder(&file.path);
+        let folder = folders.entry(name.to_string()).or_insert_with(|| FolderProgress {
+            folder_name: name.to_string(),
+            total_files: 0,
+            completed_files: 0,
+            total_bytes: 0,
+            transferred_bytes: 0,
+        });
+        folder.total_files += 1;
+        folder.total_bytes += file.size;
     }
+    folders
+}
 
-    /// Check if sync is paused
-    pub fn is_paused(&self) -> bool {
-        self.is_paused.load(Ordering::Relaxed)
+/// Count a file as done in its folder; skipped files are removed from the byte total
+fn mark_file_done(folders: &mut HashMap<String, FolderProgress>, path: &str, size: u64, skipped: bool) {
+    if let Some(folder) = folders.get_mut(top_folder(path)) {
+        folder.completed_files += 1;
+        if skipped {
+            folder.total_bytes = folder.total_bytes.saturating_sub(size);
+        } else {
+            folder.transferred_bytes += size;
+        }
     }
+}
 
-    /// Wait while paused, return error if cancelled
-    async fn wait_if_paused(&self) -> Result<(), SyncError> {
-        while self.is_paused.load(Ordering::Relaxed) {
-            if self.is_cancelled.load(Ordering::Relaxed) {
-                return Err(SyncError::Cancelled);
-            }
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+/// A change to the progress of a sync. While an upload runs, its tasks send these to a
+/// single `ProgressAggregator` instead of each taking the progress lock.
+#[derive(Debug, Clone, PartialEq)]
+enum ProgressUpdate {
+    CurrentFile { path: String },
+    /// A file is done; `transferred` is false when it failed and its bytes won't arrive
+    FileCompleted { path: String, bytes: u64, transferred: bool },
+    /// A file didn't need uploading; `reason` is set when it couldn't be uploaded
+    /// rather than being current already
+    FileSkipped { path: String, bytes: u64, reason: Option<String> },
+    FileFailed { path: String, error: String },
+    SecondaryError(String),
+    StatusChange(SyncStatus),
+}
+
+fn apply_progress_update(
+    progress: &mut SyncProgress,
+    folders: &mut HashMap<String, FolderProgress>,
+    update: ProgressUpdate,
+) {
+    match update {
+        ProgressUpdate::CurrentFile { path } => {
+            progress.active_folder = Some(top_folder(&path).to_string());
+            progress.current_file = Some(path);
         }
-        
-        if self.is_cancelled.load(Ordering::Relaxed) {
-            return Err(SyncError::Cancelled);
+        ProgressUpdate::FileCompleted { path, bytes, transferred } => {
+            mark_file_done(folders, &path, bytes, !transferred);
+            progress.completed_files += 1;
         }
-        
-        Ok(())
+        ProgressUpdate::FileSkipped { path, bytes, reason } => {
+            mark_file_done(folders, &path, bytes, true);
+            progress.skipped_files += 1;
+            if let Some(reason) = reason {
+                progress.skipped_reasons.push((path, reason));
+            }
+            progress.total_bytes = progress.total_bytes.saturating_sub(bytes);
+            progress.completed_files += 1;
+        }
+        ProgressUpdate::FileFailed { path, error } => progress.failed_files.push(FailedFile { path, error }),
+        ProgressUpdate::SecondaryError(error) => progress.secondary_errors.push(error),
+        ProgressUpdate::StatusChange(status) => progress.status = status,
     }
+}
 
-    /// Scan local folders to get list of files
-    pub async fn scan_local_folders(&self, paths: &[PathBuf]) -> Result<Vec<FileEntry>, SyncError> {
-        let mut entries = Vec::new();
-        
-        for base_path in paths {
-            let folder_name = base_path
-                .file_name()
-                .map(|n| n.to_string_lossy().to_string())
-                .unwrap_or_else(|| "folder".to_string());
-            
-            for entry in WalkDir::new(base_path).follow_links(true) {
-                let entry = entry.map_err(|e| SyncError::IoError(e.to_string()))?;
-                let path = entry.path();
-                
-                if path.is_file() {
-                    let relative = path
-                        .strip_prefix(base_path)
-                        .map_err(|e| SyncError::IoError(e.to_string()))?;
-                    
-                    let remote_path = format!("{}/{}", folder_name, relative.display());
-                    let metadata = std::fs::metadata(path)
-                        .map_err(|e| SyncError::IoError(e.to_string()))?;
-                    
-                    entries.push(FileEntry {
-                        path: remote_path,
-                        size: metadata.len(),
-                        is_dir: false,
-                    });
+type ProgressSender = Arc<std::sync::Mutex<Option<mpsc::UnboundedSender<ProgressUpdate>>>>;
+
+/// Background task applying progress updates for the length of an upload, taking the
+/// lock once for everything queued up. Stops taking updates when dropped.
+struct ProgressAggregator {
+    sender: ProgressSender,
+    task: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl ProgressAggregator {
+    fn start(sender: ProgressSender, handles: &ProgressHandles) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        *sender.lock().unwrap_or_else(|e| e.into_inner()) = Some(tx);
+
+        let (progress, folder_progress) = (Arc::clone(&handles.progress), Arc::clone(&handles.folder_progress));
+        let task = tokio::spawn(async move {
+            while let Some(update) = rx.recv().await {
+                let mut progress = progress.write().await;
+                let mut folders = folder_progress.write().await;
+                apply_progress_update(&mut progress, &mut folders, update);
+                while let Ok(update) = rx.try_recv() {
+                    apply_progress_update(&mut progress, &mut folders, update);
                 }
             }
-        }
-        
-        Ok(entries)
+        });
+        Self { sender, task: Some(task) }
     }
 
-    /// Sync local folders to cloud
-    pub async fn sync_to_cloud(&self, source_paths: &[PathBuf]) -> Result<(), SyncError> {
-        // Reset state
-        self.is_cancelled.store(false, Ordering::Relaxed);
-        self.is_paused.store(false, Ordering::Relaxed);
-        self.transferred_bytes.store(0, Ordering::Relaxed);
-        *self.start_time.write().await = Some(std::time::Instant::now());
-        
-        // Update status to scanning
-        {
-            let mut progress = self.progress.write().await;
-            progress.status = SyncStatus::Scanning;
-            progress.direction = Some(SyncDirection::LocalToCloud);
+    /// Stop taking updates and wait until the queued ones have been applied
+    async fn finish(mut self) {
+        self.sender.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
         }
-        
-        // Scan files
-        let files = self.scan_local_folders(source_paths).await?;
-        let total_bytes: u64 = files.iter().map(|f| f.size).sum();
-        let total_files = files.len() as u64;
-        
-        // Update progress with totals
-        {
-            let mut progress = self.progress.write().await;
-            progress.status = SyncStatus::Syncing;
-            progress.total_files = total_files;
-            progress.total_bytes = total_bytes;
-            progress.completed_files = 0;
+    }
+}
+
+impl Drop for ProgressAggregator {
+    fn drop(&mut self) {
+        // The task drains what is left and exits once the sender is gone
+        self.sender.lock().unwrap_or_else(|e| e.into_inner()).take();
+    }
+}
+
+/// Summary of a finished sync session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncSummary {
+    pub session_id: String,
+    pub direction: SyncDirection,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub duration_secs: f64,
+    pub total_files: u64,
+    pub transferred_bytes: u64,
+    pub skipped_files: u64,
+    /// Skipped files that couldn't be uploaded, with why (e.g. "deleted during sync")
+    pub skipped_reasons: Vec<(String, String)>,
+    /// Cloud objects the sync deleted; uploads and downloads never delete any
+    pub deleted_files: u64,
+    /// Paths of the files that failed and were skipped
+    pub failed_files: Vec<String>,
+    pub average_bps: f64,
+    /// Highest speed measured over `SPEED_WINDOW`, or the average for syncs too
+    /// short to measure one
+    pub peak_bps: f64,
+    /// 95th percentile of the per-file transfer times in this sync
+    pub percentile_95_ms: u64,
+    pub mean_ms: u64,
+    /// Path of the file that took longest, if any were transferred
+    pub slowest_file: Option<String>,
+}
+
+/// Uploads and downloads finished since the app started or the stats were reset
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SessionStats {
+    pub uploads_count: u64,
+    pub downloads_count: u64,
+    pub bytes_uploaded: u64,
+    pub bytes_downloaded: u64,
+    pub session_start: DateTime<Utc>,
+}
+
+impl Default for SessionStats {
+    fn default() -> Self {
+        Self::starting_now(&RealTimeSource)
+    }
+}
+
+impl SessionStats {
+    /// Empty totals for a session that starts at the current time of `clock`
+    pub fn starting_now(clock: &dyn TimeSource) -> Self {
+        Self {
+            uploads_count: 0,
+            downloads_count: 0,
+            bytes_uploaded: 0,
+            bytes_downloaded: 0,
+            session_start: clock.now_system().into(),
         }
-        
-        // Upload each file
-        for (idx, file) in files.iter().enumerate() {
-            self.wait_if_paused().await?;
-            
-            // Update current file
-            {
-                let mut progress = self.progress.write().await;
-                progress.current_file = Some(file.path.clone());
+    }
+
+    /// Add a finished sync; copies between cloud folders count as neither
+    pub fn record(&mut self, summary: &SyncSummary) {
+        match summary.direction {
+            SyncDirection::LocalToCloud => {
+                self.uploads_count += 1;
+                self.bytes_uploaded += summary.transferred_bytes;
             }
-            
-            // Find the source path for this file
-            let source_file = self.find_source_file(source_paths, &file.path)?;
-            
-            // Upload
-            self.s3_client
-                .upload_file(&source_file, &file.path)
-                .await
-                .map_err(|e| SyncError::S3Error(e.to_string()))?;
-            
-            // Update progress
-            self.transferred_bytes.fetch_add(file.size, Ordering::Relaxed);
-            {
-                let mut progress = self.progress.write().await;
-                progress.completed_files = (idx + 1) as u64;
+            SyncDirection::CloudToLocal => {
+                self.downloads_count += 1;
+                self.bytes_downloaded += summary.transferred_bytes;
             }
+            SyncDirection::CloudToCloud { .. } => {}
         }
-        
-        // Mark as completed
-        {
-            let mut progress = self.progress.write().await;
-            progress.status = SyncStatus::Completed;
-            progress.current_file = None;
-        }
-        
-        Ok(())
     }
+}
 
-    /// Find the actual source file path given the remote path
-    fn find_source_file(&self, source_paths: &[PathBuf], remote_path: &str) -> Result<PathBuf, SyncError> {
-        for base_path in source_paths {
-            let folder_name = base_path
-                .file_name()
-                .map(|n| n.to_string_lossy().to_string())
-                .unwrap_or_else(|| "folder".to_string());
-            
-            if let Some(relative) = remote_path.strip_prefix(&format!("{}/", folder_name)) {
-                let full_path = base_path.join(relative);
-                if full_path.exists() {
-                    return Ok(full_path);
-                }
+/// How long a single file took to transfer
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FileTransferRecord {
+    pub path: String,
+    pub size: u64,
+    pub duration_ms: u64,
+    pub direction: SyncDirection,
+}
+
+/// Nearest-rank percentile of `durations`, which must be sorted ascending
+fn percentile_ms(durations: &[u64], percentile: f64) -> u64 {
+    if durations.is_empty() {
+        return 0;
+    }
+    let rank = (percentile / 100.0 * durations.len() as f64).ceil() as usize;
+    durations[rank.clamp(1, durations.len()) - 1]
+}
+
+/// 95th percentile, mean and slowest file of a set of transfers
+fn transfer_timings(records: &[FileTransferRecord]) -> (u64, u64, Option<String>) {
+    let mut durations: Vec<u64> = records.iter().map(|r| r.duration_ms).collect();
+    durations.sort_unstable();
+    let mean_ms = match durations.len() as u64 {
+        0 => 0,
+        count => durations.iter().sum::<u64>() / count,
+    };
+    let slowest_file = records.iter().max_by_key(|r| r.duration_ms).map(|r| r.path.clone());
+    (percentile_ms(&durations, 95.0), mean_ms, slowest_file)
+}
+
+/// An upload saved while it runs, so it can be resumed if the app quits before it finishes.
+/// (Not to be confused with `SyncState`, the running/paused/cancelled flag.)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncSession {
+    /// UUID of the `sync_to_cloud` call, which names the saved file
+    pub session_id: String,
+    /// UID of the user who started the upload; only they may resume it
+    #[serde(default)]
+    pub uid: Option<String>,
+    pub progress: SyncProgress,
+    pub source_paths: Vec<PathBuf>,
+    /// `remote_path_template` as rendered when the upload started, which a resumed
+    /// upload keeps so that its files go where the first part's did
+    #[serde(default)]
+    pub rendered_template: Option<String>,
+    pub direction: SyncDirection,
+    /// Remote keys already uploaded; a resumed sync skips these
+    pub transferred_keys: Vec<String>,
+}
+
+impl SyncSession {
+    /// Read a saved state; None if there is none or it can't be parsed
+    pub fn load(path: &Path) -> Option<Self> {
+        let json = std::fs::read(path).ok()?;
+        match serde_json::from_slice(&json) {
+            Ok(state) => Some(state),
+            Err(e) => {
+                log::warn!("Ignoring unreadable sync state {}: {}", path.display(), e);
+                None
             }
         }
-        
-        Err(SyncError::IoError(format!("Source file not found: {}", remote_path)))
     }
 
-    /// Sync cloud folder to local
-    pub async fn sync_to_local(
-        &self,
-        cloud_folder: &str,
-        target_path: &Path,
-    ) -> Result<(), SyncError> {
-        // Reset state
-        self.is_cancelled.store(false, Ordering::Relaxed);
-        self.is_paused.store(false, Ordering::Relaxed);
-        self.transferred_bytes.store(0, Ordering::Relaxed);
-        *self.start_time.write().await = Some(std::time::Instant::now());
-        
-        // Update status to scanning
-        {
-            let mut progress = self.progress.write().await;
-            progress.status = SyncStatus::Scanning;
-            progress.direction = Some(SyncDirection::CloudToLocal);
+    /// Whether the upload was started by the user with `uid`, or by nobody
+    /// logged in when `uid` is None
+    pub fn belongs_to(&self, uid: Option<&str>) -> bool {
+        self.uid.as_deref() == uid
+    }
+
+    /// Write the state, replacing any previous file in one step
+    pub fn save(&self, path: &Path) -> Result<(), SyncError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| io_error(dir, e))?;
         }
-        
-        // List cloud files
-        let objects = self.s3_client
-            .list_objects(cloud_folder)
-            .await
-            .map_err(|e| SyncError::S3Error(e.to_string()))?;
-        
-        let total_bytes: u64 = objects.iter().map(|o| o.size).sum();
-        let total_files = objects.len() as u64;
-        
-        // Update progress with totals
+        let json = serde_json::to_vec(self).map_err(|e| SyncError::IoError(e.to_string()))?;
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, json).map_err(|e| io_error(&temp, e))?;
+        std::fs::rename(&temp, path).map_err(|e| io_error(path, e))
+    }
+
+    /// Delete a saved state, if there is one
+    pub fn remove(path: &Path) {
+        if let Err(e) = std::fs::remove_file(path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("Failed to remove sync state {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// The running upload's saved state and when it was last written out
+struct ResumeRecord {
+    state: SyncSession,
+    saved_at: Option<Instant>,
+}
+
+/// What to do when a single file fails to transfer
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub enum ErrorPolicy {
+    /// Stop the whole sync on the first failed file
+    #[default]
+    Abort,
+    /// Record the failure and continue with the next file
+    Skip,
+}
+
+/// Longest object lock retention `set_sync_config` accepts, about 100 years
+pub const MAX_RETAIN_DAYS: u32 = 36_500;
+
+/// Object lock applied to every upload (`SyncConfig::object_lock`)
+///
+/// Only works on buckets created with object locking enabled. Those keep every
+/// version of an object, so locked uploads skip `atomic_uploads` and
+/// `overwrite_existing`: uploading again adds a version and never replaces one.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct ObjectLockConfig {
+    /// Days each upload stays locked
+    pub retain_days: u32,
+    pub mode: LockMode,
+}
+
+/// Prices of an S3 provider, for `SyncEngine::estimate_cost`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct S3Pricing {
+    pub upload_per_gb_usd: f64,
+    pub download_per_gb_usd: f64,
+    pub storage_per_gb_month_usd: f64,
+    /// PUT, COPY, POST and multipart requests
+    pub put_per_1000_usd: f64,
+    /// GET and HEAD requests
+    pub get_per_1000_usd: f64,
+}
+
+impl S3Pricing {
+    /// Scaleway's public Standard class prices in nl-ams, converted from EUR:
+    /// free uploads and requests, paid storage and egress
+    pub fn scaleway_ams_default() -> Self {
+        Self {
+            upload_per_gb_usd: 0.0,
+            download_per_gb_usd: 0.011,
+            storage_per_gb_month_usd: 0.016,
+            put_per_1000_usd: 0.0,
+            get_per_1000_usd: 0.0,
+        }
+    }
+
+    /// Cost of moving and keeping the given bytes and making the given requests
+    pub fn estimate(
+        &self,
+        uploaded_bytes: u64,
+        downloaded_bytes: u64,
+        put_requests: u64,
+        get_requests: u64,
+    ) -> S3CostEstimate {
+        const GB: f64 = (1024 * 1024 * 1024) as f64;
+        let uploaded_gb = uploaded_bytes as f64 / GB;
+        let upload_cost_usd = uploaded_gb * self.upload_per_gb_usd;
+        let download_cost_usd = downloaded_bytes as f64 / GB * self.download_per_gb_usd;
+        let storage_cost_per_month_usd = uploaded_gb * self.storage_per_gb_month_usd;
+        let api_call_cost_usd = put_requests as f64 / 1000.0 * self.put_per_1000_usd
+            + get_requests as f64 / 1000.0 * self.get_per_1000_usd;
+        S3CostEstimate {
+            upload_cost_usd,
+            download_cost_usd,
+            storage_cost_per_month_usd,
+            api_call_cost_usd,
+            total_estimated_usd: upload_cost_usd + download_cost_usd + storage_cost_per_month_usd + api_call_cost_usd,
+        }
+    }
+}
+
+/// What a sync is expected to cost, from `SyncEngine::estimate_cost`. The total
+/// counts one month of storing the uploaded files.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub struct S3CostEstimate {
+    pub upload_cost_usd: f64,
+    pub download_cost_usd: f64,
+    pub storage_cost_per_month_usd: f64,
+    pub api_call_cost_usd: f64,
+    pub total_estimated_usd: f64,
+}
+
+/// User-configurable sync options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncConfig {
+    /// Show an OS notification when a sync finishes or fails
+    pub notifications_enabled: bool,
+    /// Only upload files that are missing or changed in the cloud
+    pub differential: bool,
+    /// Number of files transferred at the same time
+    pub concurrency: usize,
+    /// Attempts per file before it counts as failed
+    pub max_file_retries: u32,
+    pub on_error: ErrorPolicy,
+    /// Client-side encryption, which isn't implemented yet; `set_sync_config` rejects
+    /// true rather than upload plaintext the user expects to be encrypted
+    pub encrypt_contents: bool,
+    /// Glob patterns for files that are never synced
+    pub exclude_patterns: Vec<String>,
+    /// Tags attached to every uploaded file; `synced_at` is added per upload
+    pub default_tags: HashMap<String, String>,
+    /// Upload to a temp key and copy it into place, so an interrupted upload
+    /// never leaves a partial file under the real name
+    pub atomic_uploads: bool,
+    /// Scan uploads first and wait for `confirm` before transferring anything
+    pub two_phase: bool,
+    /// Replace files already in the cloud. When off, an upload that finds a
+    /// different object at its key fails instead of overwriting it.
+    pub overwrite_existing: bool,
+    /// Compute a SHA-256 of every local file during the scan
+    pub compute_hashes: bool,
+    /// Download large files as parallel ranged requests, `concurrency` at a time
+    pub multipart_download: bool,
+    /// Size in bytes above which `multipart_download` applies
+    pub multipart_threshold: u64,
+    /// Source folders scanned at the same time; 0 scans them all at once
+    pub scan_parallelism: usize,
+    /// Scan into symlinked files and folders instead of skipping them
+    pub follow_symlinks: bool,
+    /// Scan into Windows junctions instead of skipping them
+    pub follow_junctions: bool,
+    /// Leave zero-byte files out of uploads, counting them as skipped
+    pub skip_empty_files: bool,
+    /// Leave files smaller than this many bytes out of uploads, counting them as skipped
+    pub exclude_min_size: Option<u64>,
+    /// Leave files larger than this many bytes out of uploads, counting them as skipped
+    pub exclude_max_size: Option<u64>,
+    /// List the files left out by size in `SyncProgress::excluded_files`
+    pub notify_excluded: bool,
+    /// Lock uploads against being changed or deleted for a while (WORM)
+    pub object_lock: Option<ObjectLockConfig>,
+    /// Upload a file once more if it changed while being read for upload
+    pub retry_changed_files: bool,
+    /// Match local folder names case-sensitively, as Linux file systems do;
+    /// off by default on Windows and macOS
+    pub case_sensitive: bool,
+    /// Free space to leave on the target disk when downloading.
+    /// Sent to and from the frontend as `min_free_gb`.
+    #[serde(rename = "min_free_gb", with = "gigabytes")]
+    pub min_free_bytes: Option<u64>,
+    /// Remote path for each source folder instead of its name, e.g. "backups/{date}/{folder_name}".
+    /// Also takes `{datetime}` and `{uid}`; see `render_path_template`.
+    pub remote_path_template: Option<String>,
+    /// Canned ACL set on each upload, e.g. "bucket-owner-full-control"
+    pub default_acl: Option<String>,
+    /// Provider prices for cost estimates; Scaleway's nl-ams prices when None
+    pub pricing: Option<S3Pricing>,
+    /// Store a `FolderChecksum` in each source folder's cloud copy after every upload
+    pub folder_checksums: bool,
+    /// Most bytes one upload may transfer, for metered connections. Files that would
+    /// go over it are skipped and the upload ends as `StoppedByLimit`.
+    pub max_session_bytes: Option<u64>,
+    /// Refuse uploads and deletions, leaving downloads and previews working
+    pub read_only: bool,
+    /// Upload a manifest of the uploaded files and their SHA-256 after each upload;
+    /// costs an extra PUT and a read of every file not hashed during the scan
+    pub generate_manifest: bool,
+    /// Have the primary bucket encrypt objects with a key derived for the user (SSE-C).
+    /// Objects uploaded with it on can only be read with it on, and the other way round.
+    pub use_sse_c: bool,
+}
+
+/// (De)serialize an optional byte count as a number of GB
+mod gigabytes {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    const GB: f64 = (1024 * 1024 * 1024) as f64;
+
+    pub fn serialize<S: Serializer>(bytes: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        bytes.map(|bytes| bytes as f64 / GB).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+        let gb = Option::<f64>::deserialize(deserializer)?;
+        Ok(gb.map(|gb| (gb.max(0.0) * GB) as u64))
+    }
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            notifications_enabled: true,
+            differential: false,
+            concurrency: 4,
+            max_file_retries: 3,
+            on_error: ErrorPolicy::Abort,
+            encrypt_contents: false,
+            exclude_patterns: Vec::new(),
+            default_tags: HashMap::from([("app".to_string(), "sync2bucket".to_string())]),
+            atomic_uploads: false,
+            two_phase: false,
+            overwrite_existing: true,
+            compute_hashes: false,
+            multipart_download: false,
+            multipart_threshold: 64 * 1024 * 1024,
+            min_free_bytes: None,
+            scan_parallelism: 0,
+            follow_symlinks: true,
+            follow_junctions: false,
+            skip_empty_files: false,
+            exclude_min_size: None,
+            exclude_max_size: None,
+            notify_excluded: false,
+            object_lock: None,
+            retry_changed_files: true,
+            case_sensitive: cfg!(not(any(windows, target_os = "macos"))),
+            default_acl: None,
+            remote_path_template: None,
+            pricing: None,
+            use_sse_c: false,
+            generate_manifest: false,
+            read_only: false,
+            folder_checksums: false,
+            max_session_bytes: None,
+        }
+    }
+}
+
+/// Options a `.sync.toml` in a source folder sets for the files under it.
+/// Keys left out keep the global value; patterns and tags add to the global ones.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FolderSyncConfig {
+    pub exclude_patterns: Option<Vec<String>>,
+    pub default_tags: Option<HashMap<String, String>>,
+    pub overwrite_existing: Option<bool>,
+    pub atomic_uploads: Option<bool>,
+    pub retry_changed_files: Option<bool>,
+    pub skip_empty_files: Option<bool>,
+    pub exclude_min_size: Option<u64>,
+    pub exclude_max_size: Option<u64>,
+}
+
+impl FolderSyncConfig {
+    /// `base` with the options set here applied
+    pub fn apply_to(&self, base: &SyncConfig) -> SyncConfig {
+        let mut config = base.clone();
+        if let Some(patterns) = &self.exclude_patterns {
+            config.exclude_patterns.extend(patterns.iter().cloned());
+        }
+        if let Some(tags) = &self.default_tags {
+            config.default_tags.extend(tags.clone());
+        }
+        config.overwrite_existing = self.overwrite_existing.unwrap_or(config.overwrite_existing);
+        config.atomic_uploads = self.atomic_uploads.unwrap_or(config.atomic_uploads);
+        config.retry_changed_files = self.retry_changed_files.unwrap_or(config.retry_changed_files);
+        config.skip_empty_files = self.skip_empty_files.unwrap_or(config.skip_empty_files);
+        config.exclude_min_size = self.exclude_min_size.or(config.exclude_min_size);
+        config.exclude_max_size = self.exclude_max_size.or(config.exclude_max_size);
+        config
+    }
+}
+
+/// A `.sync.toml` applied to the global config, for the files below its folder
+struct FolderConfig {
+    /// Folder holding the `.sync.toml`
+    dir: PathBuf,
+    config: SyncConfig,
+    /// The file's own exclude patterns, matched against paths relative to `dir`.
+    /// The global ones are applied by the walk.
+    filter: SyncFilter,
+}
+
+impl FolderConfig {
+    /// Whether the local file `path` below `dir` is left out by the file's patterns
+    fn excludes(&self, path: &Path) -> bool {
+        path.strip_prefix(&self.dir)
+            .is_ok_and(|relative| self.filter.should_exclude(&relative.to_string_lossy()))
+    }
+}
+
+impl SyncConfig {
+    /// Whether a file of `size` bytes is left out by `skip_empty_files` or the size limits
+    pub fn excludes_size(&self, size: u64) -> bool {
+        (self.skip_empty_files && size == 0)
+            || self.exclude_min_size.is_some_and(|min| size < min)
+            || self.exclude_max_size.is_some_and(|max| size > max)
+    }
+}
+
+/// Upload rules administrators set for every user in `_admin/sync_policy.json`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyncPolicy {
+    #[serde(default)]
+    pub max_file_size_bytes: Option<u64>,
+    /// Extensions matched ignoring case, with or without the leading dot
+    #[serde(default)]
+    pub forbidden_extensions: Vec<String>,
+    /// Tags added to every upload, over the user's own
+    #[serde(default)]
+    pub required_tags: HashMap<String, String>,
+    /// Put every user in read-only mode
+    #[serde(default)]
+    pub force_read_only: bool,
+    /// Canned ACLs users may not store files with, e.g. "public-read"
+    #[serde(default)]
+    pub forbidden_acls: Vec<String>,
+}
+
+impl SyncPolicy {
+    /// Why a file at `path` of `size` bytes may not be uploaded, if it may not
+    pub fn violation(&self, path: &str, size: u64) -> Option<String> {
+        if let Some(max) = self.max_file_size_bytes.filter(|max| size > *max) {
+            return Some(format!("larger than the {} byte limit", max));
+        }
+        let extension = Path::new(path).extension()?.to_string_lossy().to_lowercase();
+        self.forbidden_extensions
+            .iter()
+            .any(|forbidden| forbidden.trim_start_matches('.').to_lowercase() == extension)
+            .then(|| format!(".{} files are not allowed", extension))
+    }
+
+    /// Why files may not be stored with the canned ACL `acl`, if they may not
+    pub fn acl_violation(&self, acl: &str) -> Option<String> {
+        self.forbidden_acls
+            .iter()
+            .any(|forbidden| forbidden == acl)
+            .then(|| format!("the {} ACL is not allowed", acl))
+    }
+}
+
+/// Last sync policy fetched, with when; shared by every engine of a session
+pub type SyncPolicyCache = Arc<RwLock<Option<(SyncPolicy, Instant)>>>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub path: String,
+    pub size: u64,
+    pub is_dir: bool,
+    /// Hex SHA-256 of a local file, when the scan computed hashes
+    #[serde(default)]
+    pub content_hash: Option<String>,
+}
+
+/// A file in an upload manifest
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub size: u64,
+    /// Hex SHA-256 of the contents
+    pub sha256: String,
+    pub uploaded_at: DateTime<Utc>,
+}
+
+/// The files an upload put in the cloud, stored with them when `generate_manifest` is set
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UploadManifest {
+    pub created_at: DateTime<Utc>,
+    pub files: Vec<ManifestEntry>,
+}
+
+/// Fingerprint of everything in a cloud folder, to notice files changed or
+/// deleted outside the app
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FolderChecksum {
+    pub prefix: String,
+    pub file_count: u64,
+    pub total_bytes: u64,
+    /// Hex BLAKE3 Merkle root over the SHA-256 of `MERKLE_LEAF`, each object's key and its ETag
+    pub merkle_root: String,
+}
+
+impl FolderChecksum {
+    /// Checksum of the objects listed under `prefix`, leaving out stored checksums
+    fn of(prefix: &str, objects: &[S3Object]) -> Self {
+        let mut objects: Vec<&S3Object> = objects
+            .iter()
+            .filter(|object| !object.key.ends_with(&format!("/{}", FOLDER_CHECKSUM_FILE)))
+            .collect();
+        objects.sort_by(|a, b| a.key.cmp(&b.key));
+        let leaves = objects
+            .iter()
+            .map(|object| {
+                let mut hasher = Sha256::new();
+                hasher.update([MERKLE_LEAF]);
+                hasher.update(object.key.as_bytes());
+                hasher.update(object.etag.as_deref().unwrap_or_default().as_bytes());
+                <[u8; 32]>::from(hasher.finalize())
+            })
+            .collect();
+        Self {
+            prefix: prefix.to_string(),
+            file_count: objects.len() as u64,
+            total_bytes: objects.iter().map(|object| object.size).sum(),
+            merkle_root: hex::encode(merkle_root(leaves)),
+        }
+    }
+}
+
+/// Why `list_pending_uploads` expects a file to be uploaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UploadReason {
+    /// Not in the bucket yet
+    NewFile,
+    /// The cloud copy differs in size or is older than the local file
+    Modified,
+    /// Differential sync is off, so every file is uploaded
+    Forced,
+}
+
+/// A local file the next upload would transfer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingFile {
+    pub local_path: String,
+    pub remote_path: String,
+    pub size: u64,
+    pub reason: UploadReason,
+}
+
+impl From<PendingFile> for FileEntry {
+    fn from(file: PendingFile) -> Self {
+        FileEntry {
+            path: file.remote_path,
+            size: file.size,
+            is_dir: false,
+            content_hash: None,
+        }
+    }
+}
+
+/// Whether a sync may keep transferring
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SyncState {
+    Running = 0,
+    Paused = 1,
+    Cancelled = 2,
+}
+
+/// A `SyncState` held in one atomic, so pause and cancel are always seen together
+#[derive(Debug, Default)]
+pub struct SyncStateCell {
+    state: AtomicU8,
+}
+
+impl SyncStateCell {
+    pub fn get(&self) -> SyncState {
+        match self.state.load(Ordering::Acquire) {
+            0 => SyncState::Running,
+            1 => SyncState::Paused,
+            _ => SyncState::Cancelled,
+        }
+    }
+
+    /// Pause a running sync; a cancelled sync stays cancelled
+    pub fn set_paused(&self) {
+        self.transition(SyncState::Running, SyncState::Paused);
+    }
+
+    /// Resume a paused sync; a cancelled sync stays cancelled
+    pub fn set_running(&self) {
+        self.transition(SyncState::Paused, SyncState::Running);
+    }
+
+    pub fn set_cancelled(&self) {
+        self.state.store(SyncState::Cancelled as u8, Ordering::Release);
+    }
+
+    /// Start over as running, whatever the previous sync left behind
+    pub fn reset(&self) {
+        self.state.store(SyncState::Running as u8, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.get() == SyncState::Cancelled
+    }
+
+    /// Resolves once the sync is cancelled
+    pub async fn cancelled(&self) {
+        while !self.is_cancelled() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    fn transition(&self, from: SyncState, to: SyncState) {
+        let _ = self
+            .state
+            .compare_exchange(from as u8, to as u8, Ordering::AcqRel, Ordering::Acquire);
+    }
+}
+
+/// Emit a sync state event with an empty payload to `window` alone; `emit` would
+/// reach every window
+fn emit_state_change<R: Runtime>(window: &Window<R>, event: &str) {
+    let target = EventTarget::webview_window(window.label());
+    if let Err(e) = window.emit_to(target, event, serde_json::json!({})) {
+        log::warn!("Failed to emit {}: {}", event, e);
+    }
+}
+
+/// Windows that asked for sync events, so a reloaded frontend can subscribe
+/// again; shared by every engine of the app
+pub struct SyncWindows<R: Runtime = Wry>(Arc<std::sync::RwLock<Vec<Window<R>>>>);
+
+impl<R: Runtime> Clone for SyncWindows<R> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<R: Runtime> Default for SyncWindows<R> {
+    fn default() -> Self {
+        Self(Arc::new(std::sync::RwLock::new(Vec::new())))
+    }
+}
+
+impl<R: Runtime> SyncWindows<R> {
+    /// Add `window`, replacing one registered earlier with the same label
+    pub fn register(&self, window: Window<R>) {
+        let mut windows = self.0.write().unwrap_or_else(|e| e.into_inner());
+        windows.retain(|registered| registered.label() != window.label());
+        windows.push(window);
+    }
+
+    pub fn unregister(&self, label: &str) {
+        self.0
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|registered| registered.label() != label);
+    }
+
+    /// Emit a state event to `origin` and every registered window other than it
+    fn emit_state_change<O: Runtime>(&self, origin: &Window<O>, event: &str) {
+        emit_state_change(origin, event);
+        let windows = self.0.read().unwrap_or_else(|e| e.into_inner());
+        for window in windows.iter().filter(|window| window.label() != origin.label()) {
+            emit_state_change(window, event);
+        }
+    }
+}
+
+/// Wait while paused; error out as soon as the sync is cancelled
+async fn wait_while_paused(state: &SyncStateCell) -> Result<(), SyncError> {
+    loop {
+        match state.get() {
+            SyncState::Running => return Ok(()),
+            SyncState::Cancelled => return Err(SyncError::Cancelled),
+            SyncState::Paused => tokio::time::sleep(Duration::from_millis(100)).await,
+        }
+    }
+}
+
+/// Shared handles a progress snapshot is built from; cheap to clone into
+/// the background task that refreshes the snapshot during a sync
+#[derive(Clone)]
+struct ProgressHandles {
+    progress: Arc<RwLock<SyncProgress>>,
+    folder_progress: Arc<RwLock<HashMap<String, FolderProgress>>>,
+    transferred_bytes: Arc<AtomicU64>,
+    current_file_bytes: Arc<AtomicU64>,
+    current_file_total: Arc<AtomicU64>,
+    current_file_received: Arc<AtomicU64>,
+    file_events: Arc<std::sync::Mutex<HashMap<String, ProgressEvent>>>,
+    active_transfers: Arc<AtomicU64>,
+    active_files: Arc<std::sync::RwLock<HashSet<String>>>,
+    scan_progress: Arc<AtomicU64>,
+    dirs_scanned: Arc<AtomicU64>,
+    last_snapshot: Arc<std::sync::Mutex<SyncProgress>>,
+    /// `(when, transferred_bytes)` over the last `SPEED_WINDOW`
+    speed_samples: Arc<std::sync::Mutex<VecDeque<(Instant, u64)>>>,
+    /// Bits of the highest `bytes_per_second` this sync, as an `f64`
+    peak_speed: Arc<AtomicU64>,
+    time_source: Arc<dyn TimeSource>,
+}
+
+impl Default for ProgressHandles {
+    fn default() -> Self {
+        Self {
+            progress: Default::default(),
+            folder_progress: Default::default(),
+            transferred_bytes: Default::default(),
+            current_file_bytes: Default::default(),
+            current_file_total: Default::default(),
+            current_file_received: Default::default(),
+            file_events: Default::default(),
+            active_transfers: Default::default(),
+            active_files: Default::default(),
+            scan_progress: Default::default(),
+            dirs_scanned: Default::default(),
+            last_snapshot: Default::default(),
+            speed_samples: Default::default(),
+            peak_speed: Default::default(),
+            time_source: Arc::new(RealTimeSource),
+        }
+    }
+}
+
+impl ProgressHandles {
+    fn now(&self) -> Instant {
+        self.time_source.now_instant()
+    }
+
+    fn elapsed(&self, since: Instant) -> Duration {
+        self.now().saturating_duration_since(since)
+    }
+
+    /// Copy the byte counters into `progress` and recompute speed and ETA
+    fn apply_counters(&self, progress: &mut SyncProgress) {
+        progress.current_file_bytes_transferred = self.current_file_bytes.load(Ordering::Relaxed);
+        progress.current_file_bytes_total = self.current_file_total.load(Ordering::Relaxed);
+        progress.current_file_bytes_received = self.current_file_received.load(Ordering::Relaxed);
         {
-            let mut progress = self.progress.write().await;
-            progress.status = SyncStatus::Syncing;
-            progress.total_files = total_files;
-            progress.total_bytes = total_bytes;
-            progress.completed_files = 0;
+            let file_events = self.file_events.lock().unwrap_or_else(|e| e.into_inner());
+            let current = progress.current_file.as_ref().and_then(|path| file_events.get(path));
+            progress.current_file_bps = current.map_or(0.0, |event| event.bytes_per_second);
+            progress.current_file_eta_secs = current.and_then(|event| event.eta_secs);
+
+            let mut active_file_progress: Vec<ActiveFileProgress> = file_events
+                .iter()
+                .map(|(path, event)| ActiveFileProgress {
+                    path: path.clone(),
+                    bytes_sent: event.bytes_sent,
+                    total_bytes: event.total_bytes,
+                    bytes_per_second: event.bytes_per_second,
+                    eta_secs: event.eta_secs,
+                })
+                .collect();
+            active_file_progress.sort_by(|a, b| a.path.cmp(&b.path));
+            active_file_progress.truncate(MAX_ACTIVE_FILES_REPORTED);
+            progress.active_file_progress = active_file_progress;
         }
-        
-        // Download each file
-        for (idx, obj) in objects.iter().enumerate() {
-            self.wait_if_paused().await?;
-            
-            // Skip directories (keys ending with /)
-            if obj.key.ends_with('/') {
-                continue;
-            }
-            
-            // Update current file
-            {
-                let mut progress = self.progress.write().await;
-                progress.current_file = Some(obj.key.clone());
-            }
-            
-            // Calculate local path
-            let relative = obj.key.strip_prefix(cloud_folder).unwrap_or(&obj.key);
-            let relative = relative.trim_start_matches('/');
-            let local_path = target_path.join(relative);
-            
-            // Download
-            self.s3_client
-                .download_file(&obj.key, &local_path)
-                .await
-                .map_err(|e| SyncError::S3Error(e.to_string()))?;
-            
-            // Update progress
-            self.transferred_bytes.fetch_add(obj.size, Ordering::Relaxed);
-            {
-                let mut progress = self.progress.write().await;
-                progress.completed_files = (idx + 1) as u64;
+        progress.active_transfers = self.active_transfers.load(Ordering::Relaxed);
+        progress.files_found_during_scan = self.scan_progress.load(Ordering::Relaxed);
+        // Counted as the scan goes, between its status updates
+        if let SyncStatus::Scanning { files_found, dirs_scanned } = &mut progress.status {
+            *files_found = progress.files_found_during_scan;
+            *dirs_scanned = self.dirs_scanned.load(Ordering::Relaxed);
+        }
+
+        let mut active_files: Vec<String> = self
+            .active_files
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .cloned()
+            .collect();
+        active_files.sort();
+        active_files.truncate(MAX_ACTIVE_FILES_REPORTED);
+        progress.active_files = active_files;
+
+        if let Some(start) = progress.started_at {
+            let elapsed = self.elapsed(start).as_secs_f64();
+            if elapsed > 0.0 {
+                let transferred = self.transferred_bytes.load(Ordering::Relaxed);
+                progress.transferred_bytes = transferred;
+                progress.bytes_per_second = self.speed(start, transferred);
+                // Speeds are never negative, so their bits order the same way as the values
+                if self.elapsed(start) >= MIN_SPEED_SPAN {
+                    self.peak_speed.fetch_max(progress.bytes_per_second.to_bits(), Ordering::Relaxed);
+                }
+
+                progress.eta_seconds = if progress.bytes_per_second > 0.0 && progress.total_bytes > transferred {
+                    let remaining = progress.total_bytes - transferred;
+                    Some((remaining as f64 / progress.bytes_per_second) as u64)
+                } else {
+                    None
+                };
             }
         }
-        
-        // Mark as completed
+    }
+
+    /// Bytes per second over the last `SPEED_WINDOW`, recording `transferred` as a sample
+    fn speed(&self, started_at: Instant, transferred: u64) -> f64 {
+        let now = self.now();
+        let mut samples = self.speed_samples.lock().unwrap_or_else(|e| e.into_inner());
+        samples.push_back((now, transferred));
+        // Samples from before this sync started count against a different total
+        while samples
+            .front()
+            .is_some_and(|&(at, _)| at < started_at || now.duration_since(at) > SPEED_WINDOW)
         {
-            let mut progress = self.progress.write().await;
-            progress.status = SyncStatus::Completed;
-            progress.current_file = None;
+            samples.pop_front();
+        }
+
+        match samples.front() {
+            Some(&(at, bytes)) if now.duration_since(at) >= MIN_SPEED_SPAN => {
+                transferred.saturating_sub(bytes) as f64 / now.duration_since(at).as_secs_f64()
+            }
+            _ => transferred as f64 / now.duration_since(started_at).as_secs_f64(),
         }
-        
-        Ok(())
     }
 
-    /// Get cloud folder structure for browsing
-    pub async fn list_cloud_folders(&self) -> Result<Vec<CloudFolder>, SyncError> {
-        let folders = self.s3_client
-            .list_folders("")
-            .await
-            .map_err(|e| SyncError::S3Error(e.to_string()))?;
-        
-        let mut result = Vec::new();
-        for folder in folders {
-            // Get total size of folder
-            let objects = self.s3_client
-                .list_objects(&folder)
-                .await
-                .map_err(|e| SyncError::S3Error(e.to_string()))?;
-            
-            let total_size: u64 = objects.iter().map(|o| o.size).sum();
-            let file_count = objects.len();
-            
-            result.push(CloudFolder {
-                name: folder.trim_end_matches('/').to_string(),
-                path: folder,
-                total_size,
-                file_count,
-            });
+    /// Full copy of `progress` with the per-folder totals filled in
+    fn snapshot(
+        &self,
+        progress: &SyncProgress,
+        folders: &HashMap<String, FolderProgress>,
+    ) -> SyncProgress {
+        let mut snapshot = progress.clone();
+        self.apply_counters(&mut snapshot);
+        snapshot.folder_progress = folders.values().cloned().collect();
+        snapshot.folder_progress.sort_by(|a, b| a.folder_name.cmp(&b.folder_name));
+        snapshot
+    }
+
+    /// Snapshot as `SyncEngine::get_progress` takes it, waiting on the locks
+    #[cfg(test)]
+    async fn read(&self) -> SyncProgress {
+        let progress = self.progress.read().await;
+        let folders = self.folder_progress.read().await;
+        self.snapshot(&progress, &folders)
+    }
+
+    /// Like `read`, but never waits: falls back to the last stored snapshot
+    /// when a sync task holds either lock
+    fn read_now(&self) -> SyncProgress {
+        match (self.progress.try_read(), self.folder_progress.try_read()) {
+            (Ok(progress), Ok(folders)) => self.snapshot(&progress, &folders),
+            _ => self.last_snapshot.lock().unwrap_or_else(|e| e.into_inner()).clone(),
         }
-        
-        Ok(result)
+    }
+
+    /// Update speed and ETA in place and store a copy as the last snapshot
+    async fn refresh(&self) {
+        let snapshot = {
+            let mut progress = self.progress.write().await;
+            self.apply_counters(&mut progress);
+            let folders = self.folder_progress.read().await;
+            self.snapshot(&progress, &folders)
+        };
+        *self.last_snapshot.lock().unwrap_or_else(|e| e.into_inner()) = snapshot;
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CloudFolder {
-    pub name: String,
-    pub path: String,
-    pub total_size: u64,
-    pub file_count: usize,
+/// A file counted in `active_transfers` and `active_files` until dropped
+struct ActiveTransfer {
+    path: String,
+    active_transfers: Arc<AtomicU64>,
+    active_files: Arc<std::sync::RwLock<HashSet<String>>>,
+}
+
+impl ActiveTransfer {
+    fn start(handles: &ProgressHandles, path: &str) -> Self {
+        handles.active_transfers.fetch_add(1, Ordering::Relaxed);
+        handles
+            .active_files
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(path.to_string());
+        Self {
+            path: path.to_string(),
+            active_transfers: Arc::clone(&handles.active_transfers),
+            active_files: Arc::clone(&handles.active_files),
+        }
+    }
 }
 
+impl Drop for ActiveTransfer {
+    fn drop(&mut self) {
+        self.active_files
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.path);
+        self.active_transfers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// One file's entry in `file_events`, removed once every clone of its progress
+/// callback has been dropped
+struct FileEventSlot {
+    path: String,
+    file_events: Arc<std::sync::Mutex<HashMap<String, ProgressEvent>>>,
+}
+
+impl FileEventSlot {
+    fn set(&self, event: ProgressEvent) {
+        self.file_events
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(self.path.clone(), event);
+    }
+}
+
+impl Drop for FileEventSlot {
+    fn drop(&mut self) {
+        self.file_events
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.path);
+    }
+}
+
+/// Background task keeping the progress snapshot fresh while a sync runs;
+/// stops when dropped
+struct SnapshotUpdater {
+    task: tokio::task::JoinHandle<()>,
+    handles: ProgressHandles,
+}
+
+impl SnapshotUpdater {
+    fn start(handles: ProgressHandles) -> Self {
+        let task = tokio::spawn({
+            let handles = handles.clone();
+            async move {
+                loop {
+                    handles.refresh().await;
+                    tokio::time::sleep(PROGRESS_SNAPSHOT_INTERVAL).await;
+                }
+            }
+        });
+        Self { task, handles }
+    }
+}
+
+impl Drop for SnapshotUpdater {
+    fn drop(&mut self) {
+        self.task.abort();
+        // Leave the final state behind for pollers that can't take the lock
+        if let (Ok(progress), Ok(folders)) =
+            (self.handles.progress.try_read(), self.handles.folder_progress.try_read())
+        {
+            let snapshot = self.handles.snapshot(&progress, &folders);
+            *self.handles.last_snapshot.lock().unwrap_or_else(|e| e.into_inner()) = snapshot;
+        }
+    }
+}
+
+/// Upload one file with `put_file_once`, trying again once with
+/// `retry_changed_files` if it changed while being read
+async fn put_file(
+    client: &S3Client,
+    config: &SyncConfig,
+    source: &Path,
+    remote: &str,
+    tags: &HashMap<String, String>,
+    now: DateTime<Utc>,
+    on_progress: impl Fn(ProgressEvent) + Clone + Send + 'static,
+) -> Result<(), S3Error> {
+    let client = &client.with_acl(default_acl(config)?);
+    match put_file_once(client, config, source, remote, tags, now, on_progress.clone()).await {
+        Err(S3Error::FileChangedDuringUpload { path }) if config.retry_changed_files => {
+            // The new version may have settled by now
+            log::warn!("{} changed during upload, uploading it again", path);
+            put_file_once(client, config, source, remote, tags, now, on_progress).await
+        }
+        result => result,
+    }
+}
+
+/// The canned ACL uploads are stored with, from `default_acl`
+fn default_acl(config: &SyncConfig) -> Result<Option<ObjectCannedAcl>, S3Error> {
+    config.default_acl.as_deref().map(str::parse).transpose()
+}
+
+/// Upload one file, locked with `object_lock`, through a temporary key with
+/// `atomic_uploads`, or only where nothing else is stored without `overwrite_existing`.
+/// Locks are held for `retain_days` from `now`.
+async fn put_file_once(
+    client: &S3Client,
+    config: &SyncConfig,
+    source: &Path,
+    remote: &str,
+    tags: &HashMap<String, String>,
+    now: DateTime<Utc>,
+    on_progress: impl Fn(ProgressEvent) + Send + 'static,
+) -> Result<(), S3Error> {
+    if let Some(lock) = config.object_lock {
+        let retain_until = now
+            .checked_add_signed(chrono::Duration::days(lock.retain_days as i64))
+            .ok_or_else(|| {
+                S3Error::InvalidConfiguration(format!("Object lock retention of {} days is too long", lock.retain_days))
+            })?;
+        client
+            .upload_file_locked_with_progress(source, remote, tags, lock.mode, retain_until, on_progress)
+            .await
+    } else if !config.overwrite_existing {
+        // The same contents already being there is as good as uploading them
+        let uploaded = if config.atomic_uploads {
+            client
+                .upload_file_atomic_if_absent_with_progress(source, remote, tags, on_progress)
+                .await
+        } else {
+            client
+                .upload_file_if_absent_with_progress(source, remote, tags, on_progress)
+                .await
+        };
+        uploaded.map(|_| ())
+    } else if config.atomic_uploads {
+        client.upload_file_atomic_with_progress(source, remote, tags, on_progress).await
+    } else {
+        client.upload_file_with_progress(source, remote, tags, on_progress).await
+    }
+}
+
+// First byte hashed for Merkle leaves and for the nodes joining them, so a leaf can't
+// pass for a node
+const MERKLE_LEAF: u8 = 0x00;
+const MERKLE_NODE: u8 = 0x01;
+
+/// Root of a Merkle tree over `leaves`, each level joining pairs with BLAKE3 of
+/// `MERKLE_NODE` and the pair, and passing an odd one out up unchanged; BLAKE3 of
+/// nothing when there are no leaves
+fn merkle_root(mut level: Vec<[u8; 32]>) -> [u8; 32] {
+    if level.is_empty() {
+        return *blake3::hash(&[]).as_bytes();
+    }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => {
+                    let mut hasher = blake3::Hasher::new();
+                    hasher.update(&[MERKLE_NODE]);
+                    hasher.update(left);
+                    hasher.update(right);
+                    *hasher.finalize().as_bytes()
+                }
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
+
+/// Manifests to delete from `keys`, sorted oldest first, to keep the newest `MANIFESTS_KEPT`
+fn old_manifests(mut keys: Vec<String>) -> Vec<String> {
+    let excess = keys.len().saturating_sub(MANIFESTS_KEPT);
+    keys.truncate(excess);
+    keys
+}
+
+/// Hex SHA-256 of a file, streamed so large files never sit in memory
+async fn hash_file(path: PathBuf) -> Result<String, SyncError> {
+    tokio::task::spawn_blocking(move || {
+        let hash = || {
+            let mut file = std::fs::File::open(&path)?;
+            let mut hasher = Sha256::new();
+            std::io::copy(&mut file, &mut hasher)?;
+            Ok(hex::encode(hasher.finalize()))
+        };
+        hash().map_err(|e| io_error(&path, e))
+    })
+    .await
+    .map_err(|e| SyncError::IoError(e.to_string()))?
+}
+
+pub struct SyncEngine {
+    /// The primary client first, then any backup destinations
+    s3_clients: Vec<Arc<S3Client>>,
+    config: SyncConfig,
+    progress: Arc<RwLock<SyncProgress>>,
+    state: Arc<SyncStateCell>,
+    transferred_bytes: Arc<AtomicU64>,
+    current_file_bytes: Arc<AtomicU64>,
+    current_file_total: Arc<AtomicU64>,
+    current_file_received: Arc<AtomicU64>,
+    /// Latest progress of each file being transferred, by path, for its speed and ETA
+    file_events: Arc<std::sync::Mutex<HashMap<String, ProgressEvent>>>,
+    active_transfers: Arc<AtomicU64>,
+    active_files: Arc<std::sync::RwLock<HashSet<String>>>,
+    /// Files and folders found so far by the current sync's scan
+    scan_progress: Arc<AtomicU64>,
+    dirs_scanned: Arc<AtomicU64>,
+    storage_stats: RwLock<Option<(StorageStats, Instant)>>,
+    folder_progress: Arc<RwLock<HashMap<String, FolderProgress>>>,
+    last_snapshot: Arc<std::sync::Mutex<SyncProgress>>,
+    speed_samples: Arc<std::sync::Mutex<VecDeque<(Instant, u64)>>>,
+    peak_speed: Arc<AtomicU64>,
+    /// Also where a running upload is saved so it can be resumed after a restart
+    sync_cache: Arc<std::sync::Mutex<SyncCache>>,
+    resume_record: std::sync::Mutex<Option<ResumeRecord>>,
+    /// Where progress updates go while a `ProgressAggregator` runs
+    progress_updates: ProgressSender,
+    /// Most recent per-file timings, across syncs
+    transfer_records: Arc<std::sync::Mutex<VecDeque<FileTransferRecord>>>,
+    /// Number of those recorded during the current sync
+    session_transfers: AtomicU64,
+    /// Summary of the last upload or download to finish
+    last_summary: Arc<std::sync::Mutex<Option<SyncSummary>>>,
+    /// Local file being downloaded, removed if the sync is cancelled before it finishes
+    current_local_path: Arc<RwLock<Option<PathBuf>>>,
+    rate_limit_handler: Option<RateLimitHandler>,
+    scan_complete_handler: Option<ScanCompleteHandler>,
+    limit_reached_handler: Option<LimitReachedHandler>,
+    /// Bytes of the files the current upload has sent or is sending, counted against
+    /// `max_session_bytes` before each file starts
+    session_bytes_transferred: Arc<AtomicU64>,
+    /// Set once a file didn't fit under `max_session_bytes`; no later file starts
+    session_limit_reached: AtomicBool,
+    /// Bytes the user's key still lets the current upload store, u64::MAX without a quota
+    quota_bytes_left: AtomicU64,
+    /// Set by `confirm` while a two-phase upload waits for it
+    confirmed: AtomicBool,
+    /// Free bytes on the disk holding a path; swapped out in tests
+    available_space: fn(&Path) -> std::io::Result<u64>,
+    /// Client without a user prefix that reads the sync policy; no policy without one
+    policy_client: Option<Arc<S3Client>>,
+    policy_cache: SyncPolicyCache,
+    windows: SyncWindows,
+    /// Config for the files in each local folder seen since the last scan; None
+    /// where no `.sync.toml` applies
+    folder_configs: std::sync::Mutex<HashMap<PathBuf, Option<Arc<FolderConfig>>>>,
+    /// Threads walking source folders, built by the first scan and grown when a scan
+    /// with `scan_parallelism` 0 has more folders than it has threads
+    scan_pool: std::sync::Mutex<Option<Arc<rayon::ThreadPool>>>,
+    /// Files uploaded in the last `RECENT_UPLOAD_TTL`, by remote path, merged into
+    /// listings in case the bucket doesn't list them yet
+    recently_uploaded: Arc<std::sync::RwLock<HashMap<String, (S3Object, Instant)>>>,
+    /// Whose uploads these are, for `{uid}` in `remote_path_template`
+    user: Option<KeyPayload>,
+    /// Clock for speeds, ETAs, cache ages and timestamps; swapped out in tests
+    time_source: Arc<dyn TimeSource>,
+}
+
+impl SyncEngine {
+    pub fn new(s3_client: S3Client) -> Self {
+        Self::new_with_config(s3_client, SyncConfig::default())
+    }
+
+    pub fn new_with_config(s3_client: S3Client, config: SyncConfig) -> Self {
+        Self::with_shared_clients(vec![Arc::new(s3_client)], config)
+    }
+
+    /// Engine that reads the time from `time_source` instead of the system clock
+    #[cfg(test)]
+    pub fn new_with_time_source(s3_client: S3Client, config: SyncConfig, time_source: Arc<dyn TimeSource>) -> Self {
+        let mut engine = Self::new_with_config(s3_client, config);
+        engine.time_source = time_source;
+        engine
+    }
+
+    /// Build a new engine with different options that talks to the same S3 client
+    pub fn reconfigured(&self, config: SyncConfig) -> Self {
+        let mut engine = Self::with_shared_clients(self.s3_clients.clone(), config);
+        engine.rate_limit_handler = self.rate_limit_handler.clone();
+        engine.scan_complete_handler = self.scan_complete_handler.clone();
+        engine.limit_reached_handler = self.limit_reached_handler.clone();
+        engine.sync_cache = Arc::clone(&self.sync_cache);
+        engine.transfer_records = Arc::clone(&self.transfer_records);
+        engine.last_summary = Arc::clone(&self.last_summary);
+        engine.available_space = self.available_space;
+        engine.policy_client = self.policy_client.clone();
+        engine.policy_cache = Arc::clone(&self.policy_cache);
+        engine.windows = self.windows.clone();
+        engine.recently_uploaded = Arc::clone(&self.recently_uploaded);
+        engine.user = self.user.clone();
+        engine.time_source = Arc::clone(&self.time_source);
+        engine.apply_sse_c();
+        engine
+    }
+
+    /// Fill in `remote_path_template` for this user and derive their SSE-C key
+    pub fn with_user(mut self, payload: KeyPayload) -> Self {
+        self.user = Some(payload);
+        self.apply_sse_c();
+        self
+    }
+
+    /// Turn SSE-C on every client on or off to match `use_sse_c`, so that backups
+    /// are stored the same way as the primary copy
+    fn apply_sse_c(&mut self) {
+        let key = match (&self.user, self.config.use_sse_c) {
+            (Some(user), true) => Some(derive_sse_c_key(&user.uid)),
+            _ => None,
+        };
+        for client in &mut self.s3_clients {
+            *client = Arc::new(client.with_sse_customer_key(key.as_ref()));
+        }
+    }
+
+    /// `remote_path_template` rendered for an upload of `source_count` folders; every
+    /// file of the upload uses this one rendering, so they all get the same date.
+    /// Without `{folder_name}` several folders would land in the same place, and
+    /// `{uid}` needs a logged-in user.
+    fn render_template(&self, source_count: usize) -> Result<Option<String>, SyncError> {
+        let Some(template) = self.config.remote_path_template.as_deref() else {
+            return Ok(None);
+        };
+        if source_count > 1 && !template.contains("{folder_name}") {
+            return Err(SyncError::InvalidTemplate(format!(
+                "{} has no {{folder_name}}, so {} folders would be uploaded to the same place",
+                template, source_count
+            )));
+        }
+        let uid = match &self.user {
+            Some(user) => user.uid.as_str(),
+            None if template.contains("{uid}") => {
+                return Err(SyncError::InvalidTemplate(format!("{} uses {{uid}}, but no user is logged in", template)));
+            }
+            None => "",
+        };
+        let now: DateTime<Local> = self.time_source.now_system().into();
+        Ok(Some(render_path_template(template, uid, now)))
+    }
+
+    /// Get notified when a transfer is rate limited and about to be retried
+    pub fn with_rate_limit_handler(mut self, handler: impl Fn(u64) + Send + Sync + 'static) -> Self {
+        self.rate_limit_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Get the scanned files when a two-phase upload starts waiting for confirmation
+    pub fn with_scan_complete_handler(mut self, handler: impl Fn(&ScanComplete) + Send + Sync + 'static) -> Self {
+        self.scan_complete_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Get notified when an upload stops at `max_session_bytes`
+    pub fn with_limit_reached_handler(mut self, handler: impl Fn(u64, u64) + Send + Sync + 'static) -> Self {
+        self.limit_reached_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Save running uploads to `dir` so they can be resumed after the app restarts
+    pub fn with_sessions_dir(self, dir: Option<PathBuf>) -> Self {
+        let mut cache = self.lock_cache();
+        *cache = std::mem::take(&mut *cache).with_sessions_dir(dir);
+        drop(cache);
+        self
+    }
+
+    /// Apply the sync policy `client` reads from the bucket root to uploads,
+    /// caching it in `cache`
+    pub fn with_sync_policy(mut self, client: S3Client, cache: SyncPolicyCache) -> Self {
+        self.policy_client = Some(Arc::new(client));
+        self.policy_cache = cache;
+        self
+    }
+
+    /// Send sync events to the windows in `windows` as well as the one that asked
+    pub fn with_windows(mut self, windows: SyncWindows) -> Self {
+        self.windows = windows;
+        self
+    }
+
+    /// Also write uploads and deletions to `client`, as a backup of the primary bucket.
+    /// Reads only ever use the primary.
+    pub fn with_secondary(mut self, client: S3Client) -> Self {
+        self.s3_clients.push(Arc::new(client));
+        self.apply_sse_c();
+        self
+    }
+
+    fn with_shared_clients(s3_clients: Vec<Arc<S3Client>>, config: SyncConfig) -> Self {
+        Self {
+            s3_clients,
+            config,
+            progress: Arc::new(RwLock::new(SyncProgress::default())),
+            state: Arc::new(SyncStateCell::default()),
+            transferred_bytes: Arc::new(AtomicU64::new(0)),
+            current_file_bytes: Arc::new(AtomicU64::new(0)),
+            current_file_total: Arc::new(AtomicU64::new(0)),
+            current_file_received: Arc::new(AtomicU64::new(0)),
+            file_events: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            active_transfers: Arc::new(AtomicU64::new(0)),
+            active_files: Arc::new(std::sync::RwLock::new(HashSet::new())),
+            scan_progress: Arc::new(AtomicU64::new(0)),
+            dirs_scanned: Arc::new(AtomicU64::new(0)),
+            storage_stats: RwLock::new(None),
+            folder_progress: Arc::new(RwLock::new(HashMap::new())),
+            last_snapshot: Arc::new(std::sync::Mutex::new(SyncProgress::default())),
+            speed_samples: Arc::new(std::sync::Mutex::new(VecDeque::new())),
+            peak_speed: Arc::new(AtomicU64::new(0)),
+            sync_cache: Arc::new(std::sync::Mutex::new(SyncCache::new())),
+            resume_record: std::sync::Mutex::new(None),
+            progress_updates: Arc::new(std::sync::Mutex::new(None)),
+            transfer_records: Arc::new(std::sync::Mutex::new(VecDeque::new())),
+            session_transfers: AtomicU64::new(0),
+            last_summary: Arc::new(std::sync::Mutex::new(None)),
+            current_local_path: Arc::new(RwLock::new(None)),
+            rate_limit_handler: None,
+            scan_complete_handler: None,
+            limit_reached_handler: None,
+            session_bytes_transferred: Arc::new(AtomicU64::new(0)),
+            session_limit_reached: AtomicBool::new(false),
+            quota_bytes_left: AtomicU64::new(u64::MAX),
+            confirmed: AtomicBool::new(false),
+            available_space: |path| fs2::available_space(path),
+            policy_client: None,
+            policy_cache: Arc::new(RwLock::new(None)),
+            windows: SyncWindows::default(),
+            folder_configs: std::sync::Mutex::new(HashMap::new()),
+            scan_pool: std::sync::Mutex::new(None),
+            recently_uploaded: Arc::new(std::sync::RwLock::new(HashMap::new())),
+            user: None,
+            time_source: Arc::new(RealTimeSource),
+        }
+    }
+
+    /// Client for the primary bucket, which every read goes to
+    fn primary(&self) -> &S3Client {
+        &self.s3_clients[0]
+    }
+
+    fn now(&self) -> Instant {
+        self.time_source.now_instant()
+    }
+
+    fn elapsed(&self, since: Instant) -> Duration {
+        self.now().saturating_duration_since(since)
+    }
+
+    fn now_utc(&self) -> DateTime<Utc> {
+        self.time_source.now_system().into()
+    }
+
+    /// Empty session totals starting at this engine's current time
+    pub fn new_session_stats(&self) -> SessionStats {
+        SessionStats::starting_now(self.time_source.as_ref())
+    }
+
+    /// Options this engine was created with
+    pub fn config(&self) -> &SyncConfig {
+        &self.config
+    }
+
+    /// Whether a sync is scanning, transferring or paused
+    pub async fn is_running(&self) -> bool {
+        matches!(
+            self.progress.read().await.status,
+            SyncStatus::Scanning { .. }
+                | SyncStatus::Hashing { .. }
+                | SyncStatus::AwaitingConfirmation { .. }
+                | SyncStatus::Syncing
+                | SyncStatus::Paused
+                | SyncStatus::Cancelling
+        )
+    }
+
+    fn progress_handles(&self) -> ProgressHandles {
+        ProgressHandles {
+            progress: Arc::clone(&self.progress),
+            folder_progress: Arc::clone(&self.folder_progress),
+            transferred_bytes: Arc::clone(&self.transferred_bytes),
+            current_file_bytes: Arc::clone(&self.current_file_bytes),
+            current_file_total: Arc::clone(&self.current_file_total),
+            current_file_received: Arc::clone(&self.current_file_received),
+            file_events: Arc::clone(&self.file_events),
+            active_transfers: Arc::clone(&self.active_transfers),
+            active_files: Arc::clone(&self.active_files),
+            scan_progress: Arc::clone(&self.scan_progress),
+            dirs_scanned: Arc::clone(&self.dirs_scanned),
+            last_snapshot: Arc::clone(&self.last_snapshot),
+            speed_samples: Arc::clone(&self.speed_samples),
+            peak_speed: Arc::clone(&self.peak_speed),
+            time_source: Arc::clone(&self.time_source),
+        }
+    }
+
+    /// Progress callback for transferring the `total` bytes of `path`, advancing
+    /// `transferred_bytes` as each chunk goes through. Other files may be transferring
+    /// at the same time, so each callback tracks its own previous byte count and
+    /// keeps the speed and ETA of its file under `path` until it is dropped.
+    fn file_progress(&self, path: &str, total: u64) -> impl Fn(ProgressEvent) + Clone + Send + Sync + 'static {
+        self.current_file_bytes.store(0, Ordering::Relaxed);
+        self.current_file_total.store(total, Ordering::Relaxed);
+        let previous = Arc::new(AtomicU64::new(0));
+        let transferred_bytes = Arc::clone(&self.transferred_bytes);
+        let current_file_bytes = Arc::clone(&self.current_file_bytes);
+        let current_file_total = Arc::clone(&self.current_file_total);
+        let slot = Arc::new(FileEventSlot {
+            path: path.to_string(),
+            file_events: Arc::clone(&self.file_events),
+        });
+        move |event: ProgressEvent| {
+            let before = previous.swap(event.bytes_sent, Ordering::Relaxed);
+            current_file_bytes.store(event.bytes_sent, Ordering::Relaxed);
+            current_file_total.store(event.total_bytes, Ordering::Relaxed);
+            slot.set(event);
+            // A retried upload starts again from zero; take back what the failed attempt sent
+            if event.bytes_sent >= before {
+                transferred_bytes.fetch_add(event.bytes_sent - before, Ordering::Relaxed);
+            } else {
+                transferred_bytes.fetch_sub(before - event.bytes_sent, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Like `file_progress` for a download's `(received, total)` reports, also
+    /// tracking the bytes received
+    fn download_progress(&self, path: &str, total: u64) -> impl Fn(u64, u64) + Clone + Send + Sync + 'static {
+        self.current_file_received.store(0, Ordering::Relaxed);
+        let file_progress = self.file_progress(path, total);
+        let current_file_received = Arc::clone(&self.current_file_received);
+        let time_source = Arc::clone(&self.time_source);
+        let started = time_source.now_instant();
+        move |received: u64, total: u64| {
+            current_file_received.store(received, Ordering::Relaxed);
+            let elapsed = time_source.now_instant().saturating_duration_since(started);
+            file_progress(ProgressEvent::new(received, total, elapsed));
+        }
+    }
+
+    /// Get current sync progress
+    pub async fn get_progress(&self) -> SyncProgress {
+        let progress = self.progress.read().await;
+        let folders = self.folder_progress.read().await;
+        self.progress_handles().snapshot(&progress, &folders)
+    }
+
+    /// Get current sync progress without waiting on the sync task's locks.
+    /// Under contention this returns the snapshot taken at most 100ms ago.
+    pub fn get_progress_sync(&self) -> SyncProgress {
+        self.progress_handles().read_now()
+    }
+
+    /// Pause the sync
+    pub fn pause(&self) {
+        self.state.set_paused();
+        self.persist_state(true);
+    }
+
+    /// Resume the sync
+    pub fn resume(&self) {
+        self.state.set_running();
+        self.persist_state(true);
+    }
+
+    /// Cancel the sync
+    pub fn cancel(&self) {
+        self.state.set_cancelled();
+    }
+
+    /// Start uploading the files a two-phase upload is waiting on
+    pub async fn confirm(&self) -> Result<(), SyncError> {
+        if !matches!(self.progress.read().await.status, SyncStatus::AwaitingConfirmation { .. }) {
+            return Err(SyncError::NoActiveSync);
+        }
+        self.confirmed.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Pause and tell the frontend right away instead of on its next poll
+    pub fn pause_with_notify<R: Runtime>(&self, window: &Window<R>) {
+        self.pause();
+        self.windows.emit_state_change(window, "sync://paused");
+    }
+
+    /// Resume and tell the frontend right away instead of on its next poll
+    pub fn resume_with_notify<R: Runtime>(&self, window: &Window<R>) {
+        self.resume();
+        self.windows.emit_state_change(window, "sync://resumed");
+    }
+
+    /// Cancel and tell the frontend; a running sync reports `Cancelling`
+    /// until its task has stopped
+    pub async fn cancel_with_notify<R: Runtime>(&self, window: &Window<R>) {
+        self.cancel();
+        {
+            let mut progress = self.progress.write().await;
+            if matches!(
+                progress.status,
+                SyncStatus::Scanning { .. }
+                    | SyncStatus::Hashing { .. }
+                    | SyncStatus::AwaitingConfirmation { .. }
+                    | SyncStatus::Syncing
+                    | SyncStatus::Paused
+            ) {
+                progress.status = SyncStatus::Cancelling;
+            }
+        }
+        self.windows.emit_state_change(window, "sync://cancelled");
+    }
+
+    /// Check if sync is paused
+    pub fn is_paused(&self) -> bool {
+        self.state.get() == SyncState::Paused
+    }
+
+    /// Check if the sync has been cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.state.is_cancelled()
+    }
+
+    /// Wait while paused, return error if cancelled
+    async fn wait_if_paused(&self) -> Result<(), SyncError> {
+        match wait_while_paused(&self.state).await {
+            Ok(()) => Ok(()),
+            Err(_) => Err(self.stop_cancelled().await),
+        }
+    }
+
+    /// Run a transfer, giving up on it as soon as the sync is cancelled
+    async fn unless_cancelled<T>(&self, transfer: impl Future<Output = T>) -> Result<T, SyncError> {
+        tokio::select! {
+            result = transfer => return Ok(result),
+            () = self.state.cancelled() => {}
+        }
+        Err(self.stop_cancelled().await)
+    }
+
+    /// The sync task stops here, which completes the cancel. A download cut
+    /// short leaves a partial file, which is removed.
+    async fn stop_cancelled(&self) -> SyncError {
+        if let Some(path) = self.current_local_path.write().await.take() {
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => log::info!("Removed partial download {}", path.display()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => log::warn!("Failed to remove partial download {}: {}", path.display(), e),
+            }
+        }
+
+        let mut progress = self.progress.write().await;
+        progress.status = SyncStatus::Idle;
+        progress.current_file = None;
+        progress.active_folder = None;
+        SyncError::Cancelled
+    }
+
+    /// Scan local folders to get list of files
+    ///
+    /// Up to `scan_parallelism` source folders are walked at once, each on its own
+    /// thread, with the count found so far reported in the `Scanning` status.
+    /// Files left out by `SyncConfig::excludes_size` are counted as skipped, as are
+    /// followed links that are broken, loop or lead outside their source folder.
+    pub async fn scan_local_folders(&self, paths: &[PathBuf]) -> Result<Vec<FileEntry>, SyncError> {
+        let template = self.render_template(paths.len())?;
+        self.scan(paths, true, template.as_deref()).await
+    }
+
+    /// Scan for `scan_local_folders`. Only a scan `for_sync` reports its progress
+    /// and stops when the sync is cancelled, so previews leave a sync's status alone.
+    /// Files go under each folder's `remote_root` for the rendered `template`.
+    async fn scan(&self, paths: &[PathBuf], for_sync: bool, template: Option<&str>) -> Result<Vec<FileEntry>, SyncError> {
+        // Pick up `.sync.toml` files changed since the last scan
+        self.lock_folder_configs().clear();
+        let (tx, mut rx) = mpsc::channel(SCAN_CHANNEL_CAPACITY);
+        let config = self.config.clone();
+        let roots: Vec<PathBuf> = paths.iter().map(|path| normalize_local_path(path)).collect();
+        let walked_roots = roots.clone();
+        let remote_roots: Vec<String> = roots.iter().map(|root| remote_root(root, template)).collect();
+        let walked_remote_roots = remote_roots.clone();
+        // Previews count on their own, leaving a sync's progress alone
+        let (files_found, dirs_scanned) = if for_sync {
+            self.scan_progress.store(0, Ordering::Relaxed);
+            self.dirs_scanned.store(0, Ordering::Relaxed);
+            (Arc::clone(&self.scan_progress), Arc::clone(&self.dirs_scanned))
+        } else {
+            (Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0)))
+        };
+        let walked_dirs = Arc::clone(&dirs_scanned);
+        let pool = self.scan_pool(roots.len())?;
+        let walk = tokio::task::spawn_blocking(move || {
+            walk_folders(&config, &pool, &walked_roots, &walked_remote_roots, tx, &walked_dirs)
+        });
+        let scanning = || SyncStatus::Scanning {
+            files_found: files_found.load(Ordering::Relaxed),
+            dirs_scanned: dirs_scanned.load(Ordering::Relaxed),
+        };
+        
+        let mut found = Vec::new();
+        while let Some(file) = rx.recv().await {
+            if for_sync && self.state.is_cancelled() {
+                // The walk stops at its next file once nothing receives them
+                drop(rx);
+                let _ = walk.await;
+                return Err(self.stop_cancelled().await);
+            }
+            if file.skipped.is_none() {
+                files_found.fetch_add(1, Ordering::Relaxed);
+            }
+            found.push(file);
+            if for_sync && found.len() % SCAN_PROGRESS_INTERVAL == 0 {
+                self.report(ProgressUpdate::StatusChange(scanning())).await;
+            }
+        }
+        walk.await.map_err(|e| SyncError::IoError(e.to_string()))??;
+        if for_sync {
+            if self.state.is_cancelled() {
+                return Err(self.stop_cancelled().await);
+            }
+            self.report(ProgressUpdate::StatusChange(scanning())).await;
+        }
+        
+        // Folders finish in any order; list them as they were given
+        found.sort_by_key(|file: &ScannedFile| file.root);
+        
+        let mut skipped_links = Vec::new();
+        let mut excluded = Vec::new();
+        found.retain(|file| {
+            if let Some(reason) = file.skipped {
+                skipped_links.push((file.entry.path.clone(), reason.to_string()));
+                return false;
+            }
+            let root = &roots[file.root];
+            let source_relative = strip_source_folder(&file.entry.path, &remote_roots[file.root], true).unwrap_or_default();
+            let local_path = root.join(source_relative);
+            let folder_config = self.file_config(std::slice::from_ref(root), &local_path);
+            if folder_config.as_ref().is_some_and(|folder| folder.excludes(&local_path)) {
+                return false;
+            }
+            let config = folder_config.as_ref().map_or(&self.config, |folder| &folder.config);
+            if !config.excludes_size(file.entry.size) {
+                return true;
+            }
+            log::debug!("Skipping {} ({} bytes) by size", file.entry.path, file.entry.size);
+            excluded.push(file.entry.path.clone());
+            false
+        });
+        if for_sync && !(excluded.is_empty() && skipped_links.is_empty()) {
+            let mut progress = self.progress.write().await;
+            progress.skipped_files += (excluded.len() + skipped_links.len()) as u64;
+            progress.skipped_reasons.extend(skipped_links);
+            if self.config.notify_excluded {
+                progress.excluded_files.extend(excluded);
+            }
+        }
+        let (mut entries, local_files): (Vec<_>, Vec<_>) = found
+            .into_iter()
+            .map(|file| (file.entry, file.local))
+            .unzip();
+        
+        if self.config.compute_hashes {
+            let local_files: Vec<_> = local_files.into_iter().flatten().collect();
+            self.hash_entries(&mut entries, &local_files, for_sync).await?;
+        }
+        
+        Ok(entries)
+    }
+
+    /// Fill in `content_hash` for scanned files, reusing cached hashes of unchanged files
+    async fn hash_entries(
+        &self,
+        entries: &mut [FileEntry],
+        local_files: &[(PathBuf, SystemTime)],
+        for_sync: bool,
+    ) -> Result<(), SyncError> {
+        let total_files = entries.len() as u64;
+        for (files_hashed, (entry, (path, modified))) in entries.iter_mut().zip(local_files).enumerate() {
+            if for_sync {
+                if self.state.is_cancelled() {
+                    return Err(self.stop_cancelled().await);
+                }
+                self.report(ProgressUpdate::StatusChange(SyncStatus::Hashing {
+                    files_hashed: files_hashed as u64,
+                    total_files,
+                }))
+                .await;
+            }
+
+            let cached = self
+                .sync_cache
+                .lock()
+                .unwrap()
+                .content_hash(path, *modified, entry.size)
+                .map(str::to_string);
+            let hash = match cached {
+                Some(hash) => hash,
+                None => {
+                    let hash = hash_file(path.clone()).await?;
+                    self.sync_cache.lock().unwrap().set_content_hash(
+                        path.clone(),
+                        *modified,
+                        entry.size,
+                        hash.clone(),
+                    );
+                    hash
+                }
+            };
+            entry.content_hash = Some(hash);
+        }
+        
+        if for_sync {
+            self.report(ProgressUpdate::StatusChange(SyncStatus::Hashing {
+                files_hashed: total_files,
+                total_files,
+            }))
+            .await;
+        }
+        Ok(())
+    }
+
+    /// Hand a progress update to the running aggregator, or apply it directly
+    async fn report(&self, update: ProgressUpdate) {
+        let sender = self.progress_updates.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let update = match sender {
+            Some(tx) => match tx.send(update) {
+                Ok(()) => return,
+                Err(unsent) => unsent.0,
+            },
+            None => update,
+        };
+        let mut progress = self.progress.write().await;
+        let mut folders = self.folder_progress.write().await;
+        apply_progress_update(&mut progress, &mut folders, update);
+    }
+
+    /// Global config with the `.sync.toml` in the folder `path` applied, if it has one.
+    /// A file that can't be read or parsed, or has an invalid exclude pattern, is
+    /// ignored with a warning.
+    fn load_folder_config(&self, path: &Path) -> Option<FolderConfig> {
+        let file = path.join(FOLDER_CONFIG_FILE);
+        let text = match std::fs::read_to_string(&file) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                log::warn!("Failed to read {}: {}", file.display(), e);
+                return None;
+            }
+        };
+        let folder = toml::from_str::<FolderSyncConfig>(&text).map_err(|e| e.to_string()).and_then(|folder| {
+            let filter = SyncFilter::new(folder.exclude_patterns.as_deref().unwrap_or_default())?;
+            Ok(FolderConfig { dir: path.to_path_buf(), config: folder.apply_to(&self.config), filter })
+        });
+        match folder {
+            Ok(folder) => Some(folder),
+            Err(e) => {
+                log::warn!("Ignoring {}: {}", file.display(), e);
+                None
+            }
+        }
+    }
+
+    /// The pool to walk `roots` source folders on: `scan_parallelism` threads, or one
+    /// per folder when it's 0
+    fn scan_pool(&self, roots: usize) -> Result<Arc<rayon::ThreadPool>, SyncError> {
+        let workers = match self.config.scan_parallelism {
+            0 => roots.max(1),
+            n => n,
+        };
+        let mut pool = self.scan_pool.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(pool) = pool.as_ref().filter(|pool| pool.current_num_threads() >= workers) {
+            return Ok(Arc::clone(pool));
+        }
+        let built = rayon::ThreadPoolBuilder::new()
+            .num_threads(workers)
+            .build()
+            .map_err(|e| SyncError::IoError(e.to_string()))?;
+        Ok(Arc::clone(pool.insert(Arc::new(built))))
+    }
+
+    fn lock_folder_configs(&self) -> std::sync::MutexGuard<'_, HashMap<PathBuf, Option<Arc<FolderConfig>>>> {
+        self.folder_configs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Config for the local file `path` under one of the source folders `roots`: that of
+    /// the nearest folder above it, up to its source folder, with a `.sync.toml`.
+    /// None when there is none and the global config applies.
+    fn file_config(&self, roots: &[PathBuf], path: &Path) -> Option<Arc<FolderConfig>> {
+        let root = roots.iter().find(|root| path.starts_with(root))?;
+        self.folder_config(root, path.parent()?)
+    }
+
+    fn folder_config(&self, root: &Path, dir: &Path) -> Option<Arc<FolderConfig>> {
+        if let Some(cached) = self.lock_folder_configs().get(dir) {
+            return cached.clone();
+        }
+        let config = match self.load_folder_config(dir) {
+            Some(config) => Some(Arc::new(config)),
+            None if dir == root => None,
+            None => dir
+                .parent()
+                .filter(|parent| parent.starts_with(root))
+                .and_then(|parent| self.folder_config(root, parent)),
+        };
+        self.lock_folder_configs().insert(dir.to_path_buf(), config.clone());
+        config
+    }
+
+    /// Tags for a file uploaded now with `config`
+    fn upload_tags(&self, config: &SyncConfig, policy: &SyncPolicy) -> HashMap<String, String> {
+        let mut tags = config.default_tags.clone();
+        tags.extend(policy.required_tags.clone());
+        tags.insert(
+            "synced_at".to_string(),
+            self.now_utc().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        );
+        tags
+    }
+
+    /// Run a single file transfer, waiting out rate limiting
+    async fn transfer<T, F, Fut>(&self, transfer: F) -> Result<T, SyncError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, S3Error>>,
+    {
+        let on_rate_limited = |secs: u64| {
+            if let Some(handler) = &self.rate_limit_handler {
+                handler(secs);
+            }
+        };
+        retry_rate_limited(self.config.max_file_retries, on_rate_limited, transfer)
+            .await
+            .map_err(SyncError::from)
+    }
+
+    /// Record a failed file, or return the error if the policy is to abort
+    async fn handle_file_error(&self, path: &str, error: SyncError) -> Result<(), SyncError> {
+        if self.config.on_error == ErrorPolicy::Abort {
+            return Err(error);
+        }
+
+        log::warn!("Skipping {}: {}", path, error);
+        self.report(ProgressUpdate::FileFailed {
+            path: path.to_string(),
+            error: error.to_string(),
+        })
+        .await;
+        Ok(())
+    }
+
+    /// Set the final status once every file has been processed
+    async fn finish(&self, direction: &SyncDirection) {
+        let mut progress = self.progress.write().await;
+        let (status, summary) = completion_status(&progress.failed_files, direction);
+        progress.status = status;
+        progress.error_summary = summary;
+        progress.current_file = None;
+        progress.active_folder = None;
+    }
+
+    /// Build a summary of the sync that just finished, keeping it for `get_last_summary`
+    pub(crate) async fn build_summary(&self, direction: SyncDirection, session_id: String) -> SyncSummary {
+        let progress = self.progress.read().await;
+        let duration_secs = progress
+            .started_at
+            .map(|start| self.elapsed(start).as_secs_f64())
+            .unwrap_or(0.0);
+        let end_time = self.now_utc();
+        let start_time = end_time - chrono::Duration::microseconds((duration_secs * 1e6) as i64);
+        let transferred_bytes = self.transferred_bytes.load(Ordering::Relaxed);
+        let average_bps = if duration_secs > 0.0 {
+            transferred_bytes as f64 / duration_secs
+        } else {
+            0.0
+        };
+        let peak_bps = f64::from_bits(self.peak_speed.load(Ordering::Relaxed)).max(average_bps);
+
+        let session = self.session_transfers.load(Ordering::Relaxed) as usize;
+        let (percentile_95_ms, mean_ms, slowest_file) = {
+            let records = self.transfer_records.lock().unwrap();
+            let session: Vec<_> = records.iter().skip(records.len().saturating_sub(session)).cloned().collect();
+            transfer_timings(&session)
+        };
+
+        let summary = SyncSummary {
+            session_id,
+            direction,
+            start_time,
+            end_time,
+            duration_secs,
+            total_files: progress.completed_files,
+            transferred_bytes,
+            skipped_files: progress.skipped_files,
+            skipped_reasons: progress.skipped_reasons.clone(),
+            deleted_files: 0,
+            failed_files: progress.failed_files.iter().map(|failed| failed.path.clone()).collect(),
+            average_bps,
+            peak_bps,
+            percentile_95_ms,
+            mean_ms,
+            slowest_file,
+        };
+        *self.last_summary.lock().unwrap_or_else(|e| e.into_inner()) = Some(summary.clone());
+        summary
+    }
+
+    /// Summary of the last upload or download to finish, if any has
+    pub fn get_last_summary(&self) -> Option<SyncSummary> {
+        self.last_summary.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Remember how long a file took, dropping the oldest record past MAX_TRANSFER_RECORDS
+    fn record_transfer_time(&self, path: &str, size: u64, started: Instant, direction: SyncDirection) {
+        let mut records = self.transfer_records.lock().unwrap();
+        if records.len() == MAX_TRANSFER_RECORDS {
+            records.pop_front();
+        }
+        records.push_back(FileTransferRecord {
+            path: path.to_string(),
+            size,
+            duration_ms: self.elapsed(started).as_millis() as u64,
+            direction,
+        });
+        self.session_transfers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Per-file timings of the most recent transfers, oldest first
+    pub fn get_transfer_records(&self) -> Vec<FileTransferRecord> {
+        self.transfer_records.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn lock_cache(&self) -> std::sync::MutexGuard<'_, SyncCache> {
+        self.sync_cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Start a new upload session and save it as it runs, replacing any older
+    /// session the same user saved. Returns the session ID.
+    fn begin_resume_record(&self, source_paths: &[PathBuf], template: Option<&str>, transferred_keys: Vec<String>) -> String {
+        let session_id = uuid::Uuid::new_v4().to_string();
+        self.lock_cache().start_session(&session_id, transferred_keys.iter().cloned());
+        *self.resume_record.lock().unwrap_or_else(|e| e.into_inner()) = Some(ResumeRecord {
+            state: SyncSession {
+                session_id: session_id.clone(),
+                uid: self.user.as_ref().map(|user| user.uid.clone()),
+                progress: SyncProgress::default(),
+                source_paths: source_paths.to_vec(),
+                rendered_template: template.map(str::to_string),
+                direction: SyncDirection::LocalToCloud,
+                transferred_keys,
+            },
+            saved_at: None,
+        });
+        self.persist_state(true);
+        let uid = self.user.as_ref().map(|user| user.uid.as_str());
+        self.lock_cache().remove_user_sessions(uid, Some(&session_id));
+        session_id
+    }
+
+    /// Note an uploaded key, saving the state if it hasn't been saved recently
+    fn record_transferred(&self, key: &str) {
+        if let Some(record) = self.resume_record.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            record.state.transferred_keys.push(key.to_string());
+            self.lock_cache().record_upload(&record.state.session_id, key);
+        }
+        self.persist_state(false);
+    }
+
+    /// Keys the running upload has transferred, including those from before a resume
+    fn transferred_keys(&self) -> HashSet<String> {
+        match self.resume_record.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            Some(record) => record.state.transferred_keys.iter().cloned().collect(),
+            None => HashSet::new(),
+        }
+    }
+
+    /// Stop saving the upload. An interrupted upload keeps its session file for
+    /// resuming; a finished or cancelled one removes it.
+    fn end_resume_record(&self, interrupted: bool) {
+        if interrupted {
+            self.persist_state(true);
+        }
+        if let Some(record) = self.resume_record.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            let mut cache = self.lock_cache();
+            cache.end_session(&record.state.session_id);
+            if !interrupted {
+                if let Some(path) = cache.session_file(&record.state.session_id) {
+                    SyncSession::remove(&path);
+                }
+            }
+        }
+    }
+
+    /// Write the running upload to its session file; unless `force`, at most once
+    /// per `STATE_PERSIST_INTERVAL`
+    fn persist_state(&self, force: bool) {
+        let mut record = self.resume_record.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(record) = record.as_mut() else {
+            return;
+        };
+        let Some(path) = self.lock_cache().session_file(&record.state.session_id) else {
+            return;
+        };
+        if !force && record.saved_at.is_some_and(|at| self.elapsed(at) < STATE_PERSIST_INTERVAL) {
+            return;
+        }
+
+        record.state.progress = self.get_progress_sync();
+        if let Err(e) = record.state.save(&path) {
+            log::warn!("Failed to save sync state: {}", e);
+        }
+        record.saved_at = Some(self.now());
+    }
+
+    /// Sync local folders to cloud, skipping `already_transferred` keys left
+    /// over from an interrupted upload
+    ///
+    /// Each call is a new session with its own ID, saved as it runs; resuming
+    /// an interrupted session starts a new one that carries over its keys.
+    pub async fn sync_to_cloud(
+        &self,
+        source_paths: &[PathBuf],
+        already_transferred: Vec<String>,
+    ) -> Result<SyncSummary, SyncError> {
+        let template = self.render_template(source_paths.len())?;
+        self.upload_to_cloud(source_paths, template.as_deref(), None, already_transferred).await
+    }
+
+    /// Continue an upload saved in `session`, skipping the files it already uploaded
+    /// and putting the rest under the same rendered `remote_path_template`
+    pub async fn resume_upload(&self, session: &SyncSession) -> Result<SyncSummary, SyncError> {
+        if !session.belongs_to(self.user.as_ref().map(|user| user.uid.as_str())) {
+            return Err(SyncError::ForeignSession);
+        }
+        self.upload_to_cloud(
+            &session.source_paths,
+            session.rendered_template.as_deref(),
+            None,
+            session.transferred_keys.clone(),
+        )
+        .await
+    }
+
+    /// Upload the files a `list_pending_uploads` preview found, without scanning
+    /// the folders or checking the bucket again
+    pub async fn sync_pending_to_cloud(
+        &self,
+        source_paths: &[PathBuf],
+        pending: Vec<PendingFile>,
+    ) -> Result<SyncSummary, SyncError> {
+        let files = pending.into_iter().map(FileEntry::from).collect();
+        let template = self.render_template(source_paths.len())?;
+        self.upload_to_cloud(source_paths, template.as_deref(), Some(files), Vec::new()).await
+    }
+
+    /// Upload local folders under each of `remote_prefixes`, e.g. `backups/daily` and
+    /// `backups/weekly`. Each file is uploaded once, then copied server-side to the
+    /// other prefixes; a destination that fails doesn't stop the rest.
+    ///
+    /// Every file counts once per destination in the progress.
+    pub async fn sync_to_cloud_multi_dest(
+        &self,
+        source_paths: &[PathBuf],
+        remote_prefixes: &[String],
+    ) -> Result<SyncSummary, SyncError> {
+        self.ensure_writable().await?;
+        let prefixes: Vec<String> = remote_prefixes
+            .iter()
+            .map(|prefix| normalize_remote_path(prefix).trim_matches('/').to_string())
+            .filter(|prefix| !prefix.is_empty())
+            .collect();
+        if prefixes.is_empty() {
+            return Err(SyncError::S3Error("No destination folders given".to_string()));
+        }
+
+        // Reset state
+        self.state.reset();
+        self.transferred_bytes.store(0, Ordering::Relaxed);
+        self.peak_speed.store(0, Ordering::Relaxed);
+        self.session_transfers.store(0, Ordering::Relaxed);
+        let _snapshots = SnapshotUpdater::start(self.progress_handles());
+        self.folder_progress.write().await.clear();
+
+        // Update status to scanning
+        {
+            let mut progress = self.progress.write().await;
+            progress.status = SyncStatus::Scanning { files_found: 0, dirs_scanned: 0 };
+            progress.started_at = Some(self.now());
+            progress.direction = Some(SyncDirection::LocalToCloud);
+            progress.active_folder = None;
+            progress.failed_files.clear();
+            progress.secondary_errors.clear();
+            progress.error_summary = None;
+            progress.destination_count = self.s3_clients.len();
+            progress.skipped_files = 0;
+            progress.skipped_reasons.clear();
+            progress.excluded_files.clear();
+        }
+
+        let result = self.upload_to_prefixes_scanned(source_paths, &prefixes).await;
+        self.fail_on_error(result).await
+    }
+
+    /// The rest of `sync_to_cloud_multi_dest` once the status is `Scanning`
+    async fn upload_to_prefixes_scanned(&self, source_paths: &[PathBuf], prefixes: &[String]) -> Result<SyncSummary, SyncError> {
+        let policy = self.sync_policy().await;
+        let template = self.render_template(source_paths.len())?;
+        let template = template.as_deref();
+        let files = self.scan(source_paths, true, template).await?;
+        let files = self.apply_policy(files, &policy).await?;
+        // Every copy takes up space, so all of them must fit before anything is sent
+        self.load_storage_quota().await?;
+        let stored_bytes: u64 = files.iter().map(|f| f.size).sum();
+        self.reserve_quota_bytes(stored_bytes.saturating_mul(prefixes.len() as u64))?;
+
+        // Copies are server-side, so only the first upload of each file moves bytes
+        {
+            let mut progress = self.progress.write().await;
+            progress.status = SyncStatus::Syncing;
+            progress.total_files = (files.len() * prefixes.len()) as u64;
+            progress.total_bytes = files.iter().map(|f| f.size).sum();
+            progress.completed_files = 0;
+        }
+
+        let aggregator = ProgressAggregator::start(Arc::clone(&self.progress_updates), &self.progress_handles());
+        let uploaded = self
+            .for_each_file_concurrently(&files, |file| self.upload_to_prefixes(source_paths, template, file, prefixes, &policy))
+            .await;
+        aggregator.finish().await;
+        uploaded?;
+
+        self.finish(&SyncDirection::LocalToCloud).await;
+        self.invalidate_storage_stats().await;
+
+        Ok(self
+            .build_summary(SyncDirection::LocalToCloud, uuid::Uuid::new_v4().to_string())
+            .await)
+    }
+
+    /// Files an upload of `source_paths` would transfer, and why, without starting it
+    ///
+    /// In differential mode each file is checked against its cloud copy; files
+    /// that are already current are left out.
+    pub async fn list_pending_uploads(&self, source_paths: &[PathBuf]) -> Result<Vec<PendingFile>, SyncError> {
+        let template = self.render_template(source_paths.len())?;
+        self.pending_uploads(source_paths, template.as_deref(), false).await
+    }
+
+    /// Expected cost of uploading the files a `list_pending_uploads` preview found
+    /// to the primary bucket, at `SyncConfig::pricing`
+    pub fn estimate_cost(&self, pending: &[PendingFile]) -> S3CostEstimate {
+        let pricing = self.config.pricing.unwrap_or_else(S3Pricing::scaleway_ams_default);
+        let bytes = pending.iter().map(|file| file.size).sum();
+        let puts = pending.iter().map(|file| self.primary().upload_requests(file.size)).sum();
+        // Differential syncs check each file with a HEAD request before uploading it
+        let gets = if self.config.differential { pending.len() as u64 } else { 0 };
+        pricing.estimate(bytes, 0, puts, gets)
+    }
+
+    /// Files for `list_pending_uploads`, scanned `for_sync` as in `scan`
+    async fn pending_uploads(
+        &self,
+        source_paths: &[PathBuf],
+        template: Option<&str>,
+        for_sync: bool,
+    ) -> Result<Vec<PendingFile>, SyncError> {
+        let files = self.scan(source_paths, for_sync, template).await?;
+        futures::stream::iter(files)
+            .map(|file| async move {
+                let local_path = self.find_source_file(source_paths, template, &file.path)?;
+                let reason = if self.config.differential {
+                    let remote = match self.primary().get_object_info(&file.path).await {
+                        Ok(remote) => Some(remote),
+                        Err(S3Error::FileNotFound(_)) => None,
+                        Err(e) => return Err(SyncError::from(e)),
+                    };
+                    let metadata = tokio::fs::metadata(&local_path)
+                        .await
+                        .map_err(|e| io_error(&local_path, e))?;
+                    upload_reason(&metadata, remote.as_ref())
+                } else {
+                    Some(UploadReason::Forced)
+                };
+                Ok(reason.map(|reason| PendingFile {
+                    local_path: local_path.display().to_string(),
+                    remote_path: file.path,
+                    size: file.size,
+                    reason,
+                }))
+            })
+            .buffered(self.config.concurrency.max(1))
+            .try_filter_map(|pending| async move { Ok(pending) })
+            .try_collect()
+            .await
+    }
+
+    /// Upload `files`, or everything under `source_paths` when no files are given.
+    /// Given files were already checked against the bucket.
+    async fn upload_to_cloud(
+        &self,
+        source_paths: &[PathBuf],
+        template: Option<&str>,
+        files: Option<Vec<FileEntry>>,
+        already_transferred: Vec<String>,
+    ) -> Result<SyncSummary, SyncError> {
+        self.ensure_writable().await?;
+
+        // Reset state
+        self.state.reset();
+        self.transferred_bytes.store(0, Ordering::Relaxed);
+        self.peak_speed.store(0, Ordering::Relaxed);
+        self.session_transfers.store(0, Ordering::Relaxed);
+        self.session_bytes_transferred.store(0, Ordering::Relaxed);
+        self.session_limit_reached.store(false, Ordering::Relaxed);
+        let _snapshots = SnapshotUpdater::start(self.progress_handles());
+        
+        // Update status to scanning
+        {
+            let mut progress = self.progress.write().await;
+            progress.status = SyncStatus::Scanning { files_found: 0, dirs_scanned: 0 };
+            progress.started_at = Some(self.now());
+            progress.direction = Some(SyncDirection::LocalToCloud);
+            progress.failed_files.clear();
+            progress.secondary_errors.clear();
+            progress.error_summary = None;
+            progress.destination_count = self.s3_clients.len();
+            progress.skipped_files = 0;
+            progress.skipped_reasons.clear();
+            progress.excluded_files.clear();
+        }
+
+        let result = self.upload_scanned(source_paths, template, files, already_transferred).await;
+        self.fail_on_error(result).await
+    }
+
+    /// The rest of `upload_to_cloud` once the status is `Scanning`
+    async fn upload_scanned(
+        &self,
+        source_paths: &[PathBuf],
+        template: Option<&str>,
+        files: Option<Vec<FileEntry>>,
+        already_transferred: Vec<String>,
+    ) -> Result<SyncSummary, SyncError> {
+        let policy = self.sync_policy().await;
+
+        // Scan files, unless a preview already did
+        let (files, check_remote) = match files {
+            Some(files) => (files, false),
+            None if self.config.two_phase => (self.scan_and_confirm(source_paths, template).await?, false),
+            None => (self.scan(source_paths, true, template).await?, self.config.differential),
+        };
+        let files = self.apply_policy(files, &policy).await?;
+        let total_bytes: u64 = files.iter().map(|f| f.size).sum();
+        let total_files = files.len() as u64;
+        self.load_storage_quota().await?;
+        *self.folder_progress.write().await = folder_totals(&files);
+        
+        // Update progress with totals
+        {
+            let mut progress = self.progress.write().await;
+            progress.status = SyncStatus::Syncing;
+            progress.total_files = total_files;
+            progress.total_bytes = total_bytes;
+            progress.completed_files = 0;
+        }
+        
+        // Remember the upload so it can be resumed if the app quits before it finishes
+        let session_id = self.begin_resume_record(source_paths, template, already_transferred);
+        
+        // Upload, up to `concurrency` files at a time, with one task applying their progress
+        let aggregator = ProgressAggregator::start(Arc::clone(&self.progress_updates), &self.progress_handles());
+        let uploaded = self
+            .for_each_file_concurrently(&files, |file| self.upload_scanned_file(source_paths, template, file, &session_id, check_remote, &policy))
+            .await;
+        aggregator.finish().await;
+        let transferred = self.transferred_keys();
+        // An upload stopped at the byte limit can be resumed to send the rest
+        let limit_reached = self.session_limit_reached.load(Ordering::Acquire);
+        self.end_resume_record(limit_reached || matches!(&uploaded, Err(e) if !matches!(e, SyncError::Cancelled)));
+        uploaded?;
+
+        // The files are in the cloud either way, so a failed manifest doesn't fail the upload
+        if self.config.generate_manifest {
+            if let Err(e) = self.upload_manifest(source_paths, template, &files, &transferred).await {
+                log::warn!("Failed to upload the manifest: {}", e);
+            }
+        }
+        if self.config.folder_checksums {
+            for base_path in source_paths {
+                let prefix = remote_root(base_path, template);
+                if let Err(e) = self.store_folder_checksum(&prefix).await {
+                    log::warn!("Failed to store the checksum of {}: {}", prefix, e);
+                }
+            }
+        }
+        
+        // Mark as completed
+        self.finish(&SyncDirection::LocalToCloud).await;
+        if limit_reached {
+            self.stop_by_limit().await;
+        }
+        self.invalidate_storage_stats().await;
+        
+        Ok(self.build_summary(SyncDirection::LocalToCloud, session_id).await)
+    }
+
+    /// Pretty-printed JSON manifest of `files`, with the hex SHA-256 of each from
+    /// `hashes` or its `content_hash`. Files with neither are left out.
+    pub fn generate_manifest(&self, files: &[FileEntry], hashes: &HashMap<String, String>) -> String {
+        let created_at = self.now_utc();
+        let recent = self.recently_uploaded.read().unwrap_or_else(|e| e.into_inner());
+        let files = files
+            .iter()
+            .filter(|file| !file.is_dir)
+            .filter_map(|file| {
+                let sha256 = hashes.get(&file.path).or(file.content_hash.as_ref())?.clone();
+                let uploaded_at = recent
+                    .get(&file.path)
+                    .and_then(|(object, _)| DateTime::from_timestamp(object.last_modified, 0))
+                    .unwrap_or(created_at);
+                Some(ManifestEntry { path: file.path.clone(), size: file.size, sha256, uploaded_at })
+            })
+            .collect();
+        let manifest = UploadManifest { created_at, files };
+        serde_json::to_string_pretty(&manifest).expect("manifest serializes to JSON")
+    }
+
+    /// Upload a manifest of the `transferred` files to the primary bucket, hashing
+    /// those the scan didn't, then delete all but the newest `MANIFESTS_KEPT`
+    async fn upload_manifest(
+        &self,
+        source_paths: &[PathBuf],
+        template: Option<&str>,
+        files: &[FileEntry],
+        transferred: &HashSet<String>,
+    ) -> Result<(), SyncError> {
+        let files: Vec<FileEntry> = files.iter().filter(|file| transferred.contains(&file.path)).cloned().collect();
+        let mut hashes = HashMap::new();
+        for file in files.iter().filter(|file| file.content_hash.is_none()) {
+            let hash = hash_file(self.find_source_file(source_paths, template, &file.path)?).await?;
+            hashes.insert(file.path.clone(), hash);
+        }
+
+        let manifest = self.generate_manifest(&files, &hashes);
+        let key = format!("{}{}.json", MANIFEST_PREFIX, self.now_utc().format("%Y%m%dT%H%M%S%3fZ"));
+        self.primary().upload_bytes(manifest.as_bytes(), &key, "application/json").await?;
+
+        for old in old_manifests(self.manifest_keys().await?) {
+            self.primary().delete_object(&old).await?;
+        }
+        Ok(())
+    }
+
+    /// Keys of the stored upload manifests, oldest first
+    async fn manifest_keys(&self) -> Result<Vec<String>, SyncError> {
+        let mut keys: Vec<String> = self
+            .primary()
+            .list_objects(MANIFEST_PREFIX)
+            .await?
+            .into_iter()
+            .map(|object| object.key)
+            .collect();
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// JSON of the newest upload manifest, if an upload has stored one
+    pub async fn last_manifest(&self) -> Result<Option<String>, SyncError> {
+        let Some(key) = self.manifest_keys().await?.pop() else {
+            return Ok(None);
+        };
+        let manifest: UploadManifest = self.primary().read_json_from_key(&key).await?;
+        Ok(Some(serde_json::to_string_pretty(&manifest).expect("manifest serializes to JSON")))
+    }
+
+    /// Checksum of the cloud folder `prefix` as it is now. Files uploaded moments ago
+    /// that the bucket doesn't list yet are looked up one by one.
+    pub async fn compute_folder_checksum(&self, prefix: &str) -> Result<FolderChecksum, SyncError> {
+        let prefix = prefix.trim_matches('/');
+        let folder = format!("{}/", prefix);
+        let mut objects = self.primary().list_objects(&folder).await?;
+        let listed: HashSet<String> = objects.iter().map(|object| object.key.clone()).collect();
+        for recent in self.recent_uploads(&folder) {
+            if listed.contains(&recent.key) {
+                continue;
+            }
+            // The recorded upload has no ETag, which the checksum needs
+            match self.primary().get_object_info(&recent.key).await {
+                Ok(object) => objects.push(object),
+                Err(S3Error::FileNotFound(_)) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(FolderChecksum::of(prefix, &objects))
+    }
+
+    /// Compute the checksum of `prefix` and store it in the folder for `verify_folder_checksum`
+    pub async fn store_folder_checksum(&self, prefix: &str) -> Result<FolderChecksum, SyncError> {
+        let checksum = self.compute_folder_checksum(prefix).await?;
+        let json = serde_json::to_vec_pretty(&checksum).expect("checksum serializes to JSON");
+        let key = format!("{}/{}", checksum.prefix, FOLDER_CHECKSUM_FILE);
+        self.primary().upload_bytes(&json, &key, "application/json").await?;
+        Ok(checksum)
+    }
+
+    /// Set a canned ACL on a cloud file, unless the sync policy forbids it
+    pub async fn set_file_acl(&self, remote_path: &str, acl: ObjectCannedAcl) -> Result<(), SyncError> {
+        if let Some(reason) = self.fetch_remote_sync_policy().await?.acl_violation(acl.as_str()) {
+            return Err(SyncError::PolicyViolation { path: remote_path.to_string(), reason });
+        }
+        Ok(self.primary().put_object_acl(remote_path, acl).await?)
+    }
+
+    /// Download a cloud file and check it against its stored SHA-256, with the SSE-C key
+    /// the file was uploaded with
+    pub async fn verify_file_checksum(&self, remote_path: &str) -> Result<bool, SyncError> {
+        Ok(self.primary().verify_object_checksum(remote_path).await?)
+    }
+
+    /// Whether the cloud folder `prefix` still matches its stored checksum, or None
+    /// if no checksum was stored for it
+    pub async fn verify_folder_checksum(&self, prefix: &str) -> Result<Option<bool>, SyncError> {
+        let key = format!("{}/{}", prefix.trim_matches('/'), FOLDER_CHECKSUM_FILE);
+        let stored: Option<FolderChecksum> = self.primary().read_json_from_key(&key).await?;
+        let Some(stored) = stored else {
+            return Ok(None);
+        };
+        Ok(Some(self.compute_folder_checksum(prefix).await? == stored))
+    }
+
+    /// Fail with `ReadOnlyMode` if `read_only` is set or the sync policy forces it
+    pub async fn ensure_writable(&self) -> Result<(), SyncError> {
+        if self.config.read_only || self.sync_policy().await.force_read_only {
+            return Err(SyncError::ReadOnlyMode);
+        }
+        Ok(())
+    }
+
+    /// The administrators' sync policy, fetched at most every `SYNC_POLICY_TTL`.
+    /// A missing policy file allows everything.
+    pub async fn fetch_remote_sync_policy(&self) -> Result<SyncPolicy, SyncError> {
+        let Some(client) = &self.policy_client else {
+            return Ok(SyncPolicy::default());
+        };
+        if let Some((policy, fetched_at)) = &*self.policy_cache.read().await {
+            if self.elapsed(*fetched_at) < SYNC_POLICY_TTL {
+                return Ok(policy.clone());
+            }
+        }
+
+        let mut cache = self.policy_cache.write().await;
+        let policy: SyncPolicy = client.read_json_from_key(SYNC_POLICY_FILE).await?;
+        *cache = Some((policy.clone(), self.now()));
+        Ok(policy)
+    }
+
+    /// The policy an upload follows: freshly fetched, or when the bucket can't be read,
+    /// the last one fetched however old it is, or failing that the default
+    async fn sync_policy(&self) -> SyncPolicy {
+        match self.fetch_remote_sync_policy().await {
+            Ok(policy) => policy,
+            Err(e) => {
+                log::warn!("Couldn't fetch the sync policy, using the last one fetched: {}", e);
+                self.policy_cache
+                    .read()
+                    .await
+                    .as_ref()
+                    .map(|(policy, _)| policy.clone())
+                    .unwrap_or_default()
+            }
+        }
+    }
+
+    /// Set the status to `Error` when `result` failed, so that a sync that stopped
+    /// early doesn't stay `Scanning` or `Syncing`. A cancel has already gone back to `Idle`.
+    async fn fail_on_error<T>(&self, result: Result<T, SyncError>) -> Result<T, SyncError> {
+        if let Err(e) = &result {
+            if !matches!(e, SyncError::Cancelled) {
+                let mut progress = self.progress.write().await;
+                progress.status = SyncStatus::Error(e.to_string());
+                progress.current_file = None;
+                progress.active_folder = None;
+            }
+        }
+        result
+    }
+
+    /// Drop the files `policy` forbids, failing each as a `PolicyViolation`
+    async fn apply_policy(&self, files: Vec<FileEntry>, policy: &SyncPolicy) -> Result<Vec<FileEntry>, SyncError> {
+        let acl_violation = self.config.default_acl.as_deref().and_then(|acl| policy.acl_violation(acl));
+        let mut allowed = Vec::with_capacity(files.len());
+        for file in files {
+            match acl_violation.clone().or_else(|| policy.violation(&file.path, file.size)) {
+                Some(reason) => {
+                    let error = SyncError::PolicyViolation { path: file.path.clone(), reason };
+                    self.handle_file_error(&file.path, error).await?
+                }
+                None => allowed.push(file),
+            }
+        }
+        Ok(allowed)
+    }
+
+    /// Run `upload` for each file, at most `config.concurrency` at a time,
+    /// counting the files in flight in the progress
+    async fn for_each_file_concurrently<'a, F, Fut>(&self, files: &'a [FileEntry], upload: F) -> Result<(), SyncError>
+    where
+        F: Fn(&'a FileEntry) -> Fut,
+        Fut: Future<Output = Result<(), SyncError>>,
+    {
+        let handles = self.progress_handles();
+        let (handles, upload) = (&handles, &upload);
+        futures::stream::iter(files.iter().map(Ok))
+            .try_for_each_concurrent(self.config.concurrency.max(1), |file| async move {
+                self.wait_if_paused().await?;
+                let _active = ActiveTransfer::start(handles, &file.path);
+                upload(file).await
+            })
+            .await
+    }
+
+    /// Scan and check the files for a two-phase upload, then wait in
+    /// `AwaitingConfirmation` until `confirm` is called. Cancelling discards the scan.
+    async fn scan_and_confirm(&self, source_paths: &[PathBuf], template: Option<&str>) -> Result<Vec<FileEntry>, SyncError> {
+        let pending = self.pending_uploads(source_paths, template, true).await?;
+        let scan_summary = ScanSummary::of(&pending);
+        let scan = ScanComplete {
+            total_bytes: scan_summary.total_bytes,
+            files: pending.into_iter().map(FileEntry::from).collect(),
+        };
+
+        self.confirmed.store(false, Ordering::Release);
+        self.report(ProgressUpdate::StatusChange(SyncStatus::AwaitingConfirmation { scan_summary }))
+            .await;
+        if let Some(handler) = &self.scan_complete_handler {
+            handler(&scan);
+        }
+        loop {
+            if self.state.is_cancelled() {
+                return Err(self.stop_cancelled().await);
+            }
+            if self.confirmed.swap(false, Ordering::AcqRel) {
+                return Ok(scan.files);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Upload one scanned file for `sync_to_cloud`, skipping it if the session already
+    /// uploaded it or, with `check_remote`, when the cloud copy is current
+    async fn upload_scanned_file(
+        &self,
+        source_paths: &[PathBuf],
+        template: Option<&str>,
+        file: &FileEntry,
+        session_id: &str,
+        check_remote: bool,
+        policy: &SyncPolicy,
+    ) -> Result<(), SyncError> {
+        self.report(ProgressUpdate::CurrentFile { path: file.path.clone() }).await;
+        
+        // Uploaded before the app was restarted
+        if self.lock_cache().was_uploaded_in_session(session_id, &file.path) {
+            self.skip_file(file).await;
+            return Ok(());
+        }
+        
+        // Find the source path for this file
+        let source_file = match self.find_source_file(source_paths, template, &file.path) {
+            Err(SyncError::LocalFileGone { .. }) => {
+                self.skip_deleted_file(file).await;
+                return Ok(());
+            }
+            source_file => source_file?,
+        };
+        
+        // In differential mode, skip files whose cloud copy is already current
+        if check_remote {
+            let needs_upload = match self.primary().object_needs_upload(&source_file, &file.path).await {
+                Err(S3Error::LocalFileGone { .. }) => {
+                    self.skip_deleted_file(file).await;
+                    return Ok(());
+                }
+                needs_upload => needs_upload.map_err(|e| SyncError::S3Error(e.to_string()))?,
+            };
+            
+            if !needs_upload {
+                self.skip_file(file).await;
+                self.record_transferred(&file.path);
+                return Ok(());
+            }
+        }
+        
+        if !self.reserve_session_bytes(file.size) {
+            self.report(ProgressUpdate::FileSkipped {
+                path: file.path.clone(),
+                bytes: file.size,
+                reason: Some(SESSION_LIMIT_REACHED.to_string()),
+            })
+            .await;
+            return Ok(());
+        }
+        self.reserve_quota_bytes(file.size)?;
+        
+        // Upload, advancing the byte counters as each part completes
+        let on_progress = self.file_progress(&file.path, file.size);
+        let folder_config = self.file_config(source_paths, &source_file);
+        let config = folder_config.as_ref().map_or(&self.config, |folder| &folder.config);
+        let tags = self.upload_tags(config, policy);
+        let (source_file, tags) = (&source_file, &tags);
+        let (started, now) = (self.now(), self.now_utc());
+        let upload = self
+            .write_to_all(&self.s3_clients, &file.path, |client, primary| {
+                // Only the primary upload counts towards the progress
+                let on_progress = on_progress.clone();
+                async move {
+                    if primary {
+                        put_file(client, config, source_file, &file.path, tags, now, on_progress).await
+                    } else {
+                        put_file(client, config, source_file, &file.path, tags, now, |_| {}).await
+                    }
+                }
+            });
+        // A cancelled atomic upload deletes its temp key when dropped
+        let uploaded = self.unless_cancelled(upload).await?;
+        if uploaded.is_err() {
+            // Nothing was sent in the end, so it doesn't count against the limits
+            self.session_bytes_transferred.fetch_sub(file.size, Ordering::AcqRel);
+            self.quota_bytes_left.fetch_add(file.size, Ordering::AcqRel);
+        }
+        let failed = match uploaded {
+            Ok(()) => false,
+            Err(SyncError::LocalFileGone { .. }) => {
+                self.skip_deleted_file(file).await;
+                return Ok(());
+            }
+            Err(e) => {
+                self.handle_file_error(&file.path, e).await?;
+                true
+            }
+        };
+        
+        // Update progress
+        self.report(ProgressUpdate::FileCompleted {
+            path: file.path.clone(),
+            bytes: file.size,
+            transferred: !failed,
+        })
+        .await;
+        if !failed {
+            self.record_transferred(&file.path);
+            self.record_transfer_time(&file.path, file.size, started, SyncDirection::LocalToCloud);
+            self.record_recent_upload(&file.path, file.size);
+        }
+        Ok(())
+    }
+
+    /// Upload one scanned file under the first of `prefixes` it can be, then copy that
+    /// upload to the others. Failed destinations are recorded as failed files; with
+    /// `ErrorPolicy::Abort` the first failure is returned once every one was tried.
+    async fn upload_to_prefixes(
+        &self,
+        source_paths: &[PathBuf],
+        template: Option<&str>,
+        file: &FileEntry,
+        prefixes: &[String],
+        policy: &SyncPolicy,
+    ) -> Result<(), SyncError> {
+        let keys = destination_keys(prefixes, &file.path);
+        let source_file = match self.find_source_file(source_paths, template, &file.path) {
+            Err(SyncError::LocalFileGone { .. }) => {
+                self.skip_deleted_copies(file, &keys).await;
+                return Ok(());
+            }
+            source_file => source_file?,
+        };
+
+        let folder_config = self.file_config(source_paths, &source_file);
+        let config = folder_config.as_ref().map_or(&self.config, |folder| &folder.config);
+        let tags = self.upload_tags(config, policy);
+        let (source_file, tags) = (&source_file, &tags);
+        let mut uploaded: Option<&str> = None;
+        let mut first_error = None;
+        for (index, key) in keys.iter().enumerate() {
+            self.report(ProgressUpdate::CurrentFile { path: key.clone() }).await;
+            let started = self.now();
+            let written = match uploaded {
+                // Already in the bucket, so copy it rather than uploading it again
+                Some(copy_from) => {
+                    let copy = self.write_to_all(&self.s3_clients, key, |client, _| async move {
+                        client.with_acl(default_acl(config)?).copy_object(copy_from, key).await
+                    });
+                    self.unless_cancelled(copy).await?
+                }
+                None => {
+                    let on_progress = self.file_progress(key, file.size);
+                    let now = self.now_utc();
+                    let upload = self.write_to_all(&self.s3_clients, key, |client, primary| {
+                        let on_progress = on_progress.clone();
+                        async move {
+                            if primary {
+                                put_file(client, config, source_file, key, tags, now, on_progress).await
+                            } else {
+                                put_file(client, config, source_file, key, tags, now, |_| {}).await
+                            }
+                        }
+                    });
+                    self.unless_cancelled(upload).await?
+                }
+            };
+            let transferred = written.is_ok();
+            match written {
+                Ok(()) => {
+                    if uploaded.is_none() {
+                        self.record_transfer_time(key, file.size, started, SyncDirection::LocalToCloud);
+                        uploaded = Some(key);
+                    }
+                    self.record_recent_upload(key, file.size);
+                }
+                Err(SyncError::LocalFileGone { .. }) => {
+                    self.skip_deleted_copies(file, &keys[index..]).await;
+                    return Ok(());
+                }
+                Err(e) => {
+                    log::warn!("Failed to upload {} to {}: {}", file.path, key, e);
+                    self.report(ProgressUpdate::FileFailed {
+                        path: key.clone(),
+                        error: e.to_string(),
+                    })
+                    .await;
+                    first_error.get_or_insert(e);
+                }
+            }
+            self.report(ProgressUpdate::FileCompleted {
+                path: key.clone(),
+                bytes: file.size,
+                transferred,
+            })
+            .await;
+        }
+        match first_error {
+            Some(e) if self.config.on_error == ErrorPolicy::Abort => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count `bytes` about to be uploaded against `max_session_bytes`. False, and no
+    /// more files this upload, once they don't fit.
+    fn reserve_session_bytes(&self, bytes: u64) -> bool {
+        if self.session_limit_reached.load(Ordering::Acquire) {
+            return false;
+        }
+        let limit = self.config.max_session_bytes.unwrap_or(u64::MAX);
+        let reserved = self
+            .session_bytes_transferred
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |sent| {
+                sent.checked_add(bytes).filter(|&total| total <= limit)
+            })
+            .is_ok();
+        if !reserved {
+            self.session_limit_reached.store(true, Ordering::Release);
+        }
+        reserved
+    }
+
+    /// Start counting this upload against the user's `max_storage_gb`, less what
+    /// their folder already holds
+    async fn load_storage_quota(&self) -> Result<(), SyncError> {
+        let left = match self.user.as_ref().and_then(KeyPayload::max_storage_bytes) {
+            Some(limit) => limit.saturating_sub(self.get_storage_stats().await?.used_bytes),
+            None => u64::MAX,
+        };
+        self.quota_bytes_left.store(left, Ordering::Release);
+        Ok(())
+    }
+
+    /// Count `bytes` about to be stored against the user's quota, failing with
+    /// `QuotaExceeded` once they don't fit
+    fn reserve_quota_bytes(&self, bytes: u64) -> Result<(), SyncError> {
+        self.quota_bytes_left
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |left| left.checked_sub(bytes))
+            .map(|_| ())
+            .map_err(|left| {
+                let limit = self.user.as_ref().and_then(KeyPayload::max_storage_bytes).unwrap_or(u64::MAX);
+                SyncError::QuotaExceeded { used: (limit - left).saturating_add(bytes), limit }
+            })
+    }
+
+    /// End an upload that reached `max_session_bytes` as `StoppedByLimit`, unless files
+    /// failed: `CompletedWithErrors` is kept so the failures aren't hidden
+    async fn stop_by_limit(&self) {
+        let limit = self.config.max_session_bytes.unwrap_or_default();
+        let transferred = self.session_bytes_transferred.load(Ordering::Acquire);
+        log::info!("Upload stopped at its limit of {} bytes after {} bytes", limit, transferred);
+        {
+            let mut progress = self.progress.write().await;
+            if progress.status == SyncStatus::Completed {
+                progress.status = SyncStatus::StoppedByLimit { transferred, limit };
+            }
+        }
+        if let Some(handler) = &self.limit_reached_handler {
+            handler(transferred, limit);
+        }
+    }
+
+    /// Remember an upload so listings include it while the bucket may not list it yet
+    fn record_recent_upload(&self, path: &str, size: u64) {
+        let object = S3Object {
+            key: path.to_string(),
+            size,
+            last_modified: self.now_utc().timestamp(),
+            etag: None,
+            content_type: None,
+            checksum_sha256: None,
+        };
+        self.recently_uploaded
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(path.to_string(), (object, self.now()));
+    }
+
+    /// Files uploaded in the last `RECENT_UPLOAD_TTL` under `prefix`, dropping older ones
+    fn recent_uploads(&self, prefix: &str) -> Vec<S3Object> {
+        let now = self.now();
+        let mut recent = self.recently_uploaded.write().unwrap_or_else(|e| e.into_inner());
+        recent.retain(|_, (_, uploaded_at)| now.saturating_duration_since(*uploaded_at) < RECENT_UPLOAD_TTL);
+        recent
+            .values()
+            .filter(|(object, _)| object.key.starts_with(prefix))
+            .map(|(object, _)| object.clone())
+            .collect()
+    }
+
+    /// `listed`, the objects under `prefix`, plus recent uploads it's missing
+    fn with_recent_uploads(&self, prefix: &str, mut listed: Vec<S3Object>) -> Vec<S3Object> {
+        let keys: HashSet<String> = listed.iter().map(|object| object.key.clone()).collect();
+        listed.extend(
+            self.recent_uploads(prefix)
+                .into_iter()
+                .filter(|object| !keys.contains(&object.key)),
+        );
+        listed
+    }
+
+    /// Top-level `folders`, plus those of recent uploads it's missing
+    fn with_recent_folders(&self, mut folders: Vec<String>) -> Vec<String> {
+        for object in self.recent_uploads("") {
+            if let Some((folder, _)) = object.key.split_once('/') {
+                let folder = format!("{}/", folder);
+                if !folders.contains(&folder) {
+                    folders.push(folder);
+                }
+            }
+        }
+        folders
+    }
+
+    /// Forget recent uploads and cached stats, so the next listing shows only
+    /// what the bucket itself lists
+    pub async fn invalidate_listing_cache(&self) {
+        self.recently_uploaded.write().unwrap_or_else(|e| e.into_inner()).clear();
+        self.invalidate_storage_stats().await;
+    }
+
+    /// Count a file as done without transferring it
+    async fn skip_file(&self, file: &FileEntry) {
+        self.report(ProgressUpdate::FileSkipped {
+            path: file.path.clone(),
+            bytes: file.size,
+            reason: None,
+        })
+        .await;
+    }
+
+    /// Skip a scanned file that was deleted before it could be uploaded
+    async fn skip_deleted_file(&self, file: &FileEntry) {
+        log::warn!("Skipping {}: deleted since the scan", file.path);
+        self.report(ProgressUpdate::FileSkipped {
+            path: file.path.clone(),
+            bytes: file.size,
+            reason: Some(DELETED_DURING_SYNC.to_string()),
+        })
+        .await;
+    }
+
+    /// Skip a deleted file at each of `keys` it was to be uploaded to. Its bytes
+    /// only count once towards the total, so they only come off it once.
+    async fn skip_deleted_copies(&self, file: &FileEntry, keys: &[String]) {
+        for (index, key) in keys.iter().enumerate() {
+            let size = if index == 0 { file.size } else { 0 };
+            self.skip_deleted_file(&FileEntry { path: key.clone(), size, ..file.clone() }).await;
+        }
+    }
+
+    /// Run a write against every client at once: the primary and any backups.
+    /// The primary's result is returned; backup failures are only recorded
+    /// in `secondary_errors`.
+    async fn write_to_all<'a, D, T, W, Fut>(&self, clients: &'a [D], path: &str, write: W) -> Result<T, SyncError>
+    where
+        W: Fn(&'a D, bool) -> Fut,
+        Fut: Future<Output = Result<T, S3Error>>,
+    {
+        let write = &write;
+        let writes = clients
+            .iter()
+            .enumerate()
+            .map(|(index, client)| self.transfer(move || write(client, index == 0)));
+        let mut results = futures::future::join_all(writes).await.into_iter();
+        let primary = results
+            .next()
+            .unwrap_or_else(|| Err(SyncError::S3Error("No S3 client configured".to_string())));
+
+        for (backup, result) in results.enumerate() {
+            if let Err(e) = result {
+                log::warn!("Backup destination {} failed for {}: {}", backup + 1, path, e);
+                self.report(ProgressUpdate::SecondaryError(format!("Backup {}: {}: {}", backup + 1, path, e)))
+                    .await;
+            }
+        }
+        primary
+    }
+
+    /// Find the actual source file path given the remote path, which the scan put
+    /// under the `remote_root` for `template`
+    ///
+    /// The source folder's name is matched ignoring case unless `case_sensitive`.
+    fn find_source_file(&self, source_paths: &[PathBuf], template: Option<&str>, remote_path: &str) -> Result<PathBuf, SyncError> {
+        let remote_path = normalize_remote_path(remote_path);
+        for base_path in source_paths {
+            let remote_root = remote_root(base_path, template);
+            if let Some(relative) = strip_source_folder(&remote_path, &remote_root, self.config.case_sensitive) {
+                let full_path = normalize_local_path(&base_path.join(relative));
+                if full_path.exists() {
+                    return Ok(full_path);
+                }
+            }
+        }
+        
+        // Scanned files that can't be found have been deleted since
+        Err(SyncError::LocalFileGone { path: remote_path })
+    }
+
+    /// Sync cloud folder to local
+    pub async fn sync_to_local(
+        &self,
+        cloud_folder: &str,
+        target_path: &Path,
+    ) -> Result<SyncSummary, SyncError> {
+        // Reset state
+        self.state.reset();
+        self.transferred_bytes.store(0, Ordering::Relaxed);
+        self.peak_speed.store(0, Ordering::Relaxed);
+        self.session_transfers.store(0, Ordering::Relaxed);
+        let session_id = uuid::Uuid::new_v4().to_string();
+        let _snapshots = SnapshotUpdater::start(self.progress_handles());
+        
+        // Update status to scanning
+        {
+            let mut progress = self.progress.write().await;
+            progress.status = SyncStatus::Scanning { files_found: 0, dirs_scanned: 0 };
+            progress.started_at = Some(self.now());
+            progress.direction = Some(SyncDirection::CloudToLocal);
+            progress.active_folder = None;
+            progress.skipped_files = 0;
+            progress.skipped_reasons.clear();
+            progress.failed_files.clear();
+            progress.secondary_errors.clear();
+            progress.error_summary = None;
+        }
+        self.folder_progress.write().await.clear();
+
+        let result = self.download_folder(cloud_folder, target_path, session_id).await;
+        self.fail_on_error(result).await
+    }
+
+    /// The rest of `sync_to_local` once the status is `Scanning`
+    async fn download_folder(
+        &self,
+        cloud_folder: &str,
+        target_path: &Path,
+        session_id: String,
+    ) -> Result<SyncSummary, SyncError> {
+        // List cloud files
+        let objects = self.primary()
+            .list_objects(cloud_folder)
+            .await
+            .map_err(|e| SyncError::S3Error(e.to_string()))?;
+        
+        let total_bytes: u64 = objects.iter().map(|o| o.size).sum();
+        let total_files = objects.len() as u64;
+        
+        // Fail before writing anything if the download won't fit
+        self.check_free_space(target_path, total_bytes)?;
+        
+        // Update progress with totals
+        {
+            let mut progress = self.progress.write().await;
+            progress.status = SyncStatus::Syncing;
+            progress.total_files = total_files;
+            progress.total_bytes = total_bytes;
+            progress.completed_files = 0;
+        }
+        
+        // Download each file
+        let mut remaining_bytes = total_bytes;
+        for (idx, obj) in objects.iter().enumerate() {
+            self.wait_if_paused().await?;
+            
+            // Other programs may be filling the disk too
+            if idx > 0 && idx % FREE_SPACE_CHECK_INTERVAL == 0 {
+                self.check_free_space(target_path, remaining_bytes)?;
+            }
+            remaining_bytes -= obj.size;
+            
+            // Skip directories (keys ending with /)
+            if obj.key.ends_with('/') {
+                continue;
+            }
+            
+            // Update current file
+            {
+                let mut progress = self.progress.write().await;
+                progress.current_file = Some(obj.key.clone());
+            }
+            
+            // Calculate local path
+            let relative = obj.key.strip_prefix(cloud_folder).unwrap_or(&obj.key);
+            let relative = relative.trim_start_matches('/');
+            let local_path = target_path.join(relative);
+            
+            // Download, advancing the byte counters as each chunk is written. A file
+            // downloaded before is only fetched again if the object has changed.
+            let on_progress = self.download_progress(&obj.key, obj.size);
+            let local_etag = self.downloaded_etag(&local_path);
+            let started = self.now();
+            let multipart = self.config.multipart_download && obj.size > self.config.multipart_threshold;
+            *self.current_local_path.write().await = Some(local_path.clone());
+            let download = self
+                .transfer(|| async {
+                    if multipart {
+                        self.primary()
+                            .download_file_multipart_with_progress(
+                                &obj.key,
+                                &local_path,
+                                DEFAULT_MULTIPART_PART_SIZE as u64,
+                                self.config.concurrency,
+                                on_progress.clone(),
+                            )
+                            .await
+                            .map(|()| DownloadResult::Downloaded)
+                    } else {
+                        self.primary()
+                            .download_file_conditional_with_progress(
+                                &obj.key,
+                                &local_path,
+                                local_etag.as_deref(),
+                                on_progress.clone(),
+                            )
+                            .await
+                    }
+                });
+            let downloaded = self.unless_cancelled(download).await?;
+            self.current_local_path.write().await.take();
+            match downloaded {
+                Ok(DownloadResult::Downloaded) => {
+                    self.record_transfer_time(&obj.key, obj.size, started, SyncDirection::CloudToLocal);
+                    self.remember_downloaded_etag(&local_path, obj.etag.as_deref());
+                }
+                Ok(DownloadResult::NotModified) => {
+                    self.report(ProgressUpdate::FileSkipped {
+                        path: obj.key.clone(),
+                        bytes: obj.size,
+                        reason: None,
+                    })
+                    .await;
+                }
+                Err(e) => self.handle_file_error(&obj.key, e).await?,
+            }
+            
+            // Update progress
+            {
+                let mut progress = self.progress.write().await;
+                progress.completed_files = (idx + 1) as u64;
+            }
+        }
+        
+        // Mark as completed
+        self.finish(&SyncDirection::CloudToLocal).await;
+        
+        Ok(self.build_summary(SyncDirection::CloudToLocal, session_id).await)
+    }
+
+    /// ETag of the object the local file `path` was downloaded from, if the file
+    /// hasn't changed since
+    fn downloaded_etag(&self, path: &Path) -> Option<String> {
+        let metadata = std::fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?;
+        self.lock_cache()
+            .downloaded_etag(path, modified, metadata.len())
+            .map(str::to_string)
+    }
+
+    /// Remember the ETag of the object `path` was just downloaded from
+    fn remember_downloaded_etag(&self, path: &Path, etag: Option<&str>) {
+        let Some(etag) = etag else {
+            return;
+        };
+        match std::fs::metadata(path).and_then(|metadata| Ok((metadata.modified()?, metadata.len()))) {
+            Ok((modified, size)) => {
+                self.lock_cache()
+                    .set_downloaded_etag(path.to_path_buf(), modified, size, etag.to_string())
+            }
+            Err(e) => log::warn!("Failed to read {} after downloading it: {}", path.display(), e),
+        }
+    }
+
+    /// Fail with `DiskFull` if writing `required_bytes` under `target_path` would
+    /// leave less than `min_free_bytes` free on its disk
+    fn check_free_space(&self, target_path: &Path, required_bytes: u64) -> Result<(), SyncError> {
+        // The target folder may not exist yet; its disk is that of the nearest existing parent
+        let Some(existing) = target_path.ancestors().find(|path| path.exists()) else {
+            return Ok(());
+        };
+        let available_bytes = match (self.available_space)(existing) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                log::warn!("Could not check free space on {}: {}", existing.display(), e);
+                return Ok(());
+            }
+        };
+        
+        let min_free = self.config.min_free_bytes.unwrap_or(0);
+        if required_bytes > available_bytes.saturating_sub(min_free) {
+            return Err(SyncError::DiskFull {
+                path: target_path.display().to_string(),
+                required_bytes,
+                available_bytes,
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether the primary bucket can be reached with this engine's credentials
+    pub async fn check_connection(&self) -> ConnectionStatus {
+        self.primary().check_connection().await
+    }
+
+    /// Time a round trip to the primary bucket, and with `probe_upload` a small upload.
+    /// The upload is skipped while the session may not write.
+    pub async fn warm_up(&self, probe_upload: bool) -> Result<ConnectionInfo, S3Error> {
+        let probe_upload = probe_upload && self.ensure_writable().await.is_ok();
+        self.primary().warm_up(probe_upload).await
+    }
+
+    /// Copy every object under one cloud folder to another, server-side
+    pub async fn sync_cloud_to_cloud(&self, source_folder: &str, dest_folder: &str) -> Result<(), SyncError> {
+        let direction = SyncDirection::CloudToCloud {
+            source_folder: source_folder.to_string(),
+            dest_folder: dest_folder.to_string(),
+        };
+        self.ensure_writable().await?;
+
+        // Reset state
+        self.state.reset();
+        self.transferred_bytes.store(0, Ordering::Relaxed);
+        self.peak_speed.store(0, Ordering::Relaxed);
+        self.session_transfers.store(0, Ordering::Relaxed);
+        let _snapshots = SnapshotUpdater::start(self.progress_handles());
+        self.folder_progress.write().await.clear();
+        
+        // Update status to scanning
+        {
+            let mut progress = self.progress.write().await;
+            progress.status = SyncStatus::Scanning { files_found: 0, dirs_scanned: 0 };
+            progress.started_at = Some(self.now());
+            progress.direction = Some(direction.clone());
+            progress.active_folder = None;
+            progress.failed_files.clear();
+            progress.secondary_errors.clear();
+            progress.error_summary = None;
+        }
+
+        let result = self.copy_folder(source_folder, dest_folder, &direction).await;
+        self.fail_on_error(result).await
+    }
+
+    /// The rest of `sync_cloud_to_cloud` once the status is `Scanning`
+    async fn copy_folder(&self, source_folder: &str, dest_folder: &str, direction: &SyncDirection) -> Result<(), SyncError> {
+        // List source objects, skipping folder markers
+        let objects: Vec<S3Object> = self.primary()
+            .list_objects(source_folder)
+            .await
+            .map_err(|e| SyncError::S3Error(e.to_string()))?
+            .into_iter()
+            .filter(|o| !o.key.ends_with('/'))
+            .collect();
+        
+        // Update progress with totals
+        {
+            let mut progress = self.progress.write().await;
+            progress.status = SyncStatus::Syncing;
+            progress.total_files = objects.len() as u64;
+            progress.total_bytes = objects.iter().map(|o| o.size).sum();
+            progress.completed_files = 0;
+            progress.skipped_files = 0;
+            progress.skipped_reasons.clear();
+        }
+        
+        // Copy each object
+        for (idx, obj) in objects.iter().enumerate() {
+            self.wait_if_paused().await?;
+            
+            {
+                let mut progress = self.progress.write().await;
+                progress.current_file = Some(obj.key.clone());
+            }
+            
+            let dest_key = copy_destination(&obj.key, source_folder, dest_folder);
+            let copied = self
+                .write_to_all(&self.s3_clients, &obj.key, |client, _| client.copy_object(&obj.key, &dest_key))
+                .await;
+            match copied {
+                Ok(()) => {
+                    self.transferred_bytes.fetch_add(obj.size, Ordering::Relaxed);
+                }
+                Err(e) => self.handle_file_error(&obj.key, e).await?,
+            }
+            
+            {
+                let mut progress = self.progress.write().await;
+                progress.completed_files = (idx + 1) as u64;
+            }
+        }
+        
+        self.finish(direction).await;
+        self.invalidate_storage_stats().await;
+        
+        Ok(())
+    }
+
+    /// Get cloud folder structure for browsing
+    ///
+    /// Lists the contents of every folder to size it; `list_cloud_folders_page`
+    /// scales better to many folders.
+    ///
+    /// Files uploaded in the last 30 seconds are included even if the bucket
+    /// doesn't list them yet.
+    pub async fn list_cloud_folders(&self) -> Result<Vec<CloudFolder>, SyncError> {
+        let folders = self.primary()
+            .list_folders("")
+            .await
+            .map_err(|e| SyncError::S3Error(e.to_string()))?;
+        let folders = self.with_recent_folders(folders);
+        
+        let mut result = Vec::new();
+        for folder in folders {
+            // Get total size of folder
+            let objects = self.primary()
+                .list_objects(&folder)
+                .await
+                .map_err(|e| SyncError::S3Error(e.to_string()))?;
+            let objects = self.with_recent_uploads(&folder, objects);
+            
+            let total_size: u64 = objects.iter().map(|o| o.size).sum();
+            let file_count = objects.len();
+            
+            result.push(CloudFolder {
+                name: folder.trim_end_matches('/').to_string(),
+                path: folder,
+                total_size,
+                file_count,
+                children: Vec::new(),
+                size_loaded: true,
+            });
+        }
+        
+        Ok(result)
+    }
+
+    /// Page `page` (from 0) of the top-level cloud folders, `page_size` at a time
+    ///
+    /// Folder names are listed up to 1000 per request; sizes are left for
+    /// `load_folder_size`, so no folder's contents are listed.
+    pub async fn list_cloud_folders_page(&self, page: usize, page_size: usize) -> Result<CloudFolderPage, SyncError> {
+        let folders = self.primary()
+            .list_folders("")
+            .await
+            .map_err(|e| SyncError::S3Error(e.to_string()))?;
+        Ok(CloudFolderPage::of(self.with_recent_folders(folders), page, page_size))
+    }
+
+    /// Size and file count of the cloud folder at `folder`
+    pub async fn load_folder_size(&self, folder: &str) -> Result<CloudFolder, SyncError> {
+        let usage = self.primary()
+            .get_bucket_usage(folder)
+            .await
+            .map_err(|e| SyncError::S3Error(e.to_string()))?;
+        Ok(CloudFolder {
+            name: folder_name(folder),
+            path: folder.to_string(),
+            total_size: usage.total_bytes,
+            file_count: usage.object_count as usize,
+            children: Vec::new(),
+            size_loaded: true,
+        })
+    }
+
+    /// Every folder below `root` in the primary bucket, nested
+    pub async fn list_cloud_folder_tree(&self, root: &str) -> Result<Vec<CloudFolder>, SyncError> {
+        self.primary()
+            .list_folder_tree(root)
+            .await
+            .map_err(|e| SyncError::S3Error(e.to_string()))
+    }
+
+    /// Delete every object in the user's folder, on the backup destinations too.
+    /// Returns how many objects were deleted from the primary.
+    pub async fn delete_all_objects(&self) -> Result<usize, SyncError> {
+        self.ensure_writable().await?;
+        let deleted = self
+            .write_to_all(&self.s3_clients, "all files", |client, _| client.delete_all_objects())
+            .await;
+        self.invalidate_listing_cache().await;
+        deleted
+    }
+
+    /// Delete temp objects left behind by atomic uploads that never finished. Does
+    /// nothing unless `atomic_uploads` is on, or in read-only mode.
+    pub async fn cleanup_stale_uploads(&self) -> Result<usize, SyncError> {
+        if !self.config.atomic_uploads || self.config.read_only {
+            return Ok(0);
+        }
+        self.ensure_writable().await?;
+        self.primary()
+            .cleanup_stale_uploads()
+            .await
+            .map_err(|e| SyncError::S3Error(e.to_string()))
+    }
+
+    /// List the files in a cloud folder in the given order
+    pub async fn list_cloud_files(
+        &self,
+        folder: &str,
+        order: SortOrder,
+        dir: SortDir,
+    ) -> Result<Vec<S3Object>, SyncError> {
+        self.primary()
+            .list_objects_sorted(folder, order, dir)
+            .await
+            .map_err(|e| SyncError::S3Error(e.to_string()))
+    }
+
+    /// Get the user's total cloud usage, reusing a result from the last few minutes
+    pub async fn get_storage_stats(&self) -> Result<StorageStats, SyncError> {
+        if let Some((stats, fetched_at)) = self.storage_stats.read().await.as_ref() {
+            if self.elapsed(*fetched_at) < STORAGE_STATS_TTL {
+                return Ok(stats.clone());
+            }
+        }
+
+        let usage = self.primary()
+            .get_bucket_usage("")
+            .await
+            .map_err(|e| SyncError::S3Error(e.to_string()))?;
+        let folders = self.primary()
+            .list_folders("")
+            .await
+            .map_err(|e| SyncError::S3Error(e.to_string()))?;
+
+        let stats = StorageStats {
+            used_bytes: usage.total_bytes,
+            file_count: usage.object_count,
+            folder_count: folders.len(),
+            largest_file: usage.largest_object,
+        };
+        *self.storage_stats.write().await = Some((stats.clone(), self.now()));
+        Ok(stats)
+    }
+
+    /// Drop the cached storage stats after the cloud contents change
+    pub async fn invalidate_storage_stats(&self) {
+        *self.storage_stats.write().await = None;
+    }
+}
+
+/// One page of the top-level cloud folders, without sizes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CloudFolderPage {
+    pub folders: Vec<CloudFolder>,
+    /// Folders across all pages
+    pub total: usize,
+    pub has_more: bool,
+}
+
+impl CloudFolderPage {
+    /// Page `page` of `paths`, `page_size` at a time
+    fn of(paths: Vec<String>, page: usize, page_size: usize) -> Self {
+        let total = paths.len();
+        let start = page.saturating_mul(page_size).min(total);
+        let end = start.saturating_add(page_size).min(total);
+        let folders = paths[start..end]
+            .iter()
+            .map(|path| CloudFolder {
+                name: folder_name(path),
+                path: path.clone(),
+                total_size: 0,
+                file_count: 0,
+                children: Vec::new(),
+                size_loaded: false,
+            })
+            .collect();
+        Self { folders, total, has_more: end < total }
+    }
+}
+
+/// Display name of the folder at `path`: its last component
+fn folder_name(path: &str) -> String {
+    path.trim_end_matches('/').rsplit('/').next().unwrap_or_default().to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageStats {
+    pub used_bytes: u64,
+    pub file_count: u64,
+    pub folder_count: usize,
+    pub largest_file: Option<S3Object>,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, size: u64) -> FileEntry {
+        FileEntry { path: path.to_string(), size, is_dir: false, content_hash: None }
+    }
+
+    #[test]
+    fn test_folder_progress_two_folders() {
+        let files = vec![
+            entry("Photos/a.jpg", 1000),
+            entry("Photos/2024/b.jpg", 3000),
+            entry("Photos/2024/c.jpg", 500),
+            entry("Docs/report.pdf", 200),
+        ];
+
+        let mut folders = folder_totals(&files);
+        assert_eq!(folders.len(), 2);
+        assert_eq!(folders["Photos"].total_files, 3);
+        assert_eq!(folders["Photos"].total_bytes, 4500);
+        assert_eq!(folders["Docs"].total_files, 1);
+        assert_eq!(folders["Docs"].total_bytes, 200);
+
+        mark_file_done(&mut folders, &files[0].path, files[0].size, false);
+        mark_file_done(&mut folders, &files[1].path, files[1].size, true);
+        mark_file_done(&mut folders, &files[3].path, files[3].size, false);
+
+        let photos = &folders["Photos"];
+        assert_eq!(photos.completed_files, 2);
+        assert_eq!(photos.transferred_bytes, 1000);
+        assert_eq!(photos.total_bytes, 1500);
+
+        let docs = &folders["Docs"];
+        assert_eq!(docs.completed_files, docs.total_files);
+        assert_eq!(docs.transferred_bytes, docs.total_bytes);
+    }
+
+    #[test]
+    fn test_skipped_failures_complete_with_errors() {
+        let failed = vec![
+            FailedFile { path: "Docs/a.pdf".to_string(), error: "timeout".to_string() },
+            FailedFile { path: "Docs/b.pdf".to_string(), error: "timeout".to_string() },
+        ];
+
+        let (status, summary) = completion_status(&failed, &SyncDirection::LocalToCloud);
+        assert_eq!(status, SyncStatus::CompletedWithErrors { failed_count: 2 });
+        assert_eq!(
+            summary.as_deref(),
+            Some("2 files failed to upload (see failed_files for details)")
+        );
+        assert_eq!(
+            serde_json::to_value(&status).unwrap(),
+            serde_json::json!({ "CompletedWithErrors": { "failed_count": 2 } })
+        );
+
+        let (status, summary) = completion_status(&[], &SyncDirection::CloudToLocal);
+        assert_eq!(status, SyncStatus::Completed);
+        assert_eq!(summary, None);
+    }
+
+    #[test]
+    fn test_copy_destination() {
+        assert_eq!(copy_destination("Photos/2024/a.jpg", "Photos", "Archive"), "Archive/2024/a.jpg");
+        assert_eq!(copy_destination("Photos/a.jpg", "Photos/", "Backup/Photos/"), "Backup/Photos/a.jpg");
+
+        let direction = SyncDirection::CloudToCloud {
+            source_folder: "Photos".to_string(),
+            dest_folder: "Archive".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_value(&direction).unwrap(),
+            serde_json::json!({ "CloudToCloud": { "source_folder": "Photos", "dest_folder": "Archive" } })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_multi_dest_keys() {
+        let prefixes = vec!["backups/daily".to_string(), "backups/weekly".to_string()];
+        assert_eq!(
+            destination_keys(&prefixes, "Photos/a.jpg"),
+            ["backups/daily/Photos/a.jpg", "backups/weekly/Photos/a.jpg"]
+        );
+
+        // Nothing is scanned without somewhere to upload to
+        let engine = hashing_engine();
+        let empty = ["/".to_string(), String::new()];
+        let result = engine.sync_to_cloud_multi_dest(&[file_tree("multi_dest", 2)], &empty).await;
+        assert!(matches!(result, Err(SyncError::S3Error(_))));
+        assert!(matches!(engine.get_progress().await.status, SyncStatus::Idle));
+    }
+
+    #[tokio::test]
+    async fn test_multi_dest_reaches_every_prefix_on_every_destination() {
+        let base = file_tree("multi_dest_mock", 2);
+        let (primary, primary_requests) = mock_s3().await;
+        let (backup, backup_requests) = mock_s3().await;
+        let engine = SyncEngine::new_with_config(primary, SyncConfig::default()).with_secondary(backup);
+
+        let prefixes = ["daily".to_string(), "weekly".to_string()];
+        engine
+            .sync_to_cloud_multi_dest(std::slice::from_ref(&base), &prefixes)
+            .await
+            .unwrap();
+        let progress = engine.get_progress().await;
+        assert!(matches!(progress.status, SyncStatus::Completed));
+        assert_eq!((progress.completed_files, progress.total_files), (4, 4));
+
+        // Uploads and server-side copies are both PUTs
+        for requests in [primary_requests, backup_requests] {
+            let mut keys: Vec<String> = requests
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|request| request.strip_prefix("PUT /mock/").map(str::to_string))
+                .collect();
+            keys.sort();
+            assert_eq!(
+                keys,
+                [
+                    "daily/multi_dest_mock/file_0000.txt",
+                    "daily/multi_dest_mock/file_0001.txt",
+                    "weekly/multi_dest_mock/file_0000.txt",
+                    "weekly/multi_dest_mock/file_0001.txt",
+                ]
+            );
+        }
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_upload_stops_at_storage_quota() {
+        const GIB: u64 = 1024 * 1024 * 1024;
+        // The user's folder is 30 bytes short of their 1 GB quota
+        let (client, requests) = mock_s3_with(|request| {
+            if request != "GET /mock" {
+                return String::new();
+            }
+            format!(
+                "<ListBucketResult><Name>mock</Name><IsTruncated>false</IsTruncated><Contents><Key>old.bin</Key>\
+                 <LastModified>2024-01-01T00:00:00.000Z</LastModified><ETag>\"0\"</ETag><Size>{}</Size></Contents>\
+                 </ListBucketResult>",
+                GIB - 30
+            )
+        })
+        .await;
+        let mut user = KeyPayload::new("Quota User");
+        user.max_storage_gb = Some(1);
+        let config = SyncConfig { concurrency: 1, ..Default::default() };
+        let engine = SyncEngine::new_with_config(client, config).with_user(user);
+
+        // Each file is 18 bytes, so only the first fits
+        let base = file_tree("quota", 3);
+        let result = engine.sync_to_cloud(std::slice::from_ref(&base), Vec::new()).await;
+        assert!(
+            matches!(result, Err(SyncError::QuotaExceeded { used, limit: GIB }) if used == GIB + 6),
+            "{:?}",
+            result
+        );
+        let puts = requests.lock().unwrap().iter().filter(|request| request.starts_with("PUT ")).count();
+        assert_eq!(puts, 1);
+        assert!(matches!(engine.get_progress().await.status, SyncStatus::Error(_)));
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_deleted_file_leaves_total_once() {
+        let engine = hashing_engine();
+        engine.progress.write().await.total_bytes = 15;
+
+        let keys = ["daily/a.txt".to_string(), "weekly/a.txt".to_string()];
+        engine.skip_deleted_copies(&entry("a.txt", 10), &keys).await;
+        let progress = engine.get_progress().await;
+        assert_eq!(progress.total_bytes, 5);
+        assert_eq!(progress.skipped_files, 2);
+    }
+
+    #[test]
+    fn test_transfer_timing_percentiles() {
+        assert_eq!(percentile_ms(&[], 95.0), 0);
+        assert_eq!(percentile_ms(&[7], 95.0), 7);
+
+        // Nearest rank: the 95th of 100 values, the 19th of 20
+        let durations: Vec<u64> = (1..=100).collect();
+        assert_eq!(percentile_ms(&durations, 95.0), 95);
+        assert_eq!(percentile_ms(&durations[..20], 95.0), 19);
+        assert_eq!(percentile_ms(&durations[..10], 95.0), 10);
+
+        let records: Vec<_> = [40, 10, 250, 20]
+            .iter()
+            .enumerate()
+            .map(|(i, &duration_ms)| FileTransferRecord {
+                path: format!("file{}.bin", i),
+                size: 1,
+                duration_ms,
+                direction: SyncDirection::LocalToCloud,
+            })
+            .collect();
+        assert_eq!(transfer_timings(&records), (250, 80, Some("file2.bin".to_string())));
+        assert_eq!(transfer_timings(&[]), (0, 0, None));
+    }
+
+    #[test]
+    fn test_transfer_records_bounded() {
+        let engine = hashing_engine();
+        let started = Instant::now();
+        for i in 0..MAX_TRANSFER_RECORDS + 5 {
+            engine.record_transfer_time(&format!("f{}", i), 1, started, SyncDirection::CloudToLocal);
+        }
+        let records = engine.get_transfer_records();
+        assert_eq!(records.len(), MAX_TRANSFER_RECORDS);
+        assert_eq!(records[0].path, "f5");
+
+        // A reconfigured engine keeps the history
+        let engine = engine.reconfigured(SyncConfig::default());
+        assert_eq!(engine.get_transfer_records().len(), MAX_TRANSFER_RECORDS);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_waits_for_retry_after() {
+        let calls = std::sync::atomic::AtomicU32::new(0);
+        let notified = std::sync::Mutex::new(Vec::new());
+
+        let started = Instant::now();
+        let result = retry_rate_limited(3, |secs| notified.lock().unwrap().push(secs), || {
+            // First response is a 429 with Retry-After: 1, then success
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if call == 0 {
+                    Err(S3Error::RateLimited { retry_after_secs: 1 })
+                } else {
+                    Ok(())
+                }
+            }
+        })
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(*notified.lock().unwrap(), vec![1]);
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_secs(1), "retried after {:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1600), "retried after {:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_gives_up_after_max_retries() {
+        let result: Result<(), S3Error> = retry_rate_limited(0, |_| {}, || async {
+            Err(S3Error::RateLimited { retry_after_secs: 30 })
+        })
+        .await;
+        assert!(matches!(result, Err(S3Error::RateLimited { retry_after_secs: 30 })));
+    }
+
+    #[tokio::test]
+    async fn test_progress_refresh_updates_speed_in_place() {
+        let handles = ProgressHandles::default();
+        {
+            let mut progress = handles.progress.write().await;
+            progress.total_bytes = 1000;
+            progress.started_at = Some(Instant::now() - Duration::from_secs(2));
+        }
+        handles.transferred_bytes.store(500, Ordering::Relaxed);
+
+        handles.refresh().await;
+
+        let progress = handles.progress.read().await;
+        assert_eq!(progress.transferred_bytes, 500);
+        assert!(progress.bytes_per_second > 0.0);
+        assert!(progress.eta_seconds.is_some());
+        assert_eq!(handles.last_snapshot.lock().unwrap().transferred_bytes, 500);
+    }
+
+    #[tokio::test]
+    async fn test_speed_measured_over_recent_window() {
+        let handles = ProgressHandles::default();
+        let now = Instant::now();
+        {
+            let mut progress = handles.progress.write().await;
+            progress.started_at = Some(now - Duration::from_secs(20));
+        }
+        // 1000 bytes in the first 16s, then 4000 in the last 4s
+        handles
+            .speed_samples
+            .lock()
+            .unwrap()
+            .extend([(now - Duration::from_secs(10), 500), (now - Duration::from_secs(4), 1000)]);
+        handles.transferred_bytes.store(5000, Ordering::Relaxed);
+
+        handles.refresh().await;
+
+        // The sample older than the window is dropped
+        let speed = handles.progress.read().await.bytes_per_second;
+        assert!((900.0..1100.0).contains(&speed), "speed {}", speed);
+        assert_eq!(handles.speed_samples.lock().unwrap().front().map(|&(_, bytes)| bytes), Some(1000));
+    }
+
+    #[tokio::test]
+    async fn test_eta_follows_mock_clock() {
+        use crate::time_source::MockTimeSource;
+
+        let clock = MockTimeSource::new();
+        let client = crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap();
+        let engine = SyncEngine::new_with_time_source(client, SyncConfig::default(), Arc::new(clock.clone()));
+        {
+            let mut progress = engine.progress.write().await;
+            progress.started_at = Some(clock.now_instant());
+            progress.total_bytes = 1000;
+        }
+
+        // 100 bytes in 10s leaves 900 at 10 B/s
+        clock.advance(Duration::from_secs(10));
+        engine.transferred_bytes.store(100, Ordering::Relaxed);
+        let progress = engine.get_progress().await;
+        assert_eq!(progress.bytes_per_second, 10.0);
+        assert_eq!(progress.eta_seconds, Some(90));
+
+        // Only the last window counts: 200 bytes in 4s leaves 700 at 50 B/s
+        clock.advance(Duration::from_secs(4));
+        engine.transferred_bytes.store(300, Ordering::Relaxed);
+        let progress = engine.get_progress().await;
+        assert_eq!(progress.bytes_per_second, 50.0);
+        assert_eq!(progress.eta_seconds, Some(14));
+
+        // Done, so nothing is left to wait for
+        clock.advance(Duration::from_secs(1));
+        engine.transferred_bytes.store(1000, Ordering::Relaxed);
+        assert_eq!(engine.get_progress().await.eta_seconds, None);
+    }
+
+    #[tokio::test]
+    async fn test_dates_follow_mock_clock() {
+        use crate::time_source::MockTimeSource;
+        use chrono::TimeZone;
+
+        let clock = MockTimeSource::new();
+        let start = Local.with_ymd_and_hms(2024, 1, 5, 7, 8, 9).unwrap();
+        *clock.current_system.write().unwrap() = start.into();
+        let base = file_tree("clock_template_src", 1);
+        let config = SyncConfig {
+            remote_path_template: Some("backups/{date}/{folder_name}".to_string()),
+            ..Default::default()
+        };
+        let client = crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap();
+        let engine = SyncEngine::new_with_time_source(client, config, Arc::new(clock.clone()));
+
+        let entries = engine.scan_local_folders(std::slice::from_ref(&base)).await.unwrap();
+        assert_eq!(entries[0].path, "backups/2024-01-05/clock_template_src/file_0000.txt");
+        clock.advance(Duration::from_secs(60));
+        let stats = engine.new_session_stats();
+        assert_eq!(stats.session_start, start.with_timezone(&Utc) + chrono::Duration::seconds(60));
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_progress_reports_speed_and_eta() {
+        let engine = SyncEngine::new(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap());
+        engine.report(ProgressUpdate::CurrentFile { path: "big.bin".to_string() }).await;
+        let on_progress = engine.file_progress("big.bin", 1000);
+        on_progress(ProgressEvent::new(250, 1000, Duration::from_secs(1)));
+
+        let progress = engine.get_progress().await;
+        assert_eq!(progress.current_file_bytes_transferred, 250);
+        assert_eq!(progress.current_file_bps, 250.0);
+        assert_eq!(progress.current_file_eta_secs, Some(3));
+
+        // A retry starting over doesn't count the first attempt's bytes twice
+        on_progress(ProgressEvent::new(600, 1000, Duration::from_secs(2)));
+        on_progress.clone()(ProgressEvent::new(100, 1000, Duration::from_secs(1)));
+        on_progress(ProgressEvent::new(1000, 1000, Duration::from_secs(3)));
+        assert_eq!(engine.transferred_bytes.load(Ordering::Relaxed), 1000);
+
+        // The next file starts without an estimate
+        engine.report(ProgressUpdate::CurrentFile { path: "next.bin".to_string() }).await;
+        let _next = engine.file_progress("next.bin", 10);
+        let progress = engine.get_progress().await;
+        assert_eq!((progress.current_file_bps, progress.current_file_eta_secs), (0.0, None));
+    }
+
+    #[tokio::test]
+    async fn test_file_progress_tracked_per_transfer() {
+        let engine = SyncEngine::new(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap());
+        let fast = engine.file_progress("fast.bin", 1000);
+        let slow = engine.file_progress("slow.bin", 1000);
+        engine.report(ProgressUpdate::CurrentFile { path: "slow.bin".to_string() }).await;
+
+        // Reports interleave; each file keeps its own speed and ETA
+        fast(ProgressEvent::new(500, 1000, Duration::from_secs(1)));
+        slow(ProgressEvent::new(100, 1000, Duration::from_secs(1)));
+        fast(ProgressEvent::new(900, 1000, Duration::from_millis(1500)));
+        let progress = engine.get_progress().await;
+        assert_eq!((progress.current_file_bps, progress.current_file_eta_secs), (100.0, Some(9)));
+        let files: Vec<(&str, f64, Option<u64>)> = progress
+            .active_file_progress
+            .iter()
+            .map(|file| (file.path.as_str(), file.bytes_per_second, file.eta_secs))
+            .collect();
+        assert_eq!(files, [("fast.bin", 600.0, Some(1)), ("slow.bin", 100.0, Some(9))]);
+
+        // A finished transfer drops out once its callback is gone
+        drop(fast);
+        let progress = engine.get_progress().await;
+        assert_eq!(progress.active_file_progress.len(), 1);
+        assert_eq!(progress.active_file_progress[0].path, "slow.bin");
+        drop(slow);
+        assert!(engine.get_progress().await.active_file_progress.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_download_progress_advances_per_chunk() {
+        use crate::s3_client::{copy_with_progress, S3ClientBuilder, DOWNLOAD_CHUNK_SIZE};
+
+        const SIZE: usize = 20 * 1024 * 1024;
+        let engine = SyncEngine::new(S3ClientBuilder::new().unscoped().build().unwrap());
+        let object = tokio::io::BufReader::with_capacity(DOWNLOAD_CHUNK_SIZE, std::io::Cursor::new(vec![1u8; SIZE]));
+
+        // Watch transferred_bytes from the callback, as the progress poller would see it
+        let seen = std::sync::Mutex::new(Vec::new());
+        let on_progress = {
+            let progress = engine.download_progress("object.bin", SIZE as u64);
+            let transferred_bytes = Arc::clone(&engine.transferred_bytes);
+            let seen = &seen;
+            move |received, total| {
+                progress(received, total);
+                seen.lock().unwrap().push(transferred_bytes.load(Ordering::Relaxed));
+            }
+        };
+        copy_with_progress(object, &mut tokio::io::sink(), SIZE as u64, &on_progress)
+            .await
+            .unwrap();
+
+        let seen = seen.into_inner().unwrap();
+        assert!(seen.len() >= 10, "only {} updates", seen.len());
+        assert!(seen.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(engine.transferred_bytes.load(Ordering::Relaxed), SIZE as u64);
+
+        let progress = engine.get_progress().await;
+        assert_eq!(progress.current_file_bytes_transferred, SIZE as u64);
+        assert_eq!(progress.current_file_bytes_received, SIZE as u64);
+        assert_eq!(progress.current_file_bytes_total, SIZE as u64);
+    }
+
+    #[tokio::test]
+    async fn test_read_now_falls_back_to_snapshot_while_locked() {
+        let handles = ProgressHandles::default();
+        handles.progress.write().await.total_files = 7;
+        handles.refresh().await;
+
+        // A sync task holding the write lock must not block the poller
+        let mut progress = handles.progress.write().await;
+        progress.total_files = 8;
+        assert_eq!(handles.read_now().total_files, 7);
+        drop(progress);
+
+        assert_eq!(handles.read_now().total_files, 8);
+    }
+
+    /// Polling latency of `read` vs `read_now` while a writer keeps the
+    /// progress lock busy. Run with
+    /// `cargo test --release bench_progress_reads -- --ignored --nocapture`
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    #[ignore]
+    async fn bench_progress_reads() {
+        const POLLS: u32 = 2000;
+        let handles = ProgressHandles::default();
+        handles.refresh().await;
+
+        let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let writer = tokio::spawn({
+            let handles = handles.clone();
+            let stop = Arc::clone(&stop);
+            async move {
+                while !stop.load(Ordering::Relaxed) {
+                    let mut progress = handles.progress.write().await;
+                    progress.completed_files += 1;
+                    std::thread::sleep(Duration::from_micros(200));
+                    drop(progress);
+                    tokio::task::yield_now().await;
+                }
+            }
+        });
+
+        // Let the writer get going before measuring
+        while handles.read_now().completed_files == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let mut async_worst = Duration::ZERO;
+        let started = Instant::now();
+        for _ in 0..POLLS {
+            let poll = Instant::now();
+            handles.read().await;
+            async_worst = async_worst.max(poll.elapsed());
+        }
+        let async_total = started.elapsed();
+
+        let mut sync_worst = Duration::ZERO;
+        let started = Instant::now();
+        for _ in 0..POLLS {
+            let poll = Instant::now();
+            handles.read_now();
+            sync_worst = sync_worst.max(poll.elapsed());
+        }
+        let sync_total = started.elapsed();
+
+        stop.store(true, Ordering::Relaxed);
+        writer.await.unwrap();
+
+        println!(
+            "async read: {:?}/poll (worst {:?}); sync read: {:?}/poll (worst {:?})",
+            async_total / POLLS,
+            async_worst,
+            sync_total / POLLS,
+            sync_worst,
+        );
+        assert!(sync_worst < async_worst);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_progress_aggregator_applies_every_update() {
+        use crate::s3_client::S3ClientBuilder;
+
+        let engine = SyncEngine::new(S3ClientBuilder::new().unscoped().build().unwrap());
+        let files: Vec<FileEntry> = (0..40).map(|i| entry(&format!("Folder{}/f{}.bin", i % 2, i), 10)).collect();
+        *engine.folder_progress.write().await = folder_totals(&files);
+        engine.progress.write().await.total_bytes = 400;
+
+        // Every fourth file is skipped, every fourth fails and the rest upload
+        async fn report_file(engine: &SyncEngine, i: usize, file: &FileEntry) {
+            engine.report(ProgressUpdate::CurrentFile { path: file.path.clone() }).await;
+            let (path, bytes) = (file.path.clone(), file.size);
+            let update = match i % 4 {
+                0 => ProgressUpdate::FileSkipped { path, bytes, reason: None },
+                1 => {
+                    let error = "boom".to_string();
+                    engine.report(ProgressUpdate::FileFailed { path: path.clone(), error }).await;
+                    ProgressUpdate::FileCompleted { path, bytes, transferred: false }
+                }
+                _ => ProgressUpdate::FileCompleted { path, bytes, transferred: true },
+            };
+            engine.report(update).await;
+        }
+
+        let aggregator = ProgressAggregator::start(Arc::clone(&engine.progress_updates), &engine.progress_handles());
+        futures::future::join_all(files.iter().enumerate().map(|(i, file)| report_file(&engine, i, file))).await;
+        aggregator.finish().await;
+
+        let progress = engine.get_progress().await;
+        assert_eq!(progress.completed_files, 40);
+        assert_eq!(progress.skipped_files, 10);
+        assert_eq!(progress.failed_files.len(), 10);
+        assert_eq!(progress.total_bytes, 300);
+        let transferred: u64 = progress.folder_progress.iter().map(|f| f.transferred_bytes).sum();
+        assert_eq!(transferred, 200);
+
+        // With the aggregator gone, updates are applied directly
+        engine.report(ProgressUpdate::StatusChange(SyncStatus::Completed)).await;
+        assert_eq!(engine.get_progress().await.status, SyncStatus::Completed);
+    }
+
+    /// Time 8 concurrent uploads spend reporting progress, through the aggregator and by
+    /// each task taking the lock itself, as a share of the time spent uploading.
+    /// `cargo test --release bench_progress_updates -- --ignored --nocapture`
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    #[ignore]
+    async fn bench_progress_updates() {
+        const TASKS: usize = 8;
+        const FILES: usize = 200;
+        const UPLOAD_TIME: Duration = Duration::from_micros(500);
+
+        // Simulated file uploads, each followed by `report`; returns the total time spent reporting
+        async fn run<F, Fut>(report: F) -> Duration
+        where
+            F: Fn(usize) -> Fut + Sync,
+            Fut: Future<Output = ()>,
+        {
+            let report = &report;
+            let uploads = (0..TASKS).map(|task| async move {
+                let mut reporting = Duration::ZERO;
+                for file in 0..FILES {
+                    tokio::time::sleep(UPLOAD_TIME).await;
+                    let started = Instant::now();
+                    report(task * FILES + file).await;
+                    reporting += started.elapsed();
+                }
+                reporting
+            });
+            futures::future::join_all(uploads).await.into_iter().sum()
+        }
+
+        let handles = ProgressHandles::default();
+        // Pollers hold the progress lock now and then, as during a real sync
+        let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let poller = tokio::spawn({
+            let (handles, stop) = (handles.clone(), Arc::clone(&stop));
+            async move {
+                while !stop.load(Ordering::Relaxed) {
+                    let progress = handles.progress.write().await;
+                    std::thread::sleep(Duration::from_micros(200));
+                    drop(progress);
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+            }
+        });
+        let update = |n: usize| ProgressUpdate::FileCompleted { path: format!("f{}", n), bytes: 1, transferred: true };
+
+        let locked = run(|n| {
+            let handles = &handles;
+            async move {
+                let mut progress = handles.progress.write().await;
+                let mut folders = handles.folder_progress.write().await;
+                apply_progress_update(&mut progress, &mut folders, update(n));
+            }
+        })
+        .await;
+
+        let sender: ProgressSender = Default::default();
+        let aggregator = ProgressAggregator::start(Arc::clone(&sender), &handles);
+        let aggregated = run(|n| {
+            if let Some(tx) = sender.lock().unwrap().as_ref() {
+                let _ = tx.send(update(n));
+            }
+            async {}
+        })
+        .await;
+        aggregator.finish().await;
+
+        stop.store(true, Ordering::Relaxed);
+        poller.await.unwrap();
+        assert_eq!(handles.progress.read().await.completed_files, (2 * TASKS * FILES) as u64);
+
+        let uploading = UPLOAD_TIME * (TASKS * FILES) as u32;
+        let overhead = |reporting: Duration| reporting.as_secs_f64() / uploading.as_secs_f64() * 100.0;
+        println!(
+            "reporting overhead: per-task locking {:?} ({:.2}%), aggregator {:?} ({:.2}%)",
+            locked,
+            overhead(locked),
+            aggregated,
+            overhead(aggregated),
+        );
+        assert!(overhead(aggregated) < 1.0);
+        assert!(aggregated < locked);
+    }
+
+    #[test]
+    fn test_sync_state_cancel_wins_over_pause_and_resume() {
+        let state = SyncStateCell::default();
+        state.set_paused();
+        assert_eq!(state.get(), SyncState::Paused);
+        state.set_cancelled();
+        state.set_running();
+        state.set_paused();
+        assert!(state.is_cancelled());
+
+        state.reset();
+        assert_eq!(state.get(), SyncState::Running);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_cancel_while_paused_returns_cancelled() {
+        let state = Arc::new(SyncStateCell::default());
+        state.set_paused();
+
+        let waiter = tokio::spawn({
+            let state = Arc::clone(&state);
+            async move { wait_while_paused(&state).await }
+        });
+
+        // Cancel from a plain OS thread while the waiter is sleeping
+        let canceller = std::thread::spawn({
+            let state = Arc::clone(&state);
+            move || {
+                std::thread::sleep(Duration::from_millis(250));
+                state.set_cancelled();
+            }
+        });
+        canceller.join().unwrap();
+
+        let result = tokio::time::timeout(Duration::from_secs(2), waiter).await.unwrap().unwrap();
+        assert!(matches!(result, Err(SyncError::Cancelled)));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_mid_download_removes_partial_file() {
+        use crate::s3_client::S3ClientBuilder;
+
+        let engine = SyncEngine::new(S3ClientBuilder::new().unscoped().build().unwrap());
+        let target = std::env::temp_dir().join(format!("sync2bucket-cancel-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&target);
+        std::fs::create_dir_all(&target).unwrap();
+        let local_path = target.join("big.bin");
+
+        // A download that writes half the file, then stalls
+        *engine.current_local_path.write().await = Some(local_path.clone());
+        let download = async {
+            tokio::fs::write(&local_path, vec![0u8; 1024]).await.unwrap();
+            std::future::pending::<()>().await
+        };
+        let state = Arc::clone(&engine.state);
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            state.set_cancelled();
+        });
+
+        let result = tokio::time::timeout(Duration::from_secs(2), engine.unless_cancelled(download))
+            .await
+            .unwrap();
+        assert!(matches!(result, Err(SyncError::Cancelled)));
+        assert_eq!(std::fs::read_dir(&target).unwrap().count(), 0);
+        assert!(engine.current_local_path.read().await.is_none());
+        assert_eq!(engine.progress.read().await.status, SyncStatus::Idle);
+
+        std::fs::remove_dir_all(target).unwrap();
+    }
+
+    /// Client for a local stand-in for S3 that answers every request with an empty
+    /// 200, and the requests it got as "METHOD /bucket/key"
+    async fn mock_s3() -> (S3Client, Arc<std::sync::Mutex<Vec<String>>>) {
+        mock_s3_with(|_| String::new()).await
+    }
+
+    /// Like `mock_s3`, answering each request with the body `respond` gives for its
+    /// "METHOD /bucket/key"
+    async fn mock_s3_with(
+        respond: impl Fn(&str) -> String + Send + Sync + 'static,
+    ) -> (S3Client, Arc<std::sync::Mutex<Vec<String>>>) {
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+        let respond = Arc::new(respond);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let seen = Arc::clone(&seen);
+                let respond = Arc::clone(&respond);
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    let mut line = String::new();
+                    // One request after another until the client hangs up
+                    while stream.read_line(&mut line).await.unwrap_or(0) > 0 {
+                        let request: Vec<&str> = line.split_whitespace().take(2).collect();
+                        let request = request.join(" ");
+                        let request = request.split('?').next().unwrap_or_default().to_string();
+                        let mut content_length = 0;
+                        loop {
+                            let mut header = String::new();
+                            if stream.read_line(&mut header).await.unwrap_or(0) == 0 {
+                                return;
+                            }
+                            let header = header.trim_end();
+                            if header.is_empty() {
+                                break;
+                            }
+                            if let Some((name, value)) = header.split_once(':') {
+                                if name.eq_ignore_ascii_case("content-length") {
+                                    content_length = value.trim().parse().unwrap_or(0);
+                                }
+                            }
+                        }
+                        let mut body = vec![0; content_length];
+                        if stream.read_exact(&mut body).await.is_err() {
+                            return;
+                        }
+                        let body = respond(&request);
+                        seen.lock().unwrap().push(request);
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\nETag: \"0\"\r\nContent-Length: {}\r\n\r\n{}",
+                            body.len(),
+                            body
+                        );
+                        if stream.get_mut().write_all(response.as_bytes()).await.is_err() {
+                            return;
+                        }
+                        line.clear();
+                    }
+                });
+            }
+        });
+
+        let client = crate::s3_client::S3ClientBuilder::new()
+            .unscoped()
+            .destination(crate::s3_client::S3Destination {
+                endpoint,
+                bucket: "mock".to_string(),
+                access_key: "key".to_string(),
+                secret_key: "secret".to_string(),
+            })
+            .build()
+            .unwrap();
+        (client, requests)
+    }
+
+    fn hashing_engine() -> SyncEngine {
+        use crate::s3_client::S3ClientBuilder;
+
+        let config = SyncConfig { compute_hashes: true, ..Default::default() };
+        SyncEngine::new_with_config(S3ClientBuilder::new().unscoped().build().unwrap(), config)
+    }
+
+    /// Fresh folder under the temp dir named `name`, holding `count` small files
+    fn file_tree(name: &str, count: usize) -> PathBuf {
+        let base = std::env::temp_dir()
+            .join(format!("sync2bucket-scan-{}", std::process::id()))
+            .join(name);
+        let _ = std::fs::remove_dir_all(&base);
+        std::fs::create_dir_all(&base).unwrap();
+        for i in 0..count {
+            std::fs::write(base.join(format!("file_{:04}.txt", i)), format!("contents of file {}", i)).unwrap();
+        }
+        base
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_scan_reports_permission_denied() {
+        use crate::s3_client::S3ClientBuilder;
+        use std::os::unix::fs::PermissionsExt;
+
+        let base = file_tree("unreadable", 1);
+        let secret = base.join("secret.txt");
+        std::fs::write(&secret, "private").unwrap();
+        std::fs::set_permissions(&secret, std::fs::Permissions::from_mode(0o000)).unwrap();
+        let roots = std::slice::from_ref(&base);
+
+        // Root reads the file regardless of its mode
+        if std::fs::File::open(&secret).is_ok() {
+            eprintln!("running as root, skipping");
+            std::fs::remove_dir_all(&base).unwrap();
+            return;
+        }
+
+        // Listing only needs the folder, hashing opens the file
+        let listing = SyncEngine::new(S3ClientBuilder::new().unscoped().build().unwrap());
+        assert_eq!(listing.scan_local_folders(roots).await.unwrap().len(), 2);
+        let result = hashing_engine().scan_local_folders(roots).await;
+        assert!(
+            matches!(&result, Err(SyncError::PermissionDenied { path }) if path == &secret.display().to_string()),
+            "{:?}",
+            result
+        );
+
+        // An unreadable folder fails the walk
+        std::fs::set_permissions(&secret, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let locked = base.join("locked");
+        std::fs::create_dir(&locked).unwrap();
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+        let result = hashing_engine().scan_local_folders(roots).await;
+        assert!(matches!(result, Err(SyncError::PermissionDenied { .. })), "{:?}", result);
+
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_check_free_space() {
+        const GB: u64 = 1024 * 1024 * 1024;
+        let config = SyncConfig { min_free_bytes: Some(2 * GB), ..Default::default() };
+        let mut engine = SyncEngine::new_with_config(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap(), config);
+        engine.available_space = |_| Ok(10 * GB);
+        let target = std::env::temp_dir().join("sync2bucket-not-created-yet");
+
+        assert!(engine.check_free_space(&target, 8 * GB).is_ok());
+        let result = engine.check_free_space(&target, 8 * GB + 1);
+        assert!(
+            matches!(
+                &result,
+                Err(SyncError::DiskFull { path, required_bytes, available_bytes })
+                    if path == &target.display().to_string() && *required_bytes == 8 * GB + 1 && *available_bytes == 10 * GB
+            ),
+            "{:?}",
+            result
+        );
+
+        // Without a minimum the whole disk may be used
+        let engine = engine.reconfigured(SyncConfig::default());
+        assert!(engine.check_free_space(&target, 10 * GB).is_ok());
+        assert!(engine.check_free_space(&target, 10 * GB + 1).is_err());
+
+        // A failing query doesn't block the download
+        let mut engine = engine;
+        engine.available_space = |_| Err(std::io::Error::other("unsupported"));
+        assert!(engine.check_free_space(&target, u64::MAX).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_download_stops_when_disk_full() {
+        let (client, _) = mock_s3_with(|request| {
+            if request != "GET /mock" {
+                return String::new();
+            }
+            "<ListBucketResult><Name>mock</Name><IsTruncated>false</IsTruncated><Contents><Key>docs/big.bin</Key>\
+             <LastModified>2024-01-01T00:00:00.000Z</LastModified><ETag>\"0\"</ETag><Size>1000</Size></Contents>\
+             </ListBucketResult>"
+                .to_string()
+        })
+        .await;
+        let mut engine = SyncEngine::new(client);
+        engine.available_space = |_| Ok(10);
+        let target = std::env::temp_dir().join(format!("sync2bucket-disk-full-{}", std::process::id()));
+
+        let result = engine.sync_to_local("docs/", &target).await;
+        assert!(matches!(result, Err(SyncError::DiskFull { required_bytes: 1000, .. })), "{:?}", result);
+        // The engine is free for the next sync
+        assert!(!engine.is_running().await);
+        assert!(matches!(engine.get_progress().await.status, SyncStatus::Error(_)));
+        assert!(!target.exists());
+    }
+
+    #[test]
+    fn test_min_free_space_serialized_in_gb() {
+        let config: SyncConfig = serde_json::from_str(r#"{"min_free_gb": 1.5}"#).unwrap();
+        assert_eq!(config.min_free_bytes, Some(1536 * 1024 * 1024));
+        assert_eq!(serde_json::to_value(&config).unwrap()["min_free_gb"], 1.5);
+
+        let config: SyncConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config.min_free_bytes, None);
+        assert!(serde_json::to_value(&config).unwrap()["min_free_gb"].is_null());
+    }
+
+    #[test]
+    fn test_io_error_kinds() {
+        use std::io::{Error, ErrorKind};
+
+        let path = Path::new("/data/file.bin");
+        assert!(matches!(
+            io_error(path, Error::from(ErrorKind::PermissionDenied)),
+            SyncError::PermissionDenied { path } if path == "/data/file.bin"
+        ));
+        for kind in [ErrorKind::StorageFull, ErrorKind::WriteZero] {
+            assert!(matches!(io_error(path, Error::from(kind)), SyncError::DiskFull { .. }));
+        }
+        assert!(matches!(io_error(path, Error::from(ErrorKind::NotFound)), SyncError::IoError(_)));
+        assert!(matches!(
+            SyncError::from(S3Error::DiskFull { path: "a".to_string(), required_bytes: 9 }),
+            SyncError::DiskFull { required_bytes: 9, available_bytes: 0, .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_scan_computes_content_hashes() {
+        let base = file_tree("hashes", 0);
+        std::fs::write(base.join("hello.txt"), "hello").unwrap();
+        let roots = std::slice::from_ref(&base);
+        let engine = hashing_engine();
+
+        let entries = engine.scan_local_folders(roots).await.unwrap();
+        assert_eq!(
+            entries[0].content_hash.as_deref(),
+            Some("2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824")
+        );
+        assert_eq!(
+            engine.get_progress().await.status,
+            SyncStatus::Hashing { files_hashed: 1, total_files: 1 }
+        );
+
+        // Unchanged files take their hash from the cache
+        let path = base.join("hello.txt");
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        engine.sync_cache.lock().unwrap().set_content_hash(path.clone(), modified, 5, "cached".to_string());
+        let entries = engine.scan_local_folders(roots).await.unwrap();
+        assert_eq!(entries[0].content_hash.as_deref(), Some("cached"));
+
+        // A cancel while scanning or hashing ends the sync the same way as one while uploading
+        engine.cancel();
+        assert!(matches!(engine.scan_local_folders(roots).await, Err(SyncError::Cancelled)));
+        assert_eq!(engine.get_progress().await.status, SyncStatus::Idle);
+        let mut entries = vec![entry("hashes/hello.txt", 5)];
+        let hashed = engine.hash_entries(&mut entries, &[(path, modified)], true).await;
+        assert!(matches!(hashed, Err(SyncError::Cancelled)));
+
+        // Hashing is off by default
+        let plain = SyncEngine::new(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap());
+        let entries = plain.scan_local_folders(roots).await.unwrap();
+        assert_eq!(entries[0].content_hash, None);
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_scan_skips_looping_and_outside_links() {
+        use std::os::unix::fs::symlink;
+
+        let base = file_tree("links", 1);
+        std::fs::create_dir(base.join("inner")).unwrap();
+        std::fs::write(base.join("inner/shared.txt"), "shared").unwrap();
+        symlink(base.join("inner"), base.join("inner_link")).unwrap();
+        symlink(base.join("inner/shared.txt"), base.join("file_link.txt")).unwrap();
+        symlink(base.join("missing"), base.join("broken_link")).unwrap();
+        let roots = std::slice::from_ref(&base);
+        let paths = |entries: Vec<FileEntry>| {
+            let mut paths: Vec<String> = entries.into_iter().map(|entry| entry.path).collect();
+            paths.sort();
+            paths
+        };
+
+        // Linked files and folders are followed, broken links skipped
+        let engine = SyncEngine::new(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap());
+        assert_eq!(
+            paths(engine.scan_local_folders(roots).await.unwrap()),
+            [
+                "links/file_0000.txt",
+                "links/file_link.txt",
+                "links/inner/shared.txt",
+                "links/inner_link/shared.txt",
+            ]
+        );
+
+        let config = SyncConfig { follow_symlinks: false, ..Default::default() };
+        let engine = engine.reconfigured(config);
+        assert_eq!(
+            paths(engine.scan_local_folders(roots).await.unwrap()),
+            ["links/file_0000.txt", "links/inner/shared.txt"]
+        );
+
+        // Links back to a parent folder or out of the source folder are skipped and counted
+        symlink(&base, base.join("inner/parent_link")).unwrap();
+        let outside = file_tree("links_outside", 1);
+        symlink(outside.join("file_0000.txt"), base.join("outside_link.txt")).unwrap();
+        let engine = engine.reconfigured(SyncConfig::default());
+        assert_eq!(
+            paths(engine.scan_local_folders(roots).await.unwrap()),
+            [
+                "links/file_0000.txt",
+                "links/file_link.txt",
+                "links/inner/shared.txt",
+                "links/inner_link/shared.txt",
+            ]
+        );
+        let progress = engine.get_progress().await;
+        let mut skipped: Vec<(&str, &str)> =
+            progress.skipped_reasons.iter().map(|(path, reason)| (path.as_str(), reason.as_str())).collect();
+        skipped.sort();
+        assert_eq!(
+            skipped,
+            [
+                ("links/broken_link", "broken link"),
+                ("links/inner/parent_link", "link loops back to a parent folder"),
+                ("links/inner_link/parent_link", "link loops back to a parent folder"),
+                ("links/outside_link.txt", "link leads outside the source folder"),
+            ]
+        );
+        assert_eq!(progress.skipped_files, 4);
+
+        // Skipped links can't loop
+        let engine = engine.reconfigured(SyncConfig { follow_symlinks: false, ..Default::default() });
+        assert_eq!(engine.scan_local_folders(roots).await.unwrap().len(), 2);
+        std::fs::remove_dir_all(base).unwrap();
+        std::fs::remove_dir_all(outside).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_scan_skips_empty_files_when_configured() {
+        let base = file_tree("empty", 2);
+        std::fs::write(base.join("empty.txt"), "").unwrap();
+        let roots = std::slice::from_ref(&base);
+
+        // Included by default, with its zero size
+        let engine = SyncEngine::new(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap());
+        let entries = engine.scan_local_folders(roots).await.unwrap();
+        assert_eq!(entries.len(), 3);
+        assert!(entries.iter().any(|entry| entry.path == "empty/empty.txt" && entry.size == 0));
+        assert_eq!(engine.get_progress().await.skipped_files, 0);
+
+        let config = SyncConfig { skip_empty_files: true, compute_hashes: true, ..Default::default() };
+        let engine = engine.reconfigured(config);
+        let entries = engine.scan_local_folders(roots).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|entry| entry.size > 0 && entry.content_hash.is_some()));
+        assert_eq!(engine.get_progress().await.skipped_files, 1);
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn test_cloud_folder_pages() {
+        let paths: Vec<String> = (0..500).map(|i| format!("folder{:03}/", i)).collect();
+
+        let first = CloudFolderPage::of(paths.clone(), 0, 200);
+        assert_eq!((first.folders.len(), first.total, first.has_more), (200, 500, true));
+        assert_eq!(first.folders[0].name, "folder000");
+        assert_eq!(first.folders[0].path, "folder000/");
+        assert!(first.folders.iter().all(|folder| !folder.size_loaded && folder.total_size == 0));
+
+        let last = CloudFolderPage::of(paths.clone(), 2, 200);
+        assert_eq!((last.folders.len(), last.has_more), (100, false));
+        assert_eq!(last.folders[0].name, "folder400");
+        assert_eq!(last.folders[99].name, "folder499");
+
+        let past_end = CloudFolderPage::of(paths.clone(), 5, 200);
+        assert!(past_end.folders.is_empty() && !past_end.has_more);
+        let exact = CloudFolderPage::of(paths, 4, 100);
+        assert_eq!((exact.folders.len(), exact.has_more), (100, false));
+    }
+
+    #[test]
+    fn test_excludes_size() {
+        const KB: u64 = 1024;
+        const MB: u64 = 1024 * KB;
+        const GB: u64 = 1024 * MB;
+        let sizes = [0, KB, 10 * MB, 5 * GB];
+        let excluded = |config: SyncConfig| sizes.map(|size| config.excludes_size(size));
+
+        assert_eq!(excluded(SyncConfig::default()), [false; 4]);
+        assert_eq!(excluded(SyncConfig { skip_empty_files: true, ..Default::default() }), [true, false, false, false]);
+        assert_eq!(excluded(SyncConfig { exclude_min_size: Some(KB), ..Default::default() }), [true, false, false, false]);
+        assert_eq!(excluded(SyncConfig { exclude_max_size: Some(10 * MB), ..Default::default() }), [false, false, false, true]);
+        let config = SyncConfig { exclude_min_size: Some(KB + 1), exclude_max_size: Some(GB), ..Default::default() };
+        assert_eq!(excluded(config), [true, true, false, true]);
+        let config = SyncConfig { exclude_min_size: Some(0), exclude_max_size: Some(0), ..Default::default() };
+        assert_eq!(excluded(config), [false, true, true, true]);
+    }
+
+    #[tokio::test]
+    async fn test_scan_excludes_files_by_size() {
+        let base = file_tree("sizes", 0);
+        std::fs::write(base.join("small.txt"), "x").unwrap();
+        std::fs::write(base.join("medium.txt"), vec![b'x'; 2048]).unwrap();
+        std::fs::write(base.join("large.txt"), vec![b'x'; 8192]).unwrap();
+        let roots = std::slice::from_ref(&base);
+
+        let config = SyncConfig { exclude_min_size: Some(1024), exclude_max_size: Some(4096), ..Default::default() };
+        let engine = SyncEngine::new(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap()).reconfigured(config);
+        let entries = engine.scan_local_folders(roots).await.unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.path.as_str()).collect::<Vec<_>>(), ["sizes/medium.txt"]);
+        let progress = engine.get_progress().await;
+        assert_eq!(progress.skipped_files, 2);
+        assert!(progress.excluded_files.is_empty());
+
+        let config = SyncConfig {
+            exclude_min_size: Some(1024),
+            exclude_max_size: Some(4096),
+            notify_excluded: true,
+            ..Default::default()
+        };
+        let engine = engine.reconfigured(config);
+        engine.scan_local_folders(roots).await.unwrap();
+        let mut excluded = engine.get_progress().await.excluded_files;
+        excluded.sort();
+        assert_eq!(excluded, ["sizes/large.txt", "sizes/small.txt"]);
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sync_toml_overrides_folder_config() {
+        let base = file_tree("folder-config", 0);
+        std::fs::create_dir_all(base.join("small-skipped/nested")).unwrap();
+        for dir in [base.clone(), base.join("small-skipped"), base.join("small-skipped/nested")] {
+            std::fs::write(dir.join("small.txt"), "x").unwrap();
+            std::fs::write(dir.join("large.bin"), vec![b'x'; 2048]).unwrap();
+            std::fs::write(dir.join("notes.tmp"), "x").unwrap();
+        }
+        let toml = "exclude_min_size = 1024\nexclude_patterns = [\"*.tmp\"]\n\n[default_tags]\nteam = \"design\"\n";
+        std::fs::write(base.join("small-skipped").join(FOLDER_CONFIG_FILE), toml).unwrap();
+
+        let engine = SyncEngine::new(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap());
+        let folder = engine.load_folder_config(&base.join("small-skipped")).unwrap().config;
+        assert_eq!(folder.exclude_min_size, Some(1024));
+        assert_eq!(folder.default_tags["team"], "design");
+        assert_eq!(folder.default_tags["app"], "sync2bucket");
+        assert!(engine.load_folder_config(&base).is_none());
+
+        let mut paths: Vec<String> = engine
+            .scan_local_folders(std::slice::from_ref(&base))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+        paths.sort();
+        // The parent folder keeps every file; the configured folder and the one
+        // inside it drop small files and *.tmp
+        assert_eq!(
+            paths,
+            [
+                "folder-config/large.bin",
+                "folder-config/notes.tmp",
+                "folder-config/small-skipped/large.bin",
+                "folder-config/small-skipped/nested/large.bin",
+                "folder-config/small.txt",
+            ]
+        );
+
+        // Patterns match below the folder holding the file, and the file itself is never uploaded
+        let config_file = base.join("small-skipped").join(FOLDER_CONFIG_FILE);
+        std::fs::write(&config_file, "exclude_patterns = [\"nested/*.bin\"]").unwrap();
+        let entries = engine.scan_local_folders(std::slice::from_ref(&base)).await.unwrap();
+        let paths: Vec<&str> = entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths.len(), 8);
+        assert!(!paths.contains(&"folder-config/small-skipped/nested/large.bin"));
+        assert!(!paths.iter().any(|path| path.ends_with(FOLDER_CONFIG_FILE)));
+
+        // A file that doesn't parse or has an invalid pattern is ignored
+        for invalid in ["exclude_min_size = \"big\"", "exclude_patterns = [\"[unclosed\"]"] {
+            std::fs::write(&config_file, invalid).unwrap();
+            assert!(engine.load_folder_config(&base.join("small-skipped")).is_none());
+            let entries = engine.scan_local_folders(std::slice::from_ref(&base)).await.unwrap();
+            assert_eq!(entries.len(), 9);
+        }
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recent_uploads_fill_in_listings() {
+        let engine = SyncEngine::new(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap());
+        let listed = |key: &str| S3Object {
+            key: key.to_string(),
+            size: 1,
+            last_modified: 0,
+            etag: None,
+            content_type: None,
+            checksum_sha256: None,
+        };
+        engine.record_recent_upload("Photos/a.jpg", 10);
+        engine.record_recent_upload("Photos/b.jpg", 20);
+        engine.record_recent_upload("New/c.txt", 5);
+
+        // Listed before the uploads showed up: the new folder and files are added
+        let folders = engine.with_recent_folders(vec!["Photos/".to_string(), "Docs/".to_string()]);
+        assert_eq!(folders, ["Photos/", "Docs/", "New/"]);
+        let mut keys: Vec<String> = engine
+            .with_recent_uploads("Photos/", vec![listed("Photos/a.jpg"), listed("Photos/old.jpg")])
+            .into_iter()
+            .map(|object| object.key)
+            .collect();
+        keys.sort();
+        assert_eq!(keys, ["Photos/a.jpg", "Photos/b.jpg", "Photos/old.jpg"]);
+
+        // Shared with reconfigured engines; expired entries are dropped
+        let reconfigured = engine.reconfigured(SyncConfig::default());
+        reconfigured.recently_uploaded.write().unwrap().get_mut("New/c.txt").unwrap().1 =
+            Instant::now() - RECENT_UPLOAD_TTL;
+        assert_eq!(engine.with_recent_folders(Vec::new()), ["Photos/"]);
+
+        engine.invalidate_listing_cache().await;
+        assert!(reconfigured.with_recent_folders(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn test_normalize_paths() {
+        assert_eq!(normalize_remote_path("/Photos/a.jpg"), "Photos/a.jpg");
+        if cfg!(windows) {
+            assert_eq!(normalize_remote_path("Photos\\2024\\a.jpg"), "Photos/2024/a.jpg");
+            assert_eq!(normalize_remote_path("/Photos/2024\\a.jpg"), "Photos/2024/a.jpg");
+        } else {
+            assert_eq!(normalize_remote_path("Photos/back\\slash.jpg"), "Photos/back\\slash.jpg");
+        }
+
+        let local = normalize_local_path(Path::new("Users/me/Photos/2024"));
+        if cfg!(windows) {
+            assert_eq!(local, PathBuf::from("Users\\me\\Photos\\2024"));
+        } else {
+            assert_eq!(local, PathBuf::from("Users/me/Photos/2024"));
+        }
+    }
+
+    #[test]
+    fn test_strip_source_folder_case() {
+        assert_eq!(strip_source_folder("Photos/2024/a.jpg", "Photos", true), Some("2024/a.jpg"));
+        assert_eq!(strip_source_folder("photos/2024/a.jpg", "Photos", true), None);
+        assert_eq!(strip_source_folder("PHOTOS/2024/A.jpg", "Photos", false), Some("2024/A.jpg"));
+        assert_eq!(strip_source_folder("Ärger/a.txt", "ärger", false), Some("a.txt"));
+        assert_eq!(strip_source_folder("Photography/a.jpg", "Photos", false), None);
+        assert_eq!(strip_source_folder("Photos", "Photos", false), None);
+        assert_eq!(strip_source_folder("backups/2024-01-15/Photos/a.jpg", "backups/2024-01-15/Photos", true), Some("a.jpg"));
+        assert_eq!(strip_source_folder("backups/2024-01-15/a.jpg", "backups/2024-01-15/Photos", true), None);
+        assert_eq!(SyncConfig::default().case_sensitive, cfg!(not(any(windows, target_os = "macos"))));
+    }
+
+    #[test]
+    fn test_render_path_template() {
+        use chrono::TimeZone;
+
+        let now = Local.with_ymd_and_hms(2024, 1, 5, 7, 8, 9).unwrap();
+        assert_eq!(render_path_template("backups/{date}/{folder_name}", "u_1", now), "backups/2024-01-05/{folder_name}");
+        assert_eq!(render_path_template("{uid}/nightly_{datetime}", "u_1", now), "u_1/nightly_20240105_070809");
+        assert_eq!(render_path_template("plain", "u_1", now), "plain");
+    }
+
+    #[tokio::test]
+    async fn test_scan_uses_remote_path_template() {
+        let base = file_tree("template_src", 2);
+        std::fs::write(base.join("skip.tmp"), "x").unwrap();
+        let roots = std::slice::from_ref(&base);
+        let config = SyncConfig {
+            remote_path_template: Some("backups/{date}/{uid}/{folder_name}".to_string()),
+            exclude_patterns: vec!["*.tmp".to_string()],
+            ..Default::default()
+        };
+        let payload = KeyPayload::new("Test User");
+        let engine = SyncEngine::new_with_config(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap(), config)
+            .with_user(payload.clone());
+
+        let entries = engine.scan_local_folders(roots).await.unwrap();
+        let prefix = format!("backups/{}/{}/template_src", Local::now().format("%Y-%m-%d"), payload.uid);
+        let mut paths: Vec<&str> = entries.iter().map(|entry| entry.path.as_str()).collect();
+        paths.sort();
+        assert_eq!(paths, [format!("{}/file_0000.txt", prefix), format!("{}/file_0001.txt", prefix)]);
+        // Templated paths still lead back to the local files
+        let template = engine.render_template(1).unwrap();
+        assert_eq!(engine.find_source_file(roots, template.as_deref(), paths[0]).unwrap(), base.join("file_0000.txt"));
+
+        // Two folders would share one place without {folder_name}, and {uid} needs a user
+        let engine_with = |template: &str| {
+            let config = SyncConfig { remote_path_template: Some(template.to_string()), ..Default::default() };
+            SyncEngine::new_with_config(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap(), config)
+        };
+        let two_roots = [base.clone(), base.join("other")];
+        let result = engine_with("backups/{date}").scan_local_folders(&two_roots).await;
+        assert!(matches!(result, Err(SyncError::InvalidTemplate(_))));
+        assert!(engine_with("backups/{date}").render_template(1).unwrap().is_some());
+        let result = engine_with("{uid}/{folder_name}").scan_local_folders(roots).await;
+        assert!(matches!(result, Err(SyncError::InvalidTemplate(_))));
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_find_source_file_with_mixed_case() {
+        let base = file_tree("MixedCase", 1);
+        let roots = std::slice::from_ref(&base);
+        let entries = SyncEngine::new(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap())
+            .scan_local_folders(roots)
+            .await
+            .unwrap();
+        let relative = entries[0].path.strip_prefix("MixedCase/").unwrap();
+
+        let config = SyncConfig { case_sensitive: false, ..Default::default() };
+        let engine = SyncEngine::new_with_config(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap(), config);
+        let found = engine.find_source_file(roots, None, &format!("mixedcase/{}", relative)).unwrap();
+        assert_eq!(found, normalize_local_path(&base.join(relative)));
+
+        let config = SyncConfig { case_sensitive: true, ..Default::default() };
+        let engine = engine.reconfigured(config);
+        assert!(engine.find_source_file(roots, None, &format!("mixedcase/{}", relative)).is_err());
+        assert!(engine.find_source_file(roots, None, &entries[0].path).is_ok());
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unicode_paths() {
+        let base = file_tree("日本語", 0);
+        std::fs::write(base.join("テスト.txt"), "test").unwrap();
+        std::fs::write(base.join("cafe\u{301}.tmp"), "nfd").unwrap();
+        let roots = std::slice::from_ref(&base);
+
+        // Excluded with a pattern typed in NFC, though the file name is NFD
+        let config = SyncConfig { exclude_patterns: vec!["caf\u{e9}.tmp".to_string()], ..Default::default() };
+        let engine = SyncEngine::new_with_config(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap(), config);
+        let entries = engine.scan_local_folders(roots).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, "日本語/テスト.txt");
+        assert_eq!(engine.find_source_file(roots, None, &entries[0].path).unwrap(), base.join("テスト.txt"));
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pending_uploads_forced_without_differential() {
+        let base = file_tree("pending", 3);
+        let engine = SyncEngine::new(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap());
+
+        let mut pending = engine.list_pending_uploads(std::slice::from_ref(&base)).await.unwrap();
+        pending.sort_by(|a, b| a.remote_path.cmp(&b.remote_path));
+        assert_eq!(pending.len(), 3);
+        assert!(pending.iter().all(|file| file.reason == UploadReason::Forced));
+        assert_eq!(pending[0].remote_path, "pending/file_0000.txt");
+        assert_eq!(pending[0].local_path, base.join("file_0000.txt").display().to_string());
+        assert_eq!(pending[0].size, "contents of file 0".len() as u64);
+        // A preview isn't a sync
+        assert_eq!(engine.get_progress().await.status, SyncStatus::Idle);
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn test_upload_reason() {
+        let base = file_tree("reason", 1);
+        let metadata = std::fs::metadata(base.join("file_0000.txt")).unwrap();
+        let remote = |size: u64, last_modified: i64| S3Object {
+            key: "reason/file_0000.txt".to_string(),
+            size,
+            last_modified,
+            etag: None,
+            content_type: None,
+            checksum_sha256: None,
+        };
+
+        assert_eq!(upload_reason(&metadata, None), Some(UploadReason::NewFile));
+        assert_eq!(upload_reason(&metadata, Some(&remote(1, i64::MAX))), Some(UploadReason::Modified));
+        assert_eq!(upload_reason(&metadata, Some(&remote(metadata.len(), 0))), Some(UploadReason::Modified));
+        assert_eq!(upload_reason(&metadata, Some(&remote(metadata.len(), i64::MAX))), None);
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_scan_progress_grows_while_scanning() {
+        let base = file_tree("scan_progress", 10_000);
+        let roots = std::slice::from_ref(&base);
+        let engine = SyncEngine::new(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap());
+
+        let done = AtomicBool::new(false);
+        let scan = async {
+            let entries = engine.scan(roots, true, None).await.unwrap();
+            done.store(true, Ordering::Relaxed);
+            entries
+        };
+        let poll = async {
+            let mut seen = Vec::new();
+            while !done.load(Ordering::Relaxed) {
+                seen.push(engine.get_progress().await.files_found_during_scan);
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            seen
+        };
+        let (entries, seen) = tokio::join!(scan, poll);
+
+        assert_eq!(entries.len(), 10_000);
+        assert!(seen.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", seen);
+        let progress = engine.get_progress().await;
+        assert_eq!(progress.files_found_during_scan, 10_000);
+        assert_eq!(progress.status, SyncStatus::Scanning { files_found: 10_000, dirs_scanned: 1 });
+
+        // Previews leave a sync's count alone
+        engine.scan(roots, false, None).await.unwrap();
+        assert_eq!(engine.get_progress().await.files_found_during_scan, 10_000);
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_scan_reports_files_found_across_folders() {
+        let roots: Vec<PathBuf> = (0..3).map(|i| file_tree(&format!("parallel_{}", i), 2 + i)).collect();
+        let engine = SyncEngine::new(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap());
+
+        let entries = engine.scan_local_folders(&roots).await.unwrap();
+        assert_eq!(engine.get_progress().await.status, SyncStatus::Scanning { files_found: 9, dirs_scanned: 3 });
+        // Listed in the order the folders were given, whichever finished first
+        let folders: Vec<&str> = entries.iter().map(|e| e.path.split('/').next().unwrap()).collect();
+        let expected: Vec<String> = (0..3).flat_map(|i| vec![format!("parallel_{}", i); 2 + i]).collect();
+        assert_eq!(folders, expected);
+
+        let sequential = engine.reconfigured(SyncConfig { scan_parallelism: 1, ..Default::default() });
+        let paths = |entries: Vec<FileEntry>| entries.into_iter().map(|e| e.path).collect::<Vec<_>>();
+        assert_eq!(paths(sequential.scan_local_folders(&roots).await.unwrap()), paths(entries));
+
+        // Scans share the engine's pool, which only grows for more folders than it has threads
+        let pool = engine.scan_pool(3).unwrap();
+        assert!(Arc::ptr_eq(&pool, &engine.scan_pool(2).unwrap()));
+        assert_eq!(engine.scan_pool(4).unwrap().current_num_threads(), 4);
+        assert_eq!(sequential.scan_pool(4).unwrap().current_num_threads(), 1);
+        for root in roots {
+            std::fs::remove_dir_all(root).unwrap();
+        }
+    }
+
+    /// Scan time of a 50,000-file tree in eight folders, one folder at a time
+    /// and all at once. Run with
+    /// `cargo test --release bench_scan_parallel -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore]
+    async fn bench_scan_parallel() {
+        let roots: Vec<PathBuf> = (0..8).map(|i| file_tree(&format!("bench_parallel_{}", i), 6250)).collect();
+        let engine = SyncEngine::new(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap());
+        let sequential = engine.reconfigured(SyncConfig { scan_parallelism: 1, ..Default::default() });
+
+        let started = Instant::now();
+        let entries = sequential.scan_local_folders(&roots).await.unwrap();
+        let sequential_time = started.elapsed();
+
+        let started = Instant::now();
+        engine.scan_local_folders(&roots).await.unwrap();
+        let parallel_time = started.elapsed();
+
+        println!("scan: {:?} sequential; {:?} parallel", sequential_time, parallel_time);
+        assert_eq!(entries.len(), 50_000);
+        for root in roots {
+            std::fs::remove_dir_all(root).unwrap();
+        }
+    }
+
+    /// Scan time of a 1000-file tree without hashes, with hashes, and with
+    /// every hash cached. Run with
+    /// `cargo test --release bench_scan_hashing -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore]
+    async fn bench_scan_hashing() {
+        let base = file_tree("bench", 1000);
+        let roots = std::slice::from_ref(&base);
+        let plain = SyncEngine::new(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap());
+        let hashing = hashing_engine();
+
+        let started = Instant::now();
+        plain.scan_local_folders(roots).await.unwrap();
+        let plain_time = started.elapsed();
+
+        let started = Instant::now();
+        hashing.scan_local_folders(roots).await.unwrap();
+        let hashing_time = started.elapsed();
+
+        let started = Instant::now();
+        let entries = hashing.scan_local_folders(roots).await.unwrap();
+        let cached_time = started.elapsed();
+
+        println!(
+            "scan: {:?}; with hashing: {:?}; with cached hashes: {:?}",
+            plain_time, hashing_time, cached_time
+        );
+        assert!(entries.iter().all(|e| e.content_hash.is_some()));
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resume_skips_transferred_files() {
+        let base = file_tree("resume", 3);
+        let sessions_dir = base.with_extension("sessions");
+        let roots = std::slice::from_ref(&base);
+        let engine = SyncEngine::new(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap())
+            .with_sessions_dir(Some(sessions_dir.clone()));
+
+        // Killed after every file was uploaded, before the sync finished
+        let keys: Vec<String> = (0..3).map(|i| format!("resume/file_{:04}.txt", i)).collect();
+        let session_id = engine.begin_resume_record(roots, None, keys[..1].to_vec());
+        engine.record_transferred(&keys[1]);
+        assert!(engine.lock_cache().was_uploaded_in_session(&session_id, &keys[1]));
+        engine.pause();
+        drop(engine);
+
+        // After the restart the session is found on disk
+        let saved = SyncCache::new()
+            .with_sessions_dir(Some(sessions_dir.clone()))
+            .get_incomplete_session()
+            .unwrap();
+        assert_eq!(saved.session_id, session_id);
+        assert_eq!(saved.source_paths, roots);
+        assert_eq!(saved.transferred_keys, keys[..2]);
+
+        // Resuming skips the saved keys; any other file would need S3
+        let engine = SyncEngine::new(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap())
+            .with_sessions_dir(Some(sessions_dir.clone()));
+        let mut transferred_keys = saved.transferred_keys;
+        transferred_keys.push(keys[2].clone());
+        engine.sync_to_cloud(&saved.source_paths, transferred_keys).await.unwrap();
+
+        let progress = engine.get_progress().await;
+        assert_eq!(progress.status, SyncStatus::Completed);
+        assert_eq!(progress.skipped_files, 3);
+        assert_eq!(progress.completed_files, 3);
+        assert!(
+            std::fs::read_dir(&sessions_dir).unwrap().next().is_none(),
+            "a finished upload leaves nothing to resume"
+        );
+        std::fs::remove_dir_all(base).unwrap();
+        std::fs::remove_dir_all(sessions_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resume_keeps_rendered_template() {
+        let base = file_tree("resume_template", 2);
+        let sessions_dir = base.with_extension("sessions");
+        let roots = std::slice::from_ref(&base);
+        let config = SyncConfig {
+            remote_path_template: Some("nightly/{date}/{folder_name}".to_string()),
+            ..Default::default()
+        };
+        let engine = || {
+            SyncEngine::new_with_config(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap(), config.clone())
+                .with_sessions_dir(Some(sessions_dir.clone()))
+        };
+
+        // Started on an earlier day, and interrupted after the first file
+        let rendered = "nightly/2024-01-05/{folder_name}";
+        let keys: Vec<String> = (0..2)
+            .map(|i| format!("nightly/2024-01-05/resume_template/file_{:04}.txt", i))
+            .collect();
+        engine().begin_resume_record(roots, Some(rendered), keys[..1].to_vec());
+        let mut saved = SyncCache::new()
+            .with_sessions_dir(Some(sessions_dir.clone()))
+            .get_incomplete_session()
+            .unwrap();
+        assert_eq!(saved.rendered_template.as_deref(), Some(rendered));
+
+        // The rest keeps the old date; today's paths wouldn't match the saved keys and would need S3
+        saved.transferred_keys = keys;
+        engine().resume_upload(&saved).await.unwrap();
+        std::fs::remove_dir_all(base).unwrap();
+        std::fs::remove_dir_all(sessions_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resume_refuses_other_users_session() {
+        let base = file_tree("resume_foreign", 1);
+        let sessions_dir = base.with_extension("sessions");
+        let roots = std::slice::from_ref(&base);
+        let engine = |user: &KeyPayload| {
+            SyncEngine::new_with_config(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap(), SyncConfig::default())
+                .with_sessions_dir(Some(sessions_dir.clone()))
+                .with_user(user.clone())
+        };
+        let owner = KeyPayload::new("Owner");
+        let other = KeyPayload::new("Other");
+
+        // Every file was already uploaded, so the owner's resume needs no S3
+        engine(&owner).begin_resume_record(roots, None, vec!["resume_foreign/file_0000.txt".to_string()]);
+        let saved = SyncCache::new()
+            .with_sessions_dir(Some(sessions_dir.clone()))
+            .get_incomplete_session()
+            .unwrap();
+        assert_eq!(saved.uid.as_deref(), Some(owner.uid.as_str()));
+        assert!(!saved.belongs_to(Some(&other.uid)));
+
+        let result = engine(&other).resume_upload(&saved).await;
+        assert!(matches!(result, Err(SyncError::ForeignSession)));
+        engine(&owner).resume_upload(&saved).await.unwrap();
+        std::fs::remove_dir_all(base).unwrap();
+        std::fs::remove_dir_all(sessions_dir).unwrap();
+    }
+
+    #[test]
+    fn test_new_session_keeps_other_users_sessions() {
+        let base = file_tree("resume_two_users", 1);
+        let sessions_dir = base.with_extension("sessions");
+        let roots = std::slice::from_ref(&base);
+        let engine = |user: &KeyPayload| {
+            SyncEngine::new_with_config(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap(), SyncConfig::default())
+                .with_sessions_dir(Some(sessions_dir.clone()))
+                .with_user(user.clone())
+        };
+        let owner = KeyPayload::new("Owner");
+        let other = KeyPayload::new("Other");
+        let cache = SyncCache::new().with_sessions_dir(Some(sessions_dir.clone()));
+        let saved = |session_id: &str| cache.session_file(session_id).unwrap().exists();
+
+        let first = engine(&owner).begin_resume_record(roots, None, Vec::new());
+        let others = engine(&other).begin_resume_record(roots, None, Vec::new());
+        assert!(saved(&first) && saved(&others));
+
+        // The owner's new upload replaces their own session only
+        let second = engine(&owner).begin_resume_record(roots, None, Vec::new());
+        assert!(!saved(&first));
+        assert!(saved(&second) && saved(&others));
+        std::fs::remove_dir_all(base).unwrap();
+        std::fs::remove_dir_all(sessions_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_overlong_lock_retention_is_an_error() {
+        let base = file_tree("lock_overflow", 1);
+        let config = SyncConfig {
+            object_lock: Some(ObjectLockConfig { retain_days: u32::MAX, mode: LockMode::Governance }),
+            ..Default::default()
+        };
+        let client = crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap();
+
+        // Fails before any request instead of panicking on the date
+        let source = base.join("file_0000.txt");
+        let result = put_file_once(&client, &config, &source, "k", &HashMap::new(), Utc::now(), |_| {}).await;
+        assert!(matches!(result, Err(S3Error::InvalidConfiguration(_))));
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_atomic_upload_without_overwrite_never_replaces() {
+        let base = file_tree("atomic_if_absent", 1);
+        let source = base.join("file_0000.txt");
+        let config = SyncConfig { overwrite_existing: false, atomic_uploads: true, ..Default::default() };
+
+        // A small file is one conditional PUT straight to its key, which is already atomic
+        let (client, requests) = mock_s3().await;
+        put_file_once(&client, &config, &source, "k", &HashMap::new(), Utc::now(), |_| {}).await.unwrap();
+        assert_eq!(*requests.lock().unwrap(), ["PUT /mock/k"]);
+
+        // A multipart-sized one finds the mock's object at its key and uploads nothing
+        let big = base.join("big.bin");
+        std::fs::write(&big, vec![1u8; crate::s3_client::DEFAULT_MULTIPART_PART_SIZE + 1]).unwrap();
+        let (client, requests) = mock_s3().await;
+        let result = put_file_once(&client, &config, &big, "big.bin", &HashMap::new(), Utc::now(), |_| {}).await;
+        assert!(matches!(result, Err(S3Error::ConflictError { .. })), "{:?}", result);
+        assert!(requests.lock().unwrap().iter().all(|request| request.starts_with("HEAD ")));
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sync_summary() {
+        let base = file_tree("summary", 3);
+        std::fs::write(base.join("empty.txt"), "").unwrap();
+        let roots = std::slice::from_ref(&base);
+        let config = SyncConfig { skip_empty_files: true, ..Default::default() };
+        let engine = SyncEngine::new_with_config(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap(), config);
+        assert!(engine.get_last_summary().is_none());
+
+        // Every file was already uploaded, so the sync needs no S3
+        let keys = (0..3).map(|i| format!("summary/file_{:04}.txt", i)).collect();
+        let before = Utc::now();
+        let summary = engine.sync_to_cloud(roots, keys).await.unwrap();
+        let after = Utc::now();
+
+        assert!(uuid::Uuid::parse_str(&summary.session_id).is_ok());
+        assert_eq!(summary.direction, SyncDirection::LocalToCloud);
+        assert!(before <= summary.start_time && summary.start_time <= summary.end_time && summary.end_time <= after);
+        let measured = (summary.end_time - summary.start_time).num_microseconds().unwrap() as f64 / 1e6;
+        assert!((measured - summary.duration_secs).abs() < 0.001);
+        assert_eq!(summary.total_files, 3);
+        assert_eq!(summary.transferred_bytes, 0);
+        // The three already uploaded and the empty file left out by the scan
+        assert_eq!(summary.skipped_files, 4);
+        assert_eq!(summary.deleted_files, 0);
+        assert!(summary.failed_files.is_empty());
+        assert_eq!((summary.average_bps, summary.peak_bps), (0.0, 0.0));
+        assert_eq!(summary.slowest_file, None);
+
+        let last = engine.reconfigured(SyncConfig::default()).get_last_summary().unwrap();
+        assert_eq!(last.session_id, summary.session_id);
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_session_stats_add_up_syncs() {
+        let engine = SyncEngine::new(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap());
+        let mut stats = SessionStats::default();
+
+        // Two uploads one after the other, then a download
+        for (bytes, direction) in [
+            (1500, SyncDirection::LocalToCloud),
+            (2500, SyncDirection::LocalToCloud),
+            (700, SyncDirection::CloudToLocal),
+        ] {
+            engine.transferred_bytes.store(bytes, Ordering::Relaxed);
+            stats.record(&engine.build_summary(direction, uuid::Uuid::new_v4().to_string()).await);
+        }
+        assert_eq!((stats.uploads_count, stats.bytes_uploaded), (2, 4000));
+        assert_eq!((stats.downloads_count, stats.bytes_downloaded), (1, 700));
+
+        let copy = SyncDirection::CloudToCloud { source_folder: "a".into(), dest_folder: "b".into() };
+        stats.record(&engine.build_summary(copy, String::new()).await);
+        assert_eq!((stats.uploads_count, stats.downloads_count), (2, 1));
+        assert!(SessionStats::default().session_start >= stats.session_start);
+    }
+
+    #[test]
+    fn test_folder_checksum() {
+        let object = |name: &str, etag: &str| S3Object {
+            key: format!("Photos/{}", name),
+            size: 100,
+            last_modified: 0,
+            etag: Some(etag.to_string()),
+            content_type: None,
+            checksum_sha256: None,
+        };
+        let objects: Vec<S3Object> = (1..=5).map(|i| object(&format!("{}.jpg", i), &format!("etag{}", i))).collect();
+
+        let checksum = FolderChecksum::of("Photos", &objects);
+        assert_eq!((checksum.file_count, checksum.total_bytes), (5, 500));
+        assert_eq!(checksum.merkle_root.len(), 64);
+        // Listing order and stored checksums don't matter
+        let mut listed = objects.clone();
+        listed.reverse();
+        listed.push(object(FOLDER_CHECKSUM_FILE, "stored"));
+        assert_eq!(FolderChecksum::of("Photos", &listed), checksum);
+
+        // A modified or deleted file changes the root
+        let mut modified = objects.clone();
+        modified[2].etag = Some("edited".to_string());
+        assert_ne!(FolderChecksum::of("Photos", &modified).merkle_root, checksum.merkle_root);
+        assert_ne!(FolderChecksum::of("Photos", &objects[..4]).merkle_root, checksum.merkle_root);
+
+        // A single file's root is its leaf, and two leaves are joined as a node
+        let leaf = |data: &[u8]| <[u8; 32]>::from(Sha256::digest(data));
+        let (first, second) = (leaf(b"\x00Photos/1.jpgetag1"), leaf(b"\x00Photos/2.jpgetag2"));
+        assert_eq!(FolderChecksum::of("Photos", &objects[..1]).merkle_root, hex::encode(first));
+        let node = blake3::Hasher::new().update(&[0x01]).update(&first).update(&second).finalize();
+        assert_eq!(FolderChecksum::of("Photos", &objects[..2]).merkle_root, node.to_hex().as_str());
+    }
+
+    #[test]
+    fn test_manifest_lists_uploaded_files() {
+        let engine = SyncEngine::new(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap());
+        let files: Vec<FileEntry> = (0..10)
+            .map(|i| FileEntry {
+                path: format!("Photos/img_{}.jpg", i),
+                size: 1000 + i,
+                is_dir: false,
+                content_hash: (i % 2 == 0).then(|| format!("{:064x}", i)),
+            })
+            .collect();
+        // Odd files weren't hashed during the scan
+        let hashes: HashMap<String, String> =
+            (1..10).step_by(2).map(|i| (format!("Photos/img_{}.jpg", i), format!("{:064x}", i))).collect();
+        engine.record_recent_upload("Photos/img_3.jpg", 1003);
+
+        let json = engine.generate_manifest(&files, &hashes);
+        let manifest: UploadManifest = serde_json::from_str(&json).unwrap();
+        assert_eq!(manifest.files.len(), 10);
+        for (i, entry) in manifest.files.iter().enumerate() {
+            assert_eq!(entry.path, files[i].path);
+            assert_eq!(entry.size, 1000 + i as u64);
+            assert_eq!(entry.sha256, format!("{:064x}", i));
+            assert!(entry.uploaded_at <= manifest.created_at);
+        }
+        assert!(json.contains("\n  \"files\": ["), "not pretty-printed: {}", json);
+
+        // Only the newest five are kept
+        let keys: Vec<String> = (1..=7).map(|i| format!("{}2026010{}T000000000Z.json", MANIFEST_PREFIX, i)).collect();
+        assert_eq!(old_manifests(keys.clone()), keys[..2]);
+        assert!(old_manifests(keys[..5].to_vec()).is_empty());
+    }
+
+    #[test]
+    fn test_use_sse_c_follows_config() {
+        let sse_c = |engine: &SyncEngine| {
+            engine.s3_clients.iter().map(|client| client.config().sse_customer_key.clone()).collect::<Vec<_>>()
+        };
+        let config = SyncConfig { use_sse_c: true, ..Default::default() };
+        let user = KeyPayload::new("Test User");
+        let engine = SyncEngine::new_with_config(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap(), config)
+            .with_user(user.clone())
+            .with_secondary(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap());
+        let expected = crate::s3_client::SseCustomerKey::new(&derive_sse_c_key(&user.uid));
+        assert_eq!(sse_c(&engine), vec![Some(expected.clone()), Some(expected)]);
+
+        let engine = engine.reconfigured(SyncConfig::default());
+        assert_eq!(sse_c(&engine), vec![None, None]);
+    }
+
+    #[tokio::test]
+    async fn test_session_byte_limit() {
+        let limited = |max_session_bytes| {
+            let config = SyncConfig { max_session_bytes: Some(max_session_bytes), ..Default::default() };
+            SyncEngine::new_with_config(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap(), config)
+        };
+
+        // Ten 200-byte files against 1024 bytes: five fit, and nothing starts after the sixth didn't
+        let engine = limited(1024);
+        let reserved: Vec<bool> = (0..10).map(|_| engine.reserve_session_bytes(200)).collect();
+        assert_eq!(reserved.iter().filter(|&&fits| fits).count(), 5);
+        assert!(reserved[..5].iter().all(|&fits| fits));
+        assert!(!engine.reserve_session_bytes(1));
+        assert_eq!(engine.session_bytes_transferred.load(Ordering::Relaxed), 1000);
+
+        // An upload whose first file is over the limit skips every file and sends nothing
+        let dir = std::env::temp_dir()
+            .join(format!("sync2bucket-scan-{}", std::process::id()))
+            .join("session_limit");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for i in 0..10 {
+            std::fs::write(dir.join(format!("file_{}.bin", i)), [0u8; 200]).unwrap();
+        }
+        let reached = Arc::new(std::sync::Mutex::new(None));
+        let engine = limited(100).with_limit_reached_handler({
+            let reached = Arc::clone(&reached);
+            move |transferred, limit| *reached.lock().unwrap() = Some((transferred, limit))
+        });
+        let summary = engine.sync_to_cloud(std::slice::from_ref(&dir), Vec::new()).await.unwrap();
+        let progress = engine.get_progress().await;
+        assert_eq!(progress.status, SyncStatus::StoppedByLimit { transferred: 0, limit: 100 });
+        assert_eq!(summary.skipped_files, 10);
+        assert!(summary.skipped_reasons.iter().all(|(_, reason)| reason == SESSION_LIMIT_REACHED));
+        assert!(summary.failed_files.is_empty());
+        assert_eq!(*reached.lock().unwrap(), Some((0, 100)));
+
+        // Against 1024 bytes, five of the files are uploaded and the other five skipped
+        let (client, requests) = mock_s3().await;
+        let config = SyncConfig { max_session_bytes: Some(1024), ..Default::default() };
+        let engine = SyncEngine::new_with_config(client, config);
+        let summary = engine.sync_to_cloud(std::slice::from_ref(&dir), Vec::new()).await.unwrap();
+        let progress = engine.get_progress().await;
+        assert_eq!(progress.status, SyncStatus::StoppedByLimit { transferred: 1000, limit: 1024 });
+        assert_eq!(summary.transferred_bytes, 1000);
+        assert_eq!(summary.skipped_files, 5);
+        let puts = requests.lock().unwrap().iter().filter(|request| request.starts_with("PUT ")).count();
+        assert_eq!(puts, 5);
+
+        // Failed files outrank the limit
+        let (path, error) = ("session_limit/file_0.bin".to_string(), "boom".to_string());
+        engine.report(ProgressUpdate::FileFailed { path, error }).await;
+        engine.finish(&SyncDirection::LocalToCloud).await;
+        engine.stop_by_limit().await;
+        assert_eq!(engine.get_progress().await.status, SyncStatus::CompletedWithErrors { failed_count: 1 });
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_estimate_cost() {
+        const GIB: u64 = 1024 * 1024 * 1024;
+        let pricing = S3Pricing {
+            upload_per_gb_usd: 0.09,
+            download_per_gb_usd: 0.05,
+            storage_per_gb_month_usd: 0.023,
+            put_per_1000_usd: 0.005,
+            get_per_1000_usd: 0.0004,
+        };
+        let config = SyncConfig { pricing: Some(pricing), differential: true, ..Default::default() };
+        let engine = SyncEngine::new_with_config(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap(), config);
+        let file = |name: &str, size| PendingFile {
+            local_path: format!("/data/{}", name),
+            remote_path: format!("data/{}", name),
+            size,
+            reason: UploadReason::NewFile,
+        };
+        // Two files of 128 8 MiB parts each, plus an empty one uploaded in a single PUT
+        let pending = [file("a.iso", GIB), file("b.iso", GIB), file("empty", 0)];
+
+        let estimate = engine.estimate_cost(&pending);
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        assert!(close(estimate.upload_cost_usd, 0.18));
+        assert!(close(estimate.download_cost_usd, 0.0));
+        assert!(close(estimate.storage_cost_per_month_usd, 0.046));
+        // 261 PUT-class requests and 3 HEADs
+        assert!(close(estimate.api_call_cost_usd, 261.0 * 0.005 / 1000.0 + 3.0 * 0.0004 / 1000.0));
+        assert!(close(estimate.total_estimated_usd, 0.18 + 0.046 + estimate.api_call_cost_usd));
+
+        assert!(close(pricing.estimate(0, 2 * GIB, 0, 0).download_cost_usd, 0.1));
+        assert_eq!(engine.estimate_cost(&[]), S3CostEstimate::default());
+    }
+
+    #[test]
+    fn test_sync_policy_violation() {
+        let policy = SyncPolicy {
+            max_file_size_bytes: Some(2 * 1024 * 1024 * 1024),
+            forbidden_extensions: vec![".exe".to_string(), "BAT".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(policy.violation("docs/report.pdf", 1024), None);
+        assert_eq!(policy.violation("tools/setup.exe", 10).unwrap(), ".exe files are not allowed");
+        assert!(policy.violation("tools/SETUP.EXE", 10).is_some());
+        assert!(policy.violation("scripts/run.bat", 10).is_some());
+        assert_eq!(policy.violation("exe", 10), None);
+        assert_eq!(policy.violation("video.mp4", 2 * 1024 * 1024 * 1024), None);
+        assert!(policy.violation("video.mp4", 2 * 1024 * 1024 * 1024 + 1).is_some());
+        assert_eq!(SyncPolicy::default().violation("setup.exe", u64::MAX), None);
+
+        let parsed: SyncPolicy = serde_json::from_str(r#"{"forbidden_extensions":["exe"]}"#).unwrap();
+        assert_eq!(parsed.max_file_size_bytes, None);
+        assert!(parsed.required_tags.is_empty());
+        assert!(parsed.forbidden_acls.is_empty());
+
+        let policy = SyncPolicy { forbidden_acls: vec!["public-read".to_string()], ..Default::default() };
+        assert_eq!(policy.acl_violation("public-read").unwrap(), "the public-read ACL is not allowed");
+        assert_eq!(policy.acl_violation("private"), None);
+    }
+
+    #[tokio::test]
+    async fn test_upload_applies_cached_policy() {
+        let base = file_tree("policy", 2);
+        std::fs::write(base.join("setup.exe"), "MZ").unwrap();
+        let roots = std::slice::from_ref(&base);
+        let keys: Vec<String> = (0..2).map(|i| format!("policy/file_{:04}.txt", i)).collect();
+        let policy = SyncPolicy {
+            forbidden_extensions: vec!["exe".to_string()],
+            ..Default::default()
+        };
+        // A fresh cached policy is used without reading the bucket
+        let cache: SyncPolicyCache = Arc::new(RwLock::new(Some((policy.clone(), Instant::now()))));
+        let client = || crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap();
+        let engine = SyncEngine::new(client()).with_sync_policy(client(), Arc::clone(&cache));
+        assert_eq!(engine.fetch_remote_sync_policy().await.unwrap(), policy);
+
+        let err = engine.sync_to_cloud(roots, keys.clone()).await.unwrap_err();
+        assert!(matches!(err, SyncError::PolicyViolation { ref path, .. } if path == "policy/setup.exe"));
+        // A failed sync doesn't stay Scanning or Syncing, which would block the next one
+        assert!(matches!(engine.get_progress().await.status, SyncStatus::Error(_)));
+        assert!(!engine.is_running().await);
+
+        let skipping = engine.reconfigured(SyncConfig { on_error: ErrorPolicy::Skip, ..Default::default() });
+        let summary = skipping.sync_to_cloud(roots, keys).await.unwrap();
+        assert_eq!(summary.total_files, 2);
+        assert_eq!(summary.failed_files, vec!["policy/setup.exe".to_string()]);
+
+        // A forbidden default ACL fails every file before anything is sent
+        *cache.write().await = Some((
+            SyncPolicy { forbidden_acls: vec!["public-read".to_string()], ..Default::default() },
+            Instant::now(),
+        ));
+        let public = engine.reconfigured(SyncConfig {
+            on_error: ErrorPolicy::Skip,
+            default_acl: Some("public-read".to_string()),
+            ..Default::default()
+        });
+        let summary = public.sync_to_cloud(roots, Vec::new()).await.unwrap();
+        assert_eq!(summary.failed_files.len(), 3);
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_policy_falls_back_to_cached() {
+        use crate::s3_client::{RetryPolicy, S3Provider, S3ProviderConfig};
+        use crate::time_source::MockTimeSource;
+
+        let base = file_tree("policy_offline", 1);
+        std::fs::write(base.join("setup.exe"), "MZ").unwrap();
+        let roots = std::slice::from_ref(&base);
+        let clock = MockTimeSource::new();
+        let policy = SyncPolicy { forbidden_extensions: vec!["exe".to_string()], ..Default::default() };
+        let cache: SyncPolicyCache = Arc::new(RwLock::new(Some((policy, clock.now_instant()))));
+        let unreachable = crate::s3_client::S3ClientBuilder::new()
+            .unscoped()
+            .provider(S3ProviderConfig::custom(S3Provider::Minio, "http://127.0.0.1:9", "us-east-1", "policy"))
+            .retry_policy(RetryPolicy { max_retries: 0, base_delay_ms: 0 })
+            .build()
+            .unwrap();
+        let engine = SyncEngine::new_with_time_source(
+            crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap(),
+            SyncConfig::default(),
+            Arc::new(clock.clone()),
+        )
+        .with_sync_policy(unreachable, cache);
+
+        // The cached policy has expired and the bucket can't be read, so the old one still applies
+        clock.advance(SYNC_POLICY_TTL * 2);
+        assert!(engine.fetch_remote_sync_policy().await.is_err());
+        let keys = vec!["policy_offline/file_0000.txt".to_string()];
+        let err = engine.sync_to_cloud(roots, keys).await.unwrap_err();
+        assert!(matches!(err, SyncError::PolicyViolation { ref path, .. } if path == "policy_offline/setup.exe"));
+        assert!(matches!(engine.get_progress().await.status, SyncStatus::Error(_)));
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_deleted_after_scan_is_skipped() {
+        let base = file_tree("deleted", 1);
+        let roots = vec![base.clone()];
+        let config = SyncConfig { two_phase: true, ..Default::default() };
+        let engine = Arc::new(SyncEngine::new_with_config(
+            crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap(),
+            config,
+        ));
+
+        let sync = tokio::spawn({
+            let (engine, roots) = (Arc::clone(&engine), roots.clone());
+            async move { engine.sync_to_cloud(&roots, Vec::new()).await }
+        });
+        assert_eq!(awaiting_confirmation(&engine).await.total_files, 1);
+        std::fs::remove_file(base.join("file_0000.txt")).unwrap();
+        engine.confirm().await.unwrap();
+
+        let summary = sync.await.unwrap().unwrap();
+        assert_eq!(engine.get_progress().await.status, SyncStatus::Completed);
+        assert_eq!(summary.skipped_files, 1);
+        assert!(summary.failed_files.is_empty());
+        assert_eq!(
+            summary.skipped_reasons,
+            vec![("deleted/file_0000.txt".to_string(), DELETED_DURING_SYNC.to_string())]
+        );
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    /// Wait until a two-phase upload is waiting for confirmation
+    async fn awaiting_confirmation(engine: &SyncEngine) -> ScanSummary {
+        let wait = async {
+            loop {
+                if let SyncStatus::AwaitingConfirmation { scan_summary } = engine.get_progress().await.status {
+                    return scan_summary;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), wait).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_two_phase_upload_waits_for_confirmation() {
+        let base = file_tree("two_phase", 3);
+        let roots = vec![base.clone()];
+        let keys: Vec<String> = (0..3).map(|i| format!("two_phase/file_{:04}.txt", i)).collect();
+        let scanned = Arc::new(std::sync::Mutex::new(None));
+        let config = SyncConfig { two_phase: true, ..Default::default() };
+        let engine = SyncEngine::new_with_config(crate::s3_client::S3ClientBuilder::new().unscoped().build().unwrap(), config)
+            .with_scan_complete_handler({
+                let scanned = Arc::clone(&scanned);
+                move |scan: &ScanComplete| *scanned.lock().unwrap() = Some(scan.clone())
+            });
+        let engine = Arc::new(engine);
+        assert!(matches!(engine.confirm().await, Err(SyncError::NoActiveSync)));
+
+        // Every file was uploaded before the restart, so the upload itself needs no S3
+        let sync = tokio::spawn({
+            let (engine, roots, keys) = (Arc::clone(&engine), roots.clone(), keys.clone());
+            async move { engine.sync_to_cloud(&roots, keys).await }
+        });
+        let summary = awaiting_confirmation(&engine).await;
+        let total_bytes: u64 = (0..3).map(|i| format!("contents of file {}", i).len() as u64).sum();
+        assert_eq!(
+            summary,
+            ScanSummary { total_files: 3, total_bytes, new_files: 0, modified_files: 0 }
+        );
+        let scan = scanned.lock().unwrap().take().unwrap();
+        assert_eq!(scan.files.len(), 3);
+        assert_eq!(scan.total_bytes, total_bytes);
+
+        // Nothing happens until the sync is confirmed
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(!sync.is_finished());
+        assert_eq!(awaiting_confirmation(&engine).await, summary);
+        engine.confirm().await.unwrap();
+        sync.await.unwrap().unwrap();
+        let progress = engine.get_progress().await;
+        assert_eq!(progress.status, SyncStatus::Completed);
+        assert_eq!(progress.skipped_files, 3);
+
+        // Cancelling while waiting discards the scan
+        let sync = tokio::spawn({
+            let engine = Arc::clone(&engine);
+            async move { engine.sync_to_cloud(&roots, Vec::new()).await }
+        });
+        awaiting_confirmation(&engine).await;
+        engine.cancel();
+        assert!(matches!(sync.await.unwrap(), Err(SyncError::Cancelled)));
+        assert_eq!(engine.get_progress().await.status, SyncStatus::Idle);
+        assert!(matches!(engine.confirm().await, Err(SyncError::NoActiveSync)));
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_active_transfers_never_exceed_concurrency() {
+        use crate::s3_client::S3ClientBuilder;
+
+        let config = SyncConfig { concurrency: 3, ..Default::default() };
+        let engine = SyncEngine::new_with_config(S3ClientBuilder::new().unscoped().build().unwrap(), config);
+        let files: Vec<FileEntry> = (0..20).map(|i| entry(&format!("Photos/{}.jpg", i), 10)).collect();
+
+        let peak = AtomicU64::new(0);
+        engine
+            .for_each_file_concurrently(&files, |file| {
+                let (engine, peak) = (&engine, &peak);
+                async move {
+                    let progress = engine.get_progress().await;
+                    assert!(progress.active_files.contains(&file.path));
+                    peak.fetch_max(progress.active_transfers, Ordering::Relaxed);
+                    tokio::time::sleep(Duration::from_millis(2)).await;
+                    Ok(())
+                }
+            })
+            .await
+            .unwrap();
+
+        assert!(peak.load(Ordering::Relaxed) <= 3);
+        assert!(peak.load(Ordering::Relaxed) > 1, "files should overlap");
+        let progress = engine.get_progress().await;
+        assert_eq!(progress.active_transfers, 0);
+        assert!(progress.active_files.is_empty());
+    }
+
+    #[test]
+    fn test_active_files_capped() {
+        let handles = ProgressHandles::default();
+        let transfers: Vec<ActiveTransfer> = (0..15)
+            .map(|i| ActiveTransfer::start(&handles, &format!("f{:02}", i)))
+            .collect();
+
+        let mut progress = SyncProgress::default();
+        handles.apply_counters(&mut progress);
+        assert_eq!(progress.active_transfers, 15);
+        assert_eq!(progress.active_files.len(), MAX_ACTIVE_FILES_REPORTED);
+
+        drop(transfers);
+        handles.apply_counters(&mut progress);
+        assert_eq!(progress.active_transfers, 0);
+        assert!(progress.active_files.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_write_to_all_reaches_primary_and_secondary() {
+        use crate::s3_client::S3ClientBuilder;
+
+        let engine = SyncEngine::new(S3ClientBuilder::new().unscoped().build().unwrap());
+
+        // A primary and a secondary fake bucket that record what they receive
+        let backends: Vec<std::sync::Mutex<Vec<String>>> = vec![Default::default(), Default::default()];
+        for path in ["Photos/a.jpg", "Photos/b.jpg", "Docs/c.pdf"] {
+            engine
+                .write_to_all(&backends, path, |backend, _primary| {
+                    backend.lock().unwrap().push(path.to_string());
+                    async { Ok(()) }
+                })
+                .await
+                .unwrap();
+        }
+        for backend in &backends {
+            assert_eq!(*backend.lock().unwrap(), ["Photos/a.jpg", "Photos/b.jpg", "Docs/c.pdf"]);
+        }
+        assert!(engine.get_progress().await.secondary_errors.is_empty());
+
+        // A failing secondary is recorded but doesn't fail the write
+        let written = engine
+            .write_to_all(&backends, "Docs/d.pdf", |_, primary| async move {
+                if primary { Ok(()) } else { Err(S3Error::OperationFailed("offline".to_string())) }
+            })
+            .await;
+        assert!(written.is_ok());
+        let errors = engine.get_progress().await.secondary_errors;
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("Docs/d.pdf"));
+
+        // A failing primary does
+        let written = engine
+            .write_to_all(&backends, "Docs/e.pdf", |_, primary| async move {
+                if primary { Err(S3Error::OperationFailed("offline".to_string())) } else { Ok(()) }
+            })
+            .await;
+        assert!(written.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_state_changes_emit_events() {
+        use crate::s3_client::S3ClientBuilder;
+        use tauri::Listener;
+
+        let app = tauri::test::mock_app();
+        let main = tauri::WebviewWindowBuilder::new(&app, "main", Default::default()).build().unwrap();
+        let settings = tauri::WebviewWindowBuilder::new(&app, "settings", Default::default()).build().unwrap();
+        let window = main.as_ref().window();
+
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        for webview in [&main, &settings] {
+            for name in ["sync://paused", "sync://resumed", "sync://cancelled"] {
+                let events = Arc::clone(&events);
+                let label = webview.label().to_string();
+                webview.listen(name, move |_| events.lock().unwrap().push(format!("{} {}", label, name)));
+            }
+        }
+
+        let engine = SyncEngine::new(S3ClientBuilder::new().unscoped().build().unwrap());
+        engine.progress.write().await.status = SyncStatus::Syncing;
+
+        engine.pause_with_notify(&window);
+        assert!(engine.is_paused());
+        engine.resume_with_notify(&window);
+        assert!(!engine.is_paused());
+        engine.cancel_with_notify(&window).await;
+        assert_eq!(engine.get_progress().await.status, SyncStatus::Cancelling);
+        // Windows that didn't register hear nothing
+        assert_eq!(
+            *events.lock().unwrap(),
+            ["main sync://paused", "main sync://resumed", "main sync://cancelled"]
+        );
+
+        // The status settles once the sync task notices the cancel
+        assert!(matches!(engine.wait_if_paused().await, Err(SyncError::Cancelled)));
+        assert_eq!(engine.get_progress().await.status, SyncStatus::Idle);
+    }
+
+    #[test]
+    fn test_sync_windows_register_by_label() {
+        let app = tauri::test::mock_app();
+        let window = tauri::WebviewWindowBuilder::new(&app, "main", Default::default())
+            .build()
+            .unwrap()
+            .as_ref()
+            .window();
+        let windows = SyncWindows::default();
+        let shared = windows.clone();
+
+        // Registering again after a reload replaces the stale window
+        windows.register(window.clone());
+        shared.register(window);
+        let labels = || windows.0.read().unwrap().iter().map(|w| w.label().to_string()).collect::<Vec<_>>();
+        assert_eq!(labels(), ["main"]);
+
+        shared.unregister("main");
+        assert!(labels().is_empty());
+    }
+
+    #[test]
+    fn test_sync_windows_emit_once_per_window() {
+        use tauri::Listener;
+
+        let app = tauri::test::mock_app();
+        let main = tauri::WebviewWindowBuilder::new(&app, "main", Default::default()).build().unwrap();
+        let settings = tauri::WebviewWindowBuilder::new(&app, "settings", Default::default()).build().unwrap();
+        let heard = Arc::new(std::sync::Mutex::new(Vec::new()));
+        for webview in [&main, &settings] {
+            let heard = Arc::clone(&heard);
+            let label = webview.label().to_string();
+            webview.listen("sync://paused", move |_| heard.lock().unwrap().push(label.clone()));
+        }
+
+        // The calling window may be registered too
+        let windows = SyncWindows::default();
+        windows.register(main.as_ref().window());
+        windows.register(settings.as_ref().window());
+        windows.emit_state_change(&main.as_ref().window(), "sync://paused");
+
+        let mut heard = heard.lock().unwrap().clone();
+        heard.sort();
+        assert_eq!(heard, ["main", "settings"]);
+    }
+}
@@ -0,0 +1,83 @@
+//! Exclude patterns applied to local files while scanning
+
+use glob::{MatchOptions, Pattern};
+use unicode_normalization::UnicodeNormalization;
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+/// Glob patterns for files that are never synced (`SyncConfig::exclude_patterns`)
+///
+/// Paths and patterns are compared in NFC, so a name macOS stores decomposed
+/// (NFD) matches a pattern typed composed, and the other way round.
+#[derive(Debug, Clone, Default)]
+pub struct SyncFilter {
+    patterns: Vec<Pattern>,
+}
+
+impl SyncFilter {
+    /// Compile `patterns`, failing on the first invalid one
+    pub fn new(patterns: &[String]) -> Result<Self, String> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                Pattern::new(&nfc(pattern)).map_err(|e| format!("Invalid exclude pattern {:?}: {}", pattern, e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { patterns })
+    }
+
+    /// Whether the file or folder at `relative_path`, relative to its source folder,
+    /// is excluded: some pattern matches the whole path or one of its names
+    pub fn should_exclude(&self, relative_path: &str) -> bool {
+        if self.patterns.is_empty() {
+            return false;
+        }
+        let path = nfc(relative_path).replace('\\', "/");
+        self.patterns.iter().any(|pattern| {
+            pattern.matches_with(&path, MATCH_OPTIONS)
+                || path.split('/').any(|name| pattern.matches_with(name, MATCH_OPTIONS))
+        })
+    }
+}
+
+fn nfc(s: &str) -> String {
+    s.nfc().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(patterns: &[&str]) -> SyncFilter {
+        SyncFilter::new(&patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn test_should_exclude() {
+        let filter = filter(&["*.tmp", "node_modules", "build/*.o"]);
+        assert!(filter.should_exclude("a.tmp"));
+        assert!(filter.should_exclude("deep/in/here.tmp"));
+        assert!(filter.should_exclude("app/node_modules/left-pad/index.js"));
+        assert!(filter.should_exclude("build/main.o"));
+        assert!(filter.should_exclude("src\\cache.tmp"));
+        assert!(!filter.should_exclude("src/main.rs"));
+        assert!(!SyncFilter::default().should_exclude("a.tmp"));
+        assert!(SyncFilter::new(&["[".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_matches_across_unicode_normalization() {
+        // "café" composed (NFC, as typed) and decomposed (NFD, as macOS may store it)
+        let composed = "caf\u{e9}";
+        let decomposed = "cafe\u{301}";
+        assert_ne!(composed, decomposed);
+
+        assert!(filter(&[&format!("{}/*", composed)]).should_exclude(&format!("{}/menu.txt", decomposed)));
+        assert!(filter(&[decomposed]).should_exclude(&format!("photos/{}", composed)));
+        assert!(filter(&["日本語"]).should_exclude("日本語/テスト.txt"));
+    }
+}
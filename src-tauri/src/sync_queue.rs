@@ -0,0 +1,284 @@
+//! Uploads and downloads queued to run one after another, highest priority first
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::Notify;
+
+/// A sync waiting in the queue
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SyncJob {
+    Upload { source_paths: Vec<PathBuf> },
+    Download { cloud_folder: String, target_path: PathBuf },
+}
+
+/// Where a job is in the queue, for `get_queue_status`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedJobStatus {
+    pub id: String,
+    /// 0 for the running job, then 1, 2, ... in the order the rest will run
+    pub position: usize,
+    pub running: bool,
+    pub priority: u8,
+    pub job: SyncJob,
+    pub queued_at: DateTime<Utc>,
+    /// Seconds until the job starts, from the average length of the jobs run so far;
+    /// None until one has finished
+    pub estimated_wait_secs: Option<u64>,
+}
+
+#[derive(Debug)]
+struct QueuedJob {
+    id: String,
+    job: SyncJob,
+    priority: u8,
+    /// Order of arrival, so jobs of the same priority run first come, first served
+    seq: u64,
+    queued_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    pending: Vec<QueuedJob>,
+    running: Option<(QueuedJob, Instant)>,
+    next_seq: u64,
+    finished_jobs: u32,
+    finished_secs: f64,
+}
+
+impl QueueState {
+    /// Move the next job to run into `running`
+    fn start_next(&mut self) -> Option<SyncJob> {
+        let next = self
+            .pending
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, queued)| (queued.priority, std::cmp::Reverse(queued.seq)))
+            .map(|(index, _)| index)?;
+        let queued = self.pending.remove(next);
+        let job = queued.job.clone();
+        self.running = Some((queued, Instant::now()));
+        Some(job)
+    }
+
+    fn finish_running(&mut self) {
+        if let Some((_, started)) = self.running.take() {
+            self.finished_jobs += 1;
+            self.finished_secs += started.elapsed().as_secs_f64();
+        }
+    }
+
+    fn average_secs(&self) -> Option<f64> {
+        (self.finished_jobs > 0).then(|| self.finished_secs / self.finished_jobs as f64)
+    }
+
+    fn status(&self) -> Vec<QueuedJobStatus> {
+        let average = self.average_secs();
+        let mut pending: Vec<&QueuedJob> = self.pending.iter().collect();
+        pending.sort_by_key(|queued| (std::cmp::Reverse(queued.priority), queued.seq));
+
+        // Time left on the running job, then a full average for each job ahead
+        let running_left = self
+            .running
+            .as_ref()
+            .map(|(_, started)| average.map(|avg| (avg - started.elapsed().as_secs_f64()).max(0.0)))
+            .unwrap_or(Some(0.0));
+
+        let status = |queued: &QueuedJob, position: usize, wait: Option<f64>| QueuedJobStatus {
+            id: queued.id.clone(),
+            position,
+            running: position == 0,
+            priority: queued.priority,
+            job: queued.job.clone(),
+            queued_at: queued.queued_at,
+            estimated_wait_secs: wait.map(|secs| secs.round() as u64),
+        };
+        let running = self.running.iter().map(|(queued, _)| status(queued, 0, Some(0.0)));
+        let waiting = pending.into_iter().enumerate().map(|(ahead, queued)| {
+            let wait = match ahead {
+                0 => running_left,
+                _ => running_left.zip(average).map(|(left, avg)| left + ahead as f64 * avg),
+            };
+            status(queued, ahead + 1, wait)
+        });
+        running.chain(waiting).collect()
+    }
+}
+
+/// Jobs run one at a time by the task from `run`. Pausing the queue stops it
+/// starting new jobs; a job already running carries on.
+#[derive(Clone, Default)]
+pub struct SyncQueue {
+    state: Arc<Mutex<QueueState>>,
+    wake: Arc<Notify>,
+    paused: Arc<AtomicBool>,
+}
+
+impl SyncQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Add a job; higher `priority` runs sooner. Returns the job's ID.
+    pub fn enqueue(&self, job: SyncJob, priority: u8) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        let mut state = self.lock();
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.pending.push(QueuedJob {
+            id: id.clone(),
+            job,
+            priority,
+            seq,
+            queued_at: Utc::now(),
+        });
+        drop(state);
+        self.wake.notify_one();
+        id
+    }
+
+    /// Stop starting queued jobs until `resume`
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Release);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Release);
+        self.wake.notify_one();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Acquire)
+    }
+
+    /// Drop every job that hasn't started
+    pub fn clear(&self) {
+        self.lock().pending.clear();
+    }
+
+    /// The running job, then the waiting ones in the order they will run
+    pub fn status(&self) -> Vec<QueuedJobStatus> {
+        self.lock().status()
+    }
+
+    /// Task that takes jobs off the queue and awaits `run` for each in turn; spawn it once
+    pub fn run<F, Fut>(&self, run: F) -> impl Future<Output = ()> + Send + 'static
+    where
+        F: Fn(SyncJob) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        let queue = self.clone();
+        async move {
+            loop {
+                let job = if queue.is_paused() { None } else { queue.lock().start_next() };
+                match job {
+                    Some(job) => {
+                        run(job).await;
+                        queue.lock().finish_running();
+                    }
+                    None => queue.wake.notified().await,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn upload(name: &str) -> SyncJob {
+        SyncJob::Upload { source_paths: vec![PathBuf::from(name)] }
+    }
+
+    #[test]
+    fn test_queue_order_and_status() {
+        let queue = SyncQueue::new();
+        let low = queue.enqueue(upload("low"), 1);
+        let high = queue.enqueue(upload("high"), 9);
+        let low_2 = queue.enqueue(upload("low 2"), 1);
+        let download = SyncJob::Download { cloud_folder: "photos".into(), target_path: PathBuf::from("/tmp/photos") };
+        let mid = queue.enqueue(download.clone(), 5);
+
+        let status = queue.status();
+        let ids: Vec<&str> = status.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, [&high, &mid, &low, &low_2]);
+        assert_eq!(status.iter().map(|s| s.position).collect::<Vec<_>>(), [1, 2, 3, 4]);
+        assert_eq!(status[1].job, download);
+        assert!(status.iter().all(|s| !s.running));
+        // Nothing has run yet to estimate from, except that the first job starts right away
+        assert_eq!(status[0].estimated_wait_secs, Some(0));
+        assert_eq!(status[1].estimated_wait_secs, None);
+
+        let mut state = queue.lock();
+        assert_eq!(state.start_next(), Some(upload("high")));
+        state.finished_jobs = 2;
+        state.finished_secs = 60.0;
+        drop(state);
+
+        let status = queue.status();
+        assert!(status[0].running && status[0].id == high);
+        let waits: Vec<_> = status.iter().map(|s| s.estimated_wait_secs.unwrap()).collect();
+        assert_eq!(waits, [0, 30, 60, 90]);
+    }
+
+    #[tokio::test]
+    async fn test_queue_runs_jobs_one_at_a_time() {
+        let queue = SyncQueue::new();
+        queue.pause();
+        queue.enqueue(upload("a"), 0);
+        queue.enqueue(upload("b"), 0);
+        queue.enqueue(upload("urgent"), 200);
+
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let running = Arc::new(AtomicBool::new(false));
+        let runner = tokio::spawn(queue.run({
+            let (ran, running) = (Arc::clone(&ran), Arc::clone(&running));
+            move |job| {
+                let (ran, running) = (Arc::clone(&ran), Arc::clone(&running));
+                async move {
+                    assert!(!running.swap(true, Ordering::SeqCst), "jobs overlapped");
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    ran.lock().unwrap().push(job);
+                    running.store(false, Ordering::SeqCst);
+                }
+            }
+        }));
+
+        // Nothing starts while paused
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(ran.lock().unwrap().is_empty());
+        assert_eq!(queue.status().len(), 3);
+
+        queue.resume();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while ran.lock().unwrap().len() < 3 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(*ran.lock().unwrap(), [upload("urgent"), upload("a"), upload("b")]);
+        assert!(queue.status().is_empty());
+
+        // Jobs queued later are picked up too
+        queue.enqueue(upload("c"), 0);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while ran.lock().unwrap().len() < 4 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        runner.abort();
+    }
+}
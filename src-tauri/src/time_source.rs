@@ -0,0 +1,72 @@
+//! Clock the sync engine reads, so tests can move time forward themselves
+
+#[cfg(test)]
+use std::sync::{Arc, RwLock};
+#[cfg(test)]
+use std::time::Duration;
+use std::time::{Instant, SystemTime};
+
+/// Where the sync engine gets the current time from
+pub trait TimeSource: Send + Sync {
+    /// Monotonic time, for speeds, ETAs and cache ages
+    fn now_instant(&self) -> Instant;
+    /// Wall-clock time, for timestamps that are stored or shown
+    fn now_system(&self) -> SystemTime;
+}
+
+/// The system clock; what every engine uses unless given another
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealTimeSource;
+
+impl TimeSource for RealTimeSource {
+    fn now_instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn now_system(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when `advance` is called. Clones share the same time.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct MockTimeSource {
+    pub current_instant: Arc<RwLock<Instant>>,
+    pub current_system: Arc<RwLock<SystemTime>>,
+}
+
+#[cfg(test)]
+impl MockTimeSource {
+    /// Stopped at the current time
+    pub fn new() -> Self {
+        Self {
+            current_instant: Arc::new(RwLock::new(Instant::now())),
+            current_system: Arc::new(RwLock::new(SystemTime::now())),
+        }
+    }
+
+    /// Move both clocks forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        *self.current_instant.write().unwrap_or_else(|e| e.into_inner()) += duration;
+        *self.current_system.write().unwrap_or_else(|e| e.into_inner()) += duration;
+    }
+}
+
+#[cfg(test)]
+impl Default for MockTimeSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+impl TimeSource for MockTimeSource {
+    fn now_instant(&self) -> Instant {
+        *self.current_instant.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn now_system(&self) -> SystemTime {
+        *self.current_system.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,48 @@
+//! Runs the keygen binary the way scripts use it: piping --format json into jq
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Pipe `input` through `jq -r <filter>`, or None if jq is not installed
+fn jq(filter: &str, input: &[u8]) -> Option<String> {
+    let mut child = Command::new("jq")
+        .args(["-r", filter])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .ok()?;
+    child.stdin.take()?.write_all(input).ok()?;
+    let output = child.wait_with_output().ok()?;
+    assert!(output.status.success(), "jq rejected keygen output");
+    Some(String::from_utf8(output.stdout).unwrap())
+}
+
+#[test]
+fn json_output_pipes_into_jq() {
+    let output = Command::new(env!("CARGO_BIN_EXE_keygen"))
+        .args(["--name", "Pipe User", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let Some(key) = jq(".key", &output.stdout) else {
+        eprintln!("jq not found, skipping");
+        return;
+    };
+    let key = key.trim();
+    assert!(key.starts_with("EXAD-"), "unexpected key: {}", key);
+    assert!(!key.contains(char::is_whitespace));
+}
+
+#[test]
+fn quiet_output_is_only_the_key() {
+    let output = Command::new(env!("CARGO_BIN_EXE_keygen"))
+        .args(["--name", "Quiet User", "--quiet"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 1);
+    assert!(stdout.starts_with("EXAD-"));
+}
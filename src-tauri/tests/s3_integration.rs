@@ -0,0 +1,679 @@
+//! Runs every S3Client operation against a real S3-compatible server.
+//!
+//! Needs `--features integration-tests` and `S3_TEST_ENDPOINT` pointing at MinIO or
+//! localstack (`make integration-test` starts MinIO from docker-compose.yml).
+//! Credentials default to MinIO's `minioadmin`; override them with
+//! `S3_TEST_ACCESS_KEY` and `S3_TEST_SECRET_KEY`.
+#![cfg(feature = "integration-tests")]
+
+use futures::FutureExt;
+use rusoto_core::{HttpClient, Region};
+use rusoto_credential::StaticProvider;
+use rusoto_s3::{
+    CreateBucketRequest, DeleteBucketRequest, DeleteObjectRequest, HeadObjectRequest, ListObjectVersionsRequest,
+    PutObjectRequest, S3Client as RusotoS3Client, S3,
+};
+use std::collections::HashMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use sync2bucket_lib::admin::{ActivityLogEntry, AdminClient};
+use sync2bucket_lib::crypto::{encrypt_key, KeyPayload};
+use sync2bucket_lib::s3_client::{
+    DownloadResult, LockMode, ObjectCannedAcl, S3Client, S3ClientBuilder, S3Destination, S3Error,
+    DEFAULT_MULTIPART_PART_SIZE,
+};
+
+struct TestServer {
+    endpoint: String,
+    access_key: String,
+    secret_key: String,
+}
+
+impl TestServer {
+    /// The server from the environment, or None to skip the test
+    fn from_env() -> Option<Self> {
+        let Ok(endpoint) = std::env::var("S3_TEST_ENDPOINT") else {
+            eprintln!("S3_TEST_ENDPOINT not set, skipping");
+            return None;
+        };
+        let var = |name: &str| std::env::var(name).unwrap_or_else(|_| "minioadmin".to_string());
+        Some(Self {
+            endpoint,
+            access_key: var("S3_TEST_ACCESS_KEY"),
+            secret_key: var("S3_TEST_SECRET_KEY"),
+        })
+    }
+
+    /// Raw client for bucket management and checks S3Client doesn't expose
+    fn raw_client(&self) -> RusotoS3Client {
+        RusotoS3Client::new_with(
+            HttpClient::new().unwrap(),
+            StaticProvider::new_minimal(self.access_key.clone(), self.secret_key.clone()),
+            Region::Custom {
+                name: "us-east-1".to_string(),
+                endpoint: self.endpoint.clone(),
+            },
+        )
+    }
+}
+
+/// Run `test` against a fresh bucket, deleting it and its contents afterwards even if
+/// the test fails
+async fn with_bucket<F, Fut>(name: &str, test: F)
+where
+    F: FnOnce(Arc<S3Client>, RusotoS3Client, String) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    with_bucket_options(name, false, test).await
+}
+
+/// Like `with_bucket`, for a bucket created with object locking enabled. Tests must
+/// leave every compliance-mode lock expired, or the bucket can't be deleted.
+async fn with_locked_bucket<F, Fut>(name: &str, test: F)
+where
+    F: FnOnce(Arc<S3Client>, RusotoS3Client, String) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    with_bucket_options(name, true, test).await
+}
+
+async fn with_bucket_options<F, Fut>(name: &str, object_lock: bool, test: F)
+where
+    F: FnOnce(Arc<S3Client>, RusotoS3Client, String) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let Some(server) = TestServer::from_env() else {
+        return;
+    };
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+    let bucket = format!("sync2bucket-{}-{}", name, nanos);
+
+    let raw = server.raw_client();
+    raw.create_bucket(CreateBucketRequest {
+        bucket: bucket.clone(),
+        object_lock_enabled_for_bucket: object_lock.then_some(true),
+        ..Default::default()
+    })
+    .await
+    .expect("failed to create test bucket");
+
+    let client = S3ClientBuilder::new()
+        .unscoped()
+        .destination(S3Destination {
+            endpoint: server.endpoint.clone(),
+            bucket: bucket.clone(),
+            access_key: server.access_key.clone(),
+            secret_key: server.secret_key.clone(),
+        })
+        .build()
+        .unwrap();
+    let client = Arc::new(client);
+
+    let result = AssertUnwindSafe(test(Arc::clone(&client), server.raw_client(), bucket.clone()))
+        .catch_unwind()
+        .await;
+
+    if object_lock {
+        delete_all_versions(&raw, &bucket).await;
+    } else {
+        client.delete_all_objects().await.unwrap();
+    }
+    raw.delete_bucket(DeleteBucketRequest {
+        bucket,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    if let Err(panic) = result {
+        std::panic::resume_unwind(panic);
+    }
+}
+
+/// Delete every version and delete marker in a versioned bucket, bypassing
+/// governance-mode locks
+async fn delete_all_versions(raw: &RusotoS3Client, bucket: &str) {
+    let listing = raw
+        .list_object_versions(ListObjectVersionsRequest {
+            bucket: bucket.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let versions = listing.versions.unwrap_or_default().into_iter().map(|v| (v.key, v.version_id));
+    let markers = listing.delete_markers.unwrap_or_default().into_iter().map(|m| (m.key, m.version_id));
+    for (key, version_id) in versions.chain(markers) {
+        raw.delete_object(DeleteObjectRequest {
+            bucket: bucket.to_string(),
+            key: key.unwrap(),
+            version_id,
+            bypass_governance_retention: Some(true),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    }
+}
+
+/// Local file under the temp dir holding `contents`
+fn local_file(name: &str, contents: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("s3_integration_{}_{}", std::process::id(), name));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// Hex MD5 of `data`, which is what S3 uses as the ETag of a single-part upload
+fn md5_hex(data: &[u8]) -> String {
+    use md5::{Digest, Md5};
+    hex::encode(Md5::digest(data))
+}
+
+#[tokio::test]
+async fn upload_download_round_trip() {
+    with_bucket("roundtrip", |client, raw, bucket| async move {
+        let contents: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+        let source = local_file("roundtrip_src", &contents);
+        let tags = HashMap::from([("project".to_string(), "integration".to_string())]);
+
+        client
+            .upload_file_with_progress(&source, "docs/data.bin", &tags, |_| {})
+            .await
+            .unwrap();
+
+        let info = client.get_object_info("docs/data.bin").await.unwrap();
+        assert_eq!(info.size, contents.len() as u64);
+        assert_eq!(info.etag.as_deref(), Some(md5_hex(&contents).as_str()));
+
+        let target = std::env::temp_dir().join(format!("s3_integration_{}_roundtrip_dst", std::process::id()));
+        client.download_file("docs/data.bin", &target).await.unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), contents);
+
+        assert_eq!(client.get_object_tags("docs/data.bin").await.unwrap(), tags);
+
+        // Uploads use the configured storage class; S3 reports STANDARD by leaving it out
+        let head = raw
+            .head_object(HeadObjectRequest {
+                bucket,
+                key: "docs/data.bin".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(matches!(head.storage_class.as_deref(), None | Some("STANDARD")));
+
+        std::fs::remove_file(source).unwrap();
+        std::fs::remove_file(target).unwrap();
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn conditional_download_skips_unchanged_object() {
+    with_bucket("conditional", |client, _, _| async move {
+        client.upload_bytes(b"version 1", "notes.txt", "text/plain").await.unwrap();
+        let etag = client.get_object_info("notes.txt").await.unwrap().etag.unwrap();
+
+        let target = local_file("conditional_dst", b"");
+        let downloaded = client.download_file_conditional("notes.txt", &target, None).await.unwrap();
+        assert_eq!(downloaded, DownloadResult::Downloaded);
+        assert_eq!(std::fs::read(&target).unwrap(), b"version 1");
+
+        // The server answers 304 and the local file isn't written to at all
+        std::fs::write(&target, b"local marker").unwrap();
+        let modified = std::fs::metadata(&target).unwrap().modified().unwrap();
+        let result = client.download_file_conditional("notes.txt", &target, Some(&etag)).await.unwrap();
+        assert_eq!(result, DownloadResult::NotModified);
+        assert_eq!(std::fs::read(&target).unwrap(), b"local marker");
+        assert_eq!(std::fs::metadata(&target).unwrap().modified().unwrap(), modified);
+
+        // A changed object is downloaded again
+        client.upload_bytes(b"version 2", "notes.txt", "text/plain").await.unwrap();
+        let result = client.download_file_conditional("notes.txt", &target, Some(&etag)).await.unwrap();
+        assert_eq!(result, DownloadResult::Downloaded);
+        assert_eq!(std::fs::read(&target).unwrap(), b"version 2");
+
+        std::fs::remove_file(target).unwrap();
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn upload_bytes_keeps_content_type() {
+    with_bucket("bytes", |client, _, _| async move {
+        client
+            .upload_bytes(br#"{"ok":true}"#, "manifest.json", "application/json")
+            .await
+            .unwrap();
+
+        let info = client.get_object_info("manifest.json").await.unwrap();
+        assert_eq!(info.content_type.as_deref(), Some("application/json"));
+        assert_eq!(info.etag.as_deref(), Some(md5_hex(br#"{"ok":true}"#).as_str()));
+
+        let manifest: HashMap<String, bool> = client.read_json_from_key("manifest.json").await.unwrap();
+        assert!(manifest["ok"]);
+        let missing: HashMap<String, bool> = client.read_json_from_key("missing.json").await.unwrap();
+        assert!(missing.is_empty());
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn multipart_upload_round_trip() {
+    with_bucket("multipart", |client, _, _| async move {
+        let contents: Vec<u8> = (0..DEFAULT_MULTIPART_PART_SIZE + 1024).map(|i| (i % 253) as u8).collect();
+        let source = local_file("multipart_src", &contents);
+
+        client.upload_file(&source, "big.bin").await.unwrap();
+
+        // Multipart ETags are `<hash>-<parts>`
+        let info = client.get_object_info("big.bin").await.unwrap();
+        assert_eq!(info.size, contents.len() as u64);
+        assert!(info.etag.unwrap().ends_with("-2"));
+
+        let target = std::env::temp_dir().join(format!("s3_integration_{}_multipart_dst", std::process::id()));
+        client.download_file("big.bin", &target).await.unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), contents);
+
+        // Ranged parallel download, with a shorter final chunk
+        client.download_file_multipart("big.bin", &target, 3 * 1024 * 1024, 4).await.unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), contents);
+
+        // An atomic upload leaves no temp object behind
+        client.upload_file_atomic(&source, "copy.bin").await.unwrap();
+        let mut keys: Vec<_> = client.list_objects("").await.unwrap().into_iter().map(|o| o.key).collect();
+        keys.sort();
+        assert_eq!(keys, vec!["big.bin".to_string(), "copy.bin".to_string()]);
+
+        std::fs::remove_file(source).unwrap();
+        std::fs::remove_file(target).unwrap();
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn list_copy_and_delete() {
+    with_bucket("listing", |client, _, _| async move {
+        for path in ["Photos/a.jpg", "Photos/b.jpg", "Docs/c.txt"] {
+            client.upload_string(path, path).await.unwrap();
+        }
+
+        let mut folders = client.list_folders("").await.unwrap();
+        folders.sort();
+        assert_eq!(folders, vec!["Docs/".to_string(), "Photos/".to_string()]);
+
+        let photos = client.list_objects("Photos/").await.unwrap();
+        assert_eq!(photos.len(), 2);
+        for object in &photos {
+            assert_eq!(object.etag.as_deref(), Some(md5_hex(object.key.as_bytes()).as_str()));
+        }
+
+        client.copy_object("Photos/a.jpg", "Archive/a.jpg").await.unwrap();
+        let copy = client.get_object_info("Archive/a.jpg").await.unwrap();
+        let original = client.get_object_info("Photos/a.jpg").await.unwrap();
+        assert_eq!(copy.etag, original.etag);
+
+        client.delete_object("Photos/a.jpg").await.unwrap();
+        assert!(client.get_object_info("Photos/a.jpg").await.is_err());
+        assert_eq!(client.list_objects("Photos/").await.unwrap().len(), 1);
+        assert_eq!(client.list_objects("").await.unwrap().len(), 3);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn unicode_keys_round_trip() {
+    with_bucket("unicode", |client, _, _| async move {
+        let source = local_file("unicode_src", "こんにちは".as_bytes());
+        client.upload_file(&source, "日本語/テスト.txt").await.unwrap();
+        client.upload_string("صور/a b+🎉.txt", "emoji").await.unwrap();
+
+        let mut folders = client.list_folders("").await.unwrap();
+        folders.sort();
+        assert_eq!(folders, vec!["صور/".to_string(), "日本語/".to_string()]);
+
+        let objects = client.list_objects("日本語/").await.unwrap();
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].key, "日本語/テスト.txt");
+        assert_eq!(client.list_objects("صور/").await.unwrap()[0].key, "صور/a b+🎉.txt");
+
+        let target = std::env::temp_dir().join(format!("s3_integration_{}_unicode_dst", std::process::id()));
+        client.download_file(&objects[0].key, &target).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "こんにちは");
+
+        std::fs::remove_file(source).unwrap();
+        std::fs::remove_file(target).unwrap();
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn zero_byte_file_round_trip() {
+    with_bucket("empty", |client, _, _| async move {
+        let source = local_file("empty_src", b"");
+        client.upload_file(&source, "empty.txt").await.unwrap();
+        assert_eq!(client.get_object_info("empty.txt").await.unwrap().size, 0);
+
+        let target = std::env::temp_dir().join(format!("s3_integration_{}_empty_dst", std::process::id()));
+        std::fs::write(&target, "stale").unwrap();
+        client.download_file("empty.txt", &target).await.unwrap();
+        assert_eq!(std::fs::metadata(&target).unwrap().len(), 0);
+
+        std::fs::remove_file(source).unwrap();
+        std::fs::remove_file(target).unwrap();
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn upload_if_absent_never_overwrites() {
+    with_bucket("if_absent", |client, raw, bucket| async move {
+        let source = local_file("if_absent_src", b"ours");
+        assert!(client.upload_file_if_absent(&source, "race.txt").await.unwrap());
+        // The same contents again are left alone
+        assert!(!client.upload_file_if_absent(&source, "race.txt").await.unwrap());
+
+        // Another client writes between our check and our upload
+        let theirs = raw
+            .put_object(PutObjectRequest {
+                bucket,
+                key: "race.txt".to_string(),
+                body: Some(b"theirs".to_vec().into()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let result = client.upload_file_if_absent(&source, "race.txt").await;
+        match result {
+            Err(S3Error::ConflictError { existing_etag }) => {
+                assert_eq!(Some(format!("\"{}\"", existing_etag)), theirs.e_tag);
+            }
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        let target = std::env::temp_dir().join(format!("s3_integration_{}_if_absent_dst", std::process::id()));
+        client.download_file("race.txt", &target).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "theirs");
+
+        // Multipart-sized files are checked with a HEAD
+        let big = local_file("if_absent_big", &vec![3u8; DEFAULT_MULTIPART_PART_SIZE + 1]);
+        assert!(client.upload_file_if_absent(&big, "big.bin").await.unwrap());
+        let result = client.upload_file_if_absent(&big, "big.bin").await;
+        assert!(matches!(result, Err(S3Error::ConflictError { .. })), "{:?}", result);
+
+        std::fs::remove_file(source).unwrap();
+        std::fs::remove_file(target).unwrap();
+        std::fs::remove_file(big).unwrap();
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn concurrent_uploads_if_absent_keep_one_winner() {
+    with_bucket("if_absent_race", |client, _, _| async move {
+        let ours = local_file("race_ours", b"ours");
+        let theirs = local_file("race_theirs", b"theirs");
+        let target = std::env::temp_dir().join(format!("s3_integration_{}_race_dst", std::process::id()));
+
+        for atomic in [false, true] {
+            let key = format!("race_{}.txt", atomic);
+            let upload = |source| {
+                let client = Arc::clone(&client);
+                let key = key.clone();
+                async move {
+                    if atomic {
+                        client.upload_file_atomic_if_absent_with_progress(source, &key, &HashMap::new(), |_| {}).await
+                    } else {
+                        client.upload_file_if_absent(source, &key).await
+                    }
+                }
+            };
+            // Two writers of different contents at once: exactly one of them lands
+            let (first, second) = tokio::join!(upload(&ours), upload(&theirs));
+            let winner = match (first, second) {
+                (Ok(true), Err(S3Error::ConflictError { .. })) => "ours",
+                (Err(S3Error::ConflictError { .. }), Ok(true)) => "theirs",
+                other => panic!("expected one upload and one conflict, got {:?}", other),
+            };
+            client.download_file(&key, &target).await.unwrap();
+            assert_eq!(std::fs::read_to_string(&target).unwrap(), winner);
+        }
+        // No temp keys are left behind
+        let keys: Vec<String> = client.list_objects("").await.unwrap().into_iter().map(|o| o.key).collect();
+        assert_eq!(keys.len(), 2, "{:?}", keys);
+
+        std::fs::remove_file(ours).unwrap();
+        std::fs::remove_file(theirs).unwrap();
+        std::fs::remove_file(target).unwrap();
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn json_write_only_if_unchanged() {
+    with_bucket("json-etag", |client, _, _| async move {
+        let (value, etag): (Vec<u32>, _) = client.read_json_with_etag("state.json").await.unwrap();
+        assert!(value.is_empty() && etag.is_none());
+
+        assert!(client.write_json_if_unchanged("state.json", &vec![1], None).await.unwrap());
+        // It's no longer absent
+        assert!(!client.write_json_if_unchanged("state.json", &vec![2], None).await.unwrap());
+
+        let (value, etag): (Vec<u32>, _) = client.read_json_with_etag("state.json").await.unwrap();
+        assert_eq!(value, vec![1]);
+        assert!(client.write_json_if_unchanged("state.json", &vec![3], etag.as_deref()).await.unwrap());
+        // Another writer got there first
+        assert!(!client.write_json_if_unchanged("state.json", &vec![4], etag.as_deref()).await.unwrap());
+        assert_eq!(client.read_json_from_key::<Vec<u32>>("state.json").await.unwrap(), vec![3]);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn concurrent_compactions_keep_every_entry() {
+    with_bucket("compaction", |client, _, _| async move {
+        let first = AdminClient::with_client(Arc::clone(&client));
+        let second = AdminClient::with_client(Arc::clone(&client));
+        for i in 0..20 {
+            let entry = ActivityLogEntry::new("EXAD-TEST", "Tester", "uid-1", "login", Some(i.to_string()));
+            first.append_activity(vec![entry]).await.unwrap();
+        }
+
+        let (a, b) = tokio::join!(first.compact_activity_log(), second.compact_activity_log());
+        let merged = a.unwrap_or(0) + b.unwrap_or(0);
+        assert!(merged >= 20, "only {} shards merged", merged);
+
+        let log = first.get_activity_log().await.unwrap();
+        assert_eq!(log.entries.len(), 20);
+        assert!(client.list_objects("_admin/activity_log/").await.unwrap().is_empty());
+    })
+    .await;
+}
+
+/// A valid key for `name`, with its uid
+fn license_key(name: &str) -> (String, String) {
+    let payload = KeyPayload::new(name);
+    (encrypt_key(&payload).unwrap(), payload.uid)
+}
+
+#[tokio::test]
+async fn revoke_and_unrevoke_with_open_whitelist() {
+    with_bucket("unrevoke-open", |client, _, _| async move {
+        let admin = AdminClient::with_client(client);
+        let (key, uid) = license_key("Revoked User");
+        let (other, _) = license_key("Other User");
+
+        admin.add_to_blacklist(&key, "Revoked User", &uid, "Lost laptop").await.unwrap();
+        assert!(!admin.validate_key_access(&key).await.unwrap().allowed);
+        assert!(admin.validate_key_access(&other).await.unwrap().allowed);
+
+        // An empty whitelist allows everyone, so the key isn't added to it
+        assert!(!admin.unrevoke_key(&key, "Revoked User", &uid).await.unwrap());
+        assert!(admin.get_whitelist().await.unwrap().entries.is_empty());
+        assert!(admin.validate_key_access(&key).await.unwrap().allowed);
+        assert!(admin.validate_key_access(&other).await.unwrap().allowed);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn revoke_and_unrevoke_with_whitelist() {
+    with_bucket("unrevoke-listed", |client, _, _| async move {
+        let admin = AdminClient::with_client(client);
+        let (key, uid) = license_key("Revoked User");
+        let (other, other_uid) = license_key("Other User");
+        admin.add_to_whitelist(&key, "Revoked User", &uid, None).await.unwrap();
+        admin.add_to_whitelist(&other, "Other User", &other_uid, None).await.unwrap();
+
+        // `keygen revoke --remove-from-whitelist`
+        admin.add_to_blacklist(&key, "Revoked User", &uid, "Lost laptop").await.unwrap();
+        admin.remove_from_whitelist(&key).await.unwrap();
+        assert!(!admin.validate_key_access(&key).await.unwrap().allowed);
+
+        assert!(admin.unrevoke_key(&key, "Revoked User", &uid).await.unwrap());
+        assert_eq!(admin.get_whitelist().await.unwrap().entries.len(), 2);
+        assert!(admin.validate_key_access(&key).await.unwrap().allowed);
+        assert!(admin.validate_key_access(&other).await.unwrap().allowed);
+
+        // A key still on the whitelist is left as it is
+        admin.add_to_blacklist(&other, "Other User", &other_uid, "Audit").await.unwrap();
+        assert!(!admin.unrevoke_key(&other, "Other User", &other_uid).await.unwrap());
+        assert!(admin.get_blacklist().await.unwrap().entries.is_empty());
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn object_lock_round_trip() {
+    with_locked_bucket("lock", |client, _, _| async move {
+        let source = local_file("lock_src", b"immutable");
+
+        // Short enough to expire before the bucket is cleaned up
+        let retain_until = chrono::Utc::now() + chrono::Duration::seconds(3);
+        client.upload_with_lock(&source, "locked.txt", retain_until).await.unwrap();
+        let info = client.get_object_lock_info("locked.txt").await.unwrap().unwrap();
+        assert_eq!(info.mode, "COMPLIANCE");
+        assert_eq!(info.retain_until.timestamp(), retain_until.timestamp());
+
+        let retain_until = chrono::Utc::now() + chrono::Duration::days(1);
+        let governance = LockMode::Governance;
+        client
+            .upload_file_locked_with_progress(&source, "governed.txt", &HashMap::new(), governance, retain_until, |_| {})
+            .await
+            .unwrap();
+        let info = client.get_object_lock_info("governed.txt").await.unwrap().unwrap();
+        assert_eq!(info.mode, "GOVERNANCE");
+
+        client.upload_file(&source, "plain.txt").await.unwrap();
+        assert_eq!(client.get_object_lock_info("plain.txt").await.unwrap(), None);
+        assert!(matches!(
+            client.get_object_lock_info("missing.txt").await,
+            Err(S3Error::FileNotFound(_))
+        ));
+
+        std::fs::remove_file(source).unwrap();
+        tokio::time::sleep(std::time::Duration::from_secs(4)).await;
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn checksum_verification() {
+    with_bucket("checksum", |client, raw, bucket| async move {
+        use base64::Engine;
+        use sha2::{Digest, Sha256};
+        let source = local_file("checksum_src", b"checked contents");
+        let expected = base64::engine::general_purpose::STANDARD.encode(Sha256::digest(b"checked contents"));
+
+        client.upload_file_with_verify(&source, "checked.txt").await.unwrap();
+        let info = client.get_object_info("checked.txt").await.unwrap();
+        assert_eq!(info.checksum_sha256.as_deref(), Some(expected.as_str()));
+        assert!(client.verify_object_checksum("checked.txt").await.unwrap());
+
+        // Corrupt the stored bytes while keeping the original checksum
+        raw.put_object(PutObjectRequest {
+            bucket,
+            key: "checked.txt".to_string(),
+            body: Some(b"checked contentz".to_vec().into()),
+            metadata: Some(HashMap::from([("sha256".to_string(), expected)])),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(!client.verify_object_checksum("checked.txt").await.unwrap());
+
+        // Multipart uploads carry no checksum to verify against
+        let big = local_file("checksum_big", &vec![7u8; DEFAULT_MULTIPART_PART_SIZE + 1]);
+        client.upload_file_with_verify(&big, "big.bin").await.unwrap();
+        assert!(client.verify_object_checksum("big.bin").await.is_err());
+
+        std::fs::remove_file(source).unwrap();
+        std::fs::remove_file(big).unwrap();
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn object_acl_round_trip() {
+    with_bucket("acl", |client, _, _| async move {
+        let source = local_file("acl_src", b"shared contents");
+
+        client.upload_with_acl(&source, "acl.txt", ObjectCannedAcl::Private).await.unwrap();
+        assert_eq!(client.get_object_acl("acl.txt").await.unwrap(), "private");
+
+        // MinIO only accepts "private"; servers with full ACL support report the change
+        match client.put_object_acl("acl.txt", ObjectCannedAcl::PublicRead).await {
+            Ok(()) => assert_eq!(client.get_object_acl("acl.txt").await.unwrap(), "public-read"),
+            Err(e) => eprintln!("public-read not supported by the server: {}", e),
+        }
+
+        std::fs::remove_file(source).unwrap();
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn warm_up_leaves_no_probe_behind() {
+    with_bucket("warmup", |client, _, bucket| async move {
+        let info = client.warm_up(false).await.unwrap();
+        assert_eq!(info.upload_latency_ms, None);
+
+        let info = client.warm_up(true).await.unwrap();
+        assert!(info.upload_latency_ms.is_some());
+        assert_eq!(info.bucket, bucket);
+        assert_eq!(info.endpoint, client.endpoint());
+        assert_eq!(info.region, client.detected_region().await.unwrap());
+        assert!(info.suggested_concurrency >= 1);
+        assert!(client.list_objects("").await.unwrap().is_empty());
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn missing_bucket_is_reported() {
+    with_bucket("exists", |client, _, _| async move {
+        assert!(client.bucket_exists().await.unwrap());
+        assert!(client.check_connection().await.bucket_exists);
+    })
+    .await;
+
+    let Some(server) = TestServer::from_env() else {
+        return;
+    };
+    let client = S3ClientBuilder::new()
+        .unscoped()
+        .destination(S3Destination {
+            endpoint: server.endpoint.clone(),
+            bucket: "sync2bucket-does-not-exist".to_string(),
+            access_key: server.access_key.clone(),
+            secret_key: server.secret_key.clone(),
+        })
+        .build()
+        .unwrap();
+
+    assert!(!client.bucket_exists().await.unwrap());
+    let status = client.check_connection().await;
+    assert!(status.reachable && !status.bucket_exists);
+    assert!(matches!(client.list_objects("").await, Err(S3Error::BucketNotFound(_))));
+}
@@ -2,14 +2,14 @@
 
 import { useState } from 'react';
 import { motion } from 'framer-motion';
-import { validateKey } from '@/lib/tauri';
+import { validateKey, errorMessage } from '@/lib/tauri';
 import { useAppStore } from '@/lib/store';
 
 export default function KeyEntry() {
   const [key, setKey] = useState('');
   const [isLoading, setIsLoading] = useState(false);
   const [error, setError] = useState<string | null>(null);
-  const { setScreen, setUser } = useAppStore();
+  const { setScreen, setUser, setKeyWarning } = useAppStore();
 
   const handleSubmit = async (e: React.FormEvent) => {
     e.preventDefault();
@@ -31,12 +31,13 @@ export default function KeyEntry() {
           name: result.user_name,
           created: Date.now() / 1000,
         });
+        setKeyWarning(result.warning);
         setScreen('main');
       } else {
         setError(result.error || 'Invalid key');
       }
     } catch (err) {
-      setError(err instanceof Error ? err.message : 'Validation failed');
+      setError(errorMessage(err, 'Validation failed'));
     } finally {
       setIsLoading(false);
     }
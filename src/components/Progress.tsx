@@ -9,12 +9,17 @@ interface ProgressProps {
   onPause: () => void;
   onResume: () => void;
   onCancel: () => void;
+  onConfirm: () => void;
 }
 
-export default function Progress({ progress, onPause, onResume, onCancel }: ProgressProps) {
+export default function Progress({ progress, onPause, onResume, onCancel, onConfirm }: ProgressProps) {
   const isPaused = progress.status === 'Paused';
-  const isSyncing = progress.status === 'Syncing' || progress.status === 'Scanning';
-  const isCompleted = progress.status === 'Completed';
+  const isAwaitingConfirmation = typeof progress.status === 'object' && 'AwaitingConfirmation' in progress.status;
+  const isSyncing = progress.status === 'Syncing' ||
+    (typeof progress.status === 'object' && ('Scanning' in progress.status || 'Hashing' in progress.status));
+  const isCompleted = progress.status === 'Completed' ||
+    (typeof progress.status === 'object' &&
+      ('CompletedWithErrors' in progress.status || 'StoppedByLimit' in progress.status));
   const hasError = typeof progress.status === 'object' && 'Error' in progress.status;
 
   const percentage = progress.total_bytes > 0
@@ -63,7 +68,8 @@ export default function Progress({ progress, onPause, onResume, onCancel }: Prog
           
           <div>
             <h3 className="font-medium text-white">
-              {progress.direction === 'LocalToCloud' ? 'Uploading' : 'Downloading'}
+              {progress.direction === 'LocalToCloud' ? 'Uploading' :
+                progress.direction === 'CloudToLocal' ? 'Downloading' : 'Copying'}
             </h3>
             <p className="text-sm text-slate-400">
               {getStatusText(progress.status)}
@@ -114,8 +120,22 @@ export default function Progress({ progress, onPause, onResume, onCancel }: Prog
         </div>
       </div>
 
+      {/* Parallel Transfers */}
+      {progress.active_transfers > 1 && (
+        <div className="mb-6">
+          <div className="text-xs text-slate-500 mb-1">
+            Uploading {progress.active_transfers} files simultaneously:
+          </div>
+          <div className="text-sm text-slate-300 font-mono bg-black/20 px-3 py-2 rounded-lg space-y-1">
+            {progress.active_files.map((file) => (
+              <div key={file} className="truncate">{file}</div>
+            ))}
+          </div>
+        </div>
+      )}
+
       {/* Current File */}
-      {progress.current_file && (
+      {progress.current_file && progress.active_transfers <= 1 && (
         <div className="mb-6">
           <div className="text-xs text-slate-500 mb-1">Current file:</div>
           <div className="text-sm text-slate-300 truncate font-mono bg-black/20 px-3 py-2 rounded-lg">
@@ -124,11 +144,33 @@ export default function Progress({ progress, onPause, onResume, onCancel }: Prog
         </div>
       )}
 
+      {/* Failed Files */}
+      {progress.error_summary && (
+        <div className="mb-6 text-sm text-amber-300 bg-amber-500/10 px-3 py-2 rounded-lg">
+          {progress.error_summary}
+        </div>
+      )}
+
+      {/* Backup destination failures */}
+      {progress.secondary_errors.length > 0 && (
+        <div className="mb-6 text-sm text-amber-300 bg-amber-500/10 px-3 py-2 rounded-lg">
+          {progress.secondary_errors.length} {progress.secondary_errors.length === 1 ? 'write' : 'writes'} to backup
+          destinations failed
+        </div>
+      )}
+
       {/* Control Buttons */}
       <div className="flex gap-3">
         {!isCompleted && !hasError && (
           <>
-            {isPaused ? (
+            {isAwaitingConfirmation ? (
+              <button onClick={onConfirm} className="btn-primary flex-1 flex items-center justify-center gap-2">
+                <svg className="w-4 h-4" fill="none" viewBox="0 0 24 24" stroke="currentColor">
+                  <path strokeLinecap="round" strokeLinejoin="round" strokeWidth={2} d="M5 13l4 4L19 7" />
+                </svg>
+                Start Upload
+              </button>
+            ) : isPaused ? (
               <button onClick={onResume} className="btn-primary flex-1 flex items-center justify-center gap-2">
                 <svg className="w-4 h-4" fill="currentColor" viewBox="0 0 24 24">
                   <path d="M8 5v14l11-7z" />
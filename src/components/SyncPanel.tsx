@@ -9,14 +9,21 @@ import {
   selectFolder,
   startUpload,
   startDownload,
+  getInterruptedSync,
+  resumeLastUploadSession,
+  discardInterruptedSync,
   pauseSync,
   resumeSync,
   cancelSync,
+  confirmSync,
   getSyncProgress,
+  onRateLimited,
+  onSyncStateChanged,
   listCloudFolders,
   deleteAllFiles,
   checkCredentialsStatus,
   formatBytes,
+  errorMessage,
 } from '@/lib/tauri';
 import Progress from './Progress';
 import type { SyncProgress, CloudFolder, CredentialsStatus } from '@/lib/types';
@@ -38,6 +45,8 @@ export default function SyncPanel() {
     setCloudFolders,
     setScreen,
     setUser,
+    keyWarning,
+    setKeyWarning,
   } = useAppStore();
 
   const [mode, setMode] = useState<SyncMode>(null);
@@ -48,6 +57,8 @@ export default function SyncPanel() {
   const [isDeleting, setIsDeleting] = useState(false);
   const [deleteResult, setDeleteResult] = useState<{ success: boolean; count?: number; error?: string } | null>(null);
   const [credentialsStatus, setCredentialsStatus] = useState<CredentialsStatus | null>(null);
+  const [rateLimitedUntil, setRateLimitedUntil] = useState<number | null>(null);
+  const [interrupted, setInterrupted] = useState<SyncProgress | null>(null);
 
   // Poll for progress during sync
   useEffect(() => {
@@ -59,7 +70,7 @@ export default function SyncPanel() {
           const p = await getSyncProgress();
           setProgress(p);
           
-          if (p.status === 'Completed' || (typeof p.status === 'object' && 'Error' in p.status)) {
+          if (p.status === 'Completed' || (typeof p.status === 'object' && !('Scanning' in p.status) && !('Hashing' in p.status) && !('AwaitingConfirmation' in p.status))) {
             setIsSyncing(false);
           }
         } catch (err) {
@@ -71,6 +82,37 @@ export default function SyncPanel() {
     return () => clearInterval(interval);
   }, [isSyncing]);
 
+  // Show a notice while the server is rate limiting us
+  useEffect(() => {
+    if (!isSyncing) return;
+
+    const unlisten = onRateLimited(({ retry_after_secs }) => {
+      setRateLimitedUntil(Date.now() + retry_after_secs * 1000);
+    });
+
+    return () => {
+      unlisten.then((fn) => fn());
+      setRateLimitedUntil(null);
+    };
+  }, [isSyncing]);
+
+  // Refresh as soon as the sync is paused, resumed or cancelled, from here or elsewhere
+  useEffect(() => {
+    if (!isSyncing) return;
+
+    const unlisten = onSyncStateChanged(async () => {
+      try {
+        setProgress(await getSyncProgress());
+      } catch (err) {
+        console.error('Failed to get progress:', err);
+      }
+    });
+
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, [isSyncing]);
+
   // Check credentials status on mount
   useEffect(() => {
     const checkStatus = async () => {
@@ -84,6 +126,35 @@ export default function SyncPanel() {
     checkStatus();
   }, []);
 
+  // Offer to resume an upload the app quit during
+  useEffect(() => {
+    getInterruptedSync()
+      .then(setInterrupted)
+      .catch((err) => console.error('Failed to check for an interrupted sync:', err));
+  }, []);
+
+  const handleResumeInterrupted = async () => {
+    setInterrupted(null);
+    try {
+      const restored = await resumeLastUploadSession();
+      if (restored) {
+        setProgress(restored);
+        setIsSyncing(true);
+      }
+    } catch (err) {
+      console.error('Failed to resume sync:', err);
+    }
+  };
+
+  const handleDiscardInterrupted = async () => {
+    setInterrupted(null);
+    try {
+      await discardInterruptedSync();
+    } catch (err) {
+      console.error('Failed to discard interrupted sync:', err);
+    }
+  };
+
   // Load cloud folders when download mode is selected
   const loadCloudFolders = useCallback(async () => {
     setIsLoading(true);
@@ -147,6 +218,10 @@ export default function SyncPanel() {
     await resumeSync();
   };
 
+  const handleConfirm = async () => {
+    await confirmSync();
+  };
+
   const handleCancel = async () => {
     await cancelSync();
     setIsSyncing(false);
@@ -156,6 +231,7 @@ export default function SyncPanel() {
   const handleLogout = async () => {
     await logout();
     setUser(null);
+    setKeyWarning(null);
     setScreen('key-entry');
   };
 
@@ -175,7 +251,7 @@ export default function SyncPanel() {
       setDeleteResult({ success: true, count });
       setShowDeleteConfirm(false);
     } catch (err) {
-      setDeleteResult({ success: false, error: err instanceof Error ? err.message : 'Delete failed' });
+      setDeleteResult({ success: false, error: errorMessage(err, 'Delete failed') });
     } finally {
       setIsDeleting(false);
     }
@@ -191,7 +267,13 @@ export default function SyncPanel() {
             onPause={handlePause}
             onResume={handleResume}
             onCancel={handleCancel}
+            onConfirm={handleConfirm}
           />
+          {rateLimitedUntil !== null && rateLimitedUntil > Date.now() && (
+            <div className="mt-4 text-sm text-amber-300 bg-amber-500/10 px-3 py-2 rounded-lg">
+              Rate limited – retrying in {Math.ceil((rateLimitedUntil - Date.now()) / 1000)} seconds
+            </div>
+          )}
         </div>
       </div>
     );
@@ -270,6 +352,40 @@ export default function SyncPanel() {
           </motion.div>
         )}
 
+        {/* Key Expiry Banner */}
+        {keyWarning && (
+          <motion.div
+            initial={{ opacity: 0, y: -10 }}
+            animate={{ opacity: 1, y: 0 }}
+            className="mb-6 p-4 rounded-2xl flex items-center gap-3 bg-[#F5B841]/10 border border-[#F5B841]/20"
+          >
+            <div className="w-10 h-10 rounded-xl flex items-center justify-center flex-shrink-0 bg-[#F5B841]/20">
+              <svg className="w-5 h-5 text-[#F5B841]" fill="none" viewBox="0 0 24 24" stroke="currentColor">
+                <path strokeLinecap="round" strokeLinejoin="round" strokeWidth={2} d="M12 8v4l3 3m6-3a9 9 0 11-18 0 9 9 0 0118 0z" />
+              </svg>
+            </div>
+            <p className="flex-1 text-sm font-medium text-[#F5B841]">{keyWarning}</p>
+          </motion.div>
+        )}
+
+        {interrupted && (
+          <motion.div
+            initial={{ opacity: 0, y: -10 }}
+            animate={{ opacity: 1, y: 0 }}
+            className="mb-6 p-4 rounded-2xl flex items-center gap-3 bg-blue-500/10 border border-blue-500/20"
+          >
+            <p className="flex-1 text-sm font-medium text-blue-300">
+              An upload was interrupted after {interrupted.completed_files} of {interrupted.total_files} files.
+            </p>
+            <button onClick={handleResumeInterrupted} className="btn-primary text-sm px-3 py-1.5">
+              Resume
+            </button>
+            <button onClick={handleDiscardInterrupted} className="btn-secondary text-sm px-3 py-1.5">
+              Dismiss
+            </button>
+          </motion.div>
+        )}
+
         {/* Mode Selection */}
         <div className="flex-1 flex items-center justify-center py-4">
           <div className="grid grid-cols-1 md:grid-cols-2 gap-5 w-full max-w-2xl">
@@ -13,6 +13,7 @@ interface AppStore extends AppState {
   setTargetFolder: (path: string | null) => void;
   setSelectedCloudFolder: (path: string | null) => void;
   setError: (error: string | null) => void;
+  setKeyWarning: (warning: string | null) => void;
   reset: () => void;
 }
 
@@ -25,6 +26,7 @@ const initialState: AppState = {
   selectedTargetFolder: null,
   selectedCloudFolder: null,
   error: null,
+  keyWarning: null,
 };
 
 export const useAppStore = create<AppStore>((set) => ({
@@ -56,6 +58,8 @@ export const useAppStore = create<AppStore>((set) => ({
   
   setError: (error) => set({ error }),
   
+  setKeyWarning: (keyWarning) => set({ keyWarning }),
+  
   reset: () => set(initialState),
 }));
 
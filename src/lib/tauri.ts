@@ -1,12 +1,24 @@
 import { invoke } from '@tauri-apps/api/core';
+import { listen, type UnlistenFn } from '@tauri-apps/api/event';
 import { open } from '@tauri-apps/plugin-dialog';
-import type { ValidationResult, KeyPayload, SyncProgress, CloudFolder, CredentialsStatus } from './types';
+import type { ValidationResult, KeyPayload, SyncProgress, SyncSummary, SyncPolicy, QueuedJobStatus, CloudFolder, CloudFolderPage, CredentialsStatus, ConnectionStatus, ConnectionInfo, S3CostEstimate, StorageStats, SyncConfig, RateLimitedEvent, LimitReachedEvent, S3Object, SortBy, SortDir, AppError, FileTransferRecord, PendingFile, ScanCompleteEvent, SessionStats, UsingCachedAclEvent } from './types';
 
 // Check if running in Tauri environment
 export const isTauri = () => {
   return typeof window !== 'undefined' && '__TAURI__' in window;
 };
 
+export function isAppError(err: unknown): err is AppError {
+  return typeof err === 'object' && err !== null && 'type' in err && 'message' in err;
+}
+
+// Human-readable text for an error thrown by a command
+export function errorMessage(err: unknown, fallback: string): string {
+  if (isAppError(err)) return err.message;
+  if (err instanceof Error) return err.message;
+  return typeof err === 'string' ? err : fallback;
+}
+
 // Auth commands
 export async function checkStoredKey(): Promise<boolean> {
   if (!isTauri()) return false;
@@ -17,12 +29,25 @@ export async function validateKey(key: string): Promise<ValidationResult> {
   return invoke<ValidationResult>('validate_key', { key });
 }
 
+export async function refreshConnection(): Promise<void> {
+  return invoke<void>('refresh_connection');
+}
+
+export async function checkConnection(): Promise<ConnectionStatus> {
+  return invoke<ConnectionStatus>('check_connection');
+}
+
+// Writes and deletes a 1-byte probe object to time an upload
+export async function checkConnectionHealth(probeUpload = false): Promise<ConnectionInfo> {
+  return invoke<ConnectionInfo>('check_connection_health', { probeUpload });
+}
+
 export async function getUserInfo(): Promise<KeyPayload | null> {
   return invoke<KeyPayload | null>('get_user_info');
 }
 
-export async function logout(): Promise<void> {
-  return invoke<void>('logout');
+export async function logout(force = false): Promise<void> {
+  return invoke<void>('logout', { force });
 }
 
 // Sync commands
@@ -30,10 +55,66 @@ export async function startUpload(sourcePaths: string[]): Promise<void> {
   return invoke<void>('start_upload', { sourcePaths });
 }
 
+// Each file is uploaded once and copied to the other prefixes, e.g. ['backups/daily', 'backups/weekly']
+export async function startMultiDestUpload(sourcePaths: string[], remotePrefixes: string[]): Promise<void> {
+  return invoke<void>('start_multi_dest_upload', { sourcePaths, remotePrefixes });
+}
+
+export async function previewUpload(sourcePaths: string[]): Promise<PendingFile[]> {
+  return invoke<PendingFile[]>('preview_upload', { sourcePaths });
+}
+
+// False if a file in the folder was changed or deleted since its last upload
+export async function verifyFolderChecksum(cloudFolder: string): Promise<boolean> {
+  return invoke<boolean>('verify_folder_checksum', { cloudFolder });
+}
+
+// JSON of an UploadManifest, or null before the first upload with generate_manifest on
+export async function getLastManifest(): Promise<string | null> {
+  return invoke<string | null>('get_last_manifest');
+}
+
+export async function estimateSyncCost(sourcePaths: string[]): Promise<S3CostEstimate> {
+  return invoke<S3CostEstimate>('estimate_sync_cost', { sourcePaths });
+}
+
+export async function getInterruptedSync(): Promise<SyncProgress | null> {
+  return invoke<SyncProgress | null>('get_interrupted_sync');
+}
+
+export async function resumeLastUploadSession(): Promise<SyncProgress | null> {
+  return invoke<SyncProgress | null>('resume_last_upload_session');
+}
+
+export async function discardInterruptedSync(): Promise<void> {
+  return invoke<void>('discard_interrupted_sync');
+}
+
 export async function startDownload(cloudFolder: string, targetPath: string): Promise<void> {
   return invoke<void>('start_download', { cloudFolder, targetPath });
 }
 
+// Queued jobs run one at a time, highest priority (0-255) first; returns the job ID
+export async function queueUpload(sourcePaths: string[], priority: number): Promise<string> {
+  return invoke<string>('queue_upload', { sourcePaths, priority });
+}
+
+export async function queueDownload(cloudFolder: string, targetPath: string, priority: number): Promise<string> {
+  return invoke<string>('queue_download', { cloudFolder, targetPath, priority });
+}
+
+export async function pauseQueue(): Promise<void> {
+  return invoke<void>('pause_queue');
+}
+
+export async function resumeQueue(): Promise<void> {
+  return invoke<void>('resume_queue');
+}
+
+export async function getQueueStatus(): Promise<QueuedJobStatus[]> {
+  return invoke<QueuedJobStatus[]>('get_queue_status');
+}
+
 export async function pauseSync(): Promise<void> {
   return invoke<void>('pause_sync');
 }
@@ -46,18 +127,141 @@ export async function cancelSync(): Promise<void> {
   return invoke<void>('cancel_sync');
 }
 
+// Call again after a reload so this window keeps getting sync events
+export async function registerForSyncEvents(): Promise<void> {
+  return invoke<void>('register_for_sync_events');
+}
+
+export async function unregisterFromSyncEvents(label: string): Promise<void> {
+  return invoke<void>('unregister_from_sync_events', { label });
+}
+
+export async function confirmSync(): Promise<void> {
+  return invoke<void>('confirm_sync');
+}
+
+export async function setSyncConfig(config: SyncConfig): Promise<void> {
+  return invoke<void>('set_sync_config', { config });
+}
+
+// Session-wide switch on top of SyncConfig.read_only
+export async function setReadOnlyMode(enabled: boolean): Promise<void> {
+  return invoke<void>('set_read_only_mode', { enabled });
+}
+
 export async function getSyncProgress(): Promise<SyncProgress> {
   return invoke<SyncProgress>('get_sync_progress');
 }
 
+// Null until an upload or download has finished this session
+export async function getLastSyncSummary(): Promise<SyncSummary | null> {
+  return invoke<SyncSummary | null>('get_last_sync_summary');
+}
+
+export async function getSyncPolicy(): Promise<SyncPolicy> {
+  return invoke<SyncPolicy>('get_sync_policy');
+}
+
+export async function onRateLimited(handler: (event: RateLimitedEvent) => void): Promise<UnlistenFn> {
+  return listen<RateLimitedEvent>('sync://rate_limited', (event) => handler(event.payload));
+}
+
+// An upload stopped at SyncConfig.max_session_bytes
+export async function onLimitReached(handler: (event: LimitReachedEvent) => void): Promise<UnlistenFn> {
+  return listen<LimitReachedEvent>('sync://limit_reached', (event) => handler(event.payload));
+}
+
+// Login checked the key against the admin lists saved locally, S3 being unreachable
+export async function onUsingCachedAcl(handler: (event: UsingCachedAclEvent) => void): Promise<UnlistenFn> {
+  return listen<UsingCachedAclEvent>('security://using_cached_acl', (event) => handler(event.payload));
+}
+
+export async function onScanComplete(handler: (event: ScanCompleteEvent) => void): Promise<UnlistenFn> {
+  return listen<ScanCompleteEvent>('sync://scan_complete', (event) => handler(event.payload));
+}
+
+export type SyncStateEvent = 'paused' | 'resumed' | 'cancelled';
+
+export async function onSyncStateChanged(handler: (state: SyncStateEvent) => void): Promise<UnlistenFn> {
+  const states: SyncStateEvent[] = ['paused', 'resumed', 'cancelled'];
+  const unlisteners = await Promise.all(
+    states.map((state) => listen(`sync://${state}`, () => handler(state)))
+  );
+  return () => unlisteners.forEach((fn) => fn());
+}
+
 export async function listCloudFolders(): Promise<CloudFolder[]> {
   return invoke<CloudFolder[]>('list_cloud_folders');
 }
 
+// One page of the top-level folders, without sizes; see loadFolderSize
+export async function listCloudFoldersPage(page: number, pageSize: number): Promise<CloudFolderPage> {
+  return invoke<CloudFolderPage>('list_cloud_folders_page', { page, pageSize });
+}
+
+export async function loadFolderSize(folder: string): Promise<CloudFolder> {
+  return invoke<CloudFolder>('load_folder_size', { folder });
+}
+
+export async function listCloudFolderTree(root: string): Promise<CloudFolder[]> {
+  return invoke<CloudFolder[]>('list_cloud_folder_tree', { root });
+}
+
+export async function listCloudFiles(folder: string, sortBy: SortBy = 'name', sortDir: SortDir = 'asc'): Promise<S3Object[]> {
+  return invoke<S3Object[]>('list_cloud_files', { folder, sortBy, sortDir });
+}
+
+export async function addSecondaryDestination(
+  endpoint: string,
+  bucket: string,
+  accessKey: string,
+  secretKey: string
+): Promise<void> {
+  return invoke('add_secondary_destination', { endpoint, bucket, accessKey, secretKey });
+}
+
+export async function getStorageStats(): Promise<StorageStats> {
+  return invoke<StorageStats>('get_storage_stats');
+}
+
+// Drop recently uploaded files the bucket may not list yet from the next listing
+export async function invalidateListingCache(): Promise<void> {
+  return invoke<void>('invalidate_listing_cache');
+}
+
+export async function getSessionStats(): Promise<SessionStats> {
+  return invoke<SessionStats>('get_session_stats');
+}
+
+export async function resetSessionStats(): Promise<void> {
+  return invoke<void>('reset_session_stats');
+}
+
+export async function getFileTransferTimings(): Promise<FileTransferRecord[]> {
+  return invoke<FileTransferRecord[]>('get_file_transfer_timings');
+}
+
 export async function deleteAllFiles(): Promise<number> {
   return invoke<number>('delete_all_files');
 }
 
+export async function tagCloudFile(cloudKey: string, tags: Record<string, string>): Promise<void> {
+  return invoke<void>('tag_cloud_file', { cloudKey, tags });
+}
+
+export async function getCloudFileTags(cloudKey: string): Promise<Record<string, string>> {
+  return invoke<Record<string, string>>('get_cloud_file_tags', { cloudKey });
+}
+
+// acl is 'private', 'public-read' or 'bucket-owner-full-control'
+export async function setCloudFileAcl(cloudKey: string, acl: string): Promise<void> {
+  return invoke<void>('set_cloud_file_acl', { cloudKey, acl });
+}
+
+export async function verifyFileChecksum(cloudKey: string): Promise<boolean> {
+  return invoke<boolean>('verify_file_checksum', { cloudKey });
+}
+
 export async function checkCredentialsStatus(): Promise<CredentialsStatus> {
   return invoke<CredentialsStatus>('check_credentials_status');
 }
@@ -117,13 +321,30 @@ export function getStatusText(status: SyncProgress['status']): string {
   if (typeof status === 'string') {
     switch (status) {
       case 'Idle': return 'Ready';
-      case 'Scanning': return 'Scanning files...';
       case 'Syncing': return 'Syncing...';
       case 'Paused': return 'Paused';
+      case 'Cancelling': return 'Cancelling...';
       case 'Completed': return 'Completed';
       default: return status;
     }
   }
+  if ('Scanning' in status) {
+    const { files_found, dirs_scanned } = status.Scanning;
+    return `Scanning files (${files_found} found in ${dirs_scanned} folders)...`;
+  }
+  if ('Hashing' in status) {
+    return `Hashing files (${status.Hashing.files_hashed}/${status.Hashing.total_files})...`;
+  }
+  if ('AwaitingConfirmation' in status) {
+    const { total_files, total_bytes } = status.AwaitingConfirmation.scan_summary;
+    return `Ready to upload ${total_files} files (${formatBytes(total_bytes)})`;
+  }
+  if ('CompletedWithErrors' in status) {
+    return `Completed with ${status.CompletedWithErrors.failed_count} failed`;
+  }
+  if ('StoppedByLimit' in status) {
+    return `Stopped at the ${formatBytes(status.StoppedByLimit.limit)} limit`;
+  }
   if ('Error' in status) {
     return `Error: ${status.Error}`;
   }
@@ -2,24 +2,86 @@ export interface KeyPayload {
   uid: string;
   name: string;
   created: number;
+  expires_at?: number;
+  max_storage_gb?: number;
+  permissions?: number;
+  // CIDR ranges the key may be used from
+  allowed_ip_ranges?: string[];
 }
 
+// Error returned by every Tauri command; switch on `type`
+export type AppError = { message: string } & (
+  | { type: 'NotAuthenticated' }
+  | { type: 'NetworkError'; detail: string }
+  | { type: 'InvalidPath'; detail: string }
+  | { type: 'QuotaExceeded'; used: number; limit: number }
+  | { type: 'CredentialsExpired' }
+  | { type: 'Cancelled' }
+  | { type: 'PermissionDenied' }
+  | { type: 'ForeignSession' }
+  | { type: 'FileAccessDenied'; detail: string }
+  | { type: 'DiskFull'; path: string; required_bytes: number; available_bytes: number }
+  | { type: 'InvalidRequest'; detail: string }
+  | { type: 'TooManyPaths'; count: number; max: number }
+  | { type: 'PathTooLong'; path: string }
+  | { type: 'SyncTooLarge'; total_bytes: number; limit: number }
+  | { type: 'InternalError'; detail: string }
+);
+
 export interface ValidationResult {
   valid: boolean;
   user_name: string | null;
   error: string | null;
+  key_expires_in_days: number | null;
+  warning: string | null;
+  s3_credentials_days_remaining: number;
 }
 
-export type SyncDirection = 'LocalToCloud' | 'CloudToLocal';
+export type SyncDirection =
+  | 'LocalToCloud'
+  | 'CloudToLocal'
+  | { CloudToCloud: { source_folder: string; dest_folder: string } };
 
 export type SyncStatus = 
   | 'Idle'
-  | 'Scanning'
+  | { Scanning: { files_found: number; dirs_scanned: number } }
+  | { Hashing: { files_hashed: number; total_files: number } }
+  | { AwaitingConfirmation: { scan_summary: ScanSummary } }
   | 'Syncing'
   | 'Paused'
+  | 'Cancelling'
   | 'Completed'
+  | { CompletedWithErrors: { failed_count: number } }
+  // Ended early at SyncConfig.max_session_bytes
+  | { StoppedByLimit: { transferred: number; limit: number } }
   | { Error: string };
 
+// What a two-phase upload will transfer once confirmed; new and modified
+// counts are only checked in differential mode
+export interface ScanSummary {
+  total_files: number;
+  total_bytes: number;
+  new_files: number;
+  modified_files: number;
+}
+
+export interface ScanCompleteEvent {
+  files: FileEntry[];
+  total_bytes: number;
+}
+
+export interface FileEntry {
+  path: string;
+  size: number;
+  is_dir: boolean;
+  content_hash: string | null;
+}
+
+export interface FailedFile {
+  path: string;
+  error: string;
+}
+
 export interface SyncProgress {
   status: SyncStatus;
   direction: SyncDirection | null;
@@ -28,8 +90,200 @@ export interface SyncProgress {
   total_bytes: number;
   transferred_bytes: number;
   current_file: string | null;
+  current_file_bytes_transferred: number;
+  current_file_bytes_total: number;
+  current_file_bytes_received: number;
+  // Speed and time left for current_file
+  current_file_bps: number;
+  current_file_eta_secs: number | null;
+  skipped_files: number;
+  // [path, reason] for skipped files that couldn't be uploaded
+  skipped_reasons: [string, string][];
   bytes_per_second: number;
   eta_seconds: number | null;
+  folder_progress: FolderProgress[];
+  active_folder: string | null;
+  failed_files: FailedFile[];
+  error_summary: string | null;
+  destination_count: number;
+  secondary_errors: string[];
+  // Files left out by size, when notify_excluded is set
+  excluded_files: string[];
+  active_transfers: number;
+  active_files: string[];
+  // Speed and time left of each file being transferred, sorted by path
+  active_file_progress: ActiveFileProgress[];
+  files_found_during_scan: number;
+}
+
+export interface ActiveFileProgress {
+  path: string;
+  bytes_sent: number;
+  total_bytes: number;
+  bytes_per_second: number;
+  eta_secs: number | null;
+}
+
+// A finished upload or download; times are RFC 3339 strings
+export interface SyncSummary {
+  session_id: string;
+  direction: SyncDirection;
+  start_time: string;
+  end_time: string;
+  duration_secs: number;
+  total_files: number;
+  transferred_bytes: number;
+  skipped_files: number;
+  skipped_reasons: [string, string][];
+  deleted_files: number;
+  failed_files: string[];
+  average_bps: number;
+  peak_bps: number;
+  percentile_95_ms: number;
+  mean_ms: number;
+  slowest_file: string | null;
+}
+
+// Upload rules set by the administrators; breaking files fail the upload
+export interface SyncPolicy {
+  max_file_size_bytes: number | null;
+  forbidden_extensions: string[];
+  required_tags: Record<string, string>;
+  force_read_only: boolean;
+  forbidden_acls: string[];
+}
+
+export type SyncJob =
+  | { Upload: { source_paths: string[] } }
+  | { Download: { cloud_folder: string; target_path: string } };
+
+// Position 0 is the running job
+export interface QueuedJobStatus {
+  id: string;
+  position: number;
+  running: boolean;
+  priority: number;
+  job: SyncJob;
+  queued_at: string;
+  estimated_wait_secs: number | null;
+}
+
+export type UploadReason = 'NewFile' | 'Modified' | 'Forced';
+
+// A file the next upload would transfer, from previewUpload
+export interface PendingFile {
+  local_path: string;
+  remote_path: string;
+  size: number;
+  reason: UploadReason;
+}
+
+export interface FolderProgress {
+  folder_name: string;
+  total_files: number;
+  completed_files: number;
+  total_bytes: number;
+  transferred_bytes: number;
+}
+
+export type ErrorPolicy = 'Abort' | 'Skip';
+
+export type LockMode = 'Governance' | 'Compliance';
+
+// Needs a bucket created with object locking enabled
+export interface ObjectLockConfig {
+  retain_days: number;
+  mode: LockMode;
+}
+
+// Provider prices for estimateSyncCost
+export interface S3Pricing {
+  upload_per_gb_usd: number;
+  download_per_gb_usd: number;
+  storage_per_gb_month_usd: number;
+  put_per_1000_usd: number;
+  get_per_1000_usd: number;
+}
+
+// The total includes one month of storage
+export interface S3CostEstimate {
+  upload_cost_usd: number;
+  download_cost_usd: number;
+  storage_cost_per_month_usd: number;
+  api_call_cost_usd: number;
+  total_estimated_usd: number;
+}
+
+export interface SyncConfig {
+  notifications_enabled: boolean;
+  differential: boolean;
+  concurrency: number;
+  max_file_retries: number;
+  on_error: ErrorPolicy;
+  // Not supported yet; setSyncConfig rejects true
+  encrypt_contents: boolean;
+  exclude_patterns: string[];
+  default_tags: Record<string, string>;
+  atomic_uploads: boolean;
+  // Scan first and wait for confirmSync before uploading
+  two_phase: boolean;
+  overwrite_existing: boolean;
+  compute_hashes: boolean;
+  multipart_download: boolean;
+  multipart_threshold: number;
+  // Source folders scanned at once; 0 scans them all at once
+  scan_parallelism: number;
+  follow_symlinks: boolean;
+  follow_junctions: boolean;
+  skip_empty_files: boolean;
+  // Files outside these sizes, in bytes, are left out of uploads
+  exclude_min_size: number | null;
+  exclude_max_size: number | null;
+  notify_excluded: boolean;
+  object_lock: ObjectLockConfig | null;
+  // Upload a file once more if it changes while being read
+  retry_changed_files: boolean;
+  // Match local folder names case-sensitively; defaults to false on Windows and macOS
+  case_sensitive: boolean;
+  // Free space to leave on the disk when downloading
+  min_free_gb: number | null;
+  // Canned ACL set on each upload, e.g. 'bucket-owner-full-control'
+  default_acl: string | null;
+  // Remote path for each source folder, e.g. 'backups/{date}/{folder_name}';
+  // also takes {datetime} and {uid}
+  remote_path_template: string | null;
+  // Scaleway nl-ams prices when null
+  pricing: S3Pricing | null;
+  // Server-side encryption with a key derived for the user (SSE-C); files uploaded
+  // with it on can only be downloaded with it on
+  use_sse_c: boolean;
+  // Store a manifest of each upload's files and their SHA-256 with them
+  generate_manifest: boolean;
+  // No uploads or deletions; previews and downloads still work
+  read_only: boolean;
+  // Store a checksum of each uploaded folder for verifyFolderChecksum
+  folder_checksums: boolean;
+  // Most bytes one upload may send; files that would go over it are skipped
+  max_session_bytes: number | null;
+}
+
+// Parsed from getLastManifest
+export interface UploadManifest {
+  created_at: string;
+  files: { path: string; size: number; sha256: string; uploaded_at: string }[];
+}
+
+export interface RateLimitedEvent {
+  retry_after_secs: number;
+}
+
+export interface LimitReachedEvent {
+  transferred_bytes: number;
+  limit_bytes: number;
+}
+
+export interface UsingCachedAclEvent {
+  cache_age_secs: number;
 }
 
 export interface CloudFolder {
@@ -37,6 +291,71 @@ export interface CloudFolder {
   path: string;
   total_size: number;
   file_count: number;
+  // Subfolders; only filled in by listCloudFolderTree
+  children: CloudFolder[];
+  // False for folders from listCloudFoldersPage until loadFolderSize
+  size_loaded: boolean;
+}
+
+export interface CloudFolderPage {
+  folders: CloudFolder[];
+  total: number;
+  has_more: boolean;
+}
+
+export interface S3Object {
+  key: string;
+  size: number;
+  last_modified: number;
+  etag: string | null;
+  content_type: string | null;
+  // Base64 SHA-256 stored with single-part uploads
+  checksum_sha256: string | null;
+}
+
+export type SortBy = 'name' | 'size' | 'last_modified';
+export type SortDir = 'asc' | 'desc';
+
+// Syncs finished since the app started or resetSessionStats
+export interface SessionStats {
+  uploads_count: number;
+  downloads_count: number;
+  bytes_uploaded: number;
+  bytes_downloaded: number;
+  session_start: string;
+}
+
+export interface FileTransferRecord {
+  path: string;
+  size: number;
+  duration_ms: number;
+  direction: SyncDirection;
+}
+
+export interface StorageStats {
+  used_bytes: number;
+  file_count: number;
+  folder_count: number;
+  largest_file: S3Object | null;
+}
+
+export interface ConnectionStatus {
+  reachable: boolean;
+  bucket_exists: boolean;
+  credentials_valid: boolean;
+}
+
+// Latency to the bucket from checkConnectionHealth
+export interface ConnectionInfo {
+  rtt_ms: number;
+  // Only timed when checkConnectionHealth is asked to probe an upload
+  upload_latency_ms: number | null;
+  endpoint: string;
+  // Where the bucket actually is, which may differ from the configured region
+  region: string;
+  bucket: string;
+  // Concurrency worth setting in SyncConfig at this round-trip time
+  suggested_concurrency: number;
 }
 
 export interface CredentialsStatus {
@@ -44,6 +363,10 @@ export interface CredentialsStatus {
   days_remaining: number;
   expiry_date: string;
   warning: string | null;
+  // Where the app connects to, for diagnosing connection problems
+  endpoint: string;
+  bucket: string;
+  region: string;
 }
 
 export type AppScreen = 'loading' | 'key-entry' | 'main';
@@ -57,5 +380,6 @@ export interface AppState {
   selectedTargetFolder: string | null;
   selectedCloudFolder: string | null;
   error: string | null;
+  keyWarning: string | null;
 }
 